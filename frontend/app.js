@@ -3,6 +3,9 @@
   const API_BASE = 'http://localhost:3000';
   const WS_BASE = 'ws://localhost:3000';
   const MAX_TABS = 10;
+  // Identifies this window to the backend across its tabs' WebSockets, so
+  // input group typing only reaches tabs this window controls
+  const CLIENT_KEY = Date.now().toString(36) + Math.random().toString(36).substr(2, 8);
 
   // DOM elements - Serial
   const portSelect = document.getElementById('port-select');
@@ -37,6 +40,7 @@
   const sessionSidebar = document.getElementById('session-sidebar');
   const sessionList = document.getElementById('session-list');
   const sessionAddBtn = document.getElementById('session-add-btn');
+  const sessionImportBtn = document.getElementById('session-import-btn');
 
   const settingsBtn = document.getElementById('settings-btn');
   const settingsModal = document.getElementById('settings-modal');
@@ -76,6 +80,52 @@
   var logPathInput = document.getElementById('log-path-input');
   var logStartBtn = document.getElementById('log-start-btn');
   var statusbarLogPath = document.getElementById('statusbar-log-path');
+  var groupBtn = document.getElementById('group-btn');
+  var uploadBtn = document.getElementById('upload-btn');
+  var uploadModal = document.getElementById('upload-modal');
+  var uploadModalCloseBtn = document.getElementById('upload-modal-close-btn');
+  var uploadPathInput = document.getElementById('upload-path-input');
+  var uploadSendBtn = document.getElementById('upload-send-btn');
+  var uploadCancelBtn = document.getElementById('upload-cancel-btn');
+  var xmodemBtn = document.getElementById('xmodem-btn');
+  var xmodemModal = document.getElementById('xmodem-modal');
+  var xmodemModalCloseBtn = document.getElementById('xmodem-modal-close-btn');
+  var flashBtn = document.getElementById('flash-btn');
+  var flashModal = document.getElementById('flash-modal');
+  var zmodemSendModal = document.getElementById('zmodem-send-modal');
+  var zmodemSendPaths = document.getElementById('zmodem-send-paths');
+  // Tab whose remote rz is waiting for files
+  var zmodemSendTab = null;
+  // 'zmodem' for rz, 'trzsz' for trz
+  var zmodemSendProtocol = 'zmodem';
+  var zmodemExistsModal = document.getElementById('zmodem-exists-modal');
+  // Tab whose ZMODEM download waits to be told what to do with a file
+  var zmodemExistsTab = null;
+
+  // DOM elements - Session import
+  var scriptBtn = document.getElementById('script-btn');
+  var scriptModal = document.getElementById('script-modal');
+  var scriptModalCloseBtn = document.getElementById('script-modal-close-btn');
+  var scriptSource = document.getElementById('script-source');
+  var scriptRunBtn = document.getElementById('script-run-btn');
+  var scriptAbortBtn = document.getElementById('script-abort-btn');
+  var scriptAttachBtn = document.getElementById('script-attach-btn');
+  var scriptDetachBtn = document.getElementById('script-detach-btn');
+  var triggersBtn = document.getElementById('triggers-btn');
+  var triggersModal = document.getElementById('triggers-modal');
+  var triggersModalCloseBtn = document.getElementById('triggers-modal-close-btn');
+  var triggersSource = document.getElementById('triggers-source');
+  var triggersCounters = document.getElementById('triggers-counters');
+  var triggersSaveBtn = document.getElementById('triggers-save-btn');
+  var triggersResetBtn = document.getElementById('triggers-reset-btn');
+  var importModal = document.getElementById('import-modal');
+  var importModalCloseBtn = document.getElementById('import-modal-close-btn');
+  var importSourceSelect = document.getElementById('import-source');
+  var importPathInput = document.getElementById('import-path');
+  var importFolderInput = document.getElementById('import-folder');
+  var importReport = document.getElementById('import-report');
+  var importPreviewBtn = document.getElementById('import-preview-btn');
+  var importRunBtn = document.getElementById('import-run-btn');
 
   // DOM elements - Tab bar
   var tabList = document.getElementById('tab-list');
@@ -93,7 +143,7 @@
 
   // Each tab: { id, label, term, ws, fitAddon, searchAddon, onDataDisposable,
   //             connected, mode, containerEl, loggingActive, loggingPath,
-  //             zmodemFileStart, zmodemFileCount }
+  //             zmodemFileStart, zmodemFileCount, inputGroup }
   var tabs = [];
   var activeTabId = null;
 
@@ -127,45 +177,89 @@
     alwaysReconnect: false,
     rememberSsh: true,
     sidebarOpen: false,
-    folders: []
+    scrollbackSize: 128 * 1024,
+    scrollbackSpill: false
   };
 
-  var defaults = loadDefaults();
-  var sessions = loadSessions();
+  // Sessions, folders, defaults and quick-connect SSH info live in the
+  // backend session store and are loaded by loadStore() at startup.
+  var defaults = Object.assign({}, DEFAULT_SETTINGS);
+  var sessions = [];
+  var folders = [];
+  var sshInfo = {};
 
-  function loadDefaults() {
-    try {
-      var saved = JSON.parse(localStorage.getItem('serial-rs-defaults'));
-      if (saved) return Object.assign({}, DEFAULT_SETTINGS, saved);
-    } catch (e) {}
-    try {
-      var old = JSON.parse(localStorage.getItem('serial-rs-settings'));
-      if (old) {
-        var d = Object.assign({}, DEFAULT_SETTINGS);
-        if (old.filterCuOnly !== undefined) d.filterCuOnly = old.filterCuOnly;
-        if (old.alwaysReconnect !== undefined) d.alwaysReconnect = old.alwaysReconnect;
-        if (old.rememberSsh !== undefined) d.rememberSsh = old.rememberSsh;
-        localStorage.setItem('serial-rs-defaults', JSON.stringify(d));
-        return d;
-      }
-    } catch (e) {}
-    return Object.assign({}, DEFAULT_SETTINGS);
+  var LEGACY_STORAGE_KEYS = ['serial-rs-sessions', 'serial-rs-defaults', 'serial-rs-settings', 'serial-rs-ssh-info'];
+
+  function storeRequest(method, path, body) {
+    var opts = { method: method };
+    if (body !== undefined) {
+      opts.headers = { 'Content-Type': 'application/json' };
+      opts.body = JSON.stringify(body);
+    }
+    return fetch(API_BASE + path, opts).then(function(res) {
+      if (!res.ok) throw new Error('HTTP ' + res.status);
+      return res.json();
+    }).catch(function(e) {
+      console.error('Session store ' + method + ' ' + path + ' failed:', e);
+      return null;
+    });
   }
 
-  function saveDefaults() {
-    localStorage.setItem('serial-rs-defaults', JSON.stringify(defaults));
+  function readLegacy(key) {
+    try { return JSON.parse(localStorage.getItem(key)); } catch (e) { return null; }
   }
 
-  function loadSessions() {
-    try {
-      var saved = JSON.parse(localStorage.getItem('serial-rs-sessions'));
-      if (Array.isArray(saved)) return saved;
-    } catch (e) {}
-    return [];
+  // Move data kept in localStorage by older versions into the backend store.
+  async function migrateLocalStorage() {
+    var saved = readLegacy('serial-rs-sessions');
+    var savedDefaults = readLegacy('serial-rs-defaults');
+    var old = readLegacy('serial-rs-settings');
+    var info = readLegacy('serial-rs-ssh-info');
+    if (!saved && !savedDefaults && !old && !info) return;
+
+    if (!savedDefaults && old) {
+      savedDefaults = {};
+      ['filterCuOnly', 'alwaysReconnect', 'rememberSsh'].forEach(function(k) {
+        if (old[k] !== undefined) savedDefaults[k] = old[k];
+      });
+    }
+    var report = await storeRequest('POST', '/api/sessions/import-local', {
+      sessions: Array.isArray(saved) ? saved : [],
+      defaults: savedDefaults || null,
+      ssh_info: info || null
+    });
+    if (!report) return;
+    if (report.errors && report.errors.length) {
+      // Keep the old data so nothing is lost; importing again skips what
+      // already made it into the store.
+      console.warn('Some saved sessions could not be migrated, keeping them for a retry:',
+                   report.errors);
+      return;
+    }
+    LEGACY_STORAGE_KEYS.forEach(function(k) { localStorage.removeItem(k); });
+  }
+
+  async function loadStore() {
+    await migrateLocalStorage();
+    var results = await Promise.all([
+      storeRequest('GET', '/api/sessions'),
+      storeRequest('GET', '/api/sessions/defaults'),
+      storeRequest('GET', '/api/sessions/ssh-info')
+    ]);
+    if (results[0]) {
+      sessions = results[0].sessions || [];
+      folders = results[0].folders || [];
+    }
+    if (results[1]) defaults = Object.assign({}, DEFAULT_SETTINGS, results[1]);
+    if (results[2]) sshInfo = results[2];
+  }
+
+  function saveDefaults() {
+    storeRequest('PUT', '/api/sessions/defaults', defaults);
   }
 
-  function saveSessions() {
-    localStorage.setItem('serial-rs-sessions', JSON.stringify(sessions));
+  function saveSession(session) {
+    storeRequest('PUT', '/api/sessions/' + encodeURIComponent(session.id), session);
   }
 
   function resolveSession(session) {
@@ -202,27 +296,27 @@
 
   function ensureFolder(name) {
     if (!name) return;
-    if (!defaults.folders) defaults.folders = [];
-    if (defaults.folders.indexOf(name) === -1) {
-      defaults.folders.push(name);
-      saveDefaults();
+    if (folders.indexOf(name) === -1) {
+      folders.push(name);
+      storeRequest('POST', '/api/sessions/folders', { name: name });
     }
   }
 
   function cleanupFolders() {
-    if (!defaults.folders) return;
     var usedFolders = {};
     sessions.forEach(function(s) {
       if (s.folder) usedFolders[s.folder] = true;
     });
-    defaults.folders = defaults.folders.filter(function(f) { return usedFolders[f]; });
-    saveDefaults();
+    folders = folders.filter(function(f) {
+      if (usedFolders[f]) return true;
+      storeRequest('DELETE', '/api/sessions/folders/' + encodeURIComponent(f));
+      return false;
+    });
   }
 
   function populateFolderSelect(selectEl, selectedFolder) {
     selectEl.innerHTML = '<option value="">(None)</option>';
-    var folders = (defaults.folders || []).slice().sort();
-    folders.forEach(function(f) {
+    folders.slice().sort().forEach(function(f) {
       var opt = document.createElement('option');
       opt.value = f;
       opt.textContent = f;
@@ -265,7 +359,7 @@
   // -----------------------------------------------------------------------
 
   function loadSshInfo() {
-    try { return JSON.parse(localStorage.getItem('serial-rs-ssh-info')) || {}; } catch (e) { return {}; }
+    return sshInfo;
   }
 
   function saveSshInfo(includePassword) {
@@ -277,7 +371,8 @@
       keyFile: sshKeyfileInput.value
     };
     if (includePassword) data.password = sshPasswordInput.value;
-    localStorage.setItem('serial-rs-ssh-info', JSON.stringify(data));
+    sshInfo = data;
+    storeRequest('PUT', '/api/sessions/ssh-info', data);
   }
 
   function applySshInfo() {
@@ -324,7 +419,7 @@
   // Tab management
   // -----------------------------------------------------------------------
 
-  function createTab(label, settings) {
+  function createTab(label, settings, tabId) {
     if (tabs.length >= MAX_TABS) {
       var active = getActiveTab();
       if (active && active.term) {
@@ -334,7 +429,7 @@
     }
 
     var ts = settings || defaults;
-    var id = genTabId();
+    var id = tabId || genTabId();
 
     // Create terminal container div
     var containerEl = document.createElement('div');
@@ -377,7 +472,8 @@
       loggingActive: false,
       loggingPath: null,
       zmodemFileStart: 0,
-      zmodemFileCount: 0
+      zmodemFileCount: 0,
+      inputGroup: { member: false, opt_out: false }
     };
 
     // SSH resize handler per-tab
@@ -423,6 +519,9 @@
     updateUI();
     renderTabBar();
     updateLogUI();
+    updateGroupUI();
+    updateScriptUI();
+    updateUploadUI();
 
     // Focus the terminal
     if (tab.term) tab.term.focus();
@@ -475,6 +574,14 @@
       label.className = 'tab-label';
       label.textContent = tab.label;
 
+      var badge = null;
+      if (tab.inputGroup.member) {
+        badge = document.createElement('span');
+        badge.className = 'tab-group-badge' + (tab.inputGroup.opt_out ? ' paused' : '');
+        badge.textContent = '\u21F6';
+        badge.title = tab.inputGroup.opt_out ? 'In input group (paused)' : 'In input group';
+      }
+
       var close = document.createElement('button');
       close.className = 'tab-close';
       close.textContent = '\u00D7';
@@ -485,6 +592,7 @@
       });
 
       el.appendChild(status);
+      if (badge) el.appendChild(badge);
       el.appendChild(label);
       el.appendChild(close);
 
@@ -540,10 +648,129 @@
     }
   }
 
+  // -----------------------------------------------------------------------
+  // Script notifications
+  // -----------------------------------------------------------------------
+
+  function handleScriptNotification(tab, str) {
+    var match = str.match(/\x1b\]script;(.*?)\x07/);
+    if (!match) return;
+    try {
+      var msg = JSON.parse(match[1]);
+      if (msg.state === 'output') {
+        tab.term.writeln('\r\n[Script] ' + msg.message);
+        return;
+      }
+      tab.scriptRunning = msg.state === 'started';
+      if (msg.state === 'started') {
+        tab.term.writeln('\r\n[Script] Running ' + msg.name);
+      } else if (msg.state === 'finished') {
+        tab.term.writeln('\r\n[Script] Finished');
+      } else {
+        tab.term.writeln('\r\n[Script] ' + (msg.state === 'aborted' ? 'Aborted' : 'Failed') +
+          (msg.message ? ': ' + msg.message : ''));
+      }
+      if (tab.id === activeTabId) updateScriptUI();
+    } catch (e) {
+      console.error('Failed to parse script notification:', e);
+    }
+  }
+
+  function handleUploadNotification(tab, str) {
+    var match = str.match(/\x1b\]upload;(.*?)\x07/);
+    if (!match) return;
+    try {
+      var msg = JSON.parse(match[1]);
+      var wasRunning = tab.uploadRunning;
+      tab.uploadRunning = msg.state === 'running';
+      if (msg.state === 'running') {
+        if (!wasRunning) tab.term.writeln('\r\n[Upload] Sending ' + msg.filename);
+        var pct = msg.total > 0 ? Math.min(100, Math.round((msg.sent / msg.total) * 100)) : 0;
+        tab.term.write('\r\x1b[K' + msg.filename + '  ' + pct + '%  ' +
+          formatBytes(msg.sent) + '/' + formatBytes(msg.total));
+      } else if (msg.state === 'completed') {
+        tab.term.write('\r\x1b[K');
+        tab.term.writeln('[Upload] ' + msg.filename + '  ' + formatBytes(msg.total) + '  ' +
+          (msg.elapsed_ms / 1000).toFixed(1) + 's');
+      } else {
+        tab.term.writeln('\r\n[Upload] ' + (msg.state === 'cancelled' ? 'Cancelled' : 'Failed') +
+          ' after ' + formatBytes(msg.sent) + (msg.message ? ': ' + msg.message : ''));
+        if (msg.failed_record) tab.term.writeln('[Upload]   ' + msg.failed_record.text);
+      }
+      if (tab.id === activeTabId) updateUploadUI();
+    } catch (e) {
+      console.error('Failed to parse upload notification:', e);
+    }
+  }
+
+  function handleTriggerNotification(tab, str) {
+    var match = str.match(/\x1b\]trigger;(.*?)\x07/);
+    if (!match) return;
+    try {
+      var msg = JSON.parse(match[1]);
+      tab.term.writeln('\r\n[Trigger] ' + msg.rule + ': ' + msg.message);
+      if (window.Notification && Notification.permission === 'granted') {
+        new Notification(tab.label || 'serial-rs', { body: msg.message });
+      }
+    } catch (e) {
+      console.error('Failed to parse trigger notification:', e);
+    }
+  }
+
   // -----------------------------------------------------------------------
   // ZMODEM inline progress
   // -----------------------------------------------------------------------
 
+  var FLASH_STAGES = {
+    connect: 'Entering the bootloader',
+    erase: 'Erasing',
+    write: 'Writing',
+    verify: 'Verifying',
+    reset: 'Resetting'
+  };
+
+  function handleFlashNotification(tab, str) {
+    var match = str.match(/\x1b\]flash;(.*?)\x07/);
+    if (!match) return;
+    try {
+      var msg = JSON.parse(match[1]);
+      if (msg.state === 'running') {
+        // Input is held back while the bootloader has the port; Ctrl+C stops
+        if (!tab.transferRunning) {
+          tab.transferRunning = true;
+          tab.flashStage = null;
+          tab.flashChip = null;
+          tab.term.writeln('\r\n[Flash] ' + msg.files.join(', ') + '  Press Ctrl+C to cancel');
+        }
+        if (msg.chip && msg.chip !== tab.flashChip) {
+          tab.flashChip = msg.chip;
+          tab.term.write('\r\x1b[K');
+          tab.term.writeln('[Flash] ' + msg.chip);
+        }
+        if (msg.stage !== tab.flashStage) {
+          if (tab.flashStage) tab.term.writeln('');
+          tab.flashStage = msg.stage;
+        }
+        var amount = msg.stage === 'write' || msg.stage === 'verify'
+          ? '  ' + (msg.total > 0 ? Math.min(100, Math.round((msg.done / msg.total) * 100)) : 0) + '%  ' +
+            formatBytes(msg.done) + '/' + formatBytes(msg.total)
+          : '';
+        tab.term.write('\r\x1b[K' + FLASH_STAGES[msg.stage] + amount);
+        return;
+      }
+      tab.transferRunning = false;
+      tab.term.write('\r\x1b[K');
+      if (msg.state === 'completed') {
+        tab.term.writeln('[Flash] Done  ' + formatBytes(msg.total) + '  ' + (msg.elapsed_ms / 1000).toFixed(1) + 's');
+      } else {
+        tab.term.writeln('[Flash] ' + (msg.state === 'cancelled' ? 'Cancelled' : 'Failed') +
+          (msg.message ? ': ' + msg.message : ''));
+      }
+    } catch (e) {
+      console.error('Failed to parse flash notification:', e);
+    }
+  }
+
   function formatBytes(bytes) {
     if (bytes < 1024) return bytes + ' B';
     if (bytes < 1024 * 1024) return (bytes / 1024).toFixed(1) + ' KB';
@@ -556,17 +783,39 @@
     if (!match) return;
     try {
       var msg = JSON.parse(match[1]);
-      if (msg.state === 'started') {
+      if (msg.state === 'upload_request') {
+        zmodemSendTab = tab;
+        zmodemSendProtocol = msg.type || 'zmodem';
+        document.getElementById('zmodem-send-title').textContent =
+          (zmodemSendProtocol === 'trzsz' ? 'trzsz' : 'ZMODEM') + ' Upload';
+        zmodemSendModal.classList.remove('hidden');
+        zmodemSendPaths.focus();
+      } else if (msg.state === 'started') {
         tab.zmodemFileCount = 0;
         tab.zmodemFileStart = Date.now();
-        tab.term.write('\r\n');
+        tab.transferRunning = true;
+        tab.term.writeln('\r\n[Transfer] Press Ctrl+C to cancel');
       } else if (msg.state === 'progress') {
+        var done = msg.received !== undefined ? msg.received : (msg.sent || 0);
         var elapsed = (Date.now() - tab.zmodemFileStart) / 1000;
-        var speed = elapsed > 0 ? (msg.received || 0) / elapsed : 0;
-        var pct = msg.total > 0 ? Math.min(100, Math.round(((msg.received || 0) / msg.total) * 100)) : 0;
-        tab.term.write('\r\x1b[K' + (msg.filename || '') + '  ' + pct + '%  ' +
-          formatBytes(msg.received || 0) + '/' + formatBytes(msg.total || 0) +
-          '  ' + formatBytes(speed) + '/s');
+        var speed = elapsed > 0 ? done / elapsed : 0;
+        // XMODEM doesn't tell the size of a file it receives
+        var amount = msg.total > 0
+          ? Math.min(100, Math.round((done / msg.total) * 100)) + '%  ' + formatBytes(done) + '/' + formatBytes(msg.total)
+          : formatBytes(done);
+        tab.term.write('\r\x1b[K' + (msg.filename || '') + '  ' + amount + '  ' + formatBytes(speed) + '/s');
+      } else if (msg.state === 'file_exists') {
+        zmodemExistsTab = tab;
+        document.getElementById('zmodem-exists-message').textContent = msg.filename + ' (' +
+          formatBytes(msg.existing || 0) + ') is already in the download folder. The new file is ' +
+          formatBytes(msg.size || 0) + '.';
+        // Only a shorter file can be the start of this one
+        document.getElementById('zmodem-exists-resume-btn').classList.toggle('hidden', !(msg.existing < msg.size));
+        zmodemExistsModal.classList.remove('hidden');
+      } else if (msg.state === 'file_skipped') {
+        tab.term.write('\r\x1b[K');
+        tab.term.writeln((msg.filename || '') + '  skipped');
+        tab.zmodemFileStart = Date.now();
       } else if (msg.state === 'file_complete') {
         var sec = (msg.elapsedMs || 0) / 1000;
         var fspeed = sec > 0 ? (msg.size || 0) / sec : 0;
@@ -575,26 +824,54 @@
         tab.zmodemFileCount++;
         tab.zmodemFileStart = Date.now();
       } else if (msg.state === 'completed') {
+        tab.transferRunning = false;
         var elapsedSec = (msg.elapsedMs || 0) / 1000;
         var cspeed = elapsedSec > 0 ? (msg.totalBytes || 0) / elapsedSec : 0;
         if (tab.zmodemFileCount > 1) {
           tab.term.writeln('Total: ' + tab.zmodemFileCount + ' files  ' +
             formatBytes(msg.totalBytes || 0) + '  ' + elapsedSec.toFixed(1) + 's  ' + formatBytes(cspeed) + '/s');
         }
-      } else if (msg.state === 'error') {
-        tab.term.writeln('\r\n[ZMODEM Error] ' + (msg.message || 'Unknown error'));
+      } else if (msg.state === 'failed') {
+        tab.transferRunning = false;
+        if (zmodemExistsTab === tab) {
+          zmodemExistsModal.classList.add('hidden');
+          zmodemExistsTab = null;
+        }
+        tab.term.writeln('\r\n[' + (msg.type || 'zmodem').toUpperCase() + ' Failed] ' + (msg.message || 'Unknown error') +
+          (msg.partial ? ' (removed partial file ' + msg.partial + ')' : ''));
       }
     } catch (e) {
       console.error('Failed to parse ZMODEM notification:', e);
     }
   }
 
+  function handleControlNotification(tab, str) {
+    var match = str.match(/\x1b\]control;(.*?)\x07/);
+    if (!match) return;
+    try {
+      var msg = JSON.parse(match[1]);
+      var previous = tab.role;
+      tab.clientId = msg.client_id;
+      tab.role = msg.role;
+      if (previous && previous !== msg.role) {
+        tab.term.writeln('\r\n[Control] ' + (msg.role === 'controller'
+          ? 'You now control this tab'
+          : 'Another client took control; this tab is read-only'));
+      } else if (!previous && msg.role === 'observer') {
+        tab.term.writeln('[Control] Observing (' + msg.clients.length + ' clients attached)');
+      }
+    } catch (e) {
+      console.error('Failed to parse control notification:', e);
+    }
+  }
+
   // -----------------------------------------------------------------------
   // WebSocket (per-tab)
   // -----------------------------------------------------------------------
 
   function openWebSocket(tab, label) {
-    tab.ws = new WebSocket(WS_BASE + '/ws?tab_id=' + encodeURIComponent(tab.id));
+    tab.ws = new WebSocket(WS_BASE + '/ws?tab_id=' + encodeURIComponent(tab.id) +
+                           '&client=' + CLIENT_KEY);
     tab.ws.binaryType = 'arraybuffer';
 
     tab.ws.onopen = function() {
@@ -610,6 +887,26 @@
             handleZmodemNotification(tab, event.data);
             return;
           }
+          if (event.data.indexOf('\x1b]upload;') !== -1) {
+            handleUploadNotification(tab, event.data);
+            return;
+          }
+          if (event.data.indexOf('\x1b]flash;') !== -1) {
+            handleFlashNotification(tab, event.data);
+            return;
+          }
+          if (event.data.indexOf('\x1b]trigger;') !== -1) {
+            handleTriggerNotification(tab, event.data);
+            return;
+          }
+          if (event.data.indexOf('\x1b]script;') !== -1) {
+            handleScriptNotification(tab, event.data);
+            return;
+          }
+          if (event.data.indexOf('\x1b]control;') !== -1) {
+            handleControlNotification(tab, event.data);
+            return;
+          }
           tab.term.write(event.data);
         } else if (event.data instanceof ArrayBuffer) {
           tab.term.write(new Uint8Array(event.data));
@@ -617,8 +914,21 @@
       };
 
       tab.onDataDisposable = tab.term.onData(function(data) {
+        // Input doesn't reach the device during a transfer; Ctrl+C stops it
+        if (tab.transferRunning) {
+          if (data === '\x03') cancelTransfer(tab);
+          return;
+        }
         if (tab.ws && tab.ws.readyState === WebSocket.OPEN) {
-          tab.ws.send(new TextEncoder().encode(data));
+          if (tab.role === 'observer') {
+            if (!confirm('This tab is controlled by another client. Take control?')) return;
+            tab.ws.send(JSON.stringify({ type: 'control', action: 'take' }));
+          }
+          if (tab.inputGroup.member && !tab.inputGroup.opt_out) {
+            tab.ws.send(JSON.stringify({ type: 'group_input', data: data }));
+          } else {
+            tab.ws.send(new TextEncoder().encode(data));
+          }
         }
       });
 
@@ -628,6 +938,10 @@
     };
 
     tab.ws.onclose = function() {
+      tab.role = null;
+      tab.transferRunning = false;
+      tab.inputGroup = { member: false, opt_out: false };
+      if (tab.id === activeTabId) updateGroupUI();
       if (tab.connected) {
         tab.connected = false;
         if (tab.id === activeTabId) updateUI();
@@ -700,6 +1014,9 @@
       stop_bits: parseInt(stopbitsSelect.value),
       parity: paritySelect.value,
       flow_control: flowcontrolSelect.value,
+      scrollback_size: defaults.scrollbackSize,
+      scrollback_spill: defaults.scrollbackSpill,
+      session_id: tab.sessionId || null,
     };
 
     try {
@@ -742,7 +1059,7 @@
 
     tab.mode = 'ssh';
 
-    var sshConfig = { tab_id: tab.id, host: host, port: port, username: username, password: password, key_file: keyFile || null };
+    var sshConfig = { tab_id: tab.id, host: host, port: port, username: username, password: password, key_file: keyFile || null, scrollback_size: defaults.scrollbackSize, scrollback_spill: defaults.scrollbackSpill, session_id: tab.sessionId || null };
 
     try {
       var res = await fetch(API_BASE + '/api/ssh/connect', {
@@ -783,7 +1100,7 @@
               if (sess) {
                 sess.password = password;
                 sess.updatedAt = Date.now();
-                saveSessions();
+                saveSession(sess);
               }
             } else {
               saveSshInfo(true);
@@ -845,6 +1162,7 @@
       applyTerminalSettings(r);
       tab.label = session.name;
     }
+    tab.sessionId = sessionId;
 
     // Switch mode and populate toolbar
     tab.mode = session.type;
@@ -924,7 +1242,7 @@
     delBtn.addEventListener('click', function(e) {
       e.stopPropagation();
       sessions = sessions.filter(function(x) { return x.id !== s.id; });
-      saveSessions();
+      storeRequest('DELETE', '/api/sessions/' + encodeURIComponent(s.id));
       if (activeSessionId === s.id) activeSessionId = null;
       cleanupFolders();
       renderSessionList();
@@ -1128,6 +1446,9 @@
       document.getElementById('setting-ssh-keyfile').value = session.keyFile || '';
     }
 
+    loadAutoLoginSettings(session);
+    loadTransferSettings(session);
+
     populateFolderSelect(document.getElementById('setting-folder'), session.folder || '');
     document.getElementById('setting-new-folder-row').classList.add('hidden');
     document.getElementById('setting-new-folder').value = '';
@@ -1140,6 +1461,76 @@
     settingsModal.classList.remove('hidden');
   }
 
+  // Auto-login: the profile is part of the session; the password goes to
+  // the system keychain through its own endpoint and is never read back.
+  function loadAutoLoginSettings(session) {
+    var a = session.autoLogin || {};
+    document.getElementById('setting-autologin-enabled').checked = !!session.autoLogin && a.enabled !== false;
+    document.getElementById('setting-autologin-username').value = a.username || '';
+    document.getElementById('setting-autologin-login-prompt').value = a.loginPrompt || '';
+    document.getElementById('setting-autologin-password-prompt').value = a.passwordPrompt || '';
+    document.getElementById('setting-autologin-shell-prompt').value = a.shellPrompt || '';
+    document.getElementById('setting-autologin-timeout').value = a.timeoutSecs || 20;
+    var passwordInput = document.getElementById('setting-autologin-password');
+    passwordInput.value = '';
+    passwordInput.placeholder = '';
+    document.getElementById('setting-autologin-clear-password').checked = false;
+    fetch(API_BASE + '/api/sessions/' + encodeURIComponent(session.id) + '/auto-login')
+      .then(function(res) { return res.json(); })
+      .then(function(data) {
+        passwordInput.placeholder = data.has_password ? 'Saved in keychain' : 'Not saved';
+      })
+      .catch(function(err) { console.error('Auto-login status error:', err); });
+  }
+
+  function saveAutoLoginSettings(session) {
+    var enabled = document.getElementById('setting-autologin-enabled').checked;
+    var username = document.getElementById('setting-autologin-username').value.trim();
+    if (!enabled && !username) {
+      session.autoLogin = null;
+    } else {
+      var a = { enabled: enabled, username: username };
+      var prompts = {
+        loginPrompt: 'setting-autologin-login-prompt',
+        passwordPrompt: 'setting-autologin-password-prompt',
+        shellPrompt: 'setting-autologin-shell-prompt'
+      };
+      Object.keys(prompts).forEach(function(key) {
+        var value = document.getElementById(prompts[key]).value.trim();
+        if (value) a[key] = value;
+      });
+      a.timeoutSecs = parseInt(document.getElementById('setting-autologin-timeout').value) || 20;
+      session.autoLogin = a;
+    }
+
+    var password = document.getElementById('setting-autologin-password').value;
+    var clear = document.getElementById('setting-autologin-clear-password').checked;
+    if (password || clear) {
+      storeRequest('PUT', '/api/sessions/' + encodeURIComponent(session.id) + '/auto-login/password',
+        { password: clear ? null : password })
+        .then(function(result) {
+          if (!result) alert('Could not update the auto-login password in the keychain');
+        });
+    }
+  }
+
+  function loadTransferSettings(session) {
+    var z = session.zmodem || {};
+    document.getElementById('setting-zmodem-directory').value = z.directory || '';
+    document.getElementById('setting-zmodem-collision').value = z.onCollision || 'rename';
+    document.getElementById('setting-zmodem-resume').checked = !!z.resume;
+  }
+
+  function saveTransferSettings(session) {
+    var z = {
+      onCollision: document.getElementById('setting-zmodem-collision').value,
+      resume: document.getElementById('setting-zmodem-resume').checked
+    };
+    var directory = document.getElementById('setting-zmodem-directory').value.trim();
+    if (directory) z.directory = directory;
+    session.zmodem = (directory || z.onCollision !== 'rename' || z.resume) ? z : null;
+  }
+
   function saveSettingsModal() {
     if (!settingsContext) return;
 
@@ -1196,8 +1587,11 @@
         session.keyFile = document.getElementById('setting-ssh-keyfile').value.trim() || null;
       }
 
+      saveAutoLoginSettings(session);
+      saveTransferSettings(session);
+
       session.updatedAt = Date.now();
-      saveSessions();
+      saveSession(session);
       cleanupFolders();
       saveDefaults();
       renderSessionList();
@@ -1324,7 +1718,7 @@
     };
 
     sessions.push(session);
-    saveSessions();
+    storeRequest('POST', '/api/sessions', session);
     renderSessionList();
     newSessionModal.classList.add('hidden');
   }
@@ -1582,6 +1976,468 @@
     }
   }
 
+  // -----------------------------------------------------------------------
+  // Input group: typing in a member tab goes to every member
+  // -----------------------------------------------------------------------
+
+  function updateGroupUI() {
+    var tab = getActiveTab();
+    var g = tab ? tab.inputGroup : { member: false, opt_out: false };
+    groupBtn.classList.toggle('active', g.member && !g.opt_out);
+    groupBtn.classList.toggle('paused', g.member && g.opt_out);
+    if (!g.member) groupBtn.title = 'Add this tab to the input group';
+    else if (!g.opt_out) groupBtn.title = 'Input goes to all group tabs. Click to pause this tab';
+    else groupBtn.title = 'Paused: this tab ignores group input. Click to leave the group';
+  }
+
+  // Cycles: not in group -> member -> paused (opted out) -> not in group
+  groupBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab || !tab.connected) return;
+    var g = tab.inputGroup;
+    var req = { tab_id: tab.id };
+    if (!g.member) req.member = true;
+    else if (!g.opt_out) req.opt_out = true;
+    else req.member = false;
+    fetch(API_BASE + '/api/input-group', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(req)
+    })
+      .then(function(res) { return res.json(); })
+      .then(function(data) {
+        if (!data.ok) { alert(data.message); return; }
+        tab.inputGroup = data.input_group;
+        renderTabBar();
+        if (tab.id === activeTabId) updateGroupUI();
+      })
+      .catch(function(err) { console.error('Input group error:', err); });
+  });
+
+  function updateUploadUI() {
+    var tab = getActiveTab();
+    var running = !!(tab && tab.uploadRunning);
+    uploadBtn.classList.toggle('active', running);
+    uploadBtn.title = running ? 'Sending a file. Click to cancel' : 'Send a file to this tab';
+  }
+
+  uploadBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab || !tab.connected) return;
+    uploadModal.classList.remove('hidden');
+    uploadPathInput.focus();
+  });
+
+  uploadModalCloseBtn.addEventListener('click', function() {
+    uploadModal.classList.add('hidden');
+  });
+
+  uploadSendBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab) return;
+    var path = uploadPathInput.value.trim();
+    if (!path) return;
+    var mode = document.getElementById('upload-mode').value;
+    var prompt = document.getElementById('upload-prompt').value;
+    var reject = document.getElementById('upload-reject').value;
+    var waits = (mode === 'ascii' || mode === 'hex') && prompt;
+    // Hex like the addresses in the file, with or without 0x
+    var address = function(id) {
+      var text = document.getElementById(id).value.trim();
+      if (!text) return null;
+      var digits = text.replace(/^0x/i, '');
+      if (!/^[0-9a-f]+$/i.test(digits)) return NaN;
+      var value = parseInt(digits, 16);
+      return value <= 0xFFFFFFFF ? value : NaN;
+    };
+    var minAddress = mode === 'hex' ? address('upload-min-address') : null;
+    var maxAddress = mode === 'hex' ? address('upload-max-address') : null;
+    if (Number.isNaN(minAddress) || Number.isNaN(maxAddress)) {
+      alert('Addresses must be hex numbers up to FFFFFFFF, e.g. 0x08000000');
+      return;
+    }
+    fetch(API_BASE + '/api/upload/start', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({
+        tab_id: tab.id,
+        path: path,
+        mode: mode,
+        line_ending: document.getElementById('upload-line-ending').value,
+        char_delay_ms: parseInt(document.getElementById('upload-char-delay').value, 10) || 0,
+        line_delay_ms: parseInt(document.getElementById('upload-line-delay').value, 10) || 0,
+        prompt: waits ? prompt : null,
+        reject: waits && reject ? reject : null,
+        min_address: minAddress,
+        max_address: maxAddress
+      })
+    })
+      .then(function(res) {
+        // A request the server can't parse is answered in plain text
+        return res.text().then(function(text) {
+          try {
+            return JSON.parse(text);
+          } catch (e) {
+            return { ok: false, message: text || 'HTTP ' + res.status };
+          }
+        });
+      })
+      .then(function(data) {
+        if (!data.ok) { alert(data.message); return; }
+        uploadModal.classList.add('hidden');
+        tab.term.focus();
+      })
+      .catch(function(err) { console.error('Upload start error:', err); });
+  });
+
+  uploadCancelBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab) return;
+    fetch(API_BASE + '/api/upload/cancel', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ tab_id: tab.id })
+    })
+      .then(function(res) { return res.json(); })
+      .then(function() { uploadModal.classList.add('hidden'); })
+      .catch(function(err) { console.error('Upload cancel error:', err); });
+  });
+
+  xmodemBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab || !tab.connected) return;
+    xmodemModal.classList.remove('hidden');
+  });
+
+  xmodemModalCloseBtn.addEventListener('click', function() {
+    xmodemModal.classList.add('hidden');
+  });
+
+  flashBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab || !tab.connected || tab.mode === 'ssh') return;
+    flashModal.classList.remove('hidden');
+  });
+
+  document.getElementById('flash-modal-close-btn').addEventListener('click', function() {
+    flashModal.classList.add('hidden');
+  });
+
+  document.getElementById('flash-start-btn').addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab) return;
+    var images = [];
+    var lines = document.getElementById('flash-images').value.split('\n');
+    for (var i = 0; i < lines.length; i++) {
+      var line = lines[i].trim();
+      if (!line) continue;
+      var space = line.search(/\s/);
+      var address = space > 0 ? Number(line.slice(0, space)) : NaN;
+      if (Number.isNaN(address)) {
+        alert('Line ' + (i + 1) + ': start with the address, e.g. 0x10000 ~/build/app.bin');
+        return;
+      }
+      images.push({ address: address, path: line.slice(space).trim() });
+    }
+    if (!images.length) return;
+    // Empty keeps the target's usual wiring; "none" skips the lines
+    var sequence = function(id) {
+      var text = document.getElementById(id).value.trim();
+      if (!text) return null;
+      return text.toLowerCase() === 'none' ? '' : text;
+    };
+    fetch(API_BASE + '/api/flash/start', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({
+        tab_id: tab.id,
+        images: images,
+        target: document.getElementById('flash-target').value,
+        baud: parseInt(document.getElementById('flash-baud').value, 10) || 115200,
+        erase: document.getElementById('flash-erase').checked,
+        verify: document.getElementById('flash-verify').checked,
+        enter_sequence: sequence('flash-enter'),
+        exit_sequence: sequence('flash-exit')
+      })
+    })
+      .then(function(res) { return res.json(); })
+      .then(function(data) {
+        if (!data.ok) { alert(data.message); return; }
+        flashModal.classList.add('hidden');
+        tab.term.focus();
+      })
+      .catch(function(err) { console.error('Flash start error:', err); });
+  });
+
+  function startXmodem(direction, body) {
+    var tab = getActiveTab();
+    if (!tab) return;
+    body.tab_id = tab.id;
+    body.protocol = document.getElementById('xmodem-protocol').value;
+    var base = body.protocol === 'kermit' ? '/api/kermit/' : '/api/xmodem/';
+    fetch(API_BASE + base + direction, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(body)
+    })
+      .then(function(res) { return res.json(); })
+      .then(function(data) {
+        if (!data.ok) { alert(data.message); return; }
+        xmodemModal.classList.add('hidden');
+        tab.term.writeln('\r\n[Transfer] ' + data.message);
+        tab.term.focus();
+      })
+      .catch(function(err) { console.error('XMODEM error:', err); });
+  }
+
+  document.getElementById('xmodem-send-btn').addEventListener('click', function() {
+    var paths = document.getElementById('xmodem-paths').value.split('\n')
+      .map(function(p) { return p.trim(); })
+      .filter(function(p) { return p; });
+    if (!paths.length) return;
+    startXmodem('send', { paths: paths });
+  });
+
+  document.getElementById('xmodem-receive-btn').addEventListener('click', function() {
+    var filename = document.getElementById('xmodem-filename').value.trim();
+    startXmodem('receive', { filename: filename || null });
+  });
+
+  document.getElementById('zmodem-send-btn').addEventListener('click', function() {
+    var tab = zmodemSendTab;
+    var paths = zmodemSendPaths.value.split('\n')
+      .map(function(p) { return p.trim(); })
+      .filter(function(p) { return p; });
+    if (!tab || !paths.length) return;
+    var endpoint = zmodemSendProtocol === 'trzsz' ? '/api/trzsz/upload' : '/api/zmodem/send';
+    fetch(API_BASE + endpoint, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ tab_id: tab.id, paths: paths })
+    })
+      .then(function(res) { return res.json(); })
+      .then(function(data) {
+        if (!data.ok) { alert(data.message); return; }
+        zmodemSendModal.classList.add('hidden');
+        zmodemSendTab = null;
+        tab.term.focus();
+      })
+      .catch(function(err) { console.error(zmodemSendProtocol + ' send error:', err); });
+  });
+
+  function cancelTransfer(tab) {
+    fetch(API_BASE + '/api/zmodem/cancel', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ tab_id: tab.id })
+    })
+      .then(function(res) { return res.json(); })
+      .then(function(data) {
+        // Already over; don't keep swallowing input
+        if (!data.ok) tab.transferRunning = false;
+      })
+      .catch(function(err) { console.error('Transfer cancel error:', err); });
+  }
+
+  // Declining the upload aborts the waiting rz with the ZMODEM cancel
+  // sequence; trz is answered by the server
+  function cancelZmodemSend() {
+    var tab = zmodemSendTab;
+    zmodemSendModal.classList.add('hidden');
+    zmodemSendTab = null;
+    if (tab && zmodemSendProtocol === 'trzsz') {
+      fetch(API_BASE + '/api/trzsz/upload', {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ tab_id: tab.id, paths: [] })
+      }).catch(function(err) { console.error('trzsz decline error:', err); });
+      tab.term.focus();
+    } else if (tab && tab.ws && tab.ws.readyState === WebSocket.OPEN) {
+      tab.ws.send(new TextEncoder().encode('\x18\x18\x18\x18\x18\x18\x18\x18\b\b\b\b\b\b\b\b\b\b'));
+      tab.term.focus();
+    }
+  }
+
+  function answerZmodemExists(action) {
+    var tab = zmodemExistsTab;
+    zmodemExistsModal.classList.add('hidden');
+    zmodemExistsTab = null;
+    if (!tab) return;
+    fetch(API_BASE + '/api/zmodem/resolve', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ tab_id: tab.id, action: action })
+    })
+      .then(function(res) { return res.json(); })
+      .then(function(data) {
+        if (!data.ok) console.error('ZMODEM resolve error:', data.message);
+        tab.term.focus();
+      })
+      .catch(function(err) { console.error('ZMODEM resolve error:', err); });
+  }
+
+  zmodemExistsModal.querySelectorAll('[data-action]').forEach(function(btn) {
+    btn.addEventListener('click', function() { answerZmodemExists(btn.getAttribute('data-action')); });
+  });
+  document.getElementById('zmodem-exists-close-btn').addEventListener('click', function() {
+    answerZmodemExists('skip');
+  });
+
+  document.getElementById('zmodem-send-cancel-btn').addEventListener('click', cancelZmodemSend);
+  document.getElementById('zmodem-send-modal-close-btn').addEventListener('click', cancelZmodemSend);
+
+  function updateScriptUI() {
+    var tab = getActiveTab();
+    var running = !!(tab && tab.scriptRunning);
+    scriptBtn.classList.toggle('active', running);
+    scriptBtn.title = running ? 'A script is running. Click to view or abort' : 'Run a script in this tab';
+  }
+
+  scriptBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab || !tab.connected) return;
+    scriptSource.value = tab.scriptSource || scriptSource.value;
+    scriptModal.classList.remove('hidden');
+    scriptSource.focus();
+  });
+
+  scriptModalCloseBtn.addEventListener('click', function() {
+    scriptModal.classList.add('hidden');
+  });
+
+  scriptRunBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab) return;
+    tab.scriptSource = scriptSource.value;
+    fetch(API_BASE + '/api/script/start', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ tab_id: tab.id, source: scriptSource.value })
+    })
+      .then(function(res) { return res.json(); })
+      .then(function(data) {
+        if (!data.ok) { alert(data.message); return; }
+        scriptModal.classList.add('hidden');
+        tab.term.focus();
+      })
+      .catch(function(err) { console.error('Script start error:', err); });
+  });
+
+  scriptAbortBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab) return;
+    fetch(API_BASE + '/api/script/abort', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ tab_id: tab.id })
+    })
+      .then(function(res) { return res.json(); })
+      .then(function() { scriptModal.classList.add('hidden'); })
+      .catch(function(err) { console.error('Script abort error:', err); });
+  });
+
+  // Rhai hook scripts stay attached to the tab and restart on reconnect
+  scriptAttachBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab) return;
+    tab.scriptSource = scriptSource.value;
+    fetch(API_BASE + '/api/hooks/attach', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ tab_id: tab.id, source: scriptSource.value })
+    })
+      .then(function(res) { return res.json(); })
+      .then(function(data) {
+        if (!data.ok) { alert(data.message); return; }
+        scriptModal.classList.add('hidden');
+        tab.term.writeln('\r\n[Script] Hooks attached');
+        tab.term.focus();
+      })
+      .catch(function(err) { console.error('Hook attach error:', err); });
+  });
+
+  scriptDetachBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab) return;
+    fetch(API_BASE + '/api/hooks/detach', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ tab_id: tab.id })
+    })
+      .then(function(res) { return res.json(); })
+      .then(function(data) {
+        scriptModal.classList.add('hidden');
+        tab.term.writeln('\r\n[Script] ' + data.message);
+      })
+      .catch(function(err) { console.error('Hook detach error:', err); });
+  });
+
+  // Trigger rules are edited as JSON; see triggers.rs for the format
+  function showTriggerCounters(counters) {
+    var names = Object.keys(counters || {});
+    triggersCounters.textContent = names.length
+      ? names.map(function(n) { return n + ': ' + counters[n]; }).join('\n')
+      : 'No counters yet';
+  }
+
+  triggersBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab) return;
+    if (window.Notification && Notification.permission === 'default') {
+      Notification.requestPermission();
+    }
+    fetch(API_BASE + '/api/triggers?tab_id=' + encodeURIComponent(tab.id))
+      .then(function(res) { return res.json(); })
+      .then(function(data) {
+        triggersSource.value = JSON.stringify(data.rules, null, 2);
+        showTriggerCounters(data.counters);
+        triggersModal.classList.remove('hidden');
+      })
+      .catch(function(err) { console.error('Triggers error:', err); });
+  });
+
+  triggersModalCloseBtn.addEventListener('click', function() {
+    triggersModal.classList.add('hidden');
+  });
+
+  triggersSaveBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab) return;
+    var rules;
+    try {
+      rules = JSON.parse(triggersSource.value || '[]');
+    } catch (e) {
+      alert('Invalid JSON: ' + e.message);
+      return;
+    }
+    fetch(API_BASE + '/api/triggers', {
+      method: 'PUT',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ tab_id: tab.id, rules: rules })
+    })
+      .then(function(res) { return res.json(); })
+      .then(function(data) {
+        if (!data.ok) { alert(data.message); return; }
+        // The backend saved the rules to the session; keep our copy in step
+        var session = tab.sessionId && findSession(tab.sessionId);
+        if (session) session.triggers = rules;
+        triggersModal.classList.add('hidden');
+      })
+      .catch(function(err) { console.error('Triggers save error:', err); });
+  });
+
+  triggersResetBtn.addEventListener('click', function() {
+    var tab = getActiveTab();
+    if (!tab) return;
+    fetch(API_BASE + '/api/triggers/counters/reset', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ tab_id: tab.id })
+    })
+      .then(function() { showTriggerCounters({}); })
+      .catch(function(err) { console.error('Counter reset error:', err); });
+  });
+
   function refreshLogStatus() {
     var tab = getActiveTab();
     if (!tab) return;
@@ -1668,15 +2524,143 @@
     }
   });
 
+  // -----------------------------------------------------------------------
+  // Session import (PuTTY, minicom, SecureCRT)
+  // -----------------------------------------------------------------------
+
+  function formatImportReport(data) {
+    var lines = [];
+    var verb = data.dry_run ? 'Found' : 'Imported';
+    lines.push(verb + ' ' + data.sessions.length + ' session(s)');
+    data.sessions.forEach(function(s) {
+      var target = s.type === 'ssh' ? (s.username ? s.username + '@' : '') + s.host : s.port + ' @ ' + s.baudRate;
+      lines.push('  ' + (s.folder ? s.folder + '/' : '') + s.name + '  (' + target + ')');
+    });
+    if (data.duplicates.length) {
+      lines.push('', 'Already present: ' + data.duplicates.join(', '));
+    }
+    if (data.skipped.length) {
+      lines.push('', 'Skipped:');
+      data.skipped.forEach(function(s) { lines.push('  ' + s.name + ': ' + s.reason); });
+    }
+    if (data.unsupported.length) {
+      lines.push('', 'Unsupported options:');
+      data.unsupported.forEach(function(u) { lines.push('  ' + u.session + ' / ' + u.option + ': ' + u.note); });
+    }
+    return lines.join('\n');
+  }
+
+  async function runImport(dryRun) {
+    var path = importPathInput.value.trim();
+    var folder = importFolderInput.value.trim();
+    try {
+      var res = await fetch(API_BASE + '/api/sessions/import', {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({
+          source: importSourceSelect.value,
+          path: path || null,
+          folder: folder || null,
+          dry_run: dryRun
+        })
+      });
+      var data = await res.json();
+      importReport.classList.remove('hidden');
+      if (!data.ok) {
+        importReport.textContent = data.message;
+        return;
+      }
+      importReport.textContent = formatImportReport(data);
+      if (!dryRun && data.sessions.length) {
+        await loadStore();
+        renderSessionList();
+      }
+    } catch (e) {
+      importReport.classList.remove('hidden');
+      importReport.textContent = 'Import failed: ' + e.message;
+    }
+  }
+
+  sessionImportBtn.addEventListener('click', function() {
+    importReport.classList.add('hidden');
+    importReport.textContent = '';
+    importModal.classList.remove('hidden');
+  });
+
+  importModalCloseBtn.addEventListener('click', function() {
+    importModal.classList.add('hidden');
+  });
+
+  importPreviewBtn.addEventListener('click', function() { runImport(true); });
+  importRunBtn.addEventListener('click', function() { runImport(false); });
+
+  // -----------------------------------------------------------------------
+  // Restore tabs from the last run
+  // -----------------------------------------------------------------------
+
+  async function restoreTabs() {
+    var saved;
+    try {
+      var res = await fetch(API_BASE + '/api/restore');
+      saved = (await res.json()).tabs || [];
+    } catch (e) {
+      return false;
+    }
+    if (saved.length === 0) return false;
+
+    var names = saved.map(function(t) {
+      return '  ' + t.label + (t.available ? '' : ' (device not found)');
+    }).join('\n');
+    if (!confirm('Reopen ' + saved.length + ' tab(s) from the last session?\n\n' + names)) {
+      fetch(API_BASE + '/api/restore/discard', { method: 'POST' }).catch(function() {});
+      return false;
+    }
+
+    var results;
+    try {
+      var res2 = await fetch(API_BASE + '/api/restore', {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({}),
+      });
+      results = (await res2.json()).results || [];
+    } catch (e) {
+      return false;
+    }
+
+    var opened = 0;
+    results.forEach(function(r) {
+      var tab = createTab(r.label, null, r.tab_id);
+      if (!tab) return;
+      opened++;
+      tab.mode = r.connection_type;
+      if (r.ok) {
+        tab.loggingActive = !!r.log_path;
+        tab.loggingPath = r.log_path;
+        openWebSocket(tab, r.label);
+      } else {
+        tab.term.writeln('\r\n[Error] Could not reopen ' + r.label + ': ' + r.message);
+      }
+    });
+    updateLogUI();
+    return opened > 0;
+  }
+
   // -----------------------------------------------------------------------
   // Initialize
   // -----------------------------------------------------------------------
 
-  // Create first tab
-  createTab('New Tab');
-  refreshPorts();
-  applySshInfo();
-  renderSessionList();
-  if (defaults.sidebarOpen) sessionSidebar.classList.add('open');
-  refreshLogStatus();
+  loadStore().then(function() {
+    // Create first tab, or reopen the tabs from the last run
+    createTab('New Tab');
+    restoreTabs().then(function(restored) {
+      var first = tabs[0];
+      if (restored && first && !first.connected && tabs.length > 1) closeTab(first.id);
+    });
+    refreshPorts();
+    applySshInfo();
+    renderSessionList();
+    if (defaults.sidebarOpen) sessionSidebar.classList.add('open');
+    refreshLogStatus();
+  });
 })();
@@ -0,0 +1,444 @@
+/// Longest CSI parameter string kept; anything beyond is ignored.
+const MAX_CSI_PARAMS: usize = 64;
+
+/// Parser state for `Parser`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Ground,
+    Escape,
+    EscIntermediate,
+    Csi,
+    /// OSC / DCS / SOS / PM / APC string, terminated by BEL or ST
+    Str,
+    StrEscape,
+}
+
+/// Something the parser found in the byte stream.
+enum Action<'a> {
+    Print(u8),
+    Control(u8),
+    /// Control sequence: parameter bytes and final byte
+    Csi(&'a [u8], u8),
+}
+
+/// Minimal VT escape sequence parser. State is kept across calls so
+/// sequences split between reads are still recognized.
+struct Parser {
+    state: State,
+    params: Vec<u8>,
+}
+
+impl Parser {
+    fn new() -> Self {
+        Parser {
+            state: State::Ground,
+            params: Vec::new(),
+        }
+    }
+
+    fn advance(&mut self, b: u8, f: &mut dyn FnMut(Action)) {
+        self.state = match self.state {
+            State::Ground => match b {
+                0x1b => State::Escape,
+                0x00..=0x1f | 0x7f => {
+                    f(Action::Control(b));
+                    State::Ground
+                }
+                _ => {
+                    f(Action::Print(b));
+                    State::Ground
+                }
+            },
+            State::Escape => match b {
+                b'[' => {
+                    self.params.clear();
+                    State::Csi
+                }
+                b']' | b'P' | b'X' | b'^' | b'_' => State::Str,
+                0x20..=0x2f => State::EscIntermediate,
+                0x1b => State::Escape,
+                _ => State::Ground,
+            },
+            State::EscIntermediate => match b {
+                0x20..=0x2f => State::EscIntermediate,
+                _ => State::Ground,
+            },
+            State::Csi => match b {
+                0x40..=0x7e => {
+                    f(Action::Csi(&self.params, b));
+                    State::Ground
+                }
+                0x1b => State::Escape,
+                _ => {
+                    if self.params.len() < MAX_CSI_PARAMS {
+                        self.params.push(b);
+                    }
+                    State::Csi
+                }
+            },
+            State::Str => match b {
+                0x07 => State::Ground,
+                0x1b => State::StrEscape,
+                _ => State::Str,
+            },
+            State::StrEscape => match b {
+                b'\\' => State::Ground,
+                0x1b => State::StrEscape,
+                _ => State::Str,
+            },
+        };
+    }
+}
+
+/// Streaming remover of terminal escape sequences.
+pub struct AnsiStripper {
+    parser: Parser,
+}
+
+impl Default for AnsiStripper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnsiStripper {
+    pub fn new() -> Self {
+        AnsiStripper {
+            parser: Parser::new(),
+        }
+    }
+
+    /// Append the printable content of `input` to `out`. Newlines and tabs
+    /// are kept; other C0 control characters (including `\r`) are dropped.
+    pub fn feed(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        let mut emit = |action: Action| match action {
+            Action::Print(b) | Action::Control(b @ (b'\n' | b'\t')) => out.push(b),
+            _ => {}
+        };
+        for &b in input {
+            self.parser.advance(b, &mut emit);
+        }
+    }
+}
+
+/// Longest line kept by `LineSplitter`; the rest of an overlong line is
+/// dropped until the next newline.
+const MAX_LINE: usize = 4096;
+
+/// Splits terminal output into complete lines of plain text.
+pub struct LineSplitter {
+    stripper: AnsiStripper,
+    plain: Vec<u8>,
+    line: Vec<u8>,
+}
+
+impl Default for LineSplitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineSplitter {
+    pub fn new() -> Self {
+        LineSplitter {
+            stripper: AnsiStripper::new(),
+            plain: Vec::new(),
+            line: Vec::new(),
+        }
+    }
+
+    /// Feed received bytes and return the lines they complete, without
+    /// escape sequences or line endings.
+    pub fn feed(&mut self, input: &[u8]) -> Vec<String> {
+        self.plain.clear();
+        self.stripper.feed(input, &mut self.plain);
+        let mut lines = Vec::new();
+        for &b in &self.plain {
+            if b == b'\n' {
+                lines.push(String::from_utf8_lossy(&self.line).into_owned());
+                self.line.clear();
+            } else if self.line.len() < MAX_LINE {
+                self.line.push(b);
+            }
+        }
+        lines
+    }
+}
+
+// ---------------------------------------------------------------------------
+// HTML rendering
+// ---------------------------------------------------------------------------
+
+/// Default colors, matching the terminal theme.
+pub const DEFAULT_FG: (u8, u8, u8) = (0xe2, 0xe4, 0xea);
+pub const DEFAULT_BG: (u8, u8, u8) = (0x0c, 0x0e, 0x14);
+
+const PALETTE_16: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x00, 0x00),
+    (0x00, 0xcd, 0x00),
+    (0xcd, 0xcd, 0x00),
+    (0x00, 0x00, 0xee),
+    (0xcd, 0x00, 0xcd),
+    (0x00, 0xcd, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x5c, 0x5c, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+/// xterm 256-color palette lookup.
+fn palette_256(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => PALETTE_16[n as usize],
+        16..=231 => {
+            let n = n - 16;
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            (level(n / 36), level((n / 6) % 6), level(n % 6))
+        }
+        _ => {
+            let v = 8 + (n - 232) * 10;
+            (v, v, v)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+struct Style {
+    fg: Option<(u8, u8, u8)>,
+    bg: Option<(u8, u8, u8)>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    inverse: bool,
+    strike: bool,
+}
+
+impl Style {
+    /// Apply an SGR parameter string (e.g. `1;38;5;208`).
+    fn apply_sgr(&mut self, params: &[u8]) {
+        let values: Vec<u16> = params
+            .split(|&b| b == b';' || b == b':')
+            .map(|p| {
+                std::str::from_utf8(p)
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let mut i = 0;
+        while i < values.len() {
+            match values[i] {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.inverse = true,
+                9 => self.strike = true,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.inverse = false,
+                29 => self.strike = false,
+                n @ 30..=37 => self.fg = Some(PALETTE_16[(n - 30) as usize]),
+                39 => self.fg = None,
+                n @ 40..=47 => self.bg = Some(PALETTE_16[(n - 40) as usize]),
+                49 => self.bg = None,
+                n @ 90..=97 => self.fg = Some(PALETTE_16[(n - 90 + 8) as usize]),
+                n @ 100..=107 => self.bg = Some(PALETTE_16[(n - 100 + 8) as usize]),
+                n @ (38 | 48) => {
+                    let color = match values.get(i + 1) {
+                        Some(5) => {
+                            let c = values.get(i + 2).map(|&v| palette_256(v as u8));
+                            i += 2;
+                            c
+                        }
+                        Some(2) => {
+                            let c = match (values.get(i + 2), values.get(i + 3), values.get(i + 4)) {
+                                (Some(&r), Some(&g), Some(&b)) => Some((r as u8, g as u8, b as u8)),
+                                _ => None,
+                            };
+                            i += 4;
+                            c
+                        }
+                        _ => None,
+                    };
+                    if n == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    fn css(&self) -> String {
+        let (mut fg, mut bg) = (self.fg, self.bg);
+        if self.inverse {
+            (fg, bg) = (Some(bg.unwrap_or(DEFAULT_BG)), Some(fg.unwrap_or(DEFAULT_FG)));
+        }
+        let mut css = String::new();
+        if let Some((r, g, b)) = fg {
+            css.push_str(&format!("color:#{:02x}{:02x}{:02x};", r, g, b));
+        }
+        if let Some((r, g, b)) = bg {
+            css.push_str(&format!("background:#{:02x}{:02x}{:02x};", r, g, b));
+        }
+        if self.bold {
+            css.push_str("font-weight:bold;");
+        }
+        if self.dim {
+            css.push_str("opacity:0.7;");
+        }
+        if self.italic {
+            css.push_str("font-style:italic;");
+        }
+        match (self.underline, self.strike) {
+            (true, true) => css.push_str("text-decoration:underline line-through;"),
+            (true, false) => css.push_str("text-decoration:underline;"),
+            (false, true) => css.push_str("text-decoration:line-through;"),
+            (false, false) => {}
+        }
+        css
+    }
+}
+
+/// Streaming converter from terminal output to HTML, turning SGR color and
+/// attribute sequences into styled `<span>`s. Other sequences are dropped.
+pub struct HtmlRenderer {
+    parser: Parser,
+    style: Style,
+    /// Style of the currently open `<span>`, if any
+    open: Option<Style>,
+}
+
+impl Default for HtmlRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HtmlRenderer {
+    pub fn new() -> Self {
+        HtmlRenderer {
+            parser: Parser::new(),
+            style: Style::default(),
+            open: None,
+        }
+    }
+
+    pub fn feed(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        let HtmlRenderer {
+            parser,
+            style,
+            open,
+        } = self;
+        let mut emit = |action: Action| match action {
+            Action::Print(b) | Action::Control(b @ (b'\n' | b'\t')) => {
+                if b != b'\n' {
+                    if open.is_some() && *open != Some(*style) {
+                        out.extend_from_slice(b"</span>");
+                        *open = None;
+                    }
+                    if open.is_none() && *style != Style::default() {
+                        out.extend_from_slice(format!("<span style=\"{}\">", style.css()).as_bytes());
+                        *open = Some(*style);
+                    }
+                }
+                match b {
+                    b'&' => out.extend_from_slice(b"&amp;"),
+                    b'<' => out.extend_from_slice(b"&lt;"),
+                    b'>' => out.extend_from_slice(b"&gt;"),
+                    b'"' => out.extend_from_slice(b"&quot;"),
+                    _ => out.push(b),
+                }
+            }
+            Action::Csi(params, b'm') => style.apply_sgr(params),
+            _ => {}
+        };
+        for &b in input {
+            parser.advance(b, &mut emit);
+        }
+    }
+
+    /// Close any open `<span>`.
+    pub fn finish(&mut self, out: &mut Vec<u8>) {
+        if self.open.take().is_some() {
+            out.extend_from_slice(b"</span>");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        AnsiStripper::new().feed(input, &mut out);
+        out
+    }
+
+    fn html(input: &[u8]) -> String {
+        let mut out = Vec::new();
+        let mut renderer = HtmlRenderer::new();
+        renderer.feed(input, &mut out);
+        renderer.finish(&mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_strip_sequences() {
+        assert_eq!(strip(b"\x1b[1;31mred\x1b[0m plain\r\n"), b"red plain\n");
+        assert_eq!(strip(b"\x1b]0;title\x07text"), b"text");
+        assert_eq!(strip(b"\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\"), b"link");
+        assert_eq!(strip(b"\x1b(Bab\x1b=c"), b"abc");
+        assert_eq!(strip("héllo\t\x08!".as_bytes()), "héllo\t!".as_bytes());
+    }
+
+    #[test]
+    fn test_strip_across_feeds() {
+        let mut stripper = AnsiStripper::new();
+        let mut out = Vec::new();
+        stripper.feed(b"ok\x1b[3", &mut out);
+        stripper.feed(b"2mgreen\x1b", &mut out);
+        stripper.feed(b"[0m", &mut out);
+        assert_eq!(out, b"okgreen");
+    }
+
+    #[test]
+    fn test_line_splitter() {
+        let mut lines = LineSplitter::new();
+        assert!(lines.feed(b"\x1b[32mOK\x1b[0m par").is_empty());
+        assert_eq!(lines.feed(b"tial\r\nnext\r\n\r\nrest"), ["OK partial", "next", ""]);
+        assert_eq!(lines.feed(b"\n"), ["rest"]);
+    }
+
+    #[test]
+    fn test_html_colors() {
+        assert_eq!(html(b"a<b>\r\n"), "a&lt;b&gt;\n");
+        assert_eq!(
+            html(b"\x1b[1;31mERR\x1b[0m ok"),
+            "<span style=\"color:#cd0000;font-weight:bold;\">ERR</span> ok"
+        );
+        assert_eq!(
+            html(b"\x1b[38;5;208mx\x1b[48;2;1;2;3my"),
+            "<span style=\"color:#ff8700;\">x</span>\
+             <span style=\"color:#ff8700;background:#010203;\">y</span>"
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::script::{quote, quote_regex, Script};
+
+/// How to log in to a console after connecting. The password is kept in
+/// the system keychain, not here.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoLogin {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    pub username: String,
+    #[serde(default = "default_login_prompt")]
+    pub login_prompt: String,
+    #[serde(default = "default_password_prompt")]
+    pub password_prompt: String,
+    /// Seen once logged in
+    #[serde(default = "default_shell_prompt")]
+    pub shell_prompt: String,
+    /// Seen when the credentials are rejected
+    #[serde(default = "default_failure_pattern")]
+    pub failure_pattern: String,
+    /// Seconds to wait for each prompt
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+    /// Send a newline first to wake up a console that's already waiting
+    #[serde(default = "default_true")]
+    pub wake: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_login_prompt() -> String {
+    r"(?i)(login|username)\s*:\s*$".to_string()
+}
+
+fn default_password_prompt() -> String {
+    r"(?i)password\s*:\s*$".to_string()
+}
+
+fn default_shell_prompt() -> String {
+    r"[$#%>]\s*$".to_string()
+}
+
+fn default_failure_pattern() -> String {
+    r"(?i)(login incorrect|access denied|authentication failed|permission denied)".to_string()
+}
+
+fn default_timeout() -> u64 {
+    20
+}
+
+impl AutoLogin {
+    pub fn validate(&self) -> Result<(), String> {
+        for (what, pattern) in [
+            ("login prompt", &self.login_prompt),
+            ("password prompt", &self.password_prompt),
+            ("shell prompt", &self.shell_prompt),
+            ("failure pattern", &self.failure_pattern),
+        ] {
+            Regex::new(pattern).map_err(|e| format!("Invalid {}: {}", what, e))?;
+        }
+        if self.timeout_secs == 0 {
+            return Err("Timeout must be at least 1 second".to_string());
+        }
+        Ok(())
+    }
+
+    /// The login sequence as a script. A console that is already at a
+    /// shell prompt is left alone.
+    pub fn script(&self, password: Option<&str>) -> Result<Script, String> {
+        self.validate()?;
+        let login = quote_regex(&self.login_prompt);
+        let pass = quote_regex(&self.password_prompt);
+        let shell = quote_regex(&self.shell_prompt);
+        let failed = quote_regex(&self.failure_pattern);
+
+        let mut lines = vec![format!("set timeout {}", self.timeout_secs)];
+        if self.wake {
+            lines.push("sendline \"\"".to_string());
+        }
+        lines.extend([
+            format!("expect {} {} {}", login, pass, shell),
+            "if timeout goto no_login".to_string(),
+            "if match 2 goto password".to_string(),
+            "if match 3 goto done".to_string(),
+            format!("sendline {}", quote(&self.username)),
+            format!("expect {} {} {}", pass, shell, failed),
+            "if timeout goto no_password".to_string(),
+            "if match 2 goto done".to_string(),
+            "if match 3 goto rejected".to_string(),
+            ":password".to_string(),
+        ]);
+        match password {
+            Some(password) => lines.push(format!("sendline {}", quote(password))),
+            None => lines.push("fail \"Password requested but none is saved\"".to_string()),
+        }
+        lines.extend([
+            format!("expect {} {} {}", shell, failed, login),
+            "if timeout goto no_shell".to_string(),
+            "if match 1 goto done".to_string(),
+            ":rejected".to_string(),
+            "fail \"Login rejected\"".to_string(),
+            ":no_login".to_string(),
+            "fail \"No login prompt\"".to_string(),
+            ":no_password".to_string(),
+            "fail \"No password prompt or shell after username\"".to_string(),
+            ":no_shell".to_string(),
+            "fail \"No shell prompt after password\"".to_string(),
+            ":done".to_string(),
+            "print \"Logged in\"".to_string(),
+        ]);
+        Script::parse(&lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::script::{run, ScriptHost, ScriptStatus};
+
+    #[derive(Default)]
+    struct Console {
+        sent: Mutex<String>,
+    }
+
+    #[async_trait::async_trait]
+    impl ScriptHost for Console {
+        async fn send(&self, data: &[u8]) -> Result<(), String> {
+            self.sent.lock().unwrap().push_str(&String::from_utf8_lossy(data));
+            Ok(())
+        }
+        async fn set_dtr(&self, _on: bool) -> Result<(), String> {
+            Ok(())
+        }
+        async fn set_rts(&self, _on: bool) -> Result<(), String> {
+            Ok(())
+        }
+        async fn send_break(&self, _duration: std::time::Duration) -> Result<(), String> {
+            Ok(())
+        }
+        async fn log_start(&self, path: &str) -> Result<String, String> {
+            Ok(path.to_string())
+        }
+        async fn log_stop(&self) -> Result<(), String> {
+            Ok(())
+        }
+        fn print(&self, _message: &str) {}
+    }
+
+    fn profile() -> AutoLogin {
+        serde_json::from_str(r#"{"username": "root", "timeoutSecs": 1}"#).unwrap()
+    }
+
+    async fn login(output: &[&[u8]], password: Option<&str>) -> (Result<(), String>, String) {
+        let script = profile().script(password).unwrap();
+        let (tx, rx) = broadcast::channel(16);
+        for data in output {
+            tx.send(data.to_vec()).unwrap();
+        }
+        let console = Console::default();
+        let status = Mutex::new(ScriptStatus::new("auto-login"));
+        let result = run(&script, &console, rx, |_| false, &status).await;
+        let sent = console.sent.into_inner().unwrap();
+        (result, sent)
+    }
+
+    #[tokio::test]
+    async fn test_login_sequences() {
+        let (result, sent) = login(
+            &[b"\r\nbuildroot login: ", b"root\r\nPassword: ", b"\r\n# "],
+            Some("p\"w"),
+        )
+        .await;
+        assert_eq!(result, Ok(()));
+        assert_eq!(sent, "\rroot\rp\"w\r");
+
+        let (result, sent) = login(&[b"\r\nroot@box:~# "], None).await;
+        assert_eq!(result, Ok(()));
+        assert_eq!(sent, "\r");
+
+        let (result, _) = login(
+            &[b"login: ", b"Password: ", b"\r\nLogin incorrect\r\nlogin: "],
+            Some("wrong"),
+        )
+        .await;
+        assert!(result.unwrap_err().ends_with("Login rejected"));
+
+        let (result, _) = login(&[b"Password: "], None).await;
+        assert!(result.unwrap_err().ends_with("Password requested but none is saved"));
+    }
+}
@@ -0,0 +1,230 @@
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Controller,
+    Observer,
+}
+
+/// A WebSocket client attached to a tab, as reported in status.
+#[derive(Serialize, Clone, Debug)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub name: String,
+    pub role: Role,
+    /// Unix time (seconds) the client attached
+    pub connected_at: u64,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ControlStatus {
+    pub controller: Option<u64>,
+    pub clients: Vec<ClientInfo>,
+}
+
+struct ClientEntry {
+    id: u64,
+    name: String,
+    /// Identifies the browser window the client belongs to; the same for its
+    /// connections to every tab
+    key: Option<String>,
+    /// Attached as a would-be controller rather than an explicit observer
+    wants_control: bool,
+    connected_at: u64,
+}
+
+struct Registry {
+    next_id: u64,
+    /// In attach order
+    clients: Vec<ClientEntry>,
+    controller: Option<u64>,
+}
+
+/// Tracks the WebSocket clients of one tab and which of them may send
+/// input. At most one client controls the tab; everyone else observes.
+pub struct TabClients {
+    inner: Mutex<Registry>,
+    status_tx: watch::Sender<ControlStatus>,
+}
+
+impl Default for TabClients {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TabClients {
+    pub fn new() -> Self {
+        TabClients {
+            inner: Mutex::new(Registry {
+                next_id: 1,
+                clients: Vec::new(),
+                controller: None,
+            }),
+            status_tx: watch::Sender::new(ControlStatus::default()),
+        }
+    }
+
+    /// Receive the client list every time it or the controller changes.
+    pub fn subscribe(&self) -> watch::Receiver<ControlStatus> {
+        self.status_tx.subscribe()
+    }
+
+    /// Register a client. A client that doesn't ask to observe gets control
+    /// if nobody holds it.
+    pub fn attach(&self, name: Option<String>, key: Option<String>, observer: bool) -> u64 {
+        let mut reg = self.inner.lock().unwrap();
+        let id = reg.next_id;
+        reg.next_id += 1;
+        let connected_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        reg.clients.push(ClientEntry {
+            id,
+            name: name.unwrap_or_else(|| format!("client-{}", id)),
+            key,
+            wants_control: !observer,
+            connected_at,
+        });
+        if !observer && reg.controller.is_none() {
+            reg.controller = Some(id);
+        }
+        self.publish(&reg);
+        id
+    }
+
+    /// Remove a client. If it held control, control passes to the longest
+    /// attached client that didn't ask to observe.
+    pub fn detach(&self, id: u64) {
+        let mut reg = self.inner.lock().unwrap();
+        reg.clients.retain(|c| c.id != id);
+        if reg.controller == Some(id) {
+            reg.controller = reg.clients.iter().find(|c| c.wants_control).map(|c| c.id);
+        }
+        self.publish(&reg);
+    }
+
+    /// Give control to `id`, demoting the current controller.
+    pub fn take_control(&self, id: u64) -> bool {
+        let mut reg = self.inner.lock().unwrap();
+        if !reg.clients.iter().any(|c| c.id == id) {
+            return false;
+        }
+        reg.controller = Some(id);
+        self.publish(&reg);
+        true
+    }
+
+    /// Give up control if `id` holds it. Nobody controls the tab afterwards
+    /// until a client takes control.
+    pub fn release_control(&self, id: u64) {
+        let mut reg = self.inner.lock().unwrap();
+        if reg.controller == Some(id) {
+            reg.controller = None;
+            self.publish(&reg);
+        }
+    }
+
+    pub fn is_controller(&self, id: u64) -> bool {
+        self.inner.lock().unwrap().controller == Some(id)
+    }
+
+    /// Whether the controller is a client attached with `key`.
+    pub fn is_controlled_by(&self, key: &str) -> bool {
+        let reg = self.inner.lock().unwrap();
+        reg.controller
+            .and_then(|id| reg.clients.iter().find(|c| c.id == id))
+            .is_some_and(|c| c.key.as_deref() == Some(key))
+    }
+
+    pub fn status(&self) -> ControlStatus {
+        Self::build_status(&self.inner.lock().unwrap())
+    }
+
+    fn build_status(reg: &Registry) -> ControlStatus {
+        ControlStatus {
+            controller: reg.controller,
+            clients: reg
+                .clients
+                .iter()
+                .map(|c| ClientInfo {
+                    id: c.id,
+                    name: c.name.clone(),
+                    role: if reg.controller == Some(c.id) {
+                        Role::Controller
+                    } else {
+                        Role::Observer
+                    },
+                    connected_at: c.connected_at,
+                })
+                .collect(),
+        }
+    }
+
+    fn publish(&self, reg: &Registry) {
+        self.status_tx.send_replace(Self::build_status(reg));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_client_controls() {
+        let clients = TabClients::new();
+        let a = clients.attach(None, None, false);
+        let b = clients.attach(Some("bob".to_string()), None, false);
+        assert!(clients.is_controller(a));
+        assert!(!clients.is_controller(b));
+        let status = clients.status();
+        assert_eq!(status.controller, Some(a));
+        assert_eq!(status.clients[1].name, "bob");
+        assert_eq!(status.clients[1].role, Role::Observer);
+    }
+
+    #[test]
+    fn test_take_and_release() {
+        let clients = TabClients::new();
+        let a = clients.attach(None, None, false);
+        let b = clients.attach(None, None, true);
+        assert!(clients.take_control(b));
+        assert!(!clients.is_controller(a));
+        clients.release_control(a);
+        assert!(clients.is_controller(b));
+        clients.release_control(b);
+        assert_eq!(clients.status().controller, None);
+        assert!(!clients.take_control(99));
+    }
+
+    #[test]
+    fn test_detach_hands_over_control() {
+        let clients = TabClients::new();
+        let a = clients.attach(None, None, false);
+        let observer = clients.attach(None, None, true);
+        let b = clients.attach(None, None, false);
+        clients.detach(a);
+        assert!(clients.is_controller(b));
+        assert!(!clients.is_controller(observer));
+    }
+
+    #[test]
+    fn test_controlled_by_key() {
+        let clients = TabClients::new();
+        let a = clients.attach(None, Some("win-a".to_string()), false);
+        let b = clients.attach(None, Some("win-b".to_string()), false);
+        assert!(clients.is_controlled_by("win-a"));
+        assert!(!clients.is_controlled_by("win-b"));
+        clients.take_control(b);
+        assert!(clients.is_controlled_by("win-b"));
+        clients.release_control(b);
+        assert!(!clients.is_controlled_by("win-b"));
+        clients.detach(a);
+        assert!(!clients.is_controlled_by("win-a"));
+    }
+}
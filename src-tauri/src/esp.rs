@@ -0,0 +1,640 @@
+//! The Espressif boot ROM's serial loader, as esptool talks to it without
+//! uploading its stub. Commands and answers are SLIP framed packets:
+//!
+//! - command: `00 OP SIZE(2) CHECKSUM(4) DATA`
+//! - answer:  `01 OP SIZE(2) VALUE(4) DATA STATUS`
+//!
+//! all little-endian, where the status bytes close the data.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tokio_serial::Parity;
+
+use crate::flasher::{Backend, Control, Segment};
+use crate::xmodem::Link;
+
+const END: u8 = 0xc0;
+const ESC: u8 = 0xdb;
+const ESC_END: u8 = 0xdc;
+const ESC_ESC: u8 = 0xdd;
+
+const FLASH_BEGIN: u8 = 0x02;
+const FLASH_DATA: u8 = 0x03;
+const FLASH_END: u8 = 0x04;
+const SYNC: u8 = 0x08;
+const READ_REG: u8 = 0x0a;
+const SPI_SET_PARAMS: u8 = 0x0b;
+const SPI_ATTACH: u8 = 0x0d;
+const CHANGE_BAUDRATE: u8 = 0x0f;
+const SPI_FLASH_MD5: u8 = 0x13;
+
+/// The ROM talks at this rate until told otherwise
+pub const ROM_BAUD: u32 = 115200;
+/// Data in a FLASH_DATA packet
+const FLASH_BLOCK: usize = 0x400;
+const SECTOR: u32 = 0x1000;
+/// Flash size told to the ROM. It only bounds writes, so the largest
+/// common part will do.
+const FLASH_SIZE: u32 = 16 * 1024 * 1024;
+/// Holds a value identifying the chip
+const CHIP_MAGIC_REG: u32 = 0x4000_1000;
+const CHECKSUM_SEED: u8 = 0xef;
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+const SYNC_TIMEOUT: Duration = Duration::from_millis(100);
+/// SYNC packets sent after each reset before giving up on it.
+const SYNC_ATTEMPTS: usize = 7;
+const WRITE_ATTEMPTS: usize = 3;
+
+/// What the ROM loader of a chip needs.
+struct Chip {
+    name: &'static str,
+    magic: &'static [u32],
+    /// The ESP8266: two status bytes, no MD5, no baud rate changes, and an
+    /// erase size bug to work around
+    legacy: bool,
+    /// FLASH_BEGIN takes a fifth word, the encryption flag
+    begin_encrypted: bool,
+}
+
+const CHIPS: &[Chip] = &[
+    Chip {
+        name: "ESP8266",
+        magic: &[0xfff0_c101],
+        legacy: true,
+        begin_encrypted: false,
+    },
+    Chip {
+        name: "ESP32",
+        magic: &[0x00f0_1d83],
+        legacy: false,
+        begin_encrypted: false,
+    },
+    Chip {
+        name: "ESP32-S2",
+        magic: &[0x0000_07c6],
+        legacy: false,
+        begin_encrypted: true,
+    },
+    Chip {
+        name: "ESP32-S3",
+        magic: &[0x0000_0009],
+        legacy: false,
+        begin_encrypted: true,
+    },
+    Chip {
+        name: "ESP32-C3",
+        magic: &[0x6921_506f, 0x1b31_506f, 0x4881_606f, 0x4361_606f],
+        legacy: false,
+        begin_encrypted: true,
+    },
+    Chip {
+        name: "ESP32-C2",
+        magic: &[0x6f51_306f, 0x7c41_a06f],
+        legacy: false,
+        begin_encrypted: true,
+    },
+    Chip {
+        name: "ESP32-C6",
+        magic: &[0x2ce0_806f],
+        legacy: false,
+        begin_encrypted: true,
+    },
+    Chip {
+        name: "ESP32-H2",
+        magic: &[0xd7b7_3e80],
+        legacy: false,
+        begin_encrypted: true,
+    },
+];
+
+/// Newer chips all take what the ESP32-S2 onwards do.
+const NEWER_CHIP: Chip = Chip {
+    name: "ESP",
+    magic: &[],
+    legacy: false,
+    begin_encrypted: true,
+};
+
+fn command_name(op: u8) -> &'static str {
+    match op {
+        FLASH_BEGIN => "FLASH_BEGIN",
+        FLASH_DATA => "FLASH_DATA",
+        FLASH_END => "FLASH_END",
+        SYNC => "SYNC",
+        READ_REG => "READ_REG",
+        SPI_SET_PARAMS => "SPI_SET_PARAMS",
+        SPI_ATTACH => "SPI_ATTACH",
+        CHANGE_BAUDRATE => "CHANGE_BAUDRATE",
+        SPI_FLASH_MD5 => "SPI_FLASH_MD5",
+        _ => "command",
+    }
+}
+
+fn error_text(code: u8) -> String {
+    match code {
+        0x05 => "invalid message".to_string(),
+        0x06 => "failed to act on the message".to_string(),
+        0x07 => "bad checksum".to_string(),
+        0x08 => "flash write error".to_string(),
+        0x09 => "flash read error".to_string(),
+        0x0a => "flash read length error".to_string(),
+        code => format!("error {:#04x}", code),
+    }
+}
+
+fn slip_encode(packet: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(packet.len() + 2);
+    out.push(END);
+    for &b in packet {
+        match b {
+            END => out.extend([ESC, ESC_END]),
+            ESC => out.extend([ESC, ESC_ESC]),
+            b => out.push(b),
+        }
+    }
+    out.push(END);
+    out
+}
+
+/// Next SLIP frame, skipping anything outside frames (the ROM's boot
+/// messages), or `None` at `deadline`.
+async fn read_frame(link: &mut Link, deadline: Instant) -> Result<Option<Vec<u8>>, String> {
+    let mut frame: Option<Vec<u8>> = None;
+    let mut escaped = false;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let Some(b) = link.byte(left).await? else {
+            return Ok(None);
+        };
+        let Some(data) = frame.as_mut() else {
+            if b == END {
+                frame = Some(Vec::new());
+            }
+            continue;
+        };
+        match b {
+            // Back to back ENDs: the first closed something else
+            END if data.is_empty() => {}
+            END => return Ok(frame),
+            ESC => escaped = true,
+            b if escaped => {
+                escaped = false;
+                data.push(match b {
+                    ESC_END => END,
+                    ESC_ESC => ESC,
+                    b => b,
+                });
+            }
+            b => data.push(b),
+        }
+    }
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Time a command on `size` bytes may take, at `secs_per_mb`.
+fn timeout_per_mb(secs_per_mb: u64, size: usize) -> Duration {
+    COMMAND_TIMEOUT.max(Duration::from_millis(secs_per_mb * size as u64 / 1000))
+}
+
+/// The ESP8266 ROM erases more than asked for; ask for less so that it
+/// erases the right amount (esptool's `get_erase_size`).
+fn esp8266_erase_size(offset: u32, size: u32) -> u32 {
+    const SECTORS_PER_BLOCK: u32 = 16;
+    let sectors = size.div_ceil(SECTOR);
+    let start = offset / SECTOR;
+    let head = (SECTORS_PER_BLOCK - start % SECTORS_PER_BLOCK).min(sectors);
+    if sectors < 2 * head {
+        sectors.div_ceil(2) * SECTOR
+    } else {
+        (sectors - head) * SECTOR
+    }
+}
+
+/// The ROM loader, flashing at `baud`.
+pub struct Rom {
+    baud: u32,
+    chip: &'static Chip,
+    /// Status bytes at the end of answers
+    status_len: usize,
+}
+
+impl Rom {
+    pub fn new(baud: u32) -> Self {
+        Rom {
+            baud,
+            chip: &NEWER_CHIP,
+            status_len: 2,
+        }
+    }
+
+    /// Send a command and wait for its answer, returning the value and
+    /// data, or `None` if there was no answer in `timeout`.
+    async fn try_command(
+        &self,
+        link: &mut Link,
+        op: u8,
+        data: &[u8],
+        checksum: u32,
+        timeout: Duration,
+    ) -> Result<Option<(u32, Vec<u8>)>, String> {
+        let mut packet = vec![0, op];
+        packet.extend((data.len() as u16).to_le_bytes());
+        packet.extend(checksum.to_le_bytes());
+        packet.extend(data);
+        link.send(slip_encode(&packet)).await?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let Some(frame) = read_frame(link, deadline).await? else {
+                return Ok(None);
+            };
+            // Answers to earlier commands, like the extra SYNC ones
+            if frame.len() < 8 || frame[0] != 1 || frame[1] != op {
+                continue;
+            }
+            let size = u16::from_le_bytes([frame[2], frame[3]]) as usize;
+            let value = u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]);
+            if size < self.status_len || frame.len() < 8 + size {
+                return Err(format!("Malformed answer to {}", command_name(op)));
+            }
+            let (data, status) = frame[8..8 + size].split_at(size - self.status_len);
+            if status[0] != 0 {
+                return Err(format!(
+                    "{} failed: {}",
+                    command_name(op),
+                    error_text(status[1])
+                ));
+            }
+            return Ok(Some((value, data.to_vec())));
+        }
+    }
+
+    async fn command(
+        &self,
+        link: &mut Link,
+        op: u8,
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<(u32, Vec<u8>), String> {
+        self.try_command(link, op, data, 0, timeout)
+            .await?
+            .ok_or_else(|| format!("No answer to {}", command_name(op)))
+    }
+
+    async fn sync(&self, link: &mut Link) -> Result<bool, String> {
+        let mut data = vec![0x07, 0x07, 0x12, 0x20];
+        data.extend([0x55; 32]);
+        for _ in 0..SYNC_ATTEMPTS {
+            if self
+                .try_command(link, SYNC, &data, 0, SYNC_TIMEOUT)
+                .await?
+                .is_some()
+            {
+                // The ROM answers a SYNC several times
+                tokio::time::sleep(SYNC_TIMEOUT).await;
+                link.purge();
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[async_trait::async_trait]
+impl Backend for Rom {
+    fn parity(&self) -> Parity {
+        Parity::None
+    }
+
+    fn connect_baud(&self) -> u32 {
+        ROM_BAUD
+    }
+
+    fn erases(&self) -> bool {
+        false
+    }
+
+    fn check(&self, segments: &[Segment]) -> Result<(), String> {
+        match segments.iter().find(|s| s.address % SECTOR != 0) {
+            Some(s) => Err(format!(
+                "{} is at {:#x}; ESP flash offsets must be multiples of {:#x}",
+                s.name, s.address, SECTOR
+            )),
+            None => Ok(()),
+        }
+    }
+
+    async fn connect(&mut self, link: &mut Link, control: &dyn Control) -> Result<String, String> {
+        self.status_len = 2;
+        if !self.sync(link).await? {
+            return Err("No answer from the ESP ROM; is GPIO0 held low at reset?".to_string());
+        }
+        let (magic, _) = self
+            .command(link, READ_REG, &words(&[CHIP_MAGIC_REG]), COMMAND_TIMEOUT)
+            .await?;
+        self.chip = CHIPS
+            .iter()
+            .find(|chip| chip.magic.contains(&magic))
+            .unwrap_or(&NEWER_CHIP);
+        let name = match self.chip.magic {
+            [] => format!("ESP (chip magic {:#010x})", magic),
+            _ => self.chip.name.to_string(),
+        };
+        if self.chip.legacy {
+            if self.baud != ROM_BAUD {
+                return Err(format!("The {} ROM can't change baud rate", name));
+            }
+            return Ok(name);
+        }
+        self.status_len = 4;
+
+        self.command(link, SPI_ATTACH, &words(&[0, 0]), COMMAND_TIMEOUT)
+            .await?;
+        let params = words(&[0, FLASH_SIZE, 0x10000, SECTOR, 0x100, 0xffff]);
+        self.command(link, SPI_SET_PARAMS, &params, COMMAND_TIMEOUT)
+            .await?;
+        if self.baud != ROM_BAUD {
+            self.command(
+                link,
+                CHANGE_BAUDRATE,
+                &words(&[self.baud, 0]),
+                COMMAND_TIMEOUT,
+            )
+            .await?;
+            control.set_baud_rate(self.baud)?;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            link.purge();
+        }
+        Ok(name)
+    }
+
+    async fn erase(&mut self, _link: &mut Link, _segments: &[Segment]) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn write(
+        &mut self,
+        link: &mut Link,
+        segment: &Segment,
+        progress: &mut (dyn FnMut(u64) + Send),
+    ) -> Result<(), String> {
+        let size = segment.data.len() as u32;
+        let erase_size = match self.chip.legacy {
+            true => esp8266_erase_size(segment.address, size),
+            false => size,
+        };
+        let blocks = segment.data.len().div_ceil(FLASH_BLOCK) as u32;
+        let mut begin = vec![erase_size, blocks, FLASH_BLOCK as u32, segment.address];
+        if self.chip.begin_encrypted {
+            begin.push(0);
+        }
+        // The ROM erases the whole region before answering
+        self.command(
+            link,
+            FLASH_BEGIN,
+            &words(&begin),
+            timeout_per_mb(30, segment.data.len()),
+        )
+        .await?;
+
+        for (seq, block) in segment.data.chunks(FLASH_BLOCK).enumerate() {
+            let mut data = words(&[FLASH_BLOCK as u32, seq as u32, 0, 0]);
+            data.extend(block);
+            data.resize(16 + FLASH_BLOCK, 0xff);
+            let checksum = data[16..].iter().fold(CHECKSUM_SEED, |c, &b| c ^ b) as u32;
+            let mut attempt = 1;
+            while self
+                .try_command(
+                    link,
+                    FLASH_DATA,
+                    &data,
+                    checksum,
+                    timeout_per_mb(40, FLASH_BLOCK),
+                )
+                .await?
+                .is_none()
+            {
+                if attempt == WRITE_ATTEMPTS {
+                    return Err(format!(
+                        "No answer writing {:#010x}",
+                        segment.address as usize + seq * FLASH_BLOCK
+                    ));
+                }
+                attempt += 1;
+            }
+            progress(((seq + 1) * FLASH_BLOCK).min(segment.data.len()) as u64);
+        }
+        Ok(())
+    }
+
+    async fn verify(
+        &mut self,
+        link: &mut Link,
+        segment: &Segment,
+        progress: &mut (dyn FnMut(u64) + Send),
+    ) -> Result<(), String> {
+        if self.chip.legacy {
+            return Err(format!(
+                "The {} ROM can't checksum flash; flash without verifying",
+                self.chip.name
+            ));
+        }
+        let size = segment.data.len();
+        let (_, answer) = self
+            .command(
+                link,
+                SPI_FLASH_MD5,
+                &words(&[segment.address, size as u32, 0, 0]),
+                timeout_per_mb(8, size),
+            )
+            .await?;
+        let expected = md5::compute(&segment.data);
+        let matches = match answer.len() {
+            // The ROM answers in hex, the stub in binary
+            32 => String::from_utf8_lossy(&answer).eq_ignore_ascii_case(&format!("{:x}", expected)),
+            16 => answer == expected.0,
+            _ => return Err("Malformed answer to SPI_FLASH_MD5".to_string()),
+        };
+        if !matches {
+            return Err(format!(
+                "{} doesn't match the flash at {:#010x} (MD5 differs)",
+                segment.name, segment.address
+            ));
+        }
+        progress(size as u64);
+        Ok(())
+    }
+
+    async fn finish(
+        &mut self,
+        link: &mut Link,
+        _segments: &[Segment],
+        reset_follows: bool,
+    ) -> Result<(), String> {
+        if !reset_follows {
+            // 0 asks the ROM to run the firmware; it may not answer first
+            self.try_command(link, FLASH_END, &words(&[0]), 0, COMMAND_TIMEOUT)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+
+    struct NoLines(Mutex<u32>);
+
+    impl Control for NoLines {
+        fn set_dtr(&self, _: bool) -> Result<(), String> {
+            Ok(())
+        }
+        fn set_rts(&self, _: bool) -> Result<(), String> {
+            Ok(())
+        }
+        fn baud_rate(&self) -> Result<u32, String> {
+            Ok(*self.0.lock().unwrap())
+        }
+        fn set_baud_rate(&self, baud: u32) -> Result<(), String> {
+            *self.0.lock().unwrap() = baud;
+            Ok(())
+        }
+        fn parity(&self) -> Result<Parity, String> {
+            Ok(Parity::None)
+        }
+        fn set_parity(&self, _: Parity) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    fn decode(data: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        let mut escaped = false;
+        for &b in data {
+            match b {
+                END => frames.push(Vec::new()),
+                ESC => escaped = true,
+                b => {
+                    let frame = frames.last_mut().unwrap();
+                    frame.push(match (escaped, b) {
+                        (true, ESC_END) => END,
+                        (true, ESC_ESC) => ESC,
+                        (_, b) => b,
+                    });
+                    escaped = false;
+                }
+            }
+        }
+        frames.retain(|f| !f.is_empty());
+        frames
+    }
+
+    /// An ESP32 ROM with 64 KiB of flash; returns the flash when the host
+    /// hangs up.
+    fn fake_rom(
+        mut from_host: mpsc::Receiver<Vec<u8>>,
+        to_host: mpsc::Sender<Vec<u8>>,
+    ) -> tokio::task::JoinHandle<Vec<u8>> {
+        tokio::spawn(async move {
+            let mut flash = vec![0u8; 0x10000];
+            let mut offset = 0;
+            to_host
+                .send(b"rst:0x1 (POWERON_RESET)\r\nwaiting for download\r\n".to_vec())
+                .await
+                .unwrap();
+            while let Some(data) = from_host.recv().await {
+                for packet in decode(&data) {
+                    let op = packet[1];
+                    let body = &packet[8..];
+                    let word =
+                        |i: usize| u32::from_le_bytes(body[i * 4..i * 4 + 4].try_into().unwrap());
+                    let mut answer = Vec::new();
+                    let mut value = 0u32;
+                    match op {
+                        READ_REG => value = 0x00f0_1d83,
+                        FLASH_BEGIN => {
+                            assert_eq!(body.len(), 16);
+                            offset = word(3) as usize;
+                            flash[offset..offset + word(0) as usize].fill(0xff);
+                        }
+                        FLASH_DATA => {
+                            let start = offset + word(1) as usize * FLASH_BLOCK;
+                            let block = &body[16..];
+                            let checksum = block.iter().fold(CHECKSUM_SEED, |c, &b| c ^ b);
+                            assert_eq!(packet[4], checksum);
+                            let end = (start + block.len()).min(flash.len());
+                            flash[start..end].copy_from_slice(&block[..end - start]);
+                        }
+                        SPI_FLASH_MD5 => {
+                            let (at, len) = (word(0) as usize, word(1) as usize);
+                            answer =
+                                format!("{:X}", md5::compute(&flash[at..at + len])).into_bytes();
+                        }
+                        _ => {}
+                    }
+                    answer.extend([0, 0, 0, 0]);
+                    let mut frame = vec![1, op];
+                    frame.extend((answer.len() as u16).to_le_bytes());
+                    frame.extend(value.to_le_bytes());
+                    frame.extend(answer);
+                    // SYNC is answered more than once
+                    for _ in 0..if op == SYNC { 3 } else { 1 } {
+                        to_host.send(slip_encode(&frame)).await.unwrap();
+                    }
+                }
+            }
+            flash
+        })
+    }
+
+    #[test]
+    fn test_slip() {
+        assert_eq!(
+            slip_encode(&[1, END, ESC, 2]),
+            [END, 1, ESC, ESC_END, ESC, ESC_ESC, 2, END]
+        );
+        assert_eq!(esp8266_erase_size(0, 0x1000), 0x1000);
+        assert_eq!(esp8266_erase_size(0, 0x20000), 0x10000);
+    }
+
+    #[tokio::test]
+    async fn test_flash() {
+        let (to_host, from_device) = mpsc::channel(64);
+        let (to_device, from_host) = mpsc::channel(64);
+        let device = fake_rom(from_host, to_host);
+        let mut link = Link::new(from_device, to_device);
+        let port = NoLines(Mutex::new(ROM_BAUD));
+
+        let mut rom = Rom::new(921600);
+        assert_eq!(rom.connect(&mut link, &port).await, Ok("ESP32".to_string()));
+        assert_eq!(*port.0.lock().unwrap(), 921600);
+
+        let data: Vec<u8> = (0..0x1234u32).map(|i| (i * 7) as u8 ^ 0xc0).collect();
+        let segment = Segment::new("app.bin", 0x2000, data.clone());
+        assert!(rom.check(std::slice::from_ref(&segment)).is_ok());
+        assert!(rom.check(&[Segment::new("x", 0x2004, vec![0])]).is_err());
+        let mut written = Vec::new();
+        rom.write(&mut link, &segment, &mut |n| written.push(n))
+            .await
+            .unwrap();
+        assert_eq!(written, [0x400, 0x800, 0xc00, 0x1000, 0x1234]);
+        rom.verify(&mut link, &segment, &mut |_| {}).await.unwrap();
+
+        // Same length, different data
+        let other = Segment::new("app.bin", 0x2000, vec![0; data.len()]);
+        assert!(rom.verify(&mut link, &other, &mut |_| {}).await.is_err());
+
+        drop(link);
+        let flash = device.await.unwrap();
+        assert_eq!(&flash[0x2000..0x3234], &data[..]);
+        assert_eq!(flash[0x3234], 0xff);
+    }
+}
@@ -0,0 +1,106 @@
+use serde::Deserialize;
+
+use crate::ansi::{AnsiStripper, HtmlRenderer, DEFAULT_BG, DEFAULT_FG};
+
+/// Output format for a scrollback export.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// Bytes exactly as received
+    Raw,
+    /// Escape sequences stripped
+    Text,
+    /// Standalone HTML page with colors preserved
+    Html,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Raw => "application/octet-stream",
+            ExportFormat::Text => "text/plain; charset=utf-8",
+            ExportFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Raw => "log",
+            ExportFormat::Text => "txt",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+enum Converter {
+    Raw,
+    Text(AnsiStripper),
+    Html(HtmlRenderer),
+}
+
+/// Streaming scrollback converter: call `header`, then `feed` for each
+/// chunk in order, then `footer`.
+pub struct Exporter {
+    converter: Converter,
+}
+
+impl Exporter {
+    pub fn new(format: ExportFormat) -> Self {
+        let converter = match format {
+            ExportFormat::Raw => Converter::Raw,
+            ExportFormat::Text => Converter::Text(AnsiStripper::new()),
+            ExportFormat::Html => Converter::Html(HtmlRenderer::new()),
+        };
+        Exporter { converter }
+    }
+
+    pub fn header(&self, title: &str) -> Vec<u8> {
+        match self.converter {
+            Converter::Html(_) => {
+                let (fr, fg, fb) = DEFAULT_FG;
+                let (br, bg, bb) = DEFAULT_BG;
+                let title = title
+                    .replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;");
+                format!(
+                    "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+                     <style>body{{margin:0;background:#{:02x}{:02x}{:02x};color:#{:02x}{:02x}{:02x};}}\
+                     pre{{margin:0;padding:12px;font-family:\"SF Mono\",Menlo,Monaco,monospace;\
+                     font-size:13px;white-space:pre-wrap;}}</style>\n</head>\n<body><pre>",
+                    title, br, bg, bb, fr, fg, fb
+                )
+                .into_bytes()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+        match self.converter {
+            Converter::Raw => chunk.to_vec(),
+            Converter::Text(ref mut stripper) => {
+                let mut out = Vec::with_capacity(chunk.len());
+                stripper.feed(chunk, &mut out);
+                out
+            }
+            Converter::Html(ref mut renderer) => {
+                let mut out = Vec::with_capacity(chunk.len() * 2);
+                renderer.feed(chunk, &mut out);
+                out
+            }
+        }
+    }
+
+    pub fn footer(&mut self) -> Vec<u8> {
+        match self.converter {
+            Converter::Html(ref mut renderer) => {
+                let mut out = Vec::new();
+                renderer.finish(&mut out);
+                out.extend_from_slice(b"</pre></body>\n</html>\n");
+                out
+            }
+            _ => Vec::new(),
+        }
+    }
+}
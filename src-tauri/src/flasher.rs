@@ -0,0 +1,655 @@
+//! Firmware flashing through a serial tab. The chip is put in its ROM
+//! bootloader with the modem control lines, a backend for that
+//! bootloader's protocol erases, writes and verifies the images, and the
+//! chip is reset into the new firmware.
+
+use std::future::Future;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tokio_serial::Parity;
+
+use crate::xmodem::Link;
+
+/// Resets tried before giving up on the bootloader.
+const CONNECT_ATTEMPTS: usize = 3;
+/// Longest delay in a control line sequence.
+const MAX_STEP_DELAY_MS: u64 = 10_000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    /// Espressif boot ROM (ESP8266 and the ESP32 family)
+    Esp,
+    /// STM32 system memory bootloader on a USART
+    Stm32,
+}
+
+impl Target {
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Esp => "ESP",
+            Target::Stm32 => "STM32",
+        }
+    }
+
+    /// Into the bootloader with the usual wiring: for ESP boards the auto
+    /// program circuit (DTR to GPIO0, RTS to EN, as esptool drives it); for
+    /// STM32, RTS to BOOT0 and DTR to NRST.
+    fn enter_sequence(self) -> &'static str {
+        match self {
+            Target::Esp => "-dtr,rts,100,dtr,-rts,50,-dtr",
+            Target::Stm32 => "rts,dtr,100,-dtr,100",
+        }
+    }
+
+    /// Out of the bootloader into the firmware, with the same wiring.
+    fn exit_sequence(self) -> &'static str {
+        match self {
+            Target::Esp => "-dtr,rts,100,-rts",
+            Target::Stm32 => "-rts,dtr,100,-dtr",
+        }
+    }
+
+    pub fn backend(self, baud: u32) -> Box<dyn Backend> {
+        match self {
+            Target::Esp => Box::new(crate::esp::Rom::new(baud)),
+            Target::Stm32 => Box::new(crate::stm32::Bootloader::new(baud)),
+        }
+    }
+}
+
+fn default_baud() -> u32 {
+    115200
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// How to flash.
+#[derive(Deserialize, Clone, Debug)]
+pub struct FlashOptions {
+    pub target: Target,
+    /// ESP: the ROM is reached at 115200 and switched to this; STM32: the
+    /// bootloader picks it up from the first byte
+    #[serde(default = "default_baud")]
+    pub baud: u32,
+    /// STM32: mass erase before writing. The ESP ROM erases what it writes
+    /// either way.
+    #[serde(default = "default_true")]
+    pub erase: bool,
+    #[serde(default = "default_true")]
+    pub verify: bool,
+    /// Control line steps into the bootloader, e.g. `rts,dtr,100,-dtr`;
+    /// the target's usual wiring if missing, none (boot mode set by hand)
+    /// if empty
+    #[serde(default)]
+    pub enter_sequence: Option<String>,
+    /// Steps that restart the chip into the new firmware
+    #[serde(default)]
+    pub exit_sequence: Option<String>,
+}
+
+impl FlashOptions {
+    /// Check the options, returning the enter and exit sequences.
+    pub fn validate(&self) -> Result<(Vec<Step>, Vec<Step>), String> {
+        if self.baud == 0 {
+            return Err("Baud rate must be at least 1".to_string());
+        }
+        let enter = self
+            .enter_sequence
+            .as_deref()
+            .unwrap_or(self.target.enter_sequence());
+        let exit = self
+            .exit_sequence
+            .as_deref()
+            .unwrap_or(self.target.exit_sequence());
+        Ok((
+            parse_sequence(enter).map_err(|e| format!("Enter sequence: {}", e))?,
+            parse_sequence(exit).map_err(|e| format!("Exit sequence: {}", e))?,
+        ))
+    }
+}
+
+/// One step of a control line sequence.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    Dtr(bool),
+    Rts(bool),
+    Wait(Duration),
+}
+
+/// Parse steps separated by commas: `dtr` or `rts` asserts the line,
+/// `-dtr` or `-rts` releases it, and a number waits that many ms.
+pub fn parse_sequence(text: &str) -> Result<Vec<Step>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|step| !step.is_empty())
+        .map(|step| match step.to_ascii_lowercase().as_str() {
+            "dtr" => Ok(Step::Dtr(true)),
+            "-dtr" => Ok(Step::Dtr(false)),
+            "rts" => Ok(Step::Rts(true)),
+            "-rts" => Ok(Step::Rts(false)),
+            ms => ms
+                .parse()
+                .ok()
+                .filter(|&ms| ms <= MAX_STEP_DELAY_MS)
+                .map(|ms| Step::Wait(Duration::from_millis(ms)))
+                .ok_or_else(|| {
+                    format!(
+                        "\"{}\" is not dtr, -dtr, rts, -rts or a delay of at most {} ms",
+                        step, MAX_STEP_DELAY_MS
+                    )
+                }),
+        })
+        .collect()
+}
+
+/// The serial port under a flash: its control lines and line settings.
+pub trait Control: Send + Sync {
+    fn set_dtr(&self, on: bool) -> Result<(), String>;
+    fn set_rts(&self, on: bool) -> Result<(), String>;
+    fn baud_rate(&self) -> Result<u32, String>;
+    fn set_baud_rate(&self, baud: u32) -> Result<(), String>;
+    fn parity(&self) -> Result<Parity, String>;
+    fn set_parity(&self, parity: Parity) -> Result<(), String>;
+}
+
+async fn apply(steps: &[Step], control: &dyn Control) -> Result<(), String> {
+    for step in steps {
+        match *step {
+            Step::Dtr(on) => control.set_dtr(on)?,
+            Step::Rts(on) => control.set_rts(on)?,
+            Step::Wait(delay) => tokio::time::sleep(delay).await,
+        }
+    }
+    Ok(())
+}
+
+/// A file to write and where it goes.
+#[derive(Debug)]
+pub struct Segment {
+    pub name: String,
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    /// The data is padded with erased flash (0xFF) to whole 32-bit words,
+    /// which both bootloaders write in.
+    pub fn new(name: &str, address: u32, mut data: Vec<u8>) -> Self {
+        data.resize(data.len().next_multiple_of(4), 0xff);
+        Segment {
+            name: name.to_string(),
+            address,
+            data,
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.address as u64 + self.data.len() as u64
+    }
+}
+
+/// Put segments in address order, refusing empty files and overlaps.
+pub fn arrange(segments: &mut [Segment]) -> Result<(), String> {
+    if segments.is_empty() {
+        return Err("Nothing to flash".to_string());
+    }
+    if let Some(empty) = segments.iter().find(|s| s.data.is_empty()) {
+        return Err(format!("{} is empty", empty.name));
+    }
+    if let Some(past) = segments.iter().find(|s| s.end() > 1 << 32) {
+        return Err(format!("{} runs past the 4 GiB address space", past.name));
+    }
+    segments.sort_by_key(|s| s.address);
+    for pair in segments.windows(2) {
+        if pair[0].end() > pair[1].address as u64 {
+            return Err(format!(
+                "{} at {:#010x} overlaps {} at {:#010x}",
+                pair[1].name, pair[1].address, pair[0].name, pair[0].address
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Connect,
+    Erase,
+    Write,
+    Verify,
+    Reset,
+}
+
+/// Progress of a flash, reported as it goes.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Stage(Stage),
+    /// The bootloader answered; what it said it is
+    Chip(String),
+    /// Bytes written or verified so far, of all the segments
+    Progress {
+        done: u64,
+        total: u64,
+    },
+}
+
+/// A bootloader protocol. `run` takes care of resets, line settings and
+/// progress; a backend only talks to the chip.
+#[async_trait::async_trait]
+pub trait Backend: Send {
+    /// Parity of the bootloader's UART
+    fn parity(&self) -> Parity;
+
+    /// Baud rate the bootloader is reached at
+    fn connect_baud(&self) -> u32;
+
+    /// Whether erasing is a step of its own rather than part of writing
+    fn erases(&self) -> bool;
+
+    /// Refuse segments the bootloader can't write.
+    fn check(&self, _segments: &[Segment]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Get the bootloader's attention right after a reset and find out
+    /// what chip it runs on.
+    async fn connect(&mut self, link: &mut Link, control: &dyn Control) -> Result<String, String>;
+
+    async fn erase(&mut self, link: &mut Link, segments: &[Segment]) -> Result<(), String>;
+
+    /// Write a segment, calling `progress` with the bytes written so far.
+    async fn write(
+        &mut self,
+        link: &mut Link,
+        segment: &Segment,
+        progress: &mut (dyn FnMut(u64) + Send),
+    ) -> Result<(), String>;
+
+    /// Check a segment against the flash, calling `progress` as it goes.
+    async fn verify(
+        &mut self,
+        link: &mut Link,
+        segment: &Segment,
+        progress: &mut (dyn FnMut(u64) + Send),
+    ) -> Result<(), String>;
+
+    /// Leave the bootloader. If `reset_follows`, the exit sequence will
+    /// restart the chip; otherwise the bootloader should start the firmware.
+    async fn finish(
+        &mut self,
+        link: &mut Link,
+        segments: &[Segment],
+        reset_follows: bool,
+    ) -> Result<(), String>;
+}
+
+/// Flash `segments` (in address order) with `backend`. The port is back
+/// at its own baud rate and parity when this returns, whether the flash
+/// worked, failed or was stopped by `cancel`, which resolves to the
+/// reason. Returns the chip's description.
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    backend: &mut dyn Backend,
+    options: &FlashOptions,
+    segments: &[Segment],
+    link: &mut Link,
+    control: &dyn Control,
+    mut on_event: impl FnMut(Event) + Send,
+    cancel: impl Future<Output = String>,
+) -> Result<String, String> {
+    let (enter, exit) = options.validate()?;
+    backend.check(segments)?;
+    let saved = (control.baud_rate()?, control.parity()?);
+    let total: u64 = segments.iter().map(|s| s.data.len() as u64).sum();
+
+    let work = async {
+        on_event(Event::Stage(Stage::Connect));
+        control.set_parity(backend.parity())?;
+        control.set_baud_rate(backend.connect_baud())?;
+        let mut attempt = 1;
+        let chip = loop {
+            apply(&enter, control).await?;
+            link.purge();
+            match backend.connect(link, control).await {
+                Ok(chip) => break chip,
+                Err(_) if attempt < CONNECT_ATTEMPTS => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        };
+        on_event(Event::Chip(chip.clone()));
+
+        if options.erase && backend.erases() {
+            on_event(Event::Stage(Stage::Erase));
+            backend.erase(link, segments).await?;
+        }
+        let mut stages = vec![Stage::Write];
+        if options.verify {
+            stages.push(Stage::Verify);
+        }
+        for stage in stages {
+            on_event(Event::Stage(stage));
+            let mut done = 0;
+            for segment in segments {
+                let mut progress = |bytes| {
+                    on_event(Event::Progress {
+                        done: done + bytes,
+                        total,
+                    })
+                };
+                match stage {
+                    Stage::Write => backend.write(link, segment, &mut progress).await?,
+                    _ => backend.verify(link, segment, &mut progress).await?,
+                }
+                done += segment.data.len() as u64;
+            }
+        }
+        backend.finish(link, segments, !exit.is_empty()).await?;
+        Ok(chip)
+    };
+    let result = tokio::select! {
+        result = work => result,
+        reason = cancel => Err(reason),
+    };
+
+    // Back to the terminal's settings before the reset, so the firmware's
+    // first output comes through
+    let restored = control
+        .set_baud_rate(saved.0)
+        .and_then(|_| control.set_parity(saved.1));
+    let chip = result?;
+    restored?;
+    if !exit.is_empty() {
+        on_event(Event::Stage(Stage::Reset));
+        apply(&exit, control).await?;
+    }
+    Ok(chip)
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlashState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Progress of a flash, as reported in status and notifications.
+#[derive(Serialize, Clone, Debug)]
+pub struct FlashStatus {
+    pub target: Target,
+    pub files: Vec<String>,
+    pub state: FlashState,
+    pub stage: Stage,
+    pub chip: Option<String>,
+    /// Bytes written or verified in the current stage
+    pub done: u64,
+    pub total: u64,
+    pub elapsed_ms: u64,
+    pub message: Option<String>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl FlashStatus {
+    pub fn new(target: Target, segments: &[Segment]) -> Self {
+        FlashStatus {
+            target,
+            files: segments.iter().map(|s| s.name.clone()).collect(),
+            state: FlashState::Running,
+            stage: Stage::Connect,
+            chip: None,
+            done: 0,
+            total: segments.iter().map(|s| s.data.len() as u64).sum(),
+            elapsed_ms: 0,
+            message: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// Apply an event; returns whether it is worth reporting right away
+    /// rather than with the next progress update.
+    pub fn update(&mut self, event: Event) -> bool {
+        self.elapsed_ms = self.started.elapsed().as_millis() as u64;
+        match event {
+            Event::Stage(stage) => {
+                self.stage = stage;
+                self.done = 0;
+                true
+            }
+            Event::Chip(chip) => {
+                self.chip = Some(chip);
+                true
+            }
+            Event::Progress { done, total } => {
+                self.done = done;
+                self.total = total;
+                false
+            }
+        }
+    }
+
+    /// Record how the flash ended.
+    pub fn finish(&mut self, state: FlashState, message: Option<String>) {
+        self.state = state;
+        self.message = message;
+        self.elapsed_ms = self.started.elapsed().as_millis() as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_sequence() {
+        assert_eq!(
+            parse_sequence("rts, -DTR,100,,").unwrap(),
+            [
+                Step::Rts(true),
+                Step::Dtr(false),
+                Step::Wait(Duration::from_millis(100))
+            ]
+        );
+        assert!(parse_sequence("").unwrap().is_empty());
+        assert!(parse_sequence("cts").is_err());
+        assert!(parse_sequence("20000").is_err());
+
+        let mut segments = vec![
+            Segment::new("app.bin", 0x10000, vec![1, 2, 3]),
+            Segment::new("boot.bin", 0x1000, vec![0; 8]),
+        ];
+        assert_eq!(segments[0].data, [1, 2, 3, 0xff]);
+        arrange(&mut segments).unwrap();
+        assert_eq!(segments[0].name, "boot.bin");
+        segments.push(Segment::new("table.bin", 0x1004, vec![0; 4]));
+        assert_eq!(
+            arrange(&mut segments).unwrap_err(),
+            "table.bin at 0x00001004 overlaps boot.bin at 0x00001000"
+        );
+    }
+
+    #[derive(Default)]
+    struct FakePort {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl Control for FakePort {
+        fn set_dtr(&self, on: bool) -> Result<(), String> {
+            self.calls.lock().unwrap().push(format!("dtr {}", on));
+            Ok(())
+        }
+        fn set_rts(&self, on: bool) -> Result<(), String> {
+            self.calls.lock().unwrap().push(format!("rts {}", on));
+            Ok(())
+        }
+        fn baud_rate(&self) -> Result<u32, String> {
+            Ok(9600)
+        }
+        fn set_baud_rate(&self, baud: u32) -> Result<(), String> {
+            self.calls.lock().unwrap().push(format!("baud {}", baud));
+            Ok(())
+        }
+        fn parity(&self) -> Result<Parity, String> {
+            Ok(Parity::None)
+        }
+        fn set_parity(&self, parity: Parity) -> Result<(), String> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("parity {:?}", parity));
+            Ok(())
+        }
+    }
+
+    /// Answers on the second reset and writes into `flash`.
+    #[derive(Default)]
+    struct FakeBackend {
+        resets: usize,
+        flash: Vec<(u32, Vec<u8>)>,
+    }
+
+    #[async_trait::async_trait]
+    impl Backend for FakeBackend {
+        fn parity(&self) -> Parity {
+            Parity::Even
+        }
+        fn connect_baud(&self) -> u32 {
+            57600
+        }
+        fn erases(&self) -> bool {
+            true
+        }
+        async fn connect(&mut self, _: &mut Link, _: &dyn Control) -> Result<String, String> {
+            self.resets += 1;
+            match self.resets {
+                1 => Err("No answer".to_string()),
+                _ => Ok("Fake".to_string()),
+            }
+        }
+        async fn erase(&mut self, _: &mut Link, _: &[Segment]) -> Result<(), String> {
+            Ok(())
+        }
+        async fn write(
+            &mut self,
+            _: &mut Link,
+            segment: &Segment,
+            progress: &mut (dyn FnMut(u64) + Send),
+        ) -> Result<(), String> {
+            self.flash.push((segment.address, segment.data.clone()));
+            progress(segment.data.len() as u64);
+            Ok(())
+        }
+        async fn verify(
+            &mut self,
+            _: &mut Link,
+            segment: &Segment,
+            _: &mut (dyn FnMut(u64) + Send),
+        ) -> Result<(), String> {
+            match segment.address {
+                0x2000 => Err("Mismatch".to_string()),
+                _ => Ok(()),
+            }
+        }
+        async fn finish(&mut self, _: &mut Link, _: &[Segment], _: bool) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let (_in_tx, in_rx) = mpsc::channel(1);
+        let (out_tx, _out_rx) = mpsc::channel(1);
+        let mut link = Link::new(in_rx, out_tx);
+        let options: FlashOptions = serde_json::from_str(
+            r#"{"target": "stm32", "enter_sequence": "rts", "exit_sequence": "-rts"}"#,
+        )
+        .unwrap();
+        let segments = [
+            Segment::new("a", 0x1000, vec![1; 8]),
+            Segment::new("b", 0x1008, vec![2; 4]),
+        ];
+        let port = FakePort::default();
+        let mut backend = FakeBackend::default();
+        let mut events = Vec::new();
+        let chip = run(
+            &mut backend,
+            &options,
+            &segments,
+            &mut link,
+            &port,
+            |e| events.push(e),
+            std::future::pending(),
+        )
+        .await;
+        assert_eq!(chip, Ok("Fake".to_string()));
+        assert_eq!(backend.flash.len(), 2);
+        assert_eq!(
+            events[..5],
+            [
+                Event::Stage(Stage::Connect),
+                Event::Chip("Fake".to_string()),
+                Event::Stage(Stage::Erase),
+                Event::Stage(Stage::Write),
+                Event::Progress { done: 8, total: 12 },
+            ]
+        );
+        assert_eq!(events.last(), Some(&Event::Stage(Stage::Reset)));
+        // Reset twice, then the settings put back before the exit reset
+        assert_eq!(
+            *port.calls.lock().unwrap(),
+            [
+                "parity Even",
+                "baud 57600",
+                "rts true",
+                "rts true",
+                "baud 9600",
+                "parity None",
+                "rts false"
+            ]
+        );
+
+        // A failed verify leaves the chip in the bootloader
+        let segments = [Segment::new("c", 0x2000, vec![3; 4])];
+        let port = FakePort::default();
+        let result = run(
+            &mut FakeBackend::default(),
+            &options,
+            &segments,
+            &mut link,
+            &port,
+            |_| {},
+            std::future::pending(),
+        )
+        .await;
+        assert_eq!(result, Err("Mismatch".to_string()));
+        assert_eq!(port.calls.lock().unwrap().last().unwrap(), "parity None");
+
+        // Stopped while waiting out the reset
+        let options = FlashOptions {
+            enter_sequence: Some("rts,1000".to_string()),
+            ..options
+        };
+        let port = FakePort::default();
+        let result = run(
+            &mut FakeBackend::default(),
+            &options,
+            &segments,
+            &mut link,
+            &port,
+            |_| {},
+            async { "Cancelled".to_string() },
+        )
+        .await;
+        assert_eq!(result, Err("Cancelled".to_string()));
+        assert_eq!(port.calls.lock().unwrap().last().unwrap(), "parity None");
+    }
+}
@@ -0,0 +1,340 @@
+//! Intel HEX and Motorola S-record files, checked before they are typed
+//! into a monitor one record per line.
+
+use serde::Serialize;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Ihex,
+    Srec,
+}
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Ihex => "Intel HEX",
+            Format::Srec => "S-records",
+        }
+    }
+}
+
+/// One line of the file.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Record {
+    /// 1-based line in the file
+    pub line: usize,
+    /// Where a data record's bytes go
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<u32>,
+    pub text: String,
+}
+
+/// A checked file.
+#[derive(Debug)]
+pub struct Image {
+    pub format: Format,
+    /// Every record, blank lines left out
+    pub records: Vec<Record>,
+    /// Bytes in data records
+    pub data_bytes: u64,
+    /// Lowest and highest address written, if any data
+    pub span: Option<(u32, u32)>,
+}
+
+fn hex_bytes(digits: &str, line: usize) -> Result<Vec<u8>, String> {
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("Line {}: not hex digits", line));
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("Line {}: odd number of hex digits", line));
+    }
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap_or(0))
+        .collect())
+}
+
+fn sum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |s, &b| s.wrapping_add(b))
+}
+
+/// Parse and check a file: record syntax and checksums, the records a
+/// file must end with, and that data stays within `range` (inclusive)
+/// without two records writing the same address.
+pub fn parse(data: &[u8], range: Option<(u32, u32)>) -> Result<Image, String> {
+    let text = std::str::from_utf8(data).map_err(|_| "Not a text file".to_string())?;
+    let mut lines = text
+        .lines()
+        .enumerate()
+        // ASCII only, as the upload skips blank lines: the records must
+        // line up with the lines it sends
+        .map(|(i, l)| (i + 1, l.trim_ascii()))
+        .filter(|(_, l)| !l.is_empty())
+        .peekable();
+    let format = match lines.peek() {
+        Some((_, l)) if l.starts_with(':') => Format::Ihex,
+        Some((_, l)) if l.starts_with('S') => Format::Srec,
+        Some((line, _)) => {
+            return Err(format!(
+                "Line {} is neither Intel HEX nor an S-record",
+                line
+            ));
+        }
+        None => return Err("The file is empty".to_string()),
+    };
+
+    let mut records = Vec::new();
+    // Data records: first and last address, and line
+    let mut spans: Vec<(u32, u32, usize)> = Vec::new();
+    let mut data_records = 0;
+    let mut ended = false;
+    // Intel HEX: base of the 16-bit addresses
+    let mut base = 0u32;
+    for (line, text) in lines {
+        if ended {
+            return Err(format!("Line {}: record after the end of the file", line));
+        }
+        let (address, len) = match format {
+            Format::Ihex => {
+                let bytes = match text.strip_prefix(':') {
+                    Some(digits) => hex_bytes(digits, line)?,
+                    None => return Err(format!("Line {}: Intel HEX records start with ':'", line)),
+                };
+                if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                    return Err(format!(
+                        "Line {}: byte count doesn't match the record",
+                        line
+                    ));
+                }
+                let (body, check) = bytes.split_at(bytes.len() - 1);
+                let expected = sum(body).wrapping_neg();
+                if check[0] != expected {
+                    return Err(format!(
+                        "Line {}: checksum is {:02X}, expected {:02X}",
+                        line, check[0], expected
+                    ));
+                }
+                let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+                let value = &bytes[4..bytes.len() - 1];
+                match (bytes[3], value.len()) {
+                    (0x00, len) => {
+                        if offset as usize + len > 0x10000 {
+                            return Err(format!(
+                                "Line {}: data runs past the end of its 64 KiB segment",
+                                line
+                            ));
+                        }
+                        (Some(base.wrapping_add(offset)), len)
+                    }
+                    (0x01, 0) => {
+                        ended = true;
+                        (None, 0)
+                    }
+                    (0x02, 2) => {
+                        base = (u16::from_be_bytes([value[0], value[1]]) as u32) << 4;
+                        (None, 0)
+                    }
+                    (0x04, 2) => {
+                        base = (u16::from_be_bytes([value[0], value[1]]) as u32) << 16;
+                        (None, 0)
+                    }
+                    // Start address
+                    (0x03 | 0x05, 4) => (None, 0),
+                    (kind @ 0x00..=0x05, _) => {
+                        return Err(format!(
+                            "Line {}: wrong length for a type {:02X} record",
+                            line, kind
+                        ));
+                    }
+                    (kind, _) => {
+                        return Err(format!("Line {}: unknown record type {:02X}", line, kind))
+                    }
+                }
+            }
+            Format::Srec => {
+                let kind = text.as_bytes().get(1).copied().unwrap_or(b' ');
+                let address_len = match (text.as_bytes()[0], kind) {
+                    (b'S', b'0' | b'1' | b'5' | b'9') => 2,
+                    (b'S', b'2' | b'6' | b'8') => 3,
+                    (b'S', b'3' | b'7') => 4,
+                    _ => return Err(format!("Line {}: not an S-record", line)),
+                };
+                let bytes = hex_bytes(&text[2..], line)?;
+                if bytes.len() < address_len + 2 || bytes.len() != bytes[0] as usize + 1 {
+                    return Err(format!(
+                        "Line {}: byte count doesn't match the record",
+                        line
+                    ));
+                }
+                let (body, check) = bytes.split_at(bytes.len() - 1);
+                let expected = !sum(body);
+                if check[0] != expected {
+                    return Err(format!(
+                        "Line {}: checksum is {:02X}, expected {:02X}",
+                        line, check[0], expected
+                    ));
+                }
+                let address = bytes[1..=address_len]
+                    .iter()
+                    .fold(0u32, |a, &b| (a << 8) | b as u32);
+                let len = bytes.len() - address_len - 2;
+                match kind {
+                    b'1'..=b'3' => {
+                        data_records += 1;
+                        (Some(address), len)
+                    }
+                    // Count of the data records so far
+                    b'5' | b'6' if address as usize != data_records => {
+                        return Err(format!(
+                            "Line {}: record count is {}, but the file has {} data records",
+                            line, address, data_records
+                        ));
+                    }
+                    b'7'..=b'9' => {
+                        ended = true;
+                        (None, 0)
+                    }
+                    _ => (None, 0),
+                }
+            }
+        };
+
+        if let Some(start) = address {
+            if len > 0 {
+                let end = start.checked_add(len as u32 - 1).ok_or_else(|| {
+                    format!("Line {}: data runs past the 4 GiB address space", line)
+                })?;
+                if let Some((low, high)) = range.filter(|&(low, high)| start < low || end > high) {
+                    return Err(format!(
+                        "Line {}: data at {:08X}-{:08X} is outside {:08X}-{:08X}",
+                        line, start, end, low, high
+                    ));
+                }
+                spans.push((start, end, line));
+            }
+        }
+        records.push(Record {
+            line,
+            address,
+            text: text.to_string(),
+        });
+    }
+    if !ended {
+        return Err(match format {
+            Format::Ihex => "No end-of-file record (:00000001FF)".to_string(),
+            Format::Srec => "No termination record (S7, S8 or S9)".to_string(),
+        });
+    }
+
+    spans.sort();
+    for pair in spans.windows(2) {
+        let ((_, end, first), (start, _, second)) = (pair[0], pair[1]);
+        if start <= end {
+            return Err(format!(
+                "Line {} writes {:08X}, which line {} already does",
+                second, start, first
+            ));
+        }
+    }
+    Ok(Image {
+        format,
+        records,
+        data_bytes: spans
+            .iter()
+            .map(|&(start, end, _)| (end - start) as u64 + 1)
+            .sum(),
+        span: spans
+            .first()
+            .map(|first| (first.0, spans.iter().map(|s| s.1).max().unwrap_or(first.1))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IHEX: &str = "\
+:020000040800F2
+:10000000000102030405060708090A0B0C0D0E0F78
+:0400100010111213A6
+
+:04000005080001D519
+:00000001FF
+";
+
+    const SREC: &str = "\
+S00F000068656C6C6F202020202000003C
+S11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000026
+S11F001C4BFFFFE5398000007D83637880010014382100107C0803A64E800020E9
+S5030002FA
+S9030000FC
+";
+
+    #[test]
+    fn test_parse() {
+        let image = parse(IHEX.as_bytes(), None).unwrap();
+        assert_eq!(image.format, Format::Ihex);
+        assert_eq!(image.records.len(), 5);
+        assert_eq!(image.records[1].address, Some(0x0800_0000));
+        // The blank line is skipped, and counted
+        assert_eq!(image.records[3].line, 5);
+        assert_eq!(image.data_bytes, 20);
+        assert_eq!(image.span, Some((0x0800_0000, 0x0800_0013)));
+        assert!(parse(IHEX.as_bytes(), Some((0x0800_0000, 0x0800_0013))).is_ok());
+
+        let image = parse(SREC.as_bytes(), None).unwrap();
+        assert_eq!(image.format, Format::Srec);
+        assert_eq!(image.records[2].address, Some(0x1c));
+        assert_eq!(image.data_bytes, 56);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |text: &str| parse(text.as_bytes(), None).unwrap_err();
+        assert_eq!(
+            err(&IHEX.replace(":0400100010111213A6", ":0400100010111213A3")),
+            "Line 3: checksum is A3, expected A6"
+        );
+        assert_eq!(
+            err(&IHEX.replace(":00000001FF\n", "")),
+            "No end-of-file record (:00000001FF)"
+        );
+        assert_eq!(
+            err(&format!("{}:00000001FF\n", IHEX)),
+            "Line 7: record after the end of the file"
+        );
+        assert_eq!(err(":0300000001020\n"), "Line 1: odd number of hex digits");
+        assert_eq!(
+            err(":0400000001020304\n"),
+            "Line 1: byte count doesn't match the record"
+        );
+        assert_eq!(
+            err("hello\n"),
+            "Line 1 is neither Intel HEX nor an S-record"
+        );
+        assert_eq!(
+            err(&IHEX.replace("\n\n", "\n\u{a0}\n")),
+            "Line 4: Intel HEX records start with ':'"
+        );
+        assert_eq!(
+            err(&SREC.replace("S5030002FA", "S5030003F9")),
+            "Line 4: record count is 3, but the file has 2 data records"
+        );
+        assert_eq!(
+            err(&SREC.replace("S9030000FC\n", "")),
+            "No termination record (S7, S8 or S9)"
+        );
+
+        // Out of range, and overlapping
+        assert_eq!(
+            parse(IHEX.as_bytes(), Some((0x0800_0000, 0x0800_000F))).unwrap_err(),
+            "Line 3: data at 08000010-08000013 is outside 08000000-0800000F"
+        );
+        let overlap = IHEX.replace(":0400100010111213A6", ":04000C0010111213AA");
+        assert_eq!(
+            err(&overlap),
+            "Line 3 writes 0800000C, which line 2 already does"
+        );
+    }
+}
@@ -0,0 +1,301 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use serde::Serialize;
+
+/// Budget for a single callback (or the top-level code on load). Scripts
+/// that exceed it are stopped with an error instead of hanging the tab.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Shortest `set_timer` interval accepted.
+const MIN_TIMER: Duration = Duration::from_millis(100);
+
+/// Largest file a script can write in one call.
+const MAX_FILE_WRITE: usize = 1024 * 1024;
+
+/// Something that happens on a tab a hook script is attached to.
+#[derive(Debug)]
+pub enum HookEvent {
+    Connect,
+    Disconnect,
+    /// A received line, without escape sequences or line ending
+    Line(String),
+    Timer,
+}
+
+impl HookEvent {
+    fn callback(&self) -> &'static str {
+        match self {
+            HookEvent::Connect => "on_connect",
+            HookEvent::Disconnect => "on_disconnect",
+            HookEvent::Line(_) => "on_line",
+            HookEvent::Timer => "on_timer",
+        }
+    }
+}
+
+/// Something a script asked for, carried out by the server once the
+/// callback returns.
+#[derive(Debug, PartialEq)]
+pub enum HookAction {
+    Send(Vec<u8>),
+    Print(String),
+    WriteFile {
+        name: String,
+        data: String,
+        append: bool,
+    },
+    /// `None` stops the timer
+    SetTimer(Option<Duration>),
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct HookStatus {
+    pub name: String,
+    pub running: bool,
+    pub callbacks: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+}
+
+/// A compiled hook script.
+///
+/// A script defines any of these functions; each is called when its
+/// event happens on the tab:
+///
+/// ```text
+/// fn on_connect() { this.lines = 0; sendline(""); }
+/// fn on_line(line) {
+///     this.lines += 1;
+///     if line.contains("login:") { sendline("root"); }
+/// }
+/// fn on_timer() { append_file("lines.txt", `${this.lines}\n`); }
+/// fn on_disconnect() { print("bye"); }
+/// set_timer(60000);
+/// ```
+///
+/// `this` is a map kept between callbacks. The functions scripts can call:
+///
+/// - `send(text)`, `sendline(text)` (appends `\r`): write to the connection
+/// - `print(value)`: show a line in the tab
+/// - `status()`: map with `tab_id`, `connected`, `type`, `port` and `logging`
+/// - `write_file(name, text)`, `append_file(name, text)`: files are kept in
+///   the app's `script-files` directory; `name` can't leave it
+/// - `set_timer(ms)`: call `on_timer` every `ms` milliseconds; 0 stops it
+///
+/// Scripts have no other access to the system, `eval` is disabled and
+/// each callback runs with an operation budget.
+pub struct HookScript {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    this: Dynamic,
+    actions: Arc<Mutex<Vec<HookAction>>>,
+    status: Arc<Mutex<Map>>,
+}
+
+fn rhai_error(message: String) -> Box<EvalAltResult> {
+    message.into()
+}
+
+fn sandboxed_engine(actions: &Arc<Mutex<Vec<HookAction>>>, status: &Arc<Mutex<Map>>) -> Engine {
+    let mut engine = Engine::new();
+    // `import` would otherwise load .rhai files from disk
+    engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(MAX_FILE_WRITE);
+    engine.set_max_array_size(10_000);
+    engine.set_max_map_size(10_000);
+    engine.disable_symbol("eval");
+
+    let a = actions.clone();
+    engine.on_print(move |s| a.lock().unwrap().push(HookAction::Print(s.to_string())));
+    engine.on_debug(|s, _, pos| tracing::debug!("Hook script {}: {}", pos, s));
+
+    let a = actions.clone();
+    engine.register_fn("send", move |text: &str| {
+        a.lock().unwrap().push(HookAction::Send(text.as_bytes().to_vec()));
+    });
+    let a = actions.clone();
+    engine.register_fn("sendline", move |text: &str| {
+        a.lock().unwrap().push(HookAction::Send(format!("{}\r", text).into_bytes()));
+    });
+    for (fn_name, append) in [("write_file", false), ("append_file", true)] {
+        let a = actions.clone();
+        engine.register_fn(fn_name, move |name: &str, data: &str| -> Result<(), Box<EvalAltResult>> {
+            check_file_name(name).map_err(rhai_error)?;
+            a.lock().unwrap().push(HookAction::WriteFile {
+                name: name.to_string(),
+                data: data.to_string(),
+                append,
+            });
+            Ok(())
+        });
+    }
+    let a = actions.clone();
+    engine.register_fn("set_timer", move |ms: i64| -> Result<(), Box<EvalAltResult>> {
+        let interval = match ms {
+            0 => None,
+            ms if ms < MIN_TIMER.as_millis() as i64 => {
+                return Err(rhai_error(format!(
+                    "Timer interval must be 0 or at least {} ms",
+                    MIN_TIMER.as_millis()
+                )));
+            }
+            ms => Some(Duration::from_millis(ms as u64)),
+        };
+        a.lock().unwrap().push(HookAction::SetTimer(interval));
+        Ok(())
+    });
+    let s = status.clone();
+    engine.register_fn("status", move || s.lock().unwrap().clone());
+
+    engine
+}
+
+/// Check that a file name given by a script stays inside the script files
+/// directory: relative, no `..`.
+pub fn check_file_name(name: &str) -> Result<(), String> {
+    let path = Path::new(name);
+    if name.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(format!("Invalid file name: {}", name));
+    }
+    Ok(())
+}
+
+/// Resolve a script file name inside `dir`.
+pub fn sandbox_path(dir: &Path, name: &str) -> Result<PathBuf, String> {
+    check_file_name(name)?;
+    Ok(dir.join(name))
+}
+
+impl HookScript {
+    pub fn compile(source: &str) -> Result<Self, String> {
+        let actions = Arc::new(Mutex::new(Vec::new()));
+        let status = Arc::new(Mutex::new(Map::new()));
+        let engine = sandboxed_engine(&actions, &status);
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        Ok(HookScript {
+            engine,
+            ast,
+            scope: Scope::new(),
+            this: Dynamic::from_map(Map::new()),
+            actions,
+            status,
+        })
+    }
+
+    /// Run the script's top-level code.
+    pub fn load(&mut self) -> Result<Vec<HookAction>, String> {
+        let result = self.engine.run_ast_with_scope(&mut self.scope, &self.ast);
+        let actions = self.take_actions();
+        result.map_err(|e| e.to_string())?;
+        Ok(actions)
+    }
+
+    /// Update what `status()` returns to the script.
+    pub fn set_status(&self, status: Map) {
+        *self.status.lock().unwrap() = status;
+    }
+
+    fn has_callback(&self, name: &str, params: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == params)
+    }
+
+    /// Call the callback for `event`, if the script defines one. Actions
+    /// queued before an error are still returned.
+    pub fn handle(&mut self, event: HookEvent) -> (Vec<HookAction>, Result<bool, String>) {
+        let name = event.callback();
+        let args: Vec<Dynamic> = match event {
+            HookEvent::Line(line) => vec![line.into()],
+            _ => Vec::new(),
+        };
+        if !self.has_callback(name, args.len()) {
+            return (Vec::new(), Ok(false));
+        }
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.this);
+        let result = self
+            .engine
+            .call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, name, args)
+            .map(|_| true)
+            .map_err(|e| format!("{}: {}", name, e));
+        (self.take_actions(), result)
+    }
+
+    fn take_actions(&self) -> Vec<HookAction> {
+        std::mem::take(&mut *self.actions.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_callbacks_and_state() {
+        let mut script = HookScript::compile(
+            r#"
+            fn on_connect() { this.count = 0; }
+            fn on_line(line) {
+                this.count += 1;
+                if line.contains("login:") { sendline("root"); }
+                print(`${this.count} ${status().port}`);
+            }
+            set_timer(1000);
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            script.load().unwrap(),
+            [HookAction::SetTimer(Some(Duration::from_secs(1)))]
+        );
+        let mut status = Map::new();
+        status.insert("port".into(), "ttyUSB0".into());
+        script.set_status(status);
+
+        assert_eq!(script.handle(HookEvent::Connect), (vec![], Ok(true)));
+        assert_eq!(script.handle(HookEvent::Timer), (vec![], Ok(false)));
+        script.handle(HookEvent::Line("boot".into())).1.unwrap();
+        let (actions, result) = script.handle(HookEvent::Line("host login: ".into()));
+        assert_eq!(result, Ok(true));
+        assert_eq!(
+            actions,
+            [
+                HookAction::Send(b"root\r".to_vec()),
+                HookAction::Print("2 ttyUSB0".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sandbox() {
+        assert!(HookScript::compile(r#"eval("1")"#).is_err());
+
+        let mut script = HookScript::compile("fn on_timer() { loop {} }").unwrap();
+        script.load().unwrap();
+        assert!(script.handle(HookEvent::Timer).1.is_err());
+
+        let mut script = HookScript::compile(r#"write_file("../x", "")"#).unwrap();
+        assert!(script.load().is_err());
+
+        let module = std::env::temp_dir().join(format!("serial-rs-hook-{}", std::process::id()));
+        std::fs::write(module.with_extension("rhai"), "export const X = 1;").unwrap();
+        let source = format!("import {:?} as m; print(m::X);", module.display().to_string());
+        let result = HookScript::compile(&source).and_then(|mut s| s.load());
+        let _ = std::fs::remove_file(module.with_extension("rhai"));
+        assert!(result.is_err());
+        assert!(sandbox_path(Path::new("/d"), "/etc/passwd").is_err());
+        assert_eq!(
+            sandbox_path(Path::new("/d"), "logs/a.txt").unwrap(),
+            Path::new("/d/logs/a.txt")
+        );
+    }
+}
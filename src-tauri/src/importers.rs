@@ -0,0 +1,713 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::sessions::{Session, SessionKind};
+
+/// Terminal programs whose saved sessions can be imported.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportSource {
+    Putty,
+    Minicom,
+    SecureCrt,
+}
+
+impl ImportSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            ImportSource::Putty => "PuTTY",
+            ImportSource::Minicom => "minicom",
+            ImportSource::SecureCrt => "SecureCRT",
+        }
+    }
+}
+
+/// A setting of an imported session that serial-rs can't represent.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Unsupported {
+    pub session: String,
+    pub option: String,
+    pub note: String,
+}
+
+/// An entry that produced no session at all.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Skipped {
+    pub name: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ConvertReport {
+    pub sessions: Vec<Session>,
+    pub unsupported: Vec<Unsupported>,
+    pub skipped: Vec<Skipped>,
+}
+
+impl ConvertReport {
+    fn unsupported(&mut self, session: &str, option: &str, note: impl Into<String>) {
+        self.unsupported.push(Unsupported {
+            session: session.to_string(),
+            option: option.to_string(),
+            note: note.into(),
+        });
+    }
+
+    fn skip(&mut self, name: &str, reason: impl Into<String>) {
+        self.skipped.push(Skipped {
+            name: name.to_string(),
+            reason: reason.into(),
+        });
+    }
+}
+
+/// Where each program keeps its sessions by default.
+pub fn default_paths(source: ImportSource) -> Vec<PathBuf> {
+    let home = dirs::home_dir().unwrap_or_default();
+    match source {
+        ImportSource::Putty => vec![home.join(".putty").join("sessions")],
+        ImportSource::Minicom => vec![home, PathBuf::from("/etc/minicom"), PathBuf::from("/etc")],
+        ImportSource::SecureCrt => {
+            let mut paths = Vec::new();
+            if cfg!(target_os = "macos") {
+                paths.push(home.join("Library/Application Support/VanDyke/SecureCRT/Config/Sessions"));
+            } else if cfg!(windows) {
+                if let Some(config) = dirs::config_dir() {
+                    paths.push(config.join("VanDyke").join("Config").join("Sessions"));
+                }
+            } else {
+                paths.push(home.join(".vandyke/SecureCRT/Config/Sessions"));
+            }
+            paths
+        }
+    }
+}
+
+/// Convert every session found under `paths` (files or directories).
+pub fn import(source: ImportSource, paths: &[PathBuf]) -> ConvertReport {
+    let mut report = ConvertReport::default();
+    for path in paths {
+        match source {
+            ImportSource::Putty => import_putty_path(path, &mut report),
+            ImportSource::Minicom => import_minicom_path(path, &mut report),
+            ImportSource::SecureCrt => import_securecrt_path(path, path, &mut report),
+        }
+    }
+    report
+}
+
+/// Read a text file, honoring a UTF-16LE or UTF-8 byte order mark (regedit
+/// exports UTF-16; SecureCRT writes UTF-8 with a BOM).
+fn read_text(path: &Path) -> std::io::Result<String> {
+    let bytes = std::fs::read(path)?;
+    if let Some(rest) = bytes.strip_prefix(&[0xff, 0xfe]) {
+        let units: Vec<u16> = rest
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        return Ok(String::from_utf16_lossy(&units));
+    }
+    let bytes = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]).unwrap_or(&bytes);
+    Ok(String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn sorted_entries(dir: &Path) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map(|rd| rd.flatten().map(|e| e.path()).collect())
+        .unwrap_or_default();
+    entries.sort();
+    entries
+}
+
+/// Serial parity names shared by PuTTY and SecureCRT's numeric encoding.
+fn parity_from_index(n: u32) -> Result<&'static str, &'static str> {
+    match n {
+        0 => Ok("none"),
+        1 => Ok("odd"),
+        2 => Ok("even"),
+        3 => Err("mark"),
+        _ => Err("space"),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// PuTTY
+// ---------------------------------------------------------------------------
+
+const PUTTY_REG_PREFIX: &str = "HKEY_CURRENT_USER\\Software\\SimonTatham\\PuTTY\\Sessions\\";
+
+/// Undo PuTTY's `%XX` escaping of session names.
+fn putty_unescape(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(b) = name
+                .get(i + 1..i + 3)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+            {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Parse a `regedit` export into `(session name, settings)` pairs. DWORD
+/// values are converted to decimal strings, like in PuTTY's Unix files.
+fn parse_putty_reg(text: &str) -> Vec<(String, HashMap<String, String>)> {
+    let mut sessions: Vec<(String, HashMap<String, String>)> = Vec::new();
+    let mut in_session = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(key) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_session = match key.strip_prefix(PUTTY_REG_PREFIX) {
+                Some(name) if !name.contains('\\') => {
+                    sessions.push((putty_unescape(name), HashMap::new()));
+                    true
+                }
+                _ => false,
+            };
+            continue;
+        }
+        if !in_session {
+            continue;
+        }
+        let Some((name, value)) = line.split_once('=') else {
+            continue;
+        };
+        let name = name.trim_matches('"').to_string();
+        let value = if let Some(dword) = value.strip_prefix("dword:") {
+            match u32::from_str_radix(dword, 16) {
+                Ok(n) => n.to_string(),
+                Err(_) => continue,
+            }
+        } else if let Some(s) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+            s.replace("\\\"", "\"").replace("\\\\", "\\")
+        } else {
+            continue;
+        };
+        if let Some((_, settings)) = sessions.last_mut() {
+            settings.insert(name, value);
+        }
+    }
+    sessions
+}
+
+/// Parse one session file from `~/.putty/sessions` (`Key=Value` lines).
+fn parse_putty_file(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn convert_putty(name: &str, settings: &HashMap<String, String>, report: &mut ConvertReport) {
+    if name == "Default Settings" {
+        report.skip(name, "PuTTY's default settings template");
+        return;
+    }
+    let get = |key: &str| settings.get(key).map(|s| s.as_str()).filter(|s| !s.is_empty());
+    let num = |key: &str| get(key).and_then(|s| s.parse::<u32>().ok());
+
+    let protocol = get("Protocol").unwrap_or("ssh");
+    let session = match protocol {
+        "ssh" => {
+            let Some(host) = get("HostName") else {
+                report.skip(name, "No host name");
+                return;
+            };
+            let mut session = Session::new(SessionKind::Ssh, name);
+            let (user, host) = match host.rsplit_once('@') {
+                Some((user, host)) => (Some(user), host),
+                None => (None, host),
+            };
+            session.host = Some(host.to_string());
+            session.ssh_port = Some(num("PortNumber").unwrap_or(22) as u16);
+            session.username = get("UserName").or(user).map(|s| s.to_string());
+            if let Some(key) = get("PublicKeyFile") {
+                session.key_file = Some(key.to_string());
+                if key.to_ascii_lowercase().ends_with(".ppk") {
+                    report.unsupported(
+                        name,
+                        "PublicKeyFile",
+                        "PuTTY .ppk keys must be converted to OpenSSH format (puttygen -O private-openssh)",
+                    );
+                }
+            }
+            if num("ProxyMethod").unwrap_or(0) != 0 {
+                report.unsupported(name, "ProxyMethod", "Proxy connections are not supported");
+            }
+            if get("PortForwardings").is_some() {
+                report.unsupported(name, "PortForwardings", "Port forwarding is not supported");
+            }
+            if num("X11Forward") == Some(1) {
+                report.unsupported(name, "X11Forward", "X11 forwarding is not supported");
+            }
+            if num("AgentFwd") == Some(1) {
+                report.unsupported(name, "AgentFwd", "Agent forwarding is not supported");
+            }
+            if get("RemoteCommand").is_some() {
+                report.unsupported(name, "RemoteCommand", "Remote commands are not supported");
+            }
+            session
+        }
+        "serial" => {
+            let Some(line) = get("SerialLine") else {
+                report.skip(name, "No serial line");
+                return;
+            };
+            let mut session = Session::new(SessionKind::Serial, name);
+            session.port = Some(line.to_string());
+            session.baud_rate = Some(num("SerialSpeed").unwrap_or(9600));
+            session.data_bits = Some(num("SerialDataBits").unwrap_or(8) as u8);
+            session.stop_bits = Some(match num("SerialStopHalfbits").unwrap_or(2) {
+                2 => 1,
+                3 => {
+                    report.unsupported(name, "SerialStopHalfbits", "1.5 stop bits; using 1");
+                    1
+                }
+                _ => 2,
+            });
+            session.parity = Some(
+                match parity_from_index(num("SerialParity").unwrap_or(0)) {
+                    Ok(p) => p,
+                    Err(p) => {
+                        report.unsupported(name, "SerialParity", format!("{} parity; using none", p));
+                        "none"
+                    }
+                }
+                .to_string(),
+            );
+            session.flow_control = Some(
+                match num("SerialFlowControl").unwrap_or(1) {
+                    0 => "none",
+                    1 => "software",
+                    2 => "hardware",
+                    _ => {
+                        report.unsupported(name, "SerialFlowControl", "DSR/DTR flow control; using none");
+                        "none"
+                    }
+                }
+                .to_string(),
+            );
+            session
+        }
+        other => {
+            report.skip(name, format!("Protocol {} is not supported", other));
+            return;
+        }
+    };
+
+    if num("LogType").unwrap_or(0) != 0 {
+        report.unsupported(name, "LogType", "Automatic session logging; start logging from the toolbar");
+    }
+    report.sessions.push(session);
+}
+
+fn import_putty_path(path: &Path, report: &mut ConvertReport) {
+    if path.is_dir() {
+        for entry in sorted_entries(path) {
+            if entry.is_file() {
+                import_putty_path(&entry, report);
+            }
+        }
+        return;
+    }
+    let text = match read_text(path) {
+        Ok(t) => t,
+        Err(e) => {
+            report.skip(&path.display().to_string(), e.to_string());
+            return;
+        }
+    };
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("reg")) {
+        for (name, settings) in parse_putty_reg(&text) {
+            convert_putty(&name, &settings, report);
+        }
+    } else {
+        convert_putty(&putty_unescape(&file_name(path)), &parse_putty_file(&text), report);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// minicom
+// ---------------------------------------------------------------------------
+
+/// Session name for a minicom config file, or `None` if it isn't one.
+fn minirc_name(file: &str) -> Option<String> {
+    let name = file
+        .strip_prefix(".minirc.")
+        .or_else(|| file.strip_prefix("minirc."))?;
+    Some(if name == "dfl" {
+        "minicom default".to_string()
+    } else {
+        format!("minicom {}", name)
+    })
+}
+
+fn convert_minirc(name: &str, text: &str, report: &mut ConvertReport) {
+    let mut session = Session::new(SessionKind::Serial, name);
+    let mut rtscts = false;
+    let mut xonxoff = false;
+    for line in text.lines() {
+        let Some(rest) = line.strip_prefix("pu ") else {
+            continue;
+        };
+        let rest = rest.trim();
+        let (key, value) = match rest.split_once(char::is_whitespace) {
+            Some((k, v)) => (k, v.trim()),
+            None => (rest, ""),
+        };
+        match key {
+            "port" => session.port = Some(value.to_string()),
+            "baudrate" => session.baud_rate = value.parse().ok(),
+            "bits" => session.data_bits = value.parse().ok(),
+            "stopbits" => session.stop_bits = value.parse().ok(),
+            "parity" => {
+                session.parity = Some(
+                    match value {
+                        "N" => "none",
+                        "O" => "odd",
+                        "E" => "even",
+                        other => {
+                            report.unsupported(name, "parity", format!("Parity {}; using none", other));
+                            "none"
+                        }
+                    }
+                    .to_string(),
+                )
+            }
+            "rtscts" => rtscts = value.eq_ignore_ascii_case("yes"),
+            "xonxoff" => xonxoff = value.eq_ignore_ascii_case("yes"),
+            _ => report.unsupported(name, key, format!("minicom option ({})", value)),
+        }
+    }
+
+    if rtscts && xonxoff {
+        report.unsupported(name, "xonxoff", "Both RTS/CTS and XON/XOFF set; using RTS/CTS");
+    }
+    session.flow_control = Some(
+        if rtscts {
+            "hardware"
+        } else if xonxoff {
+            "software"
+        } else {
+            "none"
+        }
+        .to_string(),
+    );
+
+    if session.port.is_none() {
+        report.skip(name, "No serial port set");
+        return;
+    }
+    report.sessions.push(session);
+}
+
+fn import_minicom_path(path: &Path, report: &mut ConvertReport) {
+    let files: Vec<PathBuf> = if path.is_dir() {
+        sorted_entries(path)
+            .into_iter()
+            .filter(|p| p.is_file() && minirc_name(&file_name(p)).is_some())
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    };
+    for file in files {
+        let file_name = file_name(&file);
+        let name = minirc_name(&file_name).unwrap_or(file_name);
+        match read_text(&file) {
+            Ok(text) => convert_minirc(&name, &text, report),
+            Err(e) => report.skip(&file.display().to_string(), e.to_string()),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// SecureCRT
+// ---------------------------------------------------------------------------
+
+/// Parse a SecureCRT session `.ini`. Keys keep their type prefix
+/// (`S:Hostname`, `D:Baud Rate`); DWORDs become decimal strings and binary
+/// values their byte count. Continuation lines of multi-line values are
+/// skipped.
+fn parse_securecrt(text: &str) -> HashMap<String, String> {
+    let mut settings = HashMap::new();
+    for line in text.lines() {
+        let bytes = line.as_bytes();
+        if bytes.len() < 4 || bytes[1] != b':' || bytes[2] != b'"' {
+            continue;
+        }
+        let kind = bytes[0] as char;
+        let Some((key, value)) = line[3..].split_once("\"=") else {
+            continue;
+        };
+        let value = match kind {
+            'D' | 'B' | 'Z' => match u32::from_str_radix(value.trim(), 16) {
+                Ok(n) => n.to_string(),
+                Err(_) => continue,
+            },
+            _ => value.to_string(),
+        };
+        settings.insert(format!("{}:{}", kind, key), value);
+    }
+    settings
+}
+
+fn convert_securecrt(
+    name: &str,
+    folder: Option<String>,
+    settings: &HashMap<String, String>,
+    report: &mut ConvertReport,
+) {
+    let get = |key: &str| settings.get(key).map(|s| s.as_str()).filter(|s| !s.is_empty());
+    let num = |key: &str| get(key).and_then(|s| s.parse::<u32>().ok());
+
+    let protocol = get("S:Protocol Name").unwrap_or("SSH2");
+    let mut session = match protocol {
+        "SSH2" => {
+            let Some(host) = get("S:Hostname") else {
+                report.skip(name, "No host name");
+                return;
+            };
+            let mut session = Session::new(SessionKind::Ssh, name);
+            session.host = Some(host.to_string());
+            session.ssh_port = Some(num("D:[SSH2] Port").unwrap_or(22) as u16);
+            session.username = get("S:Username").map(|s| s.to_string());
+            if let Some(key) = get("S:Identity Filename V2") {
+                // Stored as "<path>::rawkey" or "<path>::<format>"
+                let path = key.split("::").next().unwrap_or(key);
+                if !path.is_empty() {
+                    session.key_file = Some(path.to_string());
+                }
+            }
+            if get("S:Password V2").is_some() || get("S:Password").is_some() {
+                report.unsupported(name, "Password", "Saved passwords are encrypted and were not imported");
+            }
+            if get("S:Firewall Name").is_some_and(|f| f != "None") {
+                report.unsupported(name, "Firewall Name", "Firewall/proxy connections are not supported");
+            }
+            if num("B:Port Forward Table V2").unwrap_or(0) > 0 {
+                report.unsupported(name, "Port Forward Table V2", "Port forwarding is not supported");
+            }
+            if num("D:Forward X11") == Some(1) {
+                report.unsupported(name, "Forward X11", "X11 forwarding is not supported");
+            }
+            if num("D:Enable Agent Forwarding") == Some(1) {
+                report.unsupported(name, "Enable Agent Forwarding", "Agent forwarding is not supported");
+            }
+            session
+        }
+        "Serial" => {
+            let Some(port) = get("S:Com Port") else {
+                report.skip(name, "No serial port");
+                return;
+            };
+            let mut session = Session::new(SessionKind::Serial, name);
+            session.port = Some(port.to_string());
+            session.baud_rate = Some(num("D:Baud Rate").unwrap_or(9600));
+            session.data_bits = Some(num("D:Data Bits").unwrap_or(8) as u8);
+            session.stop_bits = Some(match num("D:Stop Bits").unwrap_or(0) {
+                0 => 1,
+                1 => {
+                    report.unsupported(name, "Stop Bits", "1.5 stop bits; using 1");
+                    1
+                }
+                _ => 2,
+            });
+            session.parity = Some(
+                match parity_from_index(num("D:Parity").unwrap_or(0)) {
+                    Ok(p) => p,
+                    Err(p) => {
+                        report.unsupported(name, "Parity", format!("{} parity; using none", p));
+                        "none"
+                    }
+                }
+                .to_string(),
+            );
+            let cts = num("D:CTS Flow") == Some(1);
+            let xon = num("D:XON Flow") == Some(1);
+            if num("D:DSR Flow") == Some(1) {
+                report.unsupported(name, "DSR Flow", "DSR/DTR flow control is not supported");
+            }
+            session.flow_control = Some(
+                if cts {
+                    "hardware"
+                } else if xon {
+                    "software"
+                } else {
+                    "none"
+                }
+                .to_string(),
+            );
+            session
+        }
+        "SSH1" => {
+            report.skip(name, "SSH1 is not supported");
+            return;
+        }
+        other => {
+            report.skip(name, format!("Protocol {} is not supported", other));
+            return;
+        }
+    };
+
+    if num("D:Use Login Script") == Some(1) {
+        report.unsupported(name, "Login Script", "Login scripts are not imported");
+    }
+    if num("D:Start Log Upon Connect") == Some(1) {
+        report.unsupported(name, "Start Log Upon Connect", "Automatic session logging; start logging from the toolbar");
+    }
+    session.folder = folder;
+    report.sessions.push(session);
+}
+
+/// Import `path` (a session file or a directory walked recursively). Folder
+/// names come from the directory layout below `root`.
+fn import_securecrt_path(root: &Path, path: &Path, report: &mut ConvertReport) {
+    if path.is_dir() {
+        for entry in sorted_entries(path) {
+            import_securecrt_path(root, &entry, report);
+        }
+        return;
+    }
+    if !path.extension().is_some_and(|e| e.eq_ignore_ascii_case("ini")) {
+        return;
+    }
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // Folder metadata and the template for new sessions
+    if name == "__FolderData__" || name == "Default" {
+        return;
+    }
+    let folder = path
+        .parent()
+        .and_then(|p| p.strip_prefix(root).ok())
+        .map(|p| {
+            p.components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .filter(|f| !f.is_empty());
+    match read_text(path) {
+        Ok(text) => convert_securecrt(&name, folder, &parse_securecrt(&text), report),
+        Err(e) => report.skip(&path.display().to_string(), e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_putty_reg() {
+        let text = "Windows Registry Editor Version 5.00\r\n\r\n\
+            [HKEY_CURRENT_USER\\Software\\SimonTatham\\PuTTY\\Sessions\\my%20server]\r\n\
+            \"HostName\"=\"admin@example.com\"\r\n\
+            \"PortNumber\"=dword:00000016\r\n\
+            \"Protocol\"=\"ssh\"\r\n\
+            \"PublicKeyFile\"=\"C:\\\\keys\\\\id.ppk\"\r\n\
+            \r\n\
+            [HKEY_CURRENT_USER\\Software\\SimonTatham\\PuTTY\\Sessions\\board]\r\n\
+            \"Protocol\"=\"serial\"\r\n\
+            \"SerialLine\"=\"COM3\"\r\n\
+            \"SerialSpeed\"=dword:0001c200\r\n\
+            \"SerialParity\"=dword:00000003\r\n\
+            \"SerialFlowControl\"=dword:00000000\r\n\
+            \r\n\
+            [HKEY_CURRENT_USER\\Software\\SimonTatham\\PuTTY\\Sessions\\old]\r\n\
+            \"Protocol\"=\"telnet\"\r\n";
+        let mut report = ConvertReport::default();
+        for (name, settings) in parse_putty_reg(text) {
+            convert_putty(&name, &settings, &mut report);
+        }
+
+        assert_eq!(report.sessions.len(), 2);
+        let ssh = &report.sessions[0];
+        assert_eq!(ssh.name, "my server");
+        assert_eq!(ssh.host.as_deref(), Some("example.com"));
+        assert_eq!(ssh.username.as_deref(), Some("admin"));
+        assert_eq!(ssh.ssh_port, Some(22));
+        assert_eq!(ssh.key_file.as_deref(), Some("C:\\keys\\id.ppk"));
+
+        let serial = &report.sessions[1];
+        assert_eq!(serial.port.as_deref(), Some("COM3"));
+        assert_eq!(serial.baud_rate, Some(115200));
+        assert_eq!(serial.parity.as_deref(), Some("none"));
+        assert_eq!(serial.flow_control.as_deref(), Some("none"));
+
+        let options: Vec<&str> = report.unsupported.iter().map(|u| u.option.as_str()).collect();
+        assert_eq!(options, vec!["PublicKeyFile", "SerialParity"]);
+        assert_eq!(report.skipped[0].name, "old");
+    }
+
+    #[test]
+    fn test_minirc() {
+        let text = "# Machine-generated file - use \"minicom -s\" to change parameters.\n\
+            pu port             /dev/ttyUSB0\n\
+            pu baudrate         57600\n\
+            pu bits             7\n\
+            pu parity           E\n\
+            pu stopbits         2\n\
+            pu rtscts           No\n\
+            pu xonxoff          Yes\n\
+            pu zauto            \n";
+        let mut report = ConvertReport::default();
+        convert_minirc("minicom usb", text, &mut report);
+        let s = &report.sessions[0];
+        assert_eq!(s.port.as_deref(), Some("/dev/ttyUSB0"));
+        assert_eq!((s.baud_rate, s.data_bits, s.stop_bits), (Some(57600), Some(7), Some(2)));
+        assert_eq!(s.parity.as_deref(), Some("even"));
+        assert_eq!(s.flow_control.as_deref(), Some("software"));
+        assert_eq!(report.unsupported[0].option, "zauto");
+        assert_eq!(minirc_name(".minirc.dfl").as_deref(), Some("minicom default"));
+        assert_eq!(minirc_name("profile"), None);
+    }
+
+    #[test]
+    fn test_securecrt() {
+        let text = "S:\"Protocol Name\"=Serial\n\
+            S:\"Com Port\"=COM4\n\
+            D:\"Baud Rate\"=0001c200\n\
+            D:\"Data Bits\"=00000008\n\
+            D:\"Parity\"=00000002\n\
+            D:\"Stop Bits\"=00000000\n\
+            D:\"CTS Flow\"=00000001\n\
+            B:\"Keymap\"=00000002\n 01 02\n\
+            Z:\"Description\"=00000001\n some text\n";
+        let mut report = ConvertReport::default();
+        convert_securecrt("lab board", Some("Lab/Rack 1".to_string()), &parse_securecrt(text), &mut report);
+        let s = &report.sessions[0];
+        assert_eq!(s.kind, SessionKind::Serial);
+        assert_eq!(s.port.as_deref(), Some("COM4"));
+        assert_eq!(s.baud_rate, Some(115200));
+        assert_eq!(s.parity.as_deref(), Some("even"));
+        assert_eq!(s.flow_control.as_deref(), Some("hardware"));
+        assert_eq!(s.folder.as_deref(), Some("Lab/Rack 1"));
+
+        let ssh = "S:\"Hostname\"=10.0.0.5\nD:\"[SSH2] Port\"=00000b1e\nS:\"Username\"=root\n\
+            S:\"Password V2\"=02:abcdef\nS:\"Identity Filename V2\"=/home/u/.ssh/id_ed25519::rawkey\n";
+        convert_securecrt("gw", None, &parse_securecrt(ssh), &mut report);
+        let s = &report.sessions[1];
+        assert_eq!(s.ssh_port, Some(2846));
+        assert_eq!(s.key_file.as_deref(), Some("/home/u/.ssh/id_ed25519"));
+        assert_eq!(report.unsupported[0].option, "Password");
+    }
+}
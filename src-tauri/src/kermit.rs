@@ -0,0 +1,1246 @@
+//! Kermit file transfer, with long packets and sliding windows when the
+//! other side supports them.
+//!
+//! A packet is `SOH LEN SEQ TYPE DATA CHECK CR`, every field but the mark
+//! and end made of printable characters. Control characters in the data
+//! are quoted with a prefix, and so are 8-bit bytes if the link needs it.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::xmodem::{Event, Link, Transferred};
+
+const SOH: u8 = 0x01;
+const CR: u8 = 0x0d;
+
+/// Longest packet without the long packet extension
+const MAX_SHORT: usize = 94;
+/// Longest long packet we take
+const MAX_LONG: usize = 9024;
+/// Packets in flight before waiting for ACKs; sequence numbers wrap at 64
+const MAX_WINDOW: usize = 31;
+
+/// Capabilities in the send-init packet
+const CAP_LONG: u8 = 2;
+const CAP_WINDOWS: u8 = 4;
+const CAP_ATTRIBUTES: u8 = 8;
+
+/// Attempts per packet before giving up.
+const MAX_RETRIES: usize = 10;
+/// How long a sender waits for the receiver to start.
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often the send-init packet (or a receiver's NAK) is repeated.
+const START_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the other side should wait for us.
+const OUR_TIMEOUT: u8 = 10;
+
+fn tochar(n: usize) -> u8 {
+    (n as u8).wrapping_add(32)
+}
+
+fn unchar(c: u8) -> usize {
+    c.wrapping_sub(32) as usize
+}
+
+fn ctl(c: u8) -> u8 {
+    c ^ 64
+}
+
+/// The one-character checksum, also the long packet header check.
+fn check1(data: &[u8]) -> u8 {
+    let sum: usize = data.iter().map(|&b| b as usize).sum();
+    tochar((sum + ((sum & 0xc0) >> 6)) & 0x3f)
+}
+
+/// CRC-16/KERMIT (CCITT polynomial, bit-reversed, zero start)
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in data {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x8408 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// Block check of `data` (from LEN to the end of the data field).
+fn block_check(data: &[u8], check: u8) -> Vec<u8> {
+    match check {
+        2 => {
+            let sum: usize = data.iter().map(|&b| b as usize).sum();
+            vec![tochar((sum >> 6) & 0x3f), tochar(sum & 0x3f)]
+        }
+        3 => {
+            let crc = crc16(data) as usize;
+            vec![tochar((crc >> 12) & 0x0f), tochar((crc >> 6) & 0x3f), tochar(crc & 0x3f)]
+        }
+        _ => vec![check1(data)],
+    }
+}
+
+fn packet(seq: u8, typ: u8, data: &[u8], check: u8) -> Vec<u8> {
+    let len = data.len() + check as usize;
+    let mut p = Vec::with_capacity(len + 8);
+    p.push(SOH);
+    if len + 2 <= MAX_SHORT {
+        p.extend([tochar(len + 2), tochar(seq as usize), typ]);
+    } else {
+        p.extend([tochar(0), tochar(seq as usize), typ, tochar(len / 95), tochar(len % 95)]);
+        p.push(check1(&p[1..]));
+    }
+    p.extend_from_slice(data);
+    let check = block_check(&p[1..], check);
+    p.extend(check);
+    p.push(CR);
+    p
+}
+
+/// An error packet the other side takes whatever block check it uses, for
+/// when the transfer is cancelled from outside.
+pub fn abort_sequence() -> Vec<u8> {
+    let mut out = packet(0, b'E', b"Cancelled", 1);
+    out.extend(packet(0, b'E', b"Cancelled", 3));
+    out
+}
+
+#[derive(Debug, PartialEq)]
+struct Packet {
+    seq: u8,
+    typ: u8,
+    data: Vec<u8>,
+}
+
+enum Incoming {
+    Packet(Packet),
+    /// Damaged in transit
+    Bad,
+    Timeout,
+}
+
+enum Fill {
+    Done,
+    /// Another packet started
+    Mark,
+    Timeout,
+}
+
+/// A send-init packet found in terminal output.
+#[derive(Debug, PartialEq)]
+pub struct Detected {
+    /// Where the packet starts in the data fed last; 0 if it began in an
+    /// earlier read
+    pub offset: usize,
+    /// The packet and whatever followed it
+    pub packet: Vec<u8>,
+}
+
+/// Finds a Kermit sender starting (its send-init packet, sequence 0) in
+/// connection output, even when it is split across reads. Only a whole
+/// packet with the right length and checksum counts, so stray control
+/// characters don't take over the terminal.
+#[derive(Default)]
+pub struct Detector {
+    held: Vec<u8>,
+}
+
+impl Detector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, data: &[u8]) -> Option<Detected> {
+        let held = self.held.len();
+        let mut stream = std::mem::take(&mut self.held);
+        stream.extend_from_slice(data);
+        for pos in (0..stream.len()).filter(|&i| stream[i] == SOH) {
+            let Some(&len) = stream.get(pos + 1) else {
+                self.held = stream[pos..].to_vec();
+                return None;
+            };
+            if !(b'#'..=b'~').contains(&len) {
+                continue;
+            }
+            // LEN counts what follows it, through the check
+            let end = pos + 2 + unchar(len);
+            let fields = &stream[pos + 1..end.min(stream.len())];
+            // Sequence 0, type S, and printable throughout
+            let header_ok = fields.get(1).is_none_or(|&c| c == b' ')
+                && fields.get(2).is_none_or(|&c| c == b'S');
+            if !header_ok || !fields.iter().all(|c| (b' '..=b'~').contains(c)) {
+                continue;
+            }
+            if stream.len() < end {
+                self.held = stream[pos..].to_vec();
+                return None;
+            }
+            // The send-init packet always has a one-character check
+            if check1(&stream[pos + 1..end - 1]) == stream[end - 1] {
+                return Some(Detected {
+                    offset: pos.saturating_sub(held),
+                    packet: stream[pos..].to_vec(),
+                });
+            }
+        }
+        None
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Encoding
+// ---------------------------------------------------------------------------
+
+/// Prefixes in use for data one side sends.
+#[derive(Clone, Copy)]
+struct Quoting {
+    qctl: u8,
+    qbin: Option<u8>,
+    rept: Option<u8>,
+}
+
+impl Quoting {
+    fn encode_byte(&self, b: u8, out: &mut Vec<u8>) {
+        let mut c = b;
+        if let Some(qbin) = self.qbin.filter(|_| b & 0x80 != 0) {
+            out.push(qbin);
+            c &= 0x7f;
+        }
+        let low = c & 0x7f;
+        if low < 32 || low == 127 {
+            out.extend([self.qctl, ctl(c)]);
+        } else if low == self.qctl || Some(low) == self.qbin || Some(low) == self.rept {
+            out.extend([self.qctl, c]);
+        } else {
+            out.push(c);
+        }
+    }
+
+    /// Encode as much of `data` as fits in `room` characters. Returns the
+    /// encoded field and how many bytes it holds.
+    fn encode(&self, data: &[u8], room: usize) -> (Vec<u8>, usize) {
+        let mut out = Vec::with_capacity(room.min(data.len() * 2));
+        let mut used = 0;
+        let mut one = Vec::with_capacity(4);
+        while used < data.len() {
+            let b = data[used];
+            let run = match self.rept {
+                Some(_) => data[used..].iter().take(94).take_while(|&&c| c == b).count(),
+                None => 1,
+            };
+            one.clear();
+            self.encode_byte(b, &mut one);
+            let (count, len) = if run > 2 { (run, one.len() + 2) } else { (1, one.len()) };
+            if out.len() + len > room {
+                break;
+            }
+            if let (true, Some(rept)) = (count > 1, self.rept) {
+                out.extend([rept, tochar(count)]);
+            }
+            out.extend_from_slice(&one);
+            used += count;
+        }
+        (out, used)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let bad = || "Bad data in a packet".to_string();
+        let mut out = Vec::with_capacity(data.len());
+        let mut it = data.iter().copied();
+        while let Some(mut c) = it.next() {
+            let mut count = 1;
+            if Some(c) == self.rept {
+                count = unchar(it.next().ok_or_else(bad)?);
+                c = it.next().ok_or_else(bad)?;
+            }
+            let mut high = 0;
+            if Some(c) == self.qbin {
+                high = 0x80;
+                c = it.next().ok_or_else(bad)?;
+            }
+            if c == self.qctl {
+                c = it.next().ok_or_else(bad)?;
+                if (63..=95).contains(&(c & 0x7f)) {
+                    c = ctl(c);
+                }
+            }
+            out.extend(std::iter::repeat_n(c | high, count));
+        }
+        Ok(out)
+    }
+}
+
+impl Quoting {
+    /// A whole name or message, in one field.
+    fn encode_text(&self, text: &str) -> Vec<u8> {
+        self.encode(text.as_bytes(), usize::MAX).0
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Parameters
+// ---------------------------------------------------------------------------
+
+/// What one side says about itself in its send-init packet or the ACK
+/// to it.
+#[derive(Clone, Debug, PartialEq)]
+struct Params {
+    /// Longest packet it takes
+    maxl: usize,
+    /// Seconds to wait for it
+    time: u64,
+    qctl: u8,
+    /// `Y`/`N`, or the 8-bit prefix it wants
+    qbin: u8,
+    check: u8,
+    rept: u8,
+    capabilities: u8,
+    window: usize,
+    /// Longest long packet it takes
+    maxlx: usize,
+}
+
+impl Params {
+    fn ours(check: u8, rept: u8, window: usize) -> Self {
+        Params {
+            maxl: MAX_SHORT,
+            time: OUR_TIMEOUT as u64,
+            qctl: b'#',
+            qbin: b'Y',
+            check,
+            rept,
+            capabilities: CAP_LONG | CAP_WINDOWS | CAP_ATTRIBUTES,
+            window,
+            maxlx: MAX_LONG,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        vec![
+            tochar(self.maxl),
+            tochar(self.time as usize),
+            tochar(0),
+            ctl(0),
+            tochar(CR as usize),
+            self.qctl,
+            self.qbin,
+            b'0' + self.check,
+            self.rept,
+            tochar(self.capabilities as usize),
+            tochar(self.window),
+            tochar(self.maxlx / 95),
+            tochar(self.maxlx % 95),
+        ]
+    }
+
+    /// Missing fields take the protocol's defaults.
+    fn parse(data: &[u8]) -> Self {
+        let field = |i: usize| data.get(i).copied().filter(|&c| c != b' ');
+        let mut params = Params {
+            maxl: field(0).map(unchar).filter(|&n| n >= 10).unwrap_or(80),
+            time: field(1).map(unchar).unwrap_or(0) as u64,
+            qctl: field(5).unwrap_or(b'#'),
+            qbin: field(6).unwrap_or(b'N'),
+            check: field(7)
+                .filter(|c| (b'1'..=b'3').contains(c))
+                .map(|c| c - b'0')
+                .unwrap_or(1),
+            rept: field(8).unwrap_or(b' '),
+            capabilities: 0,
+            window: 1,
+            maxlx: 500,
+        };
+        // Capability bytes continue while the lowest bit is set
+        let mut i = 9;
+        while let Some(c) = data.get(i) {
+            let bits = unchar(*c) as u8;
+            if i == 9 {
+                params.capabilities = bits;
+            }
+            i += 1;
+            if bits & 1 == 0 {
+                break;
+            }
+        }
+        if let Some(window) = field(i).map(unchar).filter(|&w| w >= 1) {
+            params.window = window.min(MAX_WINDOW);
+        }
+        if let (Some(x1), Some(x2)) = (field(i + 1), field(i + 2)) {
+            let maxlx = unchar(x1) * 95 + unchar(x2);
+            if maxlx >= 10 {
+                params.maxlx = maxlx;
+            }
+        }
+        params
+    }
+}
+
+/// Settings both sides agreed on.
+struct Agreed {
+    check: u8,
+    /// How we prefix what we send
+    ours: Quoting,
+    /// How the other side prefixes what it sends
+    theirs: Quoting,
+    /// Longest packet we may send
+    send_max: usize,
+    window: usize,
+    attributes: bool,
+    timeout: Duration,
+}
+
+fn agree(ours: &Params, theirs: &Params) -> Agreed {
+    let both = |cap: u8| ours.capabilities & theirs.capabilities & cap != 0;
+    let is_prefix = |c: u8| (33..=62).contains(&c) || (96..=126).contains(&c);
+    let qbin = match (ours.qbin, theirs.qbin) {
+        (a, b'Y') if is_prefix(a) => Some(a),
+        (b'Y', b) if is_prefix(b) => Some(b),
+        (a, b) if a == b && is_prefix(a) => Some(a),
+        _ => None,
+    };
+    let rept = Some(ours.rept).filter(|&c| c == theirs.rept && is_prefix(c) && c != ours.qctl);
+    Agreed {
+        check: if ours.check == theirs.check { ours.check } else { 1 },
+        ours: Quoting {
+            qctl: ours.qctl,
+            qbin,
+            rept,
+        },
+        theirs: Quoting {
+            qctl: theirs.qctl,
+            qbin,
+            rept,
+        },
+        send_max: if both(CAP_LONG) {
+            theirs.maxlx.min(MAX_LONG)
+        } else {
+            theirs.maxl.min(MAX_SHORT)
+        },
+        window: if both(CAP_WINDOWS) {
+            ours.window.min(theirs.window).max(1)
+        } else {
+            1
+        },
+        attributes: both(CAP_ATTRIBUTES),
+        timeout: Duration::from_secs(theirs.time.clamp(1, 60)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Session
+// ---------------------------------------------------------------------------
+
+struct Session<'a> {
+    link: &'a mut Link,
+    agreed: Agreed,
+    /// Sequence number of the next packet we send
+    seq: u8,
+    /// Set when the other side sent an error packet, which needs no answer
+    remote_failed: bool,
+}
+
+impl<'a> Session<'a> {
+    fn new(link: &'a mut Link) -> Self {
+        let defaults = Params::parse(&[]);
+        Session {
+            link,
+            agreed: agree(&defaults, &defaults),
+            seq: 0,
+            remote_failed: false,
+        }
+    }
+
+    async fn send(&self, seq: u8, typ: u8, data: &[u8]) -> Result<(), String> {
+        self.link.send(packet(seq, typ, data, self.agreed.check)).await
+    }
+
+    /// Read the next packet, skipping whatever comes between packets.
+    async fn read(&mut self, timeout: Duration) -> Result<Incoming, String> {
+        let mut marked = false;
+        loop {
+            if !marked {
+                match self.link.byte(timeout).await? {
+                    None => return Ok(Incoming::Timeout),
+                    Some(SOH) => {}
+                    Some(_) => continue,
+                }
+            }
+            let mut header = Vec::with_capacity(6);
+            match self.fill(&mut header, 3, timeout).await? {
+                Fill::Done => {}
+                Fill::Mark => {
+                    marked = true;
+                    continue;
+                }
+                Fill::Timeout => return Ok(Incoming::Timeout),
+            }
+            let len = unchar(header[0]);
+            let rest = if len == 0 {
+                // Long packet: extended length and its check
+                match self.fill(&mut header, 6, timeout).await? {
+                    Fill::Done => {}
+                    Fill::Mark => {
+                        marked = true;
+                        continue;
+                    }
+                    Fill::Timeout => return Ok(Incoming::Timeout),
+                }
+                if check1(&header[..5]) != header[5] {
+                    return Ok(Incoming::Bad);
+                }
+                unchar(header[3]) * 95 + unchar(header[4])
+            } else if len >= 2 {
+                len - 2
+            } else {
+                return Ok(Incoming::Bad);
+            };
+            let mut body = Vec::with_capacity(rest);
+            match self.fill(&mut body, rest, timeout).await? {
+                Fill::Done => {}
+                Fill::Mark => {
+                    marked = true;
+                    continue;
+                }
+                Fill::Timeout => return Ok(Incoming::Timeout),
+            }
+
+            let typ = header[2];
+            // The send-init always has the one-character check
+            let check = if typ == b'S' { 1 } else { self.agreed.check };
+            let check_len = check as usize;
+            if body.len() < check_len {
+                return Ok(Incoming::Bad);
+            }
+            let (data, received) = body.split_at(body.len() - check_len);
+            let mut checked = header;
+            checked.extend_from_slice(data);
+            if block_check(&checked, check) != received {
+                return Ok(Incoming::Bad);
+            }
+            return Ok(Incoming::Packet(Packet {
+                seq: unchar(checked[1]) as u8 & 63,
+                typ,
+                data: data.to_vec(),
+            }));
+        }
+    }
+
+    /// Read until `buf` holds `len` bytes. A mark in between starts a new
+    /// packet, as one never appears inside a packet.
+    async fn fill(&mut self, buf: &mut Vec<u8>, len: usize, timeout: Duration) -> Result<Fill, String> {
+        while buf.len() < len {
+            match self.link.byte(timeout).await? {
+                None => return Ok(Fill::Timeout),
+                Some(SOH) => return Ok(Fill::Mark),
+                Some(c) => buf.push(c),
+            }
+        }
+        Ok(Fill::Done)
+    }
+
+    /// What an error packet from the other side says.
+    fn remote_error(&mut self, packet: &Packet) -> String {
+        self.remote_failed = true;
+        let message = self.agreed.theirs.decode(&packet.data).unwrap_or_default();
+        match String::from_utf8_lossy(&message).trim() {
+            "" => "The other side gave up".to_string(),
+            message => format!("The other side gave up: {}", message),
+        }
+    }
+
+    /// Tell the other side why we gave up, unless it was the one to.
+    async fn fail(&mut self, message: &str) {
+        if !self.remote_failed {
+            let _ = self.send(self.seq, b'E', &self.agreed.ours.encode_text(message)).await;
+        }
+    }
+
+    /// Send a packet and wait for its ACK, which is returned.
+    async fn exchange(&mut self, typ: u8, data: &[u8]) -> Result<Vec<u8>, String> {
+        let seq = self.seq;
+        let next = (seq + 1) % 64;
+        for _ in 0..MAX_RETRIES {
+            self.send(seq, typ, data).await?;
+            loop {
+                match self.read(self.agreed.timeout).await? {
+                    Incoming::Packet(p) if p.typ == b'Y' && p.seq == seq => {
+                        self.seq = next;
+                        return Ok(p.data);
+                    }
+                    // A NAK for the next packet means this one arrived
+                    Incoming::Packet(p) if p.typ == b'N' && p.seq == next => {
+                        self.seq = next;
+                        return Ok(Vec::new());
+                    }
+                    Incoming::Packet(p) if p.typ == b'E' => return Err(self.remote_error(&p)),
+                    Incoming::Packet(p) if p.typ == b'N' => break,
+                    Incoming::Timeout => break,
+                    // Stale ACKs and damaged packets
+                    _ => {}
+                }
+            }
+        }
+        Err("The receiver stopped responding".to_string())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Sender
+// ---------------------------------------------------------------------------
+
+/// A data packet waiting for its ACK.
+struct InFlight {
+    seq: u8,
+    packet: Vec<u8>,
+    bytes: usize,
+    acked: bool,
+    retries: usize,
+}
+
+/// Send `files` (name and contents) to a Kermit receiver.
+pub async fn send(
+    files: &[(String, Vec<u8>)],
+    link: &mut Link,
+    mut on_event: impl FnMut(Event),
+) -> Result<Vec<Transferred>, String> {
+    let mut session = Session::new(link);
+    let result = send_files(&mut session, files, &mut on_event).await;
+    if let Err(e) = &result {
+        session.fail(e).await;
+    }
+    result
+}
+
+async fn send_files(
+    session: &mut Session<'_>,
+    files: &[(String, Vec<u8>)],
+    on_event: &mut impl FnMut(Event),
+) -> Result<Vec<Transferred>, String> {
+    // Repeat the send-init until the receiver is ready
+    let ours = Params::ours(3, b'~', MAX_WINDOW);
+    let init = packet(0, b'S', &ours.encode(), 1);
+    let deadline = tokio::time::Instant::now() + START_TIMEOUT;
+    let theirs = 'init: loop {
+        if tokio::time::Instant::now() >= deadline {
+            return Err("The receiver didn't start".to_string());
+        }
+        session.link.send(init.clone()).await?;
+        let wait = tokio::time::Instant::now() + START_INTERVAL;
+        loop {
+            let left = wait.saturating_duration_since(tokio::time::Instant::now());
+            match session.read(left).await? {
+                Incoming::Packet(p) if p.typ == b'Y' && p.seq == 0 => break 'init Params::parse(&p.data),
+                Incoming::Packet(p) if p.typ == b'E' => return Err(session.remote_error(&p)),
+                Incoming::Timeout => break,
+                _ => {}
+            }
+        }
+    };
+    session.agreed = agree(&ours, &theirs);
+    session.seq = 1;
+
+    let mut sent = Vec::new();
+    for (name, data) in files {
+        let ack = session.exchange(b'F', &session.agreed.ours.encode_text(name)).await?;
+        let remote_name = String::from_utf8_lossy(&session.agreed.theirs.decode(&ack)?).to_string();
+        if !remote_name.is_empty() && remote_name != *name {
+            tracing::info!("Kermit: {} is saved as {}", name, remote_name);
+        }
+        on_event(Event::FileStart {
+            name: name.clone(),
+            size: Some(data.len() as u64),
+        });
+        if session.agreed.attributes {
+            // Length in bytes
+            let size = data.len().to_string();
+            let mut attributes = vec![b'1', tochar(size.len())];
+            attributes.extend(size.as_bytes());
+            let ack = session.exchange(b'A', &attributes).await?;
+            if ack.first() == Some(&b'N') {
+                return Err(format!("The receiver refused {}", name));
+            }
+        }
+        send_data(session, name, data, on_event).await?;
+        session.exchange(b'Z', b"").await?;
+        on_event(Event::FileDone {
+            name: name.clone(),
+            bytes: data.len() as u64,
+        });
+        sent.push(Transferred {
+            name: name.clone(),
+            size: data.len() as u64,
+            path: None,
+        });
+    }
+    session.exchange(b'B', b"").await?;
+    Ok(sent)
+}
+
+/// Send a file's data packets, keeping up to a window of them unanswered.
+async fn send_data(
+    session: &mut Session<'_>,
+    name: &str,
+    data: &[u8],
+    on_event: &mut impl FnMut(Event),
+) -> Result<(), String> {
+    let check = session.agreed.check as usize;
+    // Room for the data field after the header and check
+    let room = if session.agreed.send_max > MAX_SHORT {
+        session.agreed.send_max - 7 - check
+    } else {
+        session.agreed.send_max - 2 - check
+    };
+    let window = session.agreed.window;
+    let mut in_flight: VecDeque<InFlight> = VecDeque::new();
+    let mut read = 0;
+    let mut acked = 0u64;
+    let resend = |flight: &mut InFlight| -> Result<Vec<u8>, String> {
+        flight.retries += 1;
+        if flight.retries > MAX_RETRIES {
+            return Err("The receiver stopped responding".to_string());
+        }
+        Ok(flight.packet.clone())
+    };
+
+    loop {
+        while in_flight.len() < window && read < data.len() {
+            let (field, used) = session.agreed.ours.encode(&data[read..], room);
+            if used == 0 {
+                return Err("Packets are too short to hold any data".to_string());
+            }
+            read += used;
+            let packet = packet(session.seq, b'D', &field, session.agreed.check);
+            session.link.send(packet.clone()).await?;
+            in_flight.push_back(InFlight {
+                seq: session.seq,
+                packet,
+                bytes: used,
+                acked: false,
+                retries: 0,
+            });
+            session.seq = (session.seq + 1) % 64;
+        }
+        if in_flight.is_empty() {
+            return Ok(());
+        }
+
+        match session.read(session.agreed.timeout).await? {
+            Incoming::Packet(p) if p.typ == b'Y' => {
+                if matches!(p.data.first(), Some(b'X' | b'Z')) {
+                    session.remote_failed = true;
+                    return Err("The receiver cancelled".to_string());
+                }
+                if let Some(flight) = in_flight.iter_mut().find(|f| f.seq == p.seq) {
+                    flight.acked = true;
+                }
+            }
+            Incoming::Packet(p) if p.typ == b'N' => {
+                match in_flight.iter_mut().find(|f| f.seq == p.seq) {
+                    Some(flight) if !flight.acked => {
+                        let packet = resend(flight)?;
+                        session.link.send(packet).await?;
+                    }
+                    Some(_) => {}
+                    // Waiting for what comes after the window, so all of
+                    // it arrived
+                    None if p.seq == session.seq => in_flight.iter_mut().for_each(|f| f.acked = true),
+                    None => {}
+                }
+            }
+            Incoming::Packet(p) if p.typ == b'E' => return Err(session.remote_error(&p)),
+            Incoming::Timeout => {
+                if let Some(flight) = in_flight.iter_mut().find(|f| !f.acked) {
+                    let packet = resend(flight)?;
+                    session.link.send(packet).await?;
+                }
+            }
+            _ => {}
+        }
+
+        let before = acked;
+        while in_flight.front().is_some_and(|f| f.acked) {
+            acked += in_flight.pop_front().unwrap().bytes as u64;
+        }
+        if acked != before {
+            on_event(Event::Progress {
+                name: name.to_string(),
+                bytes: acked,
+                size: Some(data.len() as u64),
+            });
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Receiver
+// ---------------------------------------------------------------------------
+
+/// The file being received.
+struct Receiving {
+    name: String,
+    path: PathBuf,
+    file: std::fs::File,
+    size: Option<u64>,
+    bytes: u64,
+    started: bool,
+}
+
+/// Receive files from a Kermit sender into `dir`. Names already taken get
+/// a " (n)" suffix unless `overwrite` is set.
+pub async fn receive(
+    dir: &Path,
+    overwrite: bool,
+    link: &mut Link,
+    mut on_event: impl FnMut(Event),
+) -> Result<Vec<Transferred>, String> {
+    let mut session = Session::new(link);
+    let mut current = None;
+    let result = receive_files(&mut session, dir, overwrite, &mut current, &mut on_event).await;
+    if let Err(e) = &result {
+        session.fail(e).await;
+        // Don't leave half a file behind
+        if let Some(file) = current {
+            let _ = std::fs::remove_file(file.path);
+        }
+    }
+    result
+}
+
+async fn receive_files(
+    session: &mut Session<'_>,
+    dir: &Path,
+    overwrite: bool,
+    current: &mut Option<Receiving>,
+    on_event: &mut impl FnMut(Event),
+) -> Result<Vec<Transferred>, String> {
+    // NAK until the send-init arrives
+    let deadline = tokio::time::Instant::now() + START_TIMEOUT;
+    let theirs = loop {
+        match session.read(START_INTERVAL).await? {
+            Incoming::Packet(p) if p.typ == b'S' => break Params::parse(&p.data),
+            Incoming::Packet(p) if p.typ == b'E' => return Err(session.remote_error(&p)),
+            _ if tokio::time::Instant::now() >= deadline => {
+                return Err("The sender didn't start".to_string());
+            }
+            Incoming::Timeout => session.link.send(packet(0, b'N', b"", 1)).await?,
+            _ => {}
+        }
+    };
+    // Take the sender's check and repeat prefix, which we all handle
+    let ours = Params::ours(theirs.check, theirs.rept, MAX_WINDOW);
+    let init_ack = packet(0, b'Y', &ours.encode(), 1);
+    session.link.send(init_ack.clone()).await?;
+    session.agreed = agree(&ours, &theirs);
+
+    let window = session.agreed.window;
+    let mut next = 1u8;
+    // Packets that came ahead of one that is missing, and the missing
+    // ones already asked for
+    let mut early: HashMap<u8, Packet> = HashMap::new();
+    let mut asked: HashSet<u8> = HashSet::new();
+    let mut received = Vec::new();
+    let mut retries = 0;
+    loop {
+        let incoming = match session.read(session.agreed.timeout).await? {
+            Incoming::Packet(p) => p,
+            Incoming::Bad | Incoming::Timeout => {
+                retries += 1;
+                if retries > MAX_RETRIES {
+                    return Err("The sender stopped responding".to_string());
+                }
+                session.send(next, b'N', b"").await?;
+                continue;
+            }
+        };
+        retries = 0;
+        if incoming.typ == b'E' {
+            return Err(session.remote_error(&incoming));
+        }
+        asked.remove(&incoming.seq);
+        let ahead = (incoming.seq as usize + 64 - next as usize) % 64;
+        if ahead == 0 {
+            let mut incoming = incoming;
+            loop {
+                let (seq, typ) = (incoming.seq, incoming.typ);
+                let ack = handle(&session.agreed, incoming, dir, overwrite, current, &mut received, on_event)?;
+                session.send(seq, b'Y', &ack).await?;
+                next = (next + 1) % 64;
+                if typ == b'B' {
+                    return Ok(received);
+                }
+                match early.remove(&next) {
+                    Some(p) => incoming = p,
+                    None => break,
+                }
+            }
+        } else if ahead < window {
+            // Hold it until the ones before it arrive, and ask for those
+            session.send(incoming.seq, b'Y', b"").await?;
+            early.insert(incoming.seq, incoming);
+            for missing in 0..ahead {
+                let seq = ((next as usize + missing) % 64) as u8;
+                if !early.contains_key(&seq) && asked.insert(seq) {
+                    session.send(seq, b'N', b"").await?;
+                }
+            }
+        } else if ahead >= 64 - window {
+            // Already have it; our ACK was lost
+            if incoming.typ == b'S' {
+                session.link.send(init_ack.clone()).await?;
+            } else {
+                session.send(incoming.seq, b'Y', b"").await?;
+            }
+        }
+    }
+}
+
+/// Act on a packet that arrived in order. Returns what to ACK it with.
+fn handle(
+    agreed: &Agreed,
+    packet: Packet,
+    dir: &Path,
+    overwrite: bool,
+    current: &mut Option<Receiving>,
+    received: &mut Vec<Transferred>,
+    on_event: &mut impl FnMut(Event),
+) -> Result<Vec<u8>, String> {
+    let data = || agreed.theirs.decode(&packet.data);
+    match packet.typ {
+        b'F' => {
+            let remote_name = String::from_utf8_lossy(&data()?).to_string();
+            let name = Path::new(&remote_name)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .filter(|n| !n.is_empty())
+                .unwrap_or_else(|| format!("kermit-{}.bin", received.len()));
+            let mut path = dir.join(&name);
+            if !overwrite && path.exists() {
+                path = crate::zmodem::unique_path(&path);
+            }
+            let file = std::fs::File::create(&path)
+                .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or(name);
+            let ack = agreed.ours.encode_text(&name);
+            *current = Some(Receiving {
+                name,
+                path,
+                file,
+                size: None,
+                bytes: 0,
+                started: false,
+            });
+            Ok(ack)
+        }
+        b'A' => {
+            if let Some(file) = current.as_mut() {
+                file.size = attribute_size(&packet.data);
+                file.started = true;
+                on_event(Event::FileStart {
+                    name: file.name.clone(),
+                    size: file.size,
+                });
+            }
+            Ok(b"Y".to_vec())
+        }
+        b'D' => {
+            let bytes = data()?;
+            let file = current
+                .as_mut()
+                .ok_or_else(|| "Data arrived before a file name".to_string())?;
+            if !file.started {
+                file.started = true;
+                on_event(Event::FileStart {
+                    name: file.name.clone(),
+                    size: file.size,
+                });
+            }
+            file.file
+                .write_all(&bytes)
+                .map_err(|e| format!("Failed to write {}: {}", file.path.display(), e))?;
+            file.bytes += bytes.len() as u64;
+            on_event(Event::Progress {
+                name: file.name.clone(),
+                bytes: file.bytes,
+                size: file.size,
+            });
+            Ok(Vec::new())
+        }
+        b'Z' => {
+            let Some(file) = current.take() else {
+                return Ok(Vec::new());
+            };
+            // "D" means the sender gave the file up
+            if packet.data.first() == Some(&b'D') {
+                drop(file.file);
+                let _ = std::fs::remove_file(&file.path);
+                return Ok(Vec::new());
+            }
+            if !file.started {
+                on_event(Event::FileStart {
+                    name: file.name.clone(),
+                    size: file.size,
+                });
+            }
+            on_event(Event::FileDone {
+                name: file.name.clone(),
+                bytes: file.bytes,
+            });
+            received.push(Transferred {
+                name: file.name,
+                size: file.bytes,
+                path: Some(file.path),
+            });
+            Ok(Vec::new())
+        }
+        // The end (B), text for the screen (X) and anything else
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// The file size from an attribute packet: exact bytes (`1`), or else
+/// kilobytes (`!`).
+fn attribute_size(data: &[u8]) -> Option<u64> {
+    let mut kilobytes = None;
+    let mut i = 0;
+    while i + 1 < data.len() {
+        let len = unchar(data[i + 1]);
+        let value = data.get(i + 2..i + 2 + len)?;
+        let number = String::from_utf8_lossy(value).trim().parse::<u64>().ok();
+        match data[i] {
+            b'1' => return number,
+            b'!' => kilobytes = number.and_then(|k| k.checked_mul(1024)),
+            _ => {}
+        }
+        i += 2 + len;
+    }
+    kilobytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xmodem::{link_pair, test_dir};
+    use tokio::sync::mpsc;
+
+    /// Like `link_pair`, but what the first end sends passes through
+    /// `relay`, which may drop or damage it.
+    fn lossy_pair(mut relay: impl FnMut(usize, &mut Vec<u8>) -> bool + Send + 'static) -> (Link, Link) {
+        let (a_tx, a_rx) = mpsc::channel(64);
+        let (b_tx, mut b_rx) = mpsc::channel::<Vec<u8>>(64);
+        let (relay_tx, relay_rx) = mpsc::channel(64);
+        tokio::spawn(async move {
+            let mut n = 0;
+            while let Some(mut data) = b_rx.recv().await {
+                n += 1;
+                if relay(n, &mut data) && relay_tx.send(data).await.is_err() {
+                    break;
+                }
+            }
+        });
+        (Link::new(a_rx, b_tx), Link::new(relay_rx, a_tx))
+    }
+
+    #[test]
+    fn test_checks() {
+        assert_eq!(crc16(b"123456789"), 0x2189);
+        // The send-init of the protocol manual's example
+        let init = packet(0, b'S', b"~* @-#Y3~  ", 1);
+        assert_eq!(&init[..4], b"\x01. S");
+        assert_eq!(init[init.len() - 1], CR);
+        let long = packet(5, b'D', &[b'x'; 500], 3);
+        assert_eq!(&long[1..6], b" %D%<");
+        assert_eq!(long.len(), 1 + 6 + 500 + 3 + 1);
+    }
+
+    #[test]
+    fn test_detect() {
+        let init = packet(0, b'S', &Params::ours(3, b'~', MAX_WINDOW).encode(), 1);
+        let mut output = b"C-Kermit> send fw.bin\r\n".to_vec();
+        output.extend(&init);
+        let found = Detector::new().feed(&output).unwrap();
+        assert_eq!(found.offset, 23);
+        assert_eq!(found.packet, init);
+
+        // Split anywhere
+        for split in 1..output.len() {
+            let mut detector = Detector::new();
+            let (first, second) = output.split_at(split);
+            let found = match detector.feed(first) {
+                Some(found) => found,
+                None => detector.feed(second).unwrap(),
+            };
+            assert_eq!(&found.packet[..4], &init[..4], "split at {}", split);
+        }
+
+        // Other packets, and control characters in terminal output
+        assert_eq!(Detector::new().feed(&packet(3, b'S', b"~", 1)), None);
+        assert_eq!(Detector::new().feed(&packet(0, b'D', b"data", 1)), None);
+        assert_eq!(Detector::new().feed(b"\x01\x01 S"), None);
+
+        // Looks like one, but the length or check doesn't agree
+        let mut bad_check = init.clone();
+        bad_check[init.len() - 2] ^= 1;
+        assert_eq!(Detector::new().feed(&bad_check), None);
+        let mut short = init.clone();
+        short[1] -= 1;
+        assert_eq!(Detector::new().feed(&short), None);
+        let mut detector = Detector::new();
+        assert_eq!(detector.feed(b"\x01~ S"), None);
+        assert_eq!(detector.feed(b"not a packet\r\n"), None);
+    }
+
+    #[test]
+    fn test_quoting() {
+        let all: Vec<u8> = (0..=255u8).chain([7; 40]).chain([b'~'; 3]).collect();
+        let qctl_only = Quoting {
+            qctl: b'#',
+            qbin: None,
+            rept: None,
+        };
+        let full = Quoting {
+            qctl: b'#',
+            qbin: Some(b'&'),
+            rept: Some(b'~'),
+        };
+        for quoting in [qctl_only, full] {
+            let (field, used) = quoting.encode(&all, usize::MAX);
+            assert_eq!(used, all.len());
+            assert!(field.iter().all(|&c| c & 0x7f >= 32 && c & 0x7f != 127));
+            assert_eq!(quoting.decode(&field).unwrap(), all);
+        }
+        // With 8-bit prefixing the field is plain 7-bit text
+        let (field, _) = full.encode(&all, usize::MAX);
+        assert!(field.iter().all(|&c| c < 128));
+        // The run of BELs takes 4 characters
+        assert!(field.windows(4).any(|w| w == b"~H#G"));
+
+        // Prefixed bytes aren't split across fields
+        let (field, used) = full.encode(b"ab\x01\x01", 3);
+        assert_eq!((field.as_slice(), used), (&b"ab"[..], 2));
+        assert!(full.decode(b"#").is_err());
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let ours = Params::ours(3, b'~', MAX_WINDOW);
+        assert_eq!(Params::parse(&ours.encode()), ours);
+
+        // An old Kermit: short packets, checksums, no windows
+        let old = Params::parse(b"~* @-#&1");
+        assert_eq!((old.maxl, old.qbin, old.check, old.capabilities), (94, b'&', 1, 0));
+        let agreed = agree(&ours, &old);
+        assert_eq!(agreed.check, 1);
+        assert_eq!(agreed.ours.qbin, Some(b'&'));
+        assert_eq!(agreed.ours.rept, None);
+        assert_eq!((agreed.send_max, agreed.window, agreed.attributes), (94, 1, false));
+        assert_eq!(agreed.timeout, Duration::from_secs(10));
+
+        let mut peer = Params::ours(3, b'~', 8);
+        peer.maxlx = 2000;
+        let agreed = agree(&ours, &peer);
+        assert_eq!((agreed.check, agreed.ours.qbin, agreed.ours.rept), (3, None, Some(b'~')));
+        assert_eq!((agreed.send_max, agreed.window, agreed.attributes), (2000, 8, true));
+
+        // Packets too short to be of use are ignored
+        peer.maxlx = 3;
+        assert_eq!(Params::parse(&peer.encode()).maxlx, 500);
+    }
+
+    #[test]
+    fn test_attribute_size() {
+        assert_eq!(attribute_size(b"!#1001%12345"), Some(12345));
+        assert_eq!(attribute_size(b"!\"12"), Some(12 * 1024));
+        assert_eq!(attribute_size(b"#/19890101 12:00:00"), None);
+        assert_eq!(attribute_size(b"!118014398509481984"), None);
+    }
+
+    async fn transfer(ours: Link, theirs: Link, files: Vec<(String, Vec<u8>)>, name: &str) {
+        let dir = test_dir(&format!("kermit-{}", name));
+        std::fs::write(dir.join("a.bin"), b"older").unwrap();
+        let (mut ours, mut theirs) = (ours, theirs);
+        let receive_dir = dir.clone();
+        let receiver = tokio::spawn(async move {
+            let mut events = Vec::new();
+            let result = receive(&receive_dir, false, &mut theirs, |e| events.push(e)).await;
+            (result, events)
+        });
+        let mut progress = 0;
+        let sent = send(&files, &mut ours, |e| match e {
+            Event::FileStart { .. } => progress = 0,
+            Event::Progress { bytes, .. } => {
+                assert!(bytes > progress);
+                progress = bytes;
+            }
+            Event::FileDone { .. } => {}
+        })
+        .await
+        .unwrap();
+        let (received, events) = receiver.await.unwrap();
+        let received = received.unwrap();
+
+        assert_eq!(sent.len(), files.len());
+        // The existing file is kept
+        assert_eq!(received[0].name, "a (1).bin");
+        assert_eq!(std::fs::read(dir.join("a.bin")).unwrap(), b"older");
+        for ((_, data), file) in files.iter().zip(&received) {
+            assert_eq!(&std::fs::read(file.path.as_ref().unwrap()).unwrap(), data);
+        }
+        assert_eq!(
+            events[0],
+            Event::FileStart {
+                name: "a (1).bin".into(),
+                size: Some(files[0].1.len() as u64)
+            }
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn test_files() -> Vec<(String, Vec<u8>)> {
+        let big: Vec<u8> = (0..100_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        vec![
+            ("a.bin".into(), big),
+            ("empty".into(), Vec::new()),
+            ("text.txt".into(), b"line\r\n".repeat(300)),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_transfer() {
+        let (ours, theirs) = link_pair();
+        transfer(ours, theirs, test_files(), "clean").await;
+    }
+
+    #[tokio::test]
+    async fn test_transfer_with_errors() {
+        // Lose and damage a few of the sender's packets in the windows
+        let (ours, theirs) = lossy_pair(|n, data| {
+            match n {
+                4 | 9 => return false,
+                12 | 20 => {
+                    let middle = data.len() / 2;
+                    data[middle] ^= 0x01;
+                }
+                _ => {}
+            }
+            true
+        });
+        transfer(ours, theirs, test_files(), "lossy").await;
+    }
+
+    #[tokio::test]
+    async fn test_remote_error() {
+        let (mut ours, mut theirs) = link_pair();
+        let sender = tokio::spawn(async move { send(&[("a".into(), vec![1; 10])], &mut ours, |_| {}).await });
+        let mut session = Session::new(&mut theirs);
+        match session.read(Duration::from_secs(5)).await.unwrap() {
+            Incoming::Packet(p) => assert_eq!(p.typ, b'S'),
+            _ => panic!("no send-init"),
+        }
+        session.link.send(packet(0, b'E', b"Disk full", 1)).await.unwrap();
+        assert_eq!(sender.await.unwrap().unwrap_err(), "The other side gave up: Disk full");
+    }
+}
@@ -1,9 +1,33 @@
+mod ansi;
+mod autologin;
+mod control;
+mod esp;
+mod export;
+mod flasher;
+mod hexfile;
+mod hooks;
+mod importers;
+mod kermit;
+mod restore;
+mod schedule;
+mod scrollback;
+mod script;
+mod search;
+mod secrets;
+mod serial_io;
+mod sessions;
 mod ssh;
+mod stm32;
+mod transfers;
+mod triggers;
+mod trzsz;
+mod upload;
+mod xmodem;
 #[allow(dead_code)]
 mod zmodem;
 
-use std::collections::{HashMap, VecDeque};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
@@ -14,14 +38,14 @@ use axum::{
     },
     http::StatusCode,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Json, Router,
 };
 use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    sync::{broadcast, mpsc, Mutex},
+    sync::{broadcast, mpsc, oneshot, Mutex, Notify},
     task::JoinHandle,
 };
 use rust_embed::Embed;
@@ -78,6 +102,10 @@ struct StatusResponse {
     port: Option<String>,
     config: Option<PortConfig>,
     ssh_config: Option<SshStatusConfig>,
+    controller: Option<u64>,
+    clients: Vec<control::ClientInfo>,
+    input_group: InputGroupMembership,
+    schedule: Vec<schedule::JobStatus>,
 }
 
 #[derive(Serialize)]
@@ -88,6 +116,10 @@ struct TabStatusEntry {
     port: Option<String>,
     config: Option<PortConfig>,
     ssh_config: Option<SshStatusConfig>,
+    controller: Option<u64>,
+    clients: Vec<control::ClientInfo>,
+    input_group: InputGroupMembership,
+    schedule: Vec<schedule::JobStatus>,
 }
 
 // ---------------------------------------------------------------------------
@@ -97,33 +129,187 @@ struct TabStatusEntry {
 struct SerialConnection {
     port_name: String,
     config: PortConfig,
+    device: Option<restore::DeviceIdentity>,
     tx_to_serial: mpsc::Sender<Vec<u8>>,
+    /// Shared handle for the modem control lines
+    port: serial_io::SharedSerial,
     reader_handle: JoinHandle<()>,
     writer_handle: JoinHandle<()>,
 }
 
-const SCROLLBACK_MAX: usize = 128 * 1024; // 128KB
-
 enum ConnectionKind {
     Serial(SerialConnection),
     Ssh(ssh::SshConnection),
 }
 
+/// Whether a tab receives input sent to the input group.
+#[derive(Serialize, Clone, Copy, Default, Debug)]
+struct InputGroupMembership {
+    member: bool,
+    /// Still in the group but temporarily ignoring group input
+    opt_out: bool,
+}
+
+impl ConnectionState {
+    /// Sender for data written to the device (serial) or channel (SSH).
+    fn write_tx(&self) -> mpsc::Sender<Vec<u8>> {
+        match &self.connection {
+            ConnectionKind::Serial(c) => c.tx_to_serial.clone(),
+            ConnectionKind::Ssh(c) => c.tx_to_ssh.clone(),
+        }
+    }
+
+    /// Tell the hook script that what `status()` shows changed.
+    fn status_changed(&self) {
+        if let Some(hooks) = &self.hooks {
+            hooks.status_changed.notify_one();
+        }
+    }
+}
+
+impl InputGroupMembership {
+    fn receives(&self) -> bool {
+        self.member && !self.opt_out
+    }
+}
+
 /// Per-tab connection state
 struct ConnectionState {
     connection: ConnectionKind,
     broadcast_tx: broadcast::Sender<Vec<u8>>,
-    scrollback: Arc<Mutex<VecDeque<u8>>>,
+    scrollback: Arc<scrollback::Scrollback>,
     zmodem_active: Arc<AtomicBool>,
     zmodem_data_tx_shared: Arc<Mutex<Option<mpsc::Sender<Vec<u8>>>>>,
-    zmodem_files: Vec<PathBuf>,
+    /// Cancels the file transfer in progress; see `begin_transfer`
+    transfer_cancel: Option<oneshot::Sender<()>>,
+    /// Answers a ZMODEM receive waiting on a file that already exists
+    transfer_answer: Option<mpsc::UnboundedSender<zmodem::FileAction>>,
     log_file: Option<(String, tokio::fs::File)>,
+    clients: Arc<control::TabClients>,
+    input_group: InputGroupMembership,
+    script: Option<ScriptRun>,
+    hooks: Option<HookRun>,
+    triggers: Option<TriggerRun>,
+    scheduler: Option<SchedulerRun>,
+    upload: Option<UploadRun>,
+    /// Progress of the last firmware flash; the flash itself runs as a
+    /// file transfer
+    flash: Option<Arc<std::sync::Mutex<flasher::FlashStatus>>>,
+}
+
+/// A script running against a tab. Dropping it stops the script.
+struct ScriptRun {
+    status: Arc<std::sync::Mutex<script::ScriptStatus>>,
+    handle: JoinHandle<()>,
+}
+
+impl Drop for ScriptRun {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// A hook script running against a tab's connection. Dropping it (when
+/// the connection goes away) lets the script run `on_disconnect` before
+/// it ends; `stop` ends it right away.
+struct HookRun {
+    status: Arc<std::sync::Mutex<hooks::HookStatus>>,
+    /// Wakes the script's task to refresh its connection status
+    status_changed: Arc<Notify>,
+    handle: JoinHandle<()>,
+    _disconnected: oneshot::Sender<()>,
+}
+
+impl HookRun {
+    fn stop(self) {
+        self.handle.abort();
+    }
+}
+
+/// A file being sent to a tab. Dropping it stops the upload.
+struct UploadRun {
+    status: Arc<std::sync::Mutex<upload::UploadStatus>>,
+    handle: JoinHandle<()>,
+}
+
+impl Drop for UploadRun {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Scheduled sends of a tab, set before it connects or while connected;
+/// dropped when the connection is closed.
+#[derive(Default)]
+struct TabJobs {
+    schedule: std::sync::Mutex<schedule::TabSchedule>,
+    /// Wakes the tab's scheduler when the jobs change
+    changed: Notify,
+}
+
+/// Scheduler task for a tab's connection; stops when dropped.
+struct SchedulerRun {
+    jobs: Arc<TabJobs>,
+    handle: JoinHandle<()>,
+}
+
+impl Drop for SchedulerRun {
+    fn drop(&mut self) {
+        self.handle.abort();
+        self.jobs.schedule.lock().unwrap().stop();
+    }
+}
+
+/// Trigger rule evaluation for a tab's connection; stops when dropped.
+struct TriggerRun {
+    handle: JoinHandle<()>,
+}
+
+impl Drop for TriggerRun {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Trigger rules of a tab, kept across reconnects.
+struct TabTriggers {
+    rules: Vec<triggers::TriggerRule>,
+    counters: Arc<std::sync::Mutex<HashMap<String, u64>>>,
+}
+
+/// Hook script attached to a tab; started on every connection the tab makes.
+#[derive(Clone)]
+struct HookAttachment {
+    name: String,
+    source: String,
 }
 
 struct AppState {
     connections: Mutex<HashMap<String, ConnectionState>>,
+    /// Tabs saved by the previous run that haven't been reopened or discarded
+    pending_restore: Mutex<Vec<restore::SavedTab>>,
+    /// `total_written` of each tab's scrollback at its last saved snapshot;
+    /// also serializes concurrent saves
+    restore_written: Mutex<HashMap<String, u64>>,
+    sessions: sessions::SessionStore,
+    /// Hook scripts by tab ID
+    hook_scripts: Mutex<HashMap<String, HookAttachment>>,
+    /// Session each tab was opened from, by tab ID
+    tab_sessions: Mutex<HashMap<String, String>>,
+    /// Trigger rules by tab ID
+    triggers: Mutex<HashMap<String, TabTriggers>>,
+    /// Scheduled sends by tab ID
+    schedules: Mutex<HashMap<String, Arc<TabJobs>>>,
+    /// File transfer history by tab ID, kept across reconnects
+    transfers: Mutex<HashMap<String, transfers::History>>,
 }
 
+/// Matches the Tauri bundle identifier, so files land in the app's own
+/// data and config directories.
+const APP_IDENTIFIER: &str = "com.serialrs.terminal";
+
+const RESTORE_SAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
 // ---------------------------------------------------------------------------
 // Helpers
 // ---------------------------------------------------------------------------
@@ -174,6 +360,88 @@ fn port_type_string(pt: &serialport::SerialPortType) -> String {
     }
 }
 
+/// Status messages sent on a tab's broadcast channel alongside device
+/// output; WebSocket clients get them as text frames.
+fn is_notification(data: &[u8]) -> bool {
+    data.starts_with(b"\x1b]zmodem;")
+        || data.starts_with(b"\x1b]script;")
+        || data.starts_with(b"\x1b]trigger;")
+        || data.starts_with(b"\x1b]upload;")
+        || data.starts_with(b"\x1b]flash;")
+}
+
+fn app_data_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(APP_IDENTIFIER)
+}
+
+fn app_config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(APP_IDENTIFIER)
+}
+
+/// Expand a leading `~/` to the home directory.
+fn expand_home(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some(rest) => match dirs::home_dir() {
+            Some(home) => home.join(rest).to_string_lossy().to_string(),
+            None => path.to_string(),
+        },
+        None => path.to_string(),
+    }
+}
+
+/// Open (or create) a log file for appending. Returns the expanded path.
+async fn open_log_file(path: &str) -> std::io::Result<(String, tokio::fs::File)> {
+    let path = expand_home(path);
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await?;
+    Ok((path, file))
+}
+
+fn new_scrollback(
+    tab_id: &str,
+    size: Option<usize>,
+    spill: bool,
+) -> Arc<scrollback::Scrollback> {
+    let size = size.unwrap_or(scrollback::DEFAULT_SCROLLBACK_SIZE);
+    if spill {
+        let dir = scrollback::spill_dir(tab_id);
+        match scrollback::Scrollback::with_spill(size, dir.clone()) {
+            Ok(sb) => {
+                tracing::info!("Scrollback spill enabled at {} (tab {})", dir.display(), tab_id);
+                return Arc::new(sb);
+            }
+            Err(e) => {
+                tracing::error!("Failed to create scrollback spill dir {}: {}", dir.display(), e);
+            }
+        }
+    }
+    Arc::new(scrollback::Scrollback::new(size))
+}
+
+/// Link a tab about to connect to the session it was opened from: the
+/// session's trigger rules apply to it (counters carry over), and its
+/// auto-login and download settings are used.
+async fn link_tab_session(state: &Arc<AppState>, tab_id: &str, session_id: String) {
+    load_session_triggers(state, tab_id, &session_id).await;
+    state
+        .tab_sessions
+        .lock()
+        .await
+        .insert(tab_id.to_string(), session_id);
+}
+
+/// The session the tab was opened from, if any.
+async fn tab_session(state: &AppState, tab_id: &str) -> Option<String> {
+    state.tab_sessions.lock().await.get(tab_id).cloned()
+}
+
 // ---------------------------------------------------------------------------
 // REST handlers
 // ---------------------------------------------------------------------------
@@ -209,25 +477,57 @@ struct ConnectRequest {
     tab_id: String,
     #[serde(flatten)]
     config: PortConfig,
+    /// Scrollback size in bytes (defaults to 128KB)
+    scrollback_size: Option<usize>,
+    /// Spill scrollback that falls out of memory to disk
+    scrollback_spill: Option<bool>,
+    /// Session the tab was opened from; its trigger rules are applied
+    session_id: Option<String>,
 }
 
 async fn connect(
     State(state): State<Arc<AppState>>,
     Json(req): Json<ConnectRequest>,
 ) -> impl IntoResponse {
-    let tab_id = req.tab_id;
-    let config = req.config;
+    let scrollback = new_scrollback(
+        &req.tab_id,
+        req.scrollback_size,
+        req.scrollback_spill.unwrap_or(false),
+    );
+
+    if let Some(session_id) = req.session_id {
+        link_tab_session(&state, &req.tab_id, session_id).await;
+    }
+    match open_serial_tab(&state, req.tab_id, req.config, scrollback).await {
+        Ok(port_name) => {
+            persist_tabs(&state).await;
+            (
+                StatusCode::OK,
+                Json(ApiResponse {
+                    ok: true,
+                    message: format!("Connected to {}", port_name),
+                }),
+            )
+        }
+        Err((status, message)) => (status, Json(ApiResponse { ok: false, message })),
+    }
+}
 
+/// Open a serial port and register it as the connection for `tab_id`.
+/// Returns the port name on success.
+async fn open_serial_tab(
+    state: &Arc<AppState>,
+    tab_id: String,
+    config: PortConfig,
+    scrollback: Arc<scrollback::Scrollback>,
+) -> Result<String, (StatusCode, String)> {
     let mut connections = state.connections.lock().await;
 
     if connections.contains_key(&tab_id) {
-        return (
+        return Err((
             StatusCode::CONFLICT,
-            Json(ApiResponse {
-                ok: false,
-                message: "Tab already has an active connection. Disconnect first.".to_string(),
-            }),
-        );
+            "Tab already has an active connection. Disconnect first.".to_string(),
+        ));
     }
 
     let flow_control_str = config.flow_control.as_deref().unwrap_or("none");
@@ -241,35 +541,40 @@ async fn connect(
         Ok(p) => p,
         Err(e) => {
             tracing::error!("Failed to open serial port {}: {}", config.port, e);
-            return (
+            return Err((
                 StatusCode::BAD_REQUEST,
-                Json(ApiResponse {
-                    ok: false,
-                    message: format!("Failed to open port: {}", e),
-                }),
-            );
+                format!("Failed to open port: {}", e),
+            ));
         }
     };
 
     tracing::info!("Opened serial port {} at {} baud (tab {})", config.port, config.baud_rate, tab_id);
 
-    let (mut reader, mut writer) = tokio::io::split(serial_port);
+    let port = serial_io::SharedSerial::new(serial_port);
+    let mut reader = port.clone();
+    let mut writer = port.clone();
 
     // Channel: WebSocket clients -> serial writer
     let (tx_to_serial, mut rx_from_ws) = mpsc::channel::<Vec<u8>>(256);
 
     // Per-tab broadcast channel
     let (broadcast_tx, _) = broadcast::channel::<Vec<u8>>(1024);
-    let scrollback = Arc::new(Mutex::new(VecDeque::new()));
     let zmodem_active = Arc::new(AtomicBool::new(false));
     let zmodem_data_tx_shared: Arc<Mutex<Option<mpsc::Sender<Vec<u8>>>>> =
         Arc::new(Mutex::new(None));
 
+    // Spawn ZMODEM interceptor for this tab
+    let mut router = spawn_zmodem_interceptor_for_tab(
+        tab_id.clone(),
+        broadcast_tx.clone(),
+        zmodem_active.clone(),
+        zmodem_data_tx_shared.clone(),
+        state.clone(),
+    );
+
     // Reader task: serial -> broadcast + scrollback (with ZMODEM intercept)
     let bc_tx = broadcast_tx.clone();
     let scrollback_clone = scrollback.clone();
-    let zmodem_active_clone = zmodem_active.clone();
-    let zmodem_data_tx_clone = zmodem_data_tx_shared.clone();
     let reader_handle = tokio::spawn(async move {
         let mut buf = [0u8; 1024];
         loop {
@@ -279,26 +584,15 @@ async fn connect(
                     break;
                 }
                 Ok(n) => {
-                    let data = buf[..n].to_vec();
-
-                    // If ZMODEM is active, route data to the ZMODEM handler
-                    if zmodem_active_clone.load(Ordering::Relaxed) {
-                        let tx = zmodem_data_tx_clone.lock().await;
-                        if let Some(ref zmodem_tx) = *tx {
-                            let _ = zmodem_tx.send(data).await;
-                        }
+                    // File transfers take what isn't terminal output
+                    let data = router.route(&buf[..n]).await;
+                    if data.is_empty() {
                         continue;
                     }
 
                     // Normal path: append to scrollback and broadcast
-                    {
-                        let mut sb = scrollback_clone.lock().await;
-                        sb.extend(&data);
-                        while sb.len() > SCROLLBACK_MAX {
-                            sb.pop_front();
-                        }
-                    }
-                    let _ = bc_tx.send(data);
+                    scrollback_clone.push(data);
+                    let _ = bc_tx.send(data.to_vec());
                 }
                 Err(e) => {
                     tracing::error!("Serial read error: {}", e);
@@ -320,21 +614,15 @@ async fn connect(
     });
 
     let port_name = config.port.clone();
-
-    // Spawn ZMODEM interceptor for this tab
-    spawn_zmodem_interceptor_for_tab(
-        tab_id.clone(),
-        broadcast_tx.clone(),
-        zmodem_active.clone(),
-        zmodem_data_tx_shared.clone(),
-        state.clone(),
-    );
+    let device = restore::DeviceIdentity::for_port(&port_name);
 
     connections.insert(tab_id.clone(), ConnectionState {
         connection: ConnectionKind::Serial(SerialConnection {
             port_name: port_name.clone(),
             config,
+            device,
             tx_to_serial,
+            port,
             reader_handle,
             writer_handle,
         }),
@@ -342,17 +630,24 @@ async fn connect(
         scrollback,
         zmodem_active,
         zmodem_data_tx_shared,
-        zmodem_files: Vec::new(),
+        transfer_cancel: None,
+        transfer_answer: None,
         log_file: None,
+        clients: Arc::new(control::TabClients::new()),
+        input_group: InputGroupMembership::default(),
+        script: None,
+        hooks: None,
+        triggers: None,
+        scheduler: None,
+        upload: None,
+        flash: None,
     });
+    attach_hooks_on_connect(state, &tab_id, &mut connections).await;
+    start_triggers_on_connect(state, &tab_id, &mut connections).await;
+    start_scheduler_on_connect(state, &tab_id, &mut connections).await;
+    start_auto_login_on_connect(state, &tab_id, &mut connections).await;
 
-    (
-        StatusCode::OK,
-        Json(ApiResponse {
-            ok: true,
-            message: format!("Connected to {}", port_name),
-        }),
-    )
+    Ok(port_name)
 }
 
 #[derive(Deserialize)]
@@ -364,44 +659,33 @@ async fn disconnect(
     State(state): State<Arc<AppState>>,
     Json(req): Json<DisconnectRequest>,
 ) -> impl IntoResponse {
-    let mut connections = state.connections.lock().await;
+    let message = close_connection(&state, &req.tab_id)
+        .await
+        .unwrap_or_else(|| "Not connected".to_string());
+    (StatusCode::OK, Json(ApiResponse { ok: true, message }))
+}
 
-    match connections.remove(&req.tab_id) {
-        Some(conn_state) => {
-            match conn_state.connection {
-                ConnectionKind::Serial(c) => {
-                    tracing::info!("Disconnecting from serial {} (tab {})", c.port_name, req.tab_id);
-                    c.reader_handle.abort();
-                    c.writer_handle.abort();
-                    (
-                        StatusCode::OK,
-                        Json(ApiResponse {
-                            ok: true,
-                            message: format!("Disconnected from {}", c.port_name),
-                        }),
-                    )
-                }
-                ConnectionKind::Ssh(c) => {
-                    let host = c.config.host.clone();
-                    tracing::info!("Disconnecting from SSH {} (tab {})", host, req.tab_id);
-                    c.disconnect().await;
-                    (
-                        StatusCode::OK,
-                        Json(ApiResponse {
-                            ok: true,
-                            message: format!("Disconnected from SSH {}", host),
-                        }),
-                    )
-                }
-            }
+/// Remove a tab's connection and shut it down. Returns a description of
+/// what was closed, or `None` if the tab wasn't connected.
+async fn close_connection(state: &Arc<AppState>, tab_id: &str) -> Option<String> {
+    let conn_state = state.connections.lock().await.remove(tab_id)?;
+    state.schedules.lock().await.remove(tab_id);
+    persist_tabs(state).await;
+    conn_state.scrollback.discard_spill();
+
+    match conn_state.connection {
+        ConnectionKind::Serial(c) => {
+            tracing::info!("Disconnecting from serial {} (tab {})", c.port_name, tab_id);
+            c.reader_handle.abort();
+            c.writer_handle.abort();
+            Some(format!("Disconnected from {}", c.port_name))
+        }
+        ConnectionKind::Ssh(c) => {
+            let host = c.config.host.clone();
+            tracing::info!("Disconnecting from SSH {} (tab {})", host, tab_id);
+            c.disconnect().await;
+            Some(format!("Disconnected from SSH {}", host))
         }
-        None => (
-            StatusCode::OK,
-            Json(ApiResponse {
-                ok: true,
-                message: "Not connected".to_string(),
-            }),
-        ),
     }
 }
 
@@ -410,55 +694,78 @@ struct SshConnectRequest {
     tab_id: String,
     #[serde(flatten)]
     config: ssh::SshConfig,
+    /// Scrollback size in bytes (defaults to 128KB)
+    scrollback_size: Option<usize>,
+    /// Spill scrollback that falls out of memory to disk
+    scrollback_spill: Option<bool>,
+    /// Session the tab was opened from; its trigger rules are applied
+    session_id: Option<String>,
 }
 
 async fn ssh_connect(
     State(state): State<Arc<AppState>>,
     Json(req): Json<SshConnectRequest>,
 ) -> impl IntoResponse {
-    let tab_id = req.tab_id;
-    let config = req.config;
+    let scrollback = new_scrollback(
+        &req.tab_id,
+        req.scrollback_size,
+        req.scrollback_spill.unwrap_or(false),
+    );
+
+    if let Some(session_id) = req.session_id {
+        link_tab_session(&state, &req.tab_id, session_id).await;
+    }
+    match open_ssh_tab(&state, req.tab_id, req.config, scrollback).await {
+        Ok(host) => {
+            persist_tabs(&state).await;
+            (
+                StatusCode::OK,
+                Json(ApiResponse {
+                    ok: true,
+                    message: format!("Connected to SSH {}", host),
+                }),
+            )
+        }
+        Err((status, message)) => (status, Json(ApiResponse { ok: false, message })),
+    }
+}
 
+/// Open an SSH session and register it as the connection for `tab_id`.
+/// Returns `host:port` on success.
+async fn open_ssh_tab(
+    state: &Arc<AppState>,
+    tab_id: String,
+    config: ssh::SshConfig,
+    scrollback: Arc<scrollback::Scrollback>,
+) -> Result<String, (StatusCode, String)> {
     let mut connections = state.connections.lock().await;
 
     if connections.contains_key(&tab_id) {
-        return (
+        return Err((
             StatusCode::CONFLICT,
-            Json(ApiResponse {
-                ok: false,
-                message: "Tab already has an active connection. Disconnect first.".to_string(),
-            }),
-        );
+            "Tab already has an active connection. Disconnect first.".to_string(),
+        ));
     }
 
     let host = format!("{}:{}", config.host, config.port);
 
     // Per-tab broadcast channel
     let (broadcast_tx, _) = broadcast::channel::<Vec<u8>>(1024);
-    let scrollback = Arc::new(Mutex::new(VecDeque::new()));
     let zmodem_active = Arc::new(AtomicBool::new(false));
     let zmodem_data_tx_shared: Arc<Mutex<Option<mpsc::Sender<Vec<u8>>>>> =
         Arc::new(Mutex::new(None));
 
-    match ssh::SshConnection::connect(
-        config,
+    // Spawn ZMODEM interceptor for this tab
+    let router = spawn_zmodem_interceptor_for_tab(
+        tab_id.clone(),
         broadcast_tx.clone(),
-        scrollback.clone(),
-        SCROLLBACK_MAX,
         zmodem_active.clone(),
         zmodem_data_tx_shared.clone(),
-    )
-    .await
-    {
+        state.clone(),
+    );
+
+    match ssh::SshConnection::connect(config, broadcast_tx.clone(), scrollback.clone(), router).await {
         Ok(ssh_conn) => {
-            // Spawn ZMODEM interceptor for this tab
-            spawn_zmodem_interceptor_for_tab(
-                tab_id.clone(),
-                broadcast_tx.clone(),
-                zmodem_active.clone(),
-                zmodem_data_tx_shared.clone(),
-                state.clone(),
-            );
 
             connections.insert(tab_id.clone(), ConnectionState {
                 connection: ConnectionKind::Ssh(ssh_conn),
@@ -466,26 +773,27 @@ async fn ssh_connect(
                 scrollback,
                 zmodem_active,
                 zmodem_data_tx_shared,
-                zmodem_files: Vec::new(),
+                transfer_cancel: None,
+                transfer_answer: None,
                 log_file: None,
+                clients: Arc::new(control::TabClients::new()),
+                input_group: InputGroupMembership::default(),
+                script: None,
+                hooks: None,
+                triggers: None,
+                scheduler: None,
+                upload: None,
+                flash: None,
             });
-            (
-                StatusCode::OK,
-                Json(ApiResponse {
-                    ok: true,
-                    message: format!("Connected to SSH {}", host),
-                }),
-            )
+            attach_hooks_on_connect(state, &tab_id, &mut connections).await;
+            start_triggers_on_connect(state, &tab_id, &mut connections).await;
+            start_scheduler_on_connect(state, &tab_id, &mut connections).await;
+            start_auto_login_on_connect(state, &tab_id, &mut connections).await;
+            Ok(host)
         }
         Err(e) => {
             tracing::error!("SSH connection failed: {}", e);
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ApiResponse {
-                    ok: false,
-                    message: e,
-                }),
-            )
+            Err((StatusCode::BAD_REQUEST, e))
         }
     }
 }
@@ -500,11 +808,19 @@ async fn status(
     Query(query): Query<TabIdQuery>,
 ) -> impl IntoResponse {
     let connections = state.connections.lock().await;
+    let schedules = state.schedules.lock().await;
+    let schedule_of = |tab_id: &str| {
+        schedules
+            .get(tab_id)
+            .map(|jobs| jobs.schedule.lock().unwrap().status())
+            .unwrap_or_default()
+    };
 
     if let Some(tab_id) = query.tab_id {
         // Return status for a specific tab
         match connections.get(&tab_id) {
             Some(conn_state) => {
+                let control = conn_state.clients.status();
                 match &conn_state.connection {
                     ConnectionKind::Serial(c) => Json(StatusResponse {
                         connected: true,
@@ -512,6 +828,10 @@ async fn status(
                         port: Some(c.port_name.clone()),
                         config: Some(c.config.clone()),
                         ssh_config: None,
+                        controller: control.controller,
+                        clients: control.clients,
+                        input_group: conn_state.input_group,
+                        schedule: schedule_of(&tab_id),
                     }).into_response(),
                     ConnectionKind::Ssh(c) => Json(StatusResponse {
                         connected: true,
@@ -523,6 +843,10 @@ async fn status(
                             port: c.config.port,
                             username: c.config.username.clone(),
                         }),
+                        controller: control.controller,
+                        clients: control.clients,
+                        input_group: conn_state.input_group,
+                        schedule: schedule_of(&tab_id),
                     }).into_response(),
                 }
             }
@@ -532,12 +856,17 @@ async fn status(
                 port: None,
                 config: None,
                 ssh_config: None,
+                controller: None,
+                clients: Vec::new(),
+                input_group: InputGroupMembership::default(),
+                schedule: schedule_of(&tab_id),
             }).into_response(),
         }
     } else {
         // Return status for all tabs
         let mut entries: Vec<TabStatusEntry> = Vec::new();
         for (tab_id, conn_state) in connections.iter() {
+            let control = conn_state.clients.status();
             match &conn_state.connection {
                 ConnectionKind::Serial(c) => entries.push(TabStatusEntry {
                     tab_id: tab_id.clone(),
@@ -546,6 +875,10 @@ async fn status(
                     port: Some(c.port_name.clone()),
                     config: Some(c.config.clone()),
                     ssh_config: None,
+                    controller: control.controller,
+                    clients: control.clients,
+                    input_group: conn_state.input_group,
+                    schedule: schedule_of(tab_id),
                 }),
                 ConnectionKind::Ssh(c) => entries.push(TabStatusEntry {
                     tab_id: tab_id.clone(),
@@ -558,6 +891,10 @@ async fn status(
                         port: c.config.port,
                         username: c.config.username.clone(),
                     }),
+                    controller: control.controller,
+                    clients: control.clients,
+                    input_group: conn_state.input_group,
+                    schedule: schedule_of(tab_id),
                 }),
             }
         }
@@ -569,26 +906,69 @@ async fn status(
 // WebSocket handler
 // ---------------------------------------------------------------------------
 
+#[derive(Deserialize)]
+struct WsQuery {
+    tab_id: Option<String>,
+    /// `observer` to attach read-only; otherwise the client takes control
+    /// if nobody holds it
+    role: Option<String>,
+    /// Display name reported in status
+    name: Option<String>,
+    /// Stable id of the browser window, shared by its connections to every
+    /// tab; input group sends only reach tabs it controls
+    client: Option<String>,
+}
+
 async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AppState>>,
-    Query(query): Query<TabIdQuery>,
+    Query(query): Query<WsQuery>,
 ) -> impl IntoResponse {
     let tab_id = query.tab_id.unwrap_or_default();
-    ws.on_upgrade(move |socket| handle_ws(socket, state, tab_id))
+    let observer = query.role.as_deref() == Some("observer");
+    ws.on_upgrade(move |socket| {
+        handle_ws(socket, state, tab_id, observer, query.name, query.client)
+    })
+}
+
+/// Text frame telling one client its role and who is attached to the tab.
+fn control_message(client_id: u64, status: &control::ControlStatus) -> String {
+    let role = if status.controller == Some(client_id) {
+        control::Role::Controller
+    } else {
+        control::Role::Observer
+    };
+    format!(
+        "\x1b]control;{}\x07",
+        serde_json::json!({
+            "type": "control",
+            "client_id": client_id,
+            "role": role,
+            "controller": status.controller,
+            "clients": status.clients,
+        })
+    )
 }
 
-async fn handle_ws(socket: WebSocket, state: Arc<AppState>, tab_id: String) {
+async fn handle_ws(
+    socket: WebSocket,
+    state: Arc<AppState>,
+    tab_id: String,
+    observer: bool,
+    name: Option<String>,
+    client_key: Option<String>,
+) {
     let (mut ws_tx, mut ws_rx) = socket.split();
 
     // Get broadcast_tx and scrollback for this tab
-    let (broadcast_tx, scrollback, zmodem_active, log_file_for_send) = {
+    let (broadcast_tx, scrollback, zmodem_active, clients, log_file_for_send) = {
         let connections = state.connections.lock().await;
         match connections.get(&tab_id) {
             Some(conn_state) => (
                 conn_state.broadcast_tx.clone(),
                 conn_state.scrollback.clone(),
                 conn_state.zmodem_active.clone(),
+                conn_state.clients.clone(),
                 // We cannot hold a reference to log_file across await, so we skip it here
                 // and handle logging via state lookup in the send task
                 (),
@@ -602,32 +982,28 @@ async fn handle_ws(socket: WebSocket, state: Arc<AppState>, tab_id: String) {
     };
 
     // Send scrollback buffer first so client sees previous output
-    {
-        let sb = scrollback.lock().await;
-        if !sb.is_empty() {
-            let data: Vec<u8> = sb.iter().copied().collect();
-            if ws_tx.send(Message::Binary(data.into())).await.is_err() {
-                return;
-            }
+    for chunk in scrollback.replay_chunks(scrollback::REPLAY_CHUNK_SIZE) {
+        if ws_tx.send(Message::Binary(chunk.into())).await.is_err() {
+            return;
         }
     }
 
     // Subscribe to broadcast for serial RX data
     let mut broadcast_rx = broadcast_tx.subscribe();
 
+    // Register with the tab's clients; only the controller may send input
+    let client_id = clients.attach(name, client_key.clone(), observer);
+    let mut control_rx = clients.subscribe();
+    control_rx.mark_changed();
+    tracing::info!("WebSocket client {} attached (tab {})", client_id, tab_id);
+
     // Get a clone of the mpsc sender for writing (serial or SSH)
     let get_write_tx = |state: &Arc<AppState>, tab_id: &str| {
         let state = state.clone();
         let tab_id = tab_id.to_string();
         async move {
             let connections = state.connections.lock().await;
-            match connections.get(&tab_id) {
-                Some(conn_state) => match &conn_state.connection {
-                    ConnectionKind::Serial(c) => Some(c.tx_to_serial.clone()),
-                    ConnectionKind::Ssh(c) => Some(c.tx_to_ssh.clone()),
-                },
-                None => None,
-            }
+            connections.get(&tab_id).map(ConnectionState::write_tx)
         }
     };
 
@@ -655,10 +1031,23 @@ async fn handle_ws(socket: WebSocket, state: Arc<AppState>, tab_id: String) {
     let tab_id_for_log = tab_id.clone();
     let mut send_task = tokio::spawn(async move {
         loop {
-            match broadcast_rx.recv().await {
+            let received = tokio::select! {
+                received = broadcast_rx.recv() => received,
+                changed = control_rx.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                    let msg = control_message(client_id, &control_rx.borrow_and_update());
+                    if ws_tx.send(Message::Text(msg.into())).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            match received {
                 Ok(data) => {
-                    // Always intercept ZMODEM notifications (sent as Text frames)
-                    if data.starts_with(b"\x1b]zmodem;") {
+                    // Always intercept ZMODEM and script notifications (sent as Text frames)
+                    if is_notification(&data) {
                         if ws_tx
                             .send(Message::Text(
                                 String::from_utf8_lossy(&data).to_string().into(),
@@ -698,9 +1087,11 @@ async fn handle_ws(socket: WebSocket, state: Arc<AppState>, tab_id: String) {
     });
 
     // Task B: WebSocket -> serial TX (via mpsc) — blocked during ZMODEM
+    // and for clients that don't hold control
     let state_clone = state.clone();
     let tab_id_clone = tab_id.clone();
     let zmodem_active_for_recv = zmodem_active.clone();
+    let clients_for_recv = clients.clone();
     let mut recv_task = tokio::spawn(async move {
         while let Some(Ok(msg)) = ws_rx.next().await {
             match msg {
@@ -709,6 +1100,9 @@ async fn handle_ws(socket: WebSocket, state: Arc<AppState>, tab_id: String) {
                     if zmodem_active_for_recv.load(Ordering::Relaxed) {
                         continue;
                     }
+                    if !clients_for_recv.is_controller(client_id) {
+                        continue;
+                    }
                     if let Some(tx) = get_write_tx(&state_clone, &tab_id_clone).await {
                         if tx.send(data.to_vec()).await.is_err() {
                             tracing::error!("Failed to send data to serial writer");
@@ -717,8 +1111,31 @@ async fn handle_ws(socket: WebSocket, state: Arc<AppState>, tab_id: String) {
                     }
                 }
                 Message::Text(text) => {
-                    // Try to parse as a resize command
+                    // Try to parse as a control or resize command
                     if let Ok(val) = serde_json::from_str::<serde_json::Value>(&text) {
+                        if val.get("type").and_then(|v| v.as_str()) == Some("control") {
+                            match val.get("action").and_then(|v| v.as_str()) {
+                                Some("take") => {
+                                    clients_for_recv.take_control(client_id);
+                                }
+                                Some("release") => clients_for_recv.release_control(client_id),
+                                _ => {}
+                            }
+                            continue;
+                        }
+                        if !clients_for_recv.is_controller(client_id) {
+                            continue;
+                        }
+                        if val.get("type").and_then(|v| v.as_str()) == Some("group_input") {
+                            if let Some(data) = val.get("data").and_then(|v| v.as_str()) {
+                                let sender = GroupSender {
+                                    tab_id: &tab_id_clone,
+                                    client_key: client_key.as_deref(),
+                                };
+                                send_to_tabs(&state_clone, None, Some(sender), data.as_bytes()).await;
+                            }
+                            continue;
+                        }
                         if val.get("type").and_then(|v| v.as_str()) == Some("resize") {
                             if let (Some(cols), Some(rows)) = (
                                 val.get("cols").and_then(|v| v.as_u64()),
@@ -732,6 +1149,9 @@ async fn handle_ws(socket: WebSocket, state: Arc<AppState>, tab_id: String) {
                         }
                     }
                     // Not a resize message — forward as data
+                    if !clients_for_recv.is_controller(client_id) {
+                        continue;
+                    }
                     if let Some(tx) = get_write_tx(&state_clone, &tab_id_clone).await {
                         if tx.send(text.as_bytes().to_vec()).await.is_err() {
                             tracing::error!("Failed to send data to serial writer");
@@ -755,80 +1175,3594 @@ async fn handle_ws(socket: WebSocket, state: Arc<AppState>, tab_id: String) {
         }
     }
 
+    clients.detach(client_id);
+
     tracing::info!("WebSocket connection closed (tab {})", tab_id);
 }
 
 // ---------------------------------------------------------------------------
-// ZMODEM REST handlers
+// Input group (type once, send to many tabs)
 // ---------------------------------------------------------------------------
 
-async fn zmodem_list_files(
-    State(state): State<Arc<AppState>>,
-    Query(query): Query<TabIdQuery>,
-) -> impl IntoResponse {
-    let connections = state.connections.lock().await;
-    let tab_id = query.tab_id.unwrap_or_default();
-    let names: Vec<String> = match connections.get(&tab_id) {
-        Some(conn_state) => conn_state.zmodem_files
-            .iter()
-            .filter_map(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
-            .collect(),
-        None => Vec::new(),
-    };
-    Json(serde_json::json!({ "files": names }))
+#[derive(Serialize, Default)]
+struct GroupSendResult {
+    sent: Vec<String>,
+    /// Tabs that were asked for but are gone, opted out, mid-transfer or
+    /// controlled by someone else
+    skipped: Vec<String>,
 }
 
-async fn zmodem_download_file(
-    State(state): State<Arc<AppState>>,
-    AxumPath(filename): AxumPath<String>,
-    Query(query): Query<TabIdQuery>,
-) -> impl IntoResponse {
-    let connections = state.connections.lock().await;
-    let tab_id = query.tab_id.unwrap_or_default();
-    let found_path = connections.get(&tab_id).and_then(|conn_state| {
-        conn_state.zmodem_files.iter().find(|p| {
-            p.file_name()
-                .map(|n| n.to_string_lossy() == filename)
-                .unwrap_or(false)
-        }).cloned()
-    });
-    drop(connections);
+/// The WebSocket client typing into the input group.
+struct GroupSender<'a> {
+    /// The tab it typed in, which it controls
+    tab_id: &'a str,
+    client_key: Option<&'a str>,
+}
 
-    match found_path {
-        Some(path) => match tokio::fs::read(&path).await {
-            Ok(data) => (
-                StatusCode::OK,
-                [
-                    (
-                        axum::http::header::CONTENT_TYPE,
-                        "application/octet-stream".to_string(),
-                    ),
-                    (
-                        axum::http::header::CONTENT_DISPOSITION,
-                        format!("attachment; filename=\"{}\"", filename),
-                    ),
-                ],
-                data,
-            )
-                .into_response(),
-            Err(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse {
+impl GroupSender<'_> {
+    /// The same check as input typed into a single tab: the sender must
+    /// hold control of the tab, here through its window's client there.
+    fn may_write(&self, tab_id: &str, cs: &ConnectionState) -> bool {
+        tab_id == self.tab_id
+            || self
+                .client_key
+                .is_some_and(|key| cs.clients.is_controlled_by(key))
+    }
+}
+
+/// Write `data` to the given tabs, or to every tab in the input group.
+/// Opted-out tabs, tabs running a ZMODEM transfer and, for input from a
+/// WebSocket client, tabs that client doesn't control are skipped.
+async fn send_to_tabs(
+    state: &Arc<AppState>,
+    tab_ids: Option<&[String]>,
+    sender: Option<GroupSender<'_>>,
+    data: &[u8],
+) -> GroupSendResult {
+    let mut result = GroupSendResult::default();
+    let mut targets: Vec<(String, mpsc::Sender<Vec<u8>>)> = Vec::new();
+    {
+        let connections = state.connections.lock().await;
+        let candidates: Vec<(&String, Option<&ConnectionState>)> = match tab_ids {
+            Some(ids) => ids.iter().map(|id| (id, connections.get(id))).collect(),
+            None => connections
+                .iter()
+                .filter(|(_, cs)| cs.input_group.receives())
+                .map(|(id, cs)| (id, Some(cs)))
+                .collect(),
+        };
+        for (id, cs) in candidates {
+            match cs {
+                Some(cs)
+                    if !cs.input_group.opt_out
+                        && !cs.zmodem_active.load(Ordering::Relaxed)
+                        && sender.as_ref().is_none_or(|s| s.may_write(id, cs)) =>
+                {
+                    targets.push((id.clone(), cs.write_tx()))
+                }
+                _ => result.skipped.push(id.clone()),
+            }
+        }
+    }
+
+    for (id, tx) in targets {
+        if tx.send(data.to_vec()).await.is_ok() {
+            result.sent.push(id);
+        } else {
+            result.skipped.push(id);
+        }
+    }
+    result
+}
+
+async fn input_group_list(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let connections = state.connections.lock().await;
+    let tabs: Vec<serde_json::Value> = connections
+        .iter()
+        .filter(|(_, cs)| cs.input_group.member)
+        .map(|(id, cs)| serde_json::json!({ "tab_id": id, "opt_out": cs.input_group.opt_out }))
+        .collect();
+    Json(serde_json::json!({ "tabs": tabs }))
+}
+
+#[derive(Deserialize)]
+struct InputGroupRequest {
+    tab_id: String,
+    /// Join (`true`) or leave (`false`) the group
+    member: Option<bool>,
+    opt_out: Option<bool>,
+}
+
+async fn input_group_update(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<InputGroupRequest>,
+) -> impl IntoResponse {
+    let mut connections = state.connections.lock().await;
+    let conn_state = match connections.get_mut(&req.tab_id) {
+        Some(cs) => cs,
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "ok": false, "message": "No connection for this tab" })),
+            );
+        }
+    };
+    if let Some(member) = req.member {
+        conn_state.input_group.member = member;
+        if !member {
+            conn_state.input_group.opt_out = false;
+        }
+    }
+    if let Some(opt_out) = req.opt_out {
+        conn_state.input_group.opt_out = opt_out;
+    }
+    let membership = conn_state.input_group;
+    tracing::info!(
+        "Input group: tab {} member={} opt_out={}",
+        req.tab_id,
+        membership.member,
+        membership.opt_out
+    );
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({ "ok": true, "message": "Updated", "input_group": membership })),
+    )
+}
+
+#[derive(Deserialize)]
+struct GroupSendRequest {
+    data: String,
+    /// Send to these tabs instead of the input group
+    tab_ids: Option<Vec<String>>,
+}
+
+async fn input_group_send(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<GroupSendRequest>,
+) -> impl IntoResponse {
+    let result = send_to_tabs(&state, req.tab_ids.as_deref(), None, req.data.as_bytes()).await;
+    Json(serde_json::json!({ "ok": true, "sent": result.sent, "skipped": result.skipped }))
+}
+
+// ---------------------------------------------------------------------------
+// Scripts
+// ---------------------------------------------------------------------------
+
+/// Runs script commands against a connected tab.
+struct TabScriptHost {
+    state: Arc<AppState>,
+    tab_id: String,
+    write_tx: mpsc::Sender<Vec<u8>>,
+    /// `None` for SSH tabs
+    port: Option<serial_io::SharedSerial>,
+    broadcast_tx: broadcast::Sender<Vec<u8>>,
+}
+
+impl TabScriptHost {
+    fn serial_port(&self) -> Result<&serial_io::SharedSerial, String> {
+        self.port
+            .as_ref()
+            .ok_or_else(|| "Control lines need a serial connection".to_string())
+    }
+}
+
+fn script_notification(value: serde_json::Value) -> Vec<u8> {
+    format!("\x1b]script;{}\x07", value).into_bytes()
+}
+
+#[async_trait::async_trait]
+impl script::ScriptHost for TabScriptHost {
+    async fn send(&self, data: &[u8]) -> Result<(), String> {
+        self.write_tx
+            .send(data.to_vec())
+            .await
+            .map_err(|_| "Connection closed".to_string())
+    }
+
+    async fn set_dtr(&self, on: bool) -> Result<(), String> {
+        self.serial_port()?.set_dtr(on)
+    }
+
+    async fn set_rts(&self, on: bool) -> Result<(), String> {
+        self.serial_port()?.set_rts(on)
+    }
+
+    async fn send_break(&self, duration: std::time::Duration) -> Result<(), String> {
+        self.serial_port()?.send_break(duration).await
+    }
+
+    async fn log_start(&self, path: &str) -> Result<String, String> {
+        start_tab_log(&self.state, &self.tab_id, path).await
+    }
+
+    async fn log_stop(&self) -> Result<(), String> {
+        stop_tab_log(&self.state, &self.tab_id).await;
+        Ok(())
+    }
+
+    fn print(&self, message: &str) {
+        let _ = self.broadcast_tx.send(script_notification(serde_json::json!({
+            "state": "output",
+            "message": message,
+        })));
+    }
+}
+
+/// Start logging a tab to `path` on behalf of a script or trigger.
+async fn start_tab_log(state: &Arc<AppState>, tab_id: &str, path: &str) -> Result<String, String> {
+    let (path, file) = open_log_file(path)
+        .await
+        .map_err(|e| format!("Failed to open log file: {}", e))?;
+    {
+        let mut connections = state.connections.lock().await;
+        let conn_state = connections
+            .get_mut(tab_id)
+            .ok_or_else(|| "Connection closed".to_string())?;
+        if conn_state.log_file.is_some() {
+            return Err("Logging already active".to_string());
+        }
+        conn_state.log_file = Some((path.clone(), file));
+        conn_state.status_changed();
+    }
+    tracing::info!("Started logging to {} (tab {})", path, tab_id);
+    persist_tabs(state).await;
+    Ok(path)
+}
+
+/// Stop logging a tab, if it is. Returns whether it was.
+async fn stop_tab_log(state: &Arc<AppState>, tab_id: &str) -> bool {
+    let stopped = {
+        let mut connections = state.connections.lock().await;
+        match connections.get_mut(tab_id) {
+            Some(cs) if cs.log_file.is_some() => {
+                cs.log_file = None;
+                cs.status_changed();
+                true
+            }
+            _ => false,
+        }
+    };
+    if stopped {
+        persist_tabs(state).await;
+    }
+    stopped
+}
+
+#[derive(Deserialize)]
+struct ScriptStartRequest {
+    tab_id: String,
+    source: String,
+    name: Option<String>,
+}
+
+async fn script_start(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ScriptStartRequest>,
+) -> impl IntoResponse {
+    let parsed = match script::Script::parse(&req.source) {
+        Ok(s) => s,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse {
+                    ok: false,
+                    message: e,
+                }),
+            );
+        }
+    };
+    let name = req.name.unwrap_or_else(|| "script".to_string());
+
+    let mut connections = state.connections.lock().await;
+    let conn_state = match connections.get_mut(&req.tab_id) {
+        Some(cs) => cs,
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse {
+                    ok: false,
+                    message: "No connection for this tab".to_string(),
+                }),
+            );
+        }
+    };
+    if let Some(run) = &conn_state.script {
+        if run.status.lock().unwrap().state == script::RunState::Running {
+            return (
+                StatusCode::CONFLICT,
+                Json(ApiResponse {
+                    ok: false,
+                    message: "A script is already running in this tab".to_string(),
+                }),
+            );
+        }
+    }
+
+    spawn_script(&state, &req.tab_id, conn_state, &name, async move { Ok(parsed) });
+    (
+        StatusCode::OK,
+        Json(ApiResponse {
+            ok: true,
+            message: format!("Started {}", name),
+        }),
+    )
+}
+
+/// Run a script against a tab, replacing any finished one. `script` is
+/// awaited inside the task, so it can do slow work (like reading the
+/// keychain) without holding up the caller; if it fails, so does the run.
+fn spawn_script(
+    state: &Arc<AppState>,
+    tab_id: &str,
+    conn_state: &mut ConnectionState,
+    name: &str,
+    script: impl std::future::Future<Output = Result<script::Script, String>> + Send + 'static,
+) {
+    let broadcast_tx = conn_state.broadcast_tx.clone();
+    let host = TabScriptHost {
+        state: state.clone(),
+        tab_id: tab_id.to_string(),
+        write_tx: conn_state.write_tx(),
+        port: match &conn_state.connection {
+            ConnectionKind::Serial(c) => Some(c.port.clone()),
+            ConnectionKind::Ssh(_) => None,
+        },
+        broadcast_tx: broadcast_tx.clone(),
+    };
+    let rx = broadcast_tx.subscribe();
+    let status = Arc::new(std::sync::Mutex::new(script::ScriptStatus::new(name)));
+    let status_for_task = status.clone();
+    let tab_id = tab_id.to_string();
+    let _ = broadcast_tx.send(script_notification(serde_json::json!({
+        "state": "started",
+        "name": name,
+    })));
+    tracing::info!("Starting script {} (tab {})", name, tab_id);
+
+    let handle = tokio::spawn(async move {
+        let result = match script.await {
+            Ok(parsed) => script::run(&parsed, &host, rx, is_notification, &status_for_task).await,
+            Err(e) => Err(e),
+        };
+        let (state, message) = match result {
+            Ok(()) => (script::RunState::Finished, None),
+            Err(e) => (script::RunState::Failed, Some(e)),
+        };
+        {
+            let mut status = status_for_task.lock().unwrap();
+            status.state = state;
+            status.message = message.clone();
+        }
+        tracing::info!("Script ended (tab {}): {:?} {}", tab_id, state, message.as_deref().unwrap_or(""));
+        let _ = host.broadcast_tx.send(script_notification(serde_json::json!({
+            "state": state,
+            "message": message,
+        })));
+    });
+
+    conn_state.script = Some(ScriptRun { status, handle });
+}
+
+async fn script_status(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let connections = state.connections.lock().await;
+    let tab_id = query.tab_id.unwrap_or_default();
+    let status = connections
+        .get(&tab_id)
+        .and_then(|cs| cs.script.as_ref())
+        .map(|run| run.status.lock().unwrap().clone());
+    Json(serde_json::json!({ "script": status }))
+}
+
+#[derive(Deserialize)]
+struct ScriptAbortRequest {
+    tab_id: String,
+}
+
+async fn script_abort(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ScriptAbortRequest>,
+) -> impl IntoResponse {
+    let connections = state.connections.lock().await;
+    let conn_state = connections.get(&req.tab_id);
+    let run = match conn_state.and_then(|cs| cs.script.as_ref()) {
+        Some(run) if run.status.lock().unwrap().state == script::RunState::Running => run,
+        _ => {
+            return Json(ApiResponse {
+                ok: true,
+                message: "No script running".to_string(),
+            });
+        }
+    };
+    run.handle.abort();
+    let line = {
+        let mut status = run.status.lock().unwrap();
+        status.state = script::RunState::Aborted;
+        status.message = Some("Aborted".to_string());
+        status.line
+    };
+    if let Some(cs) = conn_state {
+        let _ = cs.broadcast_tx.send(script_notification(serde_json::json!({
+            "state": script::RunState::Aborted,
+            "message": format!("Aborted at line {}", line),
+        })));
+    }
+    tracing::info!("Script aborted (tab {})", req.tab_id);
+    Json(ApiResponse {
+        ok: true,
+        message: "Script aborted".to_string(),
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Hook scripts
+// ---------------------------------------------------------------------------
+
+fn script_files_dir() -> PathBuf {
+    app_data_dir().join("script-files")
+}
+
+/// What a hook script's `status()` returns.
+fn hook_status_map(tab_id: &str, conn_state: Option<&ConnectionState>) -> rhai::Map {
+    let mut map = rhai::Map::new();
+    map.insert("tab_id".into(), tab_id.into());
+    map.insert("connected".into(), conn_state.is_some().into());
+    if let Some(cs) = conn_state {
+        let (kind, port) = match &cs.connection {
+            ConnectionKind::Serial(c) => ("serial", c.port_name.clone()),
+            ConnectionKind::Ssh(c) => ("ssh", format!("ssh://{}:{}", c.config.host, c.config.port)),
+        };
+        map.insert("type".into(), kind.into());
+        map.insert("port".into(), port.into());
+        map.insert("logging".into(), cs.log_file.is_some().into());
+    }
+    map
+}
+
+/// Start the tab's hook script, if it has one, on a connection that was
+/// just added.
+async fn attach_hooks_on_connect(
+    state: &Arc<AppState>,
+    tab_id: &str,
+    connections: &mut HashMap<String, ConnectionState>,
+) {
+    let attachment = state.hook_scripts.lock().await.get(tab_id).cloned();
+    if let (Some(attachment), Some(conn_state)) = (attachment, connections.get_mut(tab_id)) {
+        match start_hooks(state, tab_id, conn_state, attachment) {
+            Ok(run) => conn_state.hooks = Some(run),
+            Err(e) => tracing::error!("Failed to start hook script (tab {}): {}", tab_id, e),
+        }
+    }
+}
+
+fn start_hooks(
+    state: &Arc<AppState>,
+    tab_id: &str,
+    conn_state: &ConnectionState,
+    attachment: HookAttachment,
+) -> Result<HookRun, String> {
+    let script = hooks::HookScript::compile(&attachment.source)?;
+    let status = Arc::new(std::sync::Mutex::new(hooks::HookStatus {
+        name: attachment.name,
+        running: true,
+        ..Default::default()
+    }));
+    let (disconnected_tx, disconnected_rx) = oneshot::channel();
+    let status_changed = Arc::new(Notify::new());
+    let handle = tokio::spawn(run_hooks(
+        state.clone(),
+        tab_id.to_string(),
+        script,
+        status.clone(),
+        status_changed.clone(),
+        conn_state.broadcast_tx.subscribe(),
+        conn_state.broadcast_tx.clone(),
+        conn_state.write_tx(),
+        disconnected_rx,
+    ));
+    Ok(HookRun {
+        status,
+        status_changed,
+        handle,
+        _disconnected: disconnected_tx,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_hooks(
+    state: Arc<AppState>,
+    tab_id: String,
+    mut script: hooks::HookScript,
+    status: Arc<std::sync::Mutex<hooks::HookStatus>>,
+    status_changed: Arc<Notify>,
+    mut rx: broadcast::Receiver<Vec<u8>>,
+    broadcast_tx: broadcast::Sender<Vec<u8>>,
+    write_tx: mpsc::Sender<Vec<u8>>,
+    mut disconnected: oneshot::Receiver<()>,
+) {
+    let report = |message: String| {
+        let _ = broadcast_tx.send(script_notification(serde_json::json!({
+            "state": "output",
+            "message": message,
+        })));
+    };
+    let mut timer: Option<tokio::time::Interval> = None;
+
+    script.set_status(hook_status_map(&tab_id, state.connections.lock().await.get(&tab_id)));
+    let (returned, loaded) = in_hook_thread(script, |script| script.load()).await;
+    script = returned;
+    match loaded {
+        Ok(actions) => perform_hook_actions(actions, &write_tx, &report, &mut timer).await,
+        Err(e) => {
+            tracing::warn!("Hook script failed to load (tab {}): {}", tab_id, e);
+            report(format!("Hook script error: {}", e));
+            let mut status = status.lock().unwrap();
+            status.running = false;
+            status.errors += 1;
+            status.last_error = Some(e);
+            return;
+        }
+    }
+
+    let mut lines = ansi::LineSplitter::new();
+    let mut pending = vec![hooks::HookEvent::Connect];
+    let mut connected = true;
+    while connected || !pending.is_empty() {
+        if pending.is_empty() {
+            let tick = async {
+                match timer.as_mut() {
+                    Some(t) => {
+                        t.tick().await;
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = &mut disconnected => {
+                    connected = false;
+                    script.set_status(hook_status_map(&tab_id, None));
+                    pending.push(hooks::HookEvent::Disconnect);
+                }
+                _ = status_changed.notified() => {
+                    let conn_status = hook_status_map(&tab_id, state.connections.lock().await.get(&tab_id));
+                    script.set_status(conn_status);
+                }
+                result = rx.recv() => match result {
+                    Ok(data) if !is_notification(&data) => {
+                        pending.extend(lines.feed(&data).into_iter().map(hooks::HookEvent::Line));
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => {
+                        connected = false;
+                        script.set_status(hook_status_map(&tab_id, None));
+                        pending.push(hooks::HookEvent::Disconnect);
+                    }
+                },
+                _ = tick => pending.push(hooks::HookEvent::Timer),
+            }
+            continue;
+        }
+
+        let event = pending.remove(0);
+        let (returned, (actions, result)) = in_hook_thread(script, |script| script.handle(event)).await;
+        script = returned;
+        perform_hook_actions(actions, &write_tx, &report, &mut timer).await;
+        let mut status = status.lock().unwrap();
+        match result {
+            Ok(true) => status.callbacks += 1,
+            Ok(false) => {}
+            Err(e) => {
+                tracing::warn!("Hook script error (tab {}): {}", tab_id, e);
+                report(format!("Hook script error: {}", e));
+                status.callbacks += 1;
+                status.errors += 1;
+                status.last_error = Some(e);
+            }
+        }
+    }
+    status.lock().unwrap().running = false;
+}
+
+/// Run the hook script on a blocking thread: a callback may take up to
+/// the script's operation limit.
+async fn in_hook_thread<T: Send + 'static>(
+    mut script: hooks::HookScript,
+    f: impl FnOnce(&mut hooks::HookScript) -> T + Send + 'static,
+) -> (hooks::HookScript, T) {
+    tokio::task::spawn_blocking(move || {
+        let out = f(&mut script);
+        (script, out)
+    })
+    .await
+    .expect("Hook script panicked")
+}
+
+async fn perform_hook_actions(
+    actions: Vec<hooks::HookAction>,
+    write_tx: &mpsc::Sender<Vec<u8>>,
+    report: &impl Fn(String),
+    timer: &mut Option<tokio::time::Interval>,
+) {
+    for action in actions {
+        match action {
+            hooks::HookAction::Send(data) => {
+                let _ = write_tx.send(data).await;
+            }
+            hooks::HookAction::Print(message) => report(message),
+            hooks::HookAction::WriteFile { name, data, append } => {
+                if let Err(e) = write_script_file(&name, &data, append).await {
+                    report(format!("Hook script error: {}", e));
+                }
+            }
+            hooks::HookAction::SetTimer(interval) => {
+                *timer = interval.map(|period| {
+                    let mut t = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                    t.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                    t
+                });
+            }
+        }
+    }
+}
+
+async fn write_script_file(name: &str, data: &str, append: bool) -> Result<(), String> {
+    let path = hooks::sandbox_path(&script_files_dir(), name)?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(&path)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    file.write_all(data.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[derive(Deserialize)]
+struct HookAttachRequest {
+    tab_id: String,
+    source: String,
+    name: Option<String>,
+}
+
+async fn hooks_attach(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<HookAttachRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = hooks::HookScript::compile(&req.source) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                ok: false,
+                message: e,
+            }),
+        );
+    }
+    let attachment = HookAttachment {
+        name: req.name.unwrap_or_else(|| "hooks".to_string()),
+        source: req.source,
+    };
+    let name = attachment.name.clone();
+
+    let mut connections = state.connections.lock().await;
+    state
+        .hook_scripts
+        .lock()
+        .await
+        .insert(req.tab_id.clone(), attachment.clone());
+    if let Some(conn_state) = connections.get_mut(&req.tab_id) {
+        if let Some(old) = conn_state.hooks.take() {
+            old.stop();
+        }
+        match start_hooks(&state, &req.tab_id, conn_state, attachment) {
+            Ok(run) => conn_state.hooks = Some(run),
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse {
+                        ok: false,
+                        message: e,
+                    }),
+                );
+            }
+        }
+    }
+    tracing::info!("Attached hook script {} (tab {})", name, req.tab_id);
+    (
+        StatusCode::OK,
+        Json(ApiResponse {
+            ok: true,
+            message: format!("Attached {}", name),
+        }),
+    )
+}
+
+#[derive(Deserialize)]
+struct HookDetachRequest {
+    tab_id: String,
+}
+
+async fn hooks_detach(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<HookDetachRequest>,
+) -> impl IntoResponse {
+    let mut connections = state.connections.lock().await;
+    let removed = state.hook_scripts.lock().await.remove(&req.tab_id);
+    if let Some(run) = connections.get_mut(&req.tab_id).and_then(|cs| cs.hooks.take()) {
+        run.stop();
+    }
+    Json(ApiResponse {
+        ok: true,
+        message: if removed.is_some() {
+            "Hook script detached".to_string()
+        } else {
+            "No hook script attached".to_string()
+        },
+    })
+}
+
+async fn hooks_status(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let tab_id = query.tab_id.unwrap_or_default();
+    let connections = state.connections.lock().await;
+    let attached = state.hook_scripts.lock().await.get(&tab_id).map(|a| a.name.clone());
+    let status = connections
+        .get(&tab_id)
+        .and_then(|cs| cs.hooks.as_ref())
+        .map(|run| run.status.lock().unwrap().clone());
+    Json(serde_json::json!({
+        "attached": attached.is_some(),
+        "name": attached,
+        "status": status,
+    }))
+}
+
+// ---------------------------------------------------------------------------
+// Trigger rules
+// ---------------------------------------------------------------------------
+
+/// Use the trigger rules saved in `session_id` for a tab about to connect.
+async fn load_session_triggers(state: &Arc<AppState>, tab_id: &str, session_id: &str) {
+    let rules = state
+        .sessions
+        .get(session_id)
+        .map(|s| s.triggers)
+        .unwrap_or_default();
+    let mut triggers = state.triggers.lock().await;
+    let counters = triggers
+        .remove(tab_id)
+        .map(|t| t.counters)
+        .unwrap_or_default();
+    triggers.insert(
+        tab_id.to_string(),
+        TabTriggers { rules, counters },
+    );
+}
+
+/// Start evaluating the tab's trigger rules on a connection that was just
+/// added.
+async fn start_triggers_on_connect(
+    state: &Arc<AppState>,
+    tab_id: &str,
+    connections: &mut HashMap<String, ConnectionState>,
+) {
+    let triggers = state.triggers.lock().await;
+    if let (Some(tab_triggers), Some(conn_state)) = (triggers.get(tab_id), connections.get_mut(tab_id)) {
+        match start_triggers(state, tab_id, conn_state, tab_triggers) {
+            Ok(run) => conn_state.triggers = run,
+            Err(e) => tracing::error!("Invalid trigger rules (tab {}): {}", tab_id, e),
+        }
+    }
+}
+
+fn start_triggers(
+    state: &Arc<AppState>,
+    tab_id: &str,
+    conn_state: &ConnectionState,
+    tab_triggers: &TabTriggers,
+) -> Result<Option<TriggerRun>, String> {
+    let set = triggers::TriggerSet::compile(&tab_triggers.rules)?;
+    if set.is_empty() {
+        return Ok(None);
+    }
+    let handle = tokio::spawn(run_triggers(
+        state.clone(),
+        tab_id.to_string(),
+        set,
+        tab_triggers.counters.clone(),
+        conn_state.broadcast_tx.subscribe(),
+        conn_state.broadcast_tx.clone(),
+        conn_state.write_tx(),
+    ));
+    Ok(Some(TriggerRun { handle }))
+}
+
+async fn run_triggers(
+    state: Arc<AppState>,
+    tab_id: String,
+    set: triggers::TriggerSet,
+    counters: Arc<std::sync::Mutex<HashMap<String, u64>>>,
+    mut rx: broadcast::Receiver<Vec<u8>>,
+    broadcast_tx: broadcast::Sender<Vec<u8>>,
+    write_tx: mpsc::Sender<Vec<u8>>,
+) {
+    let mut lines = ansi::LineSplitter::new();
+    loop {
+        let data = match rx.recv().await {
+            Ok(data) if !is_notification(&data) => data,
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        for line in lines.feed(&data) {
+            for fired in set.evaluate(&line) {
+                tracing::debug!("Trigger {} matched (tab {}): {}", fired.rule, tab_id, line);
+                for action in fired.actions {
+                    match action {
+                        triggers::TriggerAction::Send { data } => {
+                            let _ = write_tx.send(data.into_bytes()).await;
+                        }
+                        triggers::TriggerAction::LogStart { path } => {
+                            if let Err(e) = start_tab_log(&state, &tab_id, &path).await {
+                                tracing::warn!("Trigger {} (tab {}): {}", fired.rule, tab_id, e);
+                            }
+                        }
+                        triggers::TriggerAction::LogStop => {
+                            stop_tab_log(&state, &tab_id).await;
+                        }
+                        triggers::TriggerAction::Notify { message } => {
+                            let msg = serde_json::json!({ "rule": fired.rule, "message": message });
+                            let _ = broadcast_tx.send(format!("\x1b]trigger;{}\x07", msg).into_bytes());
+                        }
+                        triggers::TriggerAction::Counter { name } => {
+                            *counters.lock().unwrap().entry(name).or_insert(0) += 1;
+                        }
+                        triggers::TriggerAction::Disconnect => {
+                            tracing::info!("Trigger {} disconnecting tab {}", fired.rule, tab_id);
+                            // Closing the connection aborts this task, so do
+                            // it from another one
+                            let state = state.clone();
+                            let tab_id = tab_id.clone();
+                            tokio::spawn(async move {
+                                close_connection(&state, &tab_id).await;
+                            });
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Scheduled sends
+// ---------------------------------------------------------------------------
+
+/// Run the tab's scheduled sends, if it has any, on a connection that was
+/// just added.
+async fn start_scheduler_on_connect(
+    state: &Arc<AppState>,
+    tab_id: &str,
+    connections: &mut HashMap<String, ConnectionState>,
+) {
+    let jobs = state.schedules.lock().await.get(tab_id).cloned();
+    if let (Some(jobs), Some(conn_state)) = (jobs, connections.get_mut(tab_id)) {
+        conn_state.scheduler = Some(start_scheduler(tab_id, conn_state, jobs));
+    }
+}
+
+fn start_scheduler(
+    tab_id: &str,
+    conn_state: &ConnectionState,
+    jobs: Arc<TabJobs>,
+) -> SchedulerRun {
+    jobs.schedule.lock().unwrap().start(chrono::Local::now());
+    let handle = tokio::spawn(run_scheduler(
+        tab_id.to_string(),
+        jobs.clone(),
+        conn_state.write_tx(),
+        conn_state.zmodem_active.clone(),
+    ));
+    SchedulerRun { jobs, handle }
+}
+
+async fn run_scheduler(
+    tab_id: String,
+    jobs: Arc<TabJobs>,
+    write_tx: mpsc::Sender<Vec<u8>>,
+    zmodem_active: Arc<AtomicBool>,
+) {
+    loop {
+        let wakeup = jobs.schedule.lock().unwrap().next_wakeup();
+        let sleep = async {
+            match wakeup {
+                Some(at) => {
+                    let wait = (at - chrono::Local::now().timestamp_millis()).max(0);
+                    tokio::time::sleep(std::time::Duration::from_millis(wait as u64)).await;
+                }
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = jobs.changed.notified() => continue,
+            _ = sleep => {}
+        }
+
+        let due = jobs.schedule.lock().unwrap().take_due(chrono::Local::now());
+        for job in due {
+            // Don't inject data into a file transfer
+            if zmodem_active.load(Ordering::Relaxed) {
+                tracing::debug!(
+                    "Skipping scheduled send {} during transfer (tab {})",
+                    job.id,
+                    tab_id
+                );
+                continue;
+            }
+            if write_tx.send(job.bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn schedule_get(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let tab_id = query.tab_id.unwrap_or_default();
+    let jobs = state
+        .schedules
+        .lock()
+        .await
+        .get(&tab_id)
+        .map(|jobs| jobs.schedule.lock().unwrap().status())
+        .unwrap_or_default();
+    Json(serde_json::json!({ "jobs": jobs }))
+}
+
+#[derive(Deserialize)]
+struct ScheduleUpdateRequest {
+    tab_id: String,
+    jobs: Vec<schedule::Job>,
+}
+
+/// Replace a tab's scheduled sends. They take effect right away if the
+/// tab is connected, otherwise from its next connection.
+async fn schedule_put(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ScheduleUpdateRequest>,
+) -> impl IntoResponse {
+    let mut jobs = req.jobs;
+    for job in jobs.iter_mut().filter(|j| j.id.is_empty()) {
+        job.id = sessions::gen_id();
+    }
+    let count = jobs.len();
+
+    let mut connections = state.connections.lock().await;
+    let tab_jobs = state
+        .schedules
+        .lock()
+        .await
+        .entry(req.tab_id.clone())
+        .or_default()
+        .clone();
+    if let Err(e) = tab_jobs.schedule.lock().unwrap().set_jobs(jobs) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                ok: false,
+                message: e,
+            }),
+        );
+    }
+    if let Some(conn_state) = connections.get_mut(&req.tab_id) {
+        if conn_state.scheduler.is_some() {
+            tab_jobs.schedule.lock().unwrap().start(chrono::Local::now());
+            tab_jobs.changed.notify_one();
+        } else {
+            conn_state.scheduler = Some(start_scheduler(&req.tab_id, conn_state, tab_jobs));
+        }
+    }
+    tracing::info!("Scheduled {} jobs (tab {})", count, req.tab_id);
+    (
+        StatusCode::OK,
+        Json(ApiResponse {
+            ok: true,
+            message: format!("{} scheduled jobs", count),
+        }),
+    )
+}
+
+// ---------------------------------------------------------------------------
+// Auto-login
+// ---------------------------------------------------------------------------
+
+/// Run the auto-login of the tab's session, if it has one, on a connection
+/// that was just added.
+async fn start_auto_login_on_connect(
+    state: &Arc<AppState>,
+    tab_id: &str,
+    connections: &mut HashMap<String, ConnectionState>,
+) {
+    let Some(session_id) = tab_session(state, tab_id).await else {
+        return;
+    };
+    let profile = match state.sessions.get(&session_id).and_then(|s| s.auto_login) {
+        Some(profile) if profile.enabled => profile,
+        _ => return,
+    };
+    let conn_state = match connections.get_mut(tab_id) {
+        Some(cs) => cs,
+        None => return,
+    };
+    let script = async move {
+        let account = secrets::auto_login_account(&session_id);
+        let password = tokio::task::spawn_blocking(move || secrets::get(&account))
+            .await
+            .map_err(|e| format!("Keychain task failed: {}", e))??;
+        profile.script(password.as_deref())
+    };
+    spawn_script(state, tab_id, conn_state, "auto-login", script);
+}
+
+async fn auto_login_get(
+    State(state): State<Arc<AppState>>,
+    AxumPath(id): AxumPath<String>,
+) -> impl IntoResponse {
+    let session = match state.sessions.get(&id) {
+        Some(s) => s,
+        None => return store_error_response(sessions::StoreError::NotFound(format!("Session {}", id))),
+    };
+    let account = secrets::auto_login_account(&id);
+    let has_password = match tokio::task::spawn_blocking(move || secrets::get(&account)).await {
+        Ok(Ok(password)) => password.is_some(),
+        Ok(Err(e)) => {
+            tracing::warn!("{}", e);
+            false
+        }
+        Err(_) => false,
+    };
+    Json(serde_json::json!({
+        "ok": true,
+        "auto_login": session.auto_login,
+        "has_password": has_password,
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct AutoLoginPasswordRequest {
+    /// Empty or missing removes the saved password
+    password: Option<String>,
+}
+
+/// Save or remove the auto-login password of a session in the keychain.
+async fn auto_login_password_put(
+    State(state): State<Arc<AppState>>,
+    AxumPath(id): AxumPath<String>,
+    Json(req): Json<AutoLoginPasswordRequest>,
+) -> impl IntoResponse {
+    if state.sessions.get(&id).is_none() {
+        return store_error_response(sessions::StoreError::NotFound(format!("Session {}", id)));
+    }
+    let account = secrets::auto_login_account(&id);
+    let result = tokio::task::spawn_blocking(move || match req.password.filter(|p| !p.is_empty()) {
+        Some(password) => secrets::set(&account, &password).map(|_| "Password saved"),
+        None => secrets::delete(&account).map(|_| "Password removed"),
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("Keychain task failed: {}", e)));
+    match result {
+        Ok(message) => store_ok_response(message),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse {
+                ok: false,
+                message: e,
+            }),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct TriggersResponse {
+    session_id: Option<String>,
+    rules: Vec<triggers::TriggerRule>,
+    counters: HashMap<String, u64>,
+}
+
+async fn triggers_get(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let tab_id = query.tab_id.unwrap_or_default();
+    let session_id = tab_session(&state, &tab_id).await;
+    let triggers = state.triggers.lock().await;
+    Json(match triggers.get(&tab_id) {
+        Some(t) => TriggersResponse {
+            session_id,
+            rules: t.rules.clone(),
+            counters: t.counters.lock().unwrap().clone(),
+        },
+        None => TriggersResponse {
+            session_id,
+            rules: Vec::new(),
+            counters: HashMap::new(),
+        },
+    })
+}
+
+#[derive(Deserialize)]
+struct TriggersUpdateRequest {
+    tab_id: String,
+    rules: Vec<triggers::TriggerRule>,
+}
+
+/// Replace a tab's trigger rules. They take effect immediately and are
+/// saved to the tab's session, if it has one.
+async fn triggers_put(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<TriggersUpdateRequest>,
+) -> axum::response::Response {
+    if let Err(e) = triggers::TriggerSet::compile(&req.rules) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                ok: false,
+                message: e,
+            }),
+        )
+            .into_response();
+    }
+    let mut rules = req.rules;
+    for rule in rules.iter_mut().filter(|r| r.id.is_empty()) {
+        rule.id = sessions::gen_id();
+    }
+
+    let session_id = tab_session(&state, &req.tab_id).await;
+    let mut connections = state.connections.lock().await;
+    let mut triggers = state.triggers.lock().await;
+    let tab_triggers = triggers.entry(req.tab_id.clone()).or_insert_with(|| TabTriggers {
+        rules: Vec::new(),
+        counters: Default::default(),
+    });
+    tab_triggers.rules = rules;
+
+    if let Some(session_id) = &session_id {
+        if let Some(mut session) = state.sessions.get(session_id) {
+            session.triggers = tab_triggers.rules.clone();
+            let session_id = session_id.clone();
+            if let Err(e) = with_store(&state, move |store| store.update(&session_id, session)).await {
+                return store_error_response(e);
+            }
+        }
+    }
+    if let Some(conn_state) = connections.get_mut(&req.tab_id) {
+        conn_state.triggers = None;
+        match start_triggers(&state, &req.tab_id, conn_state, tab_triggers) {
+            Ok(run) => conn_state.triggers = run,
+            Err(e) => tracing::error!("Invalid trigger rules (tab {}): {}", req.tab_id, e),
+        }
+    }
+    (
+        StatusCode::OK,
+        Json(ApiResponse {
+            ok: true,
+            message: format!("{} trigger rules", tab_triggers.rules.len()),
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct TriggerCountersResetRequest {
+    tab_id: String,
+}
+
+async fn trigger_counters_reset(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<TriggerCountersResetRequest>,
+) -> impl IntoResponse {
+    if let Some(t) = state.triggers.lock().await.get(&req.tab_id) {
+        t.counters.lock().unwrap().clear();
+    }
+    Json(ApiResponse {
+        ok: true,
+        message: "Counters reset".to_string(),
+    })
+}
+
+// ---------------------------------------------------------------------------
+// File upload (raw / ASCII / HEX)
+// ---------------------------------------------------------------------------
+
+fn upload_notification(status: &upload::UploadStatus) -> Vec<u8> {
+    format!(
+        "\x1b]upload;{}\x07",
+        serde_json::to_string(status).unwrap_or_default()
+    )
+    .into_bytes()
+}
+
+#[derive(Deserialize)]
+struct UploadStartRequest {
+    tab_id: String,
+    path: String,
+    #[serde(flatten)]
+    options: upload::UploadOptions,
+}
+
+async fn read_upload_file(path: &str) -> Result<(String, Vec<u8>), String> {
+    let path = PathBuf::from(expand_home(path));
+    let metadata = tokio::fs::metadata(&path)
+        .await
+        .map_err(|e| format!("Failed to open file: {}", e))?;
+    if !metadata.is_file() {
+        return Err("Not a file".to_string());
+    }
+    if metadata.len() > upload::MAX_UPLOAD {
+        return Err(format!(
+            "File is too large ({} bytes, at most {})",
+            metadata.len(),
+            upload::MAX_UPLOAD
+        ));
+    }
+    let data = tokio::fs::read(&path)
+        .await
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    let filename = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    Ok((filename, data))
+}
+
+async fn upload_start(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<UploadStartRequest>,
+) -> impl IntoResponse {
+    let bad_request = |message: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                ok: false,
+                message,
+            }),
+        )
+    };
+    let patterns = match req.options.validate() {
+        Ok(patterns) => patterns,
+        Err(e) => return bad_request(e),
+    };
+    let (filename, data) = match read_upload_file(&req.path).await {
+        Ok(file) => file,
+        Err(e) => return bad_request(e),
+    };
+    // HEX mode: the whole file is checked before a record goes out
+    let image = if req.options.mode == upload::UploadMode::Hex {
+        match hexfile::parse(&data, req.options.address_range()) {
+            Ok(image) => Some(image),
+            Err(e) => return bad_request(format!("{}: {}", filename, e)),
+        }
+    } else {
+        None
+    };
+
+    let mut connections = state.connections.lock().await;
+    let conn_state = match connections.get_mut(&req.tab_id) {
+        Some(cs) => cs,
+        None => return bad_request("No connection for this tab".to_string()),
+    };
+    let busy = if conn_state.zmodem_active.load(Ordering::Relaxed) {
+        Some("A file transfer is in progress")
+    } else if conn_state
+        .upload
+        .as_ref()
+        .is_some_and(|run| run.status.lock().unwrap().state == upload::UploadState::Running)
+    {
+        Some("An upload is already running in this tab")
+    } else {
+        None
+    };
+    if let Some(message) = busy {
+        return (
+            StatusCode::CONFLICT,
+            Json(ApiResponse {
+                ok: false,
+                message: message.to_string(),
+            }),
+        );
+    }
+
+    let options = req.options;
+    let message = match &image {
+        Some(image) => format!(
+            "Uploading {} ({}): {} records, {} data bytes{}",
+            filename,
+            image.format.name(),
+            image.records.len(),
+            image.data_bytes,
+            image
+                .span
+                .map(|(low, high)| format!(" at {:08X}-{:08X}", low, high))
+                .unwrap_or_default()
+        ),
+        None => format!("Uploading {}", filename),
+    };
+    let status = Arc::new(std::sync::Mutex::new(upload::UploadStatus::new(
+        &filename,
+        options.mode,
+        upload::split(&data, &options),
+    )));
+    let status_for_task = status.clone();
+    let broadcast_tx = conn_state.broadcast_tx.clone();
+    let write_tx = conn_state.write_tx();
+    let rx = broadcast_tx.subscribe();
+    let tab_id = req.tab_id.clone();
+    let _ = broadcast_tx.send(upload_notification(&status.lock().unwrap()));
+    tracing::info!(
+        "Uploading {} ({} bytes, {:?}) (tab {})",
+        filename,
+        data.len(),
+        options.mode,
+        tab_id
+    );
+
+    let handle = tokio::spawn(async move {
+        let progress_tx = broadcast_tx.clone();
+        let result = upload::send(
+            upload::split(&data, &options),
+            &options,
+            &patterns,
+            &write_tx,
+            rx,
+            is_notification,
+            &status_for_task,
+            move |status| {
+                let _ = progress_tx.send(upload_notification(status));
+            },
+        )
+        .await;
+        let status = {
+            let mut status = status_for_task.lock().unwrap();
+            match result {
+                Ok(()) => status.finish(upload::UploadState::Completed, None),
+                Err(mut e) => {
+                    // Point at the record in the file, not just its number
+                    let record = image
+                        .as_ref()
+                        .and_then(|image| image.records.get(status.pieces_sent));
+                    if let Some(record) = record {
+                        e = match record.address {
+                            Some(address) => format!(
+                                "{} (line {}, address {:08X})",
+                                e, record.line, address
+                            ),
+                            None => format!("{} (line {})", e, record.line),
+                        };
+                        status.failed_record = Some(record.clone());
+                    }
+                    status.finish(upload::UploadState::Failed, Some(e))
+                }
+            }
+            status.clone()
+        };
+        tracing::info!(
+            "Upload of {} ended (tab {}): {:?} {}",
+            status.filename,
+            tab_id,
+            status.state,
+            status.message.as_deref().unwrap_or("")
+        );
+        let _ = broadcast_tx.send(upload_notification(&status));
+    });
+    conn_state.upload = Some(UploadRun { status, handle });
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse {
+            ok: true,
+            message,
+        }),
+    )
+}
+
+async fn upload_status(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let connections = state.connections.lock().await;
+    let tab_id = query.tab_id.unwrap_or_default();
+    let status = connections
+        .get(&tab_id)
+        .and_then(|cs| cs.upload.as_ref())
+        .map(|run| run.status.lock().unwrap().clone());
+    Json(serde_json::json!({ "upload": status }))
+}
+
+#[derive(Deserialize)]
+struct UploadCancelRequest {
+    tab_id: String,
+}
+
+async fn upload_cancel(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<UploadCancelRequest>,
+) -> impl IntoResponse {
+    let connections = state.connections.lock().await;
+    let conn_state = connections.get(&req.tab_id);
+    let run = match conn_state.and_then(|cs| cs.upload.as_ref()) {
+        Some(run) if run.status.lock().unwrap().state == upload::UploadState::Running => run,
+        _ => {
+            return Json(ApiResponse {
+                ok: true,
+                message: "No upload running".to_string(),
+            });
+        }
+    };
+    run.handle.abort();
+    let status = {
+        let mut status = run.status.lock().unwrap();
+        status.finish(upload::UploadState::Cancelled, None);
+        status.clone()
+    };
+    if let Some(cs) = conn_state {
+        let _ = cs.broadcast_tx.send(upload_notification(&status));
+    }
+    tracing::info!("Upload of {} cancelled (tab {})", status.filename, req.tab_id);
+    Json(ApiResponse {
+        ok: true,
+        message: "Upload cancelled".to_string(),
+    })
+}
+
+// ---------------------------------------------------------------------------
+// XMODEM / YMODEM
+// ---------------------------------------------------------------------------
+
+/// A transfer that receives nothing for this long has failed.
+const TRANSFER_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Route a tab's incoming data to a file transfer instead of the terminal.
+/// Fails if another transfer or an upload is using the connection. The
+/// second receiver fires when the transfer is cancelled through the API,
+/// or with an error once the connection is gone.
+async fn begin_transfer(
+    conn_state: &mut ConnectionState,
+) -> Result<(mpsc::Receiver<Vec<u8>>, oneshot::Receiver<()>), String> {
+    if conn_state
+        .upload
+        .as_ref()
+        .is_some_and(|run| run.status.lock().unwrap().state == upload::UploadState::Running)
+    {
+        return Err("An upload is running in this tab".to_string());
+    }
+    let rx = claim_transfer(&conn_state.zmodem_active, &conn_state.zmodem_data_tx_shared)
+        .await
+        .ok_or_else(|| "A file transfer is in progress".to_string())?;
+    Ok((rx, register_transfer_cancel(conn_state)))
+}
+
+/// Switch the connection to transfer mode, unless a transfer is already
+/// running, and return the receiver for its incoming data.
+async fn claim_transfer(
+    zmodem_active: &AtomicBool,
+    zmodem_data_tx_shared: &Mutex<Option<mpsc::Sender<Vec<u8>>>>,
+) -> Option<mpsc::Receiver<Vec<u8>>> {
+    // Hold the lock while claiming, so the reader never sees the transfer
+    // active without somewhere to send data
+    let mut data_tx = zmodem_data_tx_shared.lock().await;
+    if zmodem_active.swap(true, Ordering::SeqCst) {
+        return None;
+    }
+    let (tx, rx) = mpsc::channel(256);
+    *data_tx = Some(tx);
+    Some(rx)
+}
+
+/// Let the cancel API stop the transfer that is starting on the tab.
+fn register_transfer_cancel(conn_state: &mut ConnectionState) -> oneshot::Receiver<()> {
+    let (cancel_tx, cancel_rx) = oneshot::channel();
+    conn_state.transfer_cancel = Some(cancel_tx);
+    cancel_rx
+}
+
+/// Why a transfer stopped, given what its cancel receiver resolved to.
+fn transfer_cancelled(result: Result<(), oneshot::error::RecvError>) -> String {
+    match result {
+        Ok(()) => "Cancelled".to_string(),
+        Err(_) => "Connection closed".to_string(),
+    }
+}
+
+/// Move a resumed ZMODEM download to where its file continues. That means
+/// feeding zmodem2 the whole existing part, so it runs on a blocking thread.
+async fn zmodem_catch_up(mut receiver: zmodem::ZmodemReceiver) -> (zmodem::ZmodemReceiver, Vec<u8>) {
+    tokio::task::spawn_blocking(move || {
+        let response = receiver.catch_up();
+        (receiver, response)
+    })
+    .await
+    .expect("ZMODEM catch-up panicked")
+}
+
+/// Give the connection back to the terminal after a transfer. Drop the
+/// transfer's data receiver first: with nobody reading it, the reader could
+/// be stuck sending to it.
+async fn end_transfer(
+    zmodem_active: &AtomicBool,
+    zmodem_data_tx_shared: &Mutex<Option<mpsc::Sender<Vec<u8>>>>,
+) {
+    *zmodem_data_tx_shared.lock().await = None;
+    zmodem_active.store(false, Ordering::SeqCst);
+}
+
+/// The download settings of the session the tab was opened from, or the
+/// defaults for an ad-hoc connection.
+async fn download_settings(state: &AppState, tab_id: &str) -> zmodem::DownloadSettings {
+    let Some(session_id) = tab_session(state, tab_id).await else {
+        return zmodem::DownloadSettings::default();
+    };
+    state
+        .sessions
+        .get(&session_id)
+        .and_then(|s| s.zmodem)
+        .unwrap_or_default()
+}
+
+/// Directory received files are saved to, created if missing.
+fn download_directory(settings: &zmodem::DownloadSettings) -> Result<PathBuf, String> {
+    let dir = match settings.directory.as_deref().map(str::trim) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(expand_home(dir)),
+        _ => dirs::download_dir().unwrap_or_else(|| PathBuf::from("/tmp")),
+    };
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+/// Add a finished (or given up) file to the tab's transfer history.
+async fn record_transfer(state: &AppState, tab_id: &str, record: transfers::Record) {
+    state
+        .transfers
+        .lock()
+        .await
+        .entry(tab_id.to_string())
+        .or_default()
+        .push(record);
+}
+
+/// Transfer status, sent on the same channel as ZMODEM's.
+fn transfer_notification(value: serde_json::Value) -> Vec<u8> {
+    format!("\x1b]zmodem;{}\x07", value).into_bytes()
+}
+
+#[derive(Deserialize)]
+struct XmodemSendRequest {
+    tab_id: String,
+    protocol: xmodem::Protocol,
+    /// XMODEM sends only one file
+    paths: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct XmodemReceiveRequest {
+    tab_id: String,
+    protocol: xmodem::Protocol,
+    /// Name to save an XMODEM file as; YMODEM sends its own names
+    filename: Option<String>,
+}
+
+/// A transfer run over the connection's data by one of the line-oriented
+/// protocol modules.
+enum LinkTransfer {
+    XmodemSend(xmodem::Protocol, Vec<(String, Vec<u8>)>),
+    XmodemReceive {
+        protocol: xmodem::Protocol,
+        dir: PathBuf,
+        filename: String,
+        overwrite: bool,
+    },
+    /// Files streamed from disk to the remote's `trz`
+    TrzszUpload(Vec<PathBuf>),
+    /// The remote's `tsz` sending into `dir`
+    TrzszDownload { dir: PathBuf, overwrite: bool },
+    KermitSend(Vec<(String, Vec<u8>)>),
+    KermitReceive { dir: PathBuf, overwrite: bool },
+}
+
+impl LinkTransfer {
+    fn kind(&self) -> &'static str {
+        match self {
+            LinkTransfer::XmodemSend(protocol, _) => protocol.name(),
+            LinkTransfer::XmodemReceive { protocol, .. } => protocol.name(),
+            LinkTransfer::TrzszUpload(_) | LinkTransfer::TrzszDownload { .. } => "trzsz",
+            LinkTransfer::KermitSend(_) | LinkTransfer::KermitReceive { .. } => "kermit",
+        }
+    }
+
+    fn sending(&self) -> bool {
+        matches!(
+            self,
+            LinkTransfer::XmodemSend(..) | LinkTransfer::TrzszUpload(_) | LinkTransfer::KermitSend(_)
+        )
+    }
+
+    /// Where received files go
+    fn dir(&self) -> Option<&Path> {
+        match self {
+            LinkTransfer::XmodemReceive { dir, .. }
+            | LinkTransfer::TrzszDownload { dir, .. }
+            | LinkTransfer::KermitReceive { dir, .. } => Some(dir),
+            _ => None,
+        }
+    }
+
+    /// What makes the remote side give up
+    fn abort_sequence(&self) -> Vec<u8> {
+        match self {
+            LinkTransfer::XmodemSend(..) | LinkTransfer::XmodemReceive { .. } => zmodem::ABORT_SEQUENCE.to_vec(),
+            LinkTransfer::TrzszUpload(_) | LinkTransfer::TrzszDownload { .. } => trzsz::fail_line("Cancelled"),
+            LinkTransfer::KermitSend(_) | LinkTransfer::KermitReceive { .. } => kermit::abort_sequence(),
+        }
+    }
+}
+
+async fn xmodem_send(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<XmodemSendRequest>,
+) -> impl IntoResponse {
+    let count = match req.protocol {
+        xmodem::Protocol::Ymodem => req.paths.len(),
+        _ => req.paths.len().min(1),
+    };
+    if count == 0 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                ok: false,
+                message: "No file to send".to_string(),
+            }),
+        );
+    }
+    let mut files = Vec::with_capacity(count);
+    for path in &req.paths[..count] {
+        match read_upload_file(path).await {
+            Ok(file) => files.push(file),
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse {
+                        ok: false,
+                        message: format!("{}: {}", path, e),
+                    }),
+                );
+            }
+        }
+    }
+    start_link_transfer(&state, &req.tab_id, LinkTransfer::XmodemSend(req.protocol, files)).await
+}
+
+async fn xmodem_receive(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<XmodemReceiveRequest>,
+) -> impl IntoResponse {
+    let settings = download_settings(&state, &req.tab_id).await;
+    let dir = match download_directory(&settings) {
+        Ok(dir) => dir,
+        Err(message) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse { ok: false, message }),
+            );
+        }
+    };
+    let filename = req
+        .filename
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| format!("xmodem-{}.bin", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+    let overwrite = settings.on_collision == zmodem::Collision::Overwrite;
+    start_link_transfer(
+        &state,
+        &req.tab_id,
+        LinkTransfer::XmodemReceive {
+            protocol: req.protocol,
+            dir,
+            filename,
+            overwrite,
+        },
+    )
+    .await
+}
+
+async fn start_link_transfer(
+    state: &Arc<AppState>,
+    tab_id: &str,
+    transfer: LinkTransfer,
+) -> (StatusCode, Json<ApiResponse>) {
+    let mut connections = state.connections.lock().await;
+    let conn_state = match connections.get_mut(tab_id) {
+        Some(cs) => cs,
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse {
+                    ok: false,
+                    message: "No connection for this tab".to_string(),
+                }),
+            );
+        }
+    };
+    let (data_rx, cancel_rx) = match begin_transfer(conn_state).await {
+        Ok(rx) => rx,
+        Err(e) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(ApiResponse {
+                    ok: false,
+                    message: e,
+                }),
+            );
+        }
+    };
+    let message = spawn_link_transfer(state, tab_id, conn_state, transfer, data_rx, cancel_rx);
+    (
+        StatusCode::OK,
+        Json(ApiResponse { ok: true, message }),
+    )
+}
+
+/// Run `transfer` on the data claimed for it, reporting progress to the
+/// tab and recording the files in its history. Returns what is starting.
+fn spawn_link_transfer(
+    state: &Arc<AppState>,
+    tab_id: &str,
+    conn_state: &ConnectionState,
+    transfer: LinkTransfer,
+    data_rx: mpsc::Receiver<Vec<u8>>,
+    mut cancel_rx: oneshot::Receiver<()>,
+) -> String {
+    let write_tx = conn_state.write_tx();
+    let mut link = xmodem::Link::new(data_rx, write_tx.clone());
+    let broadcast_tx = conn_state.broadcast_tx.clone();
+    let zmodem_active = conn_state.zmodem_active.clone();
+    let zmodem_data_tx_shared = conn_state.zmodem_data_tx_shared.clone();
+    let state = state.clone();
+    let tab_id = tab_id.to_string();
+    let kind = transfer.kind();
+    let sending = transfer.sending();
+    let message = if sending {
+        format!("Sending with {}; waiting for the receiver", kind.to_uppercase())
+    } else {
+        format!("Receiving with {}; waiting for the sender", kind.to_uppercase())
+    };
+    tracing::info!("{} (tab {})", message, tab_id);
+    let _ = broadcast_tx.send(transfer_notification(
+        serde_json::json!({"type": kind, "state": "started"}),
+    ));
+
+    tokio::spawn(async move {
+        let transfer_start = std::time::Instant::now();
+        let mut file_start = transfer_start;
+        let mut last_progress = transfer_start;
+        let progress_tx = broadcast_tx.clone();
+        let transfer_direction = if sending {
+            transfers::Direction::Send
+        } else {
+            transfers::Direction::Receive
+        };
+        // The file in progress and its bytes so far; a received one is
+        // removed if the transfer fails
+        let partial = std::sync::Mutex::new(None);
+        let finished = std::sync::Mutex::new(Vec::new());
+        let on_event = |event: xmodem::Event| match event {
+            xmodem::Event::FileStart { name, .. } => {
+                file_start = std::time::Instant::now();
+                *partial.lock().unwrap() = Some((name, 0));
+            }
+            xmodem::Event::Progress { name, bytes, size } => {
+                if let Some((_, so_far)) = partial.lock().unwrap().as_mut() {
+                    *so_far = bytes;
+                }
+                if last_progress.elapsed() >= std::time::Duration::from_millis(200) {
+                    last_progress = std::time::Instant::now();
+                    let _ = progress_tx.send(transfer_notification(serde_json::json!({
+                        "type": kind,
+                        "state": "progress",
+                        "filename": name,
+                        (if sending { "sent" } else { "received" }): bytes,
+                        "total": size.unwrap_or(0)
+                    })));
+                }
+            }
+            xmodem::Event::FileDone { name, bytes } => {
+                *partial.lock().unwrap() = None;
+                finished.lock().unwrap().push(transfers::Record::new(
+                    kind,
+                    transfer_direction,
+                    &name,
+                    bytes,
+                    file_start.elapsed(),
+                    transfers::Status::Completed,
+                ));
+                let _ = progress_tx.send(transfer_notification(serde_json::json!({
+                    "type": kind,
+                    "state": "file_complete",
+                    "filename": name,
+                    "size": bytes,
+                    "elapsedMs": file_start.elapsed().as_millis() as u64
+                })));
+            }
+        };
+        let run = async {
+            match &transfer {
+                LinkTransfer::XmodemSend(protocol, files) => {
+                    xmodem::send(*protocol, files, &mut link, on_event).await
+                }
+                LinkTransfer::XmodemReceive { protocol, dir, filename, overwrite } => {
+                    xmodem::receive(*protocol, dir, filename, *overwrite, &mut link, on_event).await
+                }
+                LinkTransfer::TrzszUpload(paths) => trzsz::upload(&mut link, paths, on_event).await,
+                LinkTransfer::TrzszDownload { dir, overwrite } => {
+                    trzsz::download(&mut link, dir, *overwrite, on_event).await
+                }
+                LinkTransfer::KermitSend(files) => kermit::send(files, &mut link, on_event).await,
+                LinkTransfer::KermitReceive { dir, overwrite } => {
+                    kermit::receive(dir, *overwrite, &mut link, on_event).await
+                }
+            }
+        };
+        let result = tokio::select! {
+            result = run => result,
+            cancelled = &mut cancel_rx => {
+                let _ = write_tx.send(transfer.abort_sequence()).await;
+                Err(transfer_cancelled(cancelled))
+            }
+        };
+        let partial = partial.into_inner().unwrap();
+        if let (Err(_), Some(dir), Some((name, _))) = (&result, transfer.dir(), &partial) {
+            let _ = std::fs::remove_file(dir.join(name));
+        }
+        drop(link);
+        end_transfer(&zmodem_active, &zmodem_data_tx_shared).await;
+
+        let mut finished = finished.into_inner().unwrap();
+        if let Some(dir) = transfer.dir() {
+            for record in &mut finished {
+                record.path = Some(dir.join(&record.filename));
+            }
+        }
+        if let (Err(e), Some((name, bytes))) = (&result, &partial) {
+            let mut record = transfers::Record::new(
+                kind,
+                transfer_direction,
+                name,
+                *bytes,
+                file_start.elapsed(),
+                transfers::Status::Failed,
+            );
+            record.message = Some(e.clone());
+            finished.push(record);
+        }
+        for record in finished {
+            record_transfer(&state, &tab_id, record).await;
+        }
+
+        let notification = match result {
+            Ok(files) => {
+                let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+                tracing::info!("{} transfer complete (tab {}): {:?}", kind, tab_id, names);
+                serde_json::json!({
+                    "type": kind,
+                    "state": "completed",
+                    "files": names,
+                    "totalBytes": files.iter().map(|f| f.size).sum::<u64>(),
+                    "elapsedMs": transfer_start.elapsed().as_millis() as u64
+                })
+            }
+            Err(e) => {
+                tracing::error!("{} transfer failed (tab {}): {}", kind, tab_id, e);
+                let partial = partial.filter(|_| !sending).map(|(name, _)| name);
+                serde_json::json!({"type": kind, "state": "failed", "message": e, "partial": partial})
+            }
+        };
+        let _ = broadcast_tx.send(transfer_notification(notification));
+    });
+
+    message
+}
+
+// ---------------------------------------------------------------------------
+// Kermit
+// ---------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+struct KermitSendRequest {
+    tab_id: String,
+    paths: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct KermitReceiveRequest {
+    tab_id: String,
+}
+
+async fn kermit_send(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<KermitSendRequest>,
+) -> impl IntoResponse {
+    if req.paths.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                ok: false,
+                message: "No file to send".to_string(),
+            }),
+        );
+    }
+    let mut files = Vec::with_capacity(req.paths.len());
+    for path in &req.paths {
+        match read_upload_file(path).await {
+            Ok(file) => files.push(file),
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse {
+                        ok: false,
+                        message: format!("{}: {}", path, e),
+                    }),
+                );
+            }
+        }
+    }
+    start_link_transfer(&state, &req.tab_id, LinkTransfer::KermitSend(files)).await
+}
+
+/// Wait for a Kermit sender, for when it was started before the tab could
+/// see its first packet.
+async fn kermit_receive(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<KermitReceiveRequest>,
+) -> impl IntoResponse {
+    let settings = download_settings(&state, &req.tab_id).await;
+    let dir = match download_directory(&settings) {
+        Ok(dir) => dir,
+        Err(message) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse { ok: false, message }),
+            );
+        }
+    };
+    let overwrite = settings.on_collision == zmodem::Collision::Overwrite;
+    start_link_transfer(&state, &req.tab_id, LinkTransfer::KermitReceive { dir, overwrite }).await
+}
+
+/// Receive from the Kermit sender the tab's `IncomingRouter` found, which
+/// has already routed the connection to `data_rx`.
+async fn start_kermit_receive(state: &Arc<AppState>, tab_id: &str, data_rx: mpsc::Receiver<Vec<u8>>) {
+    let settings = download_settings(state, tab_id).await;
+    let mut connections = state.connections.lock().await;
+    let Some(conn_state) = connections.get_mut(tab_id) else {
+        return;
+    };
+    match download_directory(&settings) {
+        Ok(dir) => {
+            let overwrite = settings.on_collision == zmodem::Collision::Overwrite;
+            let cancel_rx = register_transfer_cancel(conn_state);
+            let transfer = LinkTransfer::KermitReceive { dir, overwrite };
+            spawn_link_transfer(state, tab_id, conn_state, transfer, data_rx, cancel_rx);
+        }
+        Err(message) => {
+            tracing::error!("Kermit receive refused (tab {}): {}", tab_id, message);
+            drop(data_rx);
+            end_transfer(&conn_state.zmodem_active, &conn_state.zmodem_data_tx_shared).await;
+            let _ = conn_state.write_tx().send(kermit::abort_sequence()).await;
+            let _ = conn_state.broadcast_tx.send(transfer_notification(serde_json::json!({
+                "type": "kermit",
+                "state": "failed",
+                "message": message,
+                "partial": null
+            })));
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// trzsz
+// ---------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+struct TrzszUploadRequest {
+    tab_id: String,
+    /// Empty to turn the remote's `trz` down
+    paths: Vec<String>,
+}
+
+/// Send files to the remote's `trz`, after an `upload_request`
+/// notification.
+async fn trzsz_upload(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<TrzszUploadRequest>,
+) -> impl IntoResponse {
+    let mut paths = Vec::with_capacity(req.paths.len());
+    for path in &req.paths {
+        let path = PathBuf::from(expand_home(path));
+        if !path.is_file() {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse {
+                    ok: false,
+                    message: format!("Not a file: {}", path.display()),
+                }),
+            );
+        }
+        paths.push(path);
+    }
+    if paths.is_empty() {
+        let write_tx = match state.connections.lock().await.get(&req.tab_id) {
+            Some(cs) => cs.write_tx(),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse {
+                        ok: false,
+                        message: "No connection for this tab".to_string(),
+                    }),
+                );
+            }
+        };
+        let _ = write_tx.send(trzsz::decline_line()).await;
+        return (
+            StatusCode::OK,
+            Json(ApiResponse {
+                ok: true,
+                message: "Upload declined".to_string(),
+            }),
+        );
+    }
+    start_link_transfer(&state, &req.tab_id, LinkTransfer::TrzszUpload(paths)).await
+}
+
+/// Answer the remote's `tsz` by receiving into the session's download
+/// folder, or turn it down if the tab can't take the transfer.
+async fn start_trzsz_download(state: &Arc<AppState>, tab_id: &str) {
+    let settings = download_settings(state, tab_id).await;
+    let mut connections = state.connections.lock().await;
+    let Some(conn_state) = connections.get_mut(tab_id) else {
+        return;
+    };
+    let started = match download_directory(&settings) {
+        Ok(dir) => begin_transfer(conn_state).await.map(|rx| (dir, rx)),
+        Err(e) => Err(e),
+    };
+    match started {
+        Ok((dir, (data_rx, cancel_rx))) => {
+            let overwrite = settings.on_collision == zmodem::Collision::Overwrite;
+            let transfer = LinkTransfer::TrzszDownload { dir, overwrite };
+            spawn_link_transfer(state, tab_id, conn_state, transfer, data_rx, cancel_rx);
+        }
+        Err(message) => {
+            tracing::error!("trzsz download refused (tab {}): {}", tab_id, message);
+            let _ = conn_state.write_tx().send(trzsz::decline_line()).await;
+            let _ = conn_state.broadcast_tx.send(transfer_notification(serde_json::json!({
+                "type": "trzsz",
+                "state": "failed",
+                "message": message,
+                "partial": null
+            })));
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Firmware flashing
+// ---------------------------------------------------------------------------
+
+fn flash_notification(status: &flasher::FlashStatus) -> Vec<u8> {
+    format!(
+        "\x1b]flash;{}\x07",
+        serde_json::to_string(status).unwrap_or_default()
+    )
+    .into_bytes()
+}
+
+#[derive(Deserialize)]
+struct FlashImage {
+    path: String,
+    address: u32,
+}
+
+#[derive(Deserialize)]
+struct FlashStartRequest {
+    tab_id: String,
+    images: Vec<FlashImage>,
+    #[serde(flatten)]
+    options: flasher::FlashOptions,
+}
+
+/// Take a serial tab over to flash firmware, and give it back to the
+/// terminal afterwards. It is cancelled like a file transfer, through
+/// /api/zmodem/cancel.
+async fn flash_start(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<FlashStartRequest>,
+) -> impl IntoResponse {
+    let bad_request = |message: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                ok: false,
+                message,
+            }),
+        )
+    };
+    if let Err(e) = req.options.validate() {
+        return bad_request(e);
+    }
+    let mut segments = Vec::new();
+    for image in &req.images {
+        match read_upload_file(&image.path).await {
+            Ok((name, data)) => segments.push(flasher::Segment::new(&name, image.address, data)),
+            Err(e) => return bad_request(format!("{}: {}", image.path, e)),
+        }
+    }
+    if let Err(e) = flasher::arrange(&mut segments) {
+        return bad_request(e);
+    }
+
+    let mut connections = state.connections.lock().await;
+    let conn_state = match connections.get_mut(&req.tab_id) {
+        Some(cs) => cs,
+        None => return bad_request("No connection for this tab".to_string()),
+    };
+    let port = match &conn_state.connection {
+        ConnectionKind::Serial(c) => match c.config.flow_control.as_deref() {
+            None | Some("none") => c.port.clone(),
+            // It would hold up the data, or take over RTS
+            Some(_) => return bad_request("Turn flow control off to flash".to_string()),
+        },
+        ConnectionKind::Ssh(_) => {
+            return bad_request("Flashing needs a serial connection".to_string());
+        }
+    };
+    let (data_rx, mut cancel_rx) = match begin_transfer(conn_state).await {
+        Ok(rx) => rx,
+        Err(e) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(ApiResponse {
+                    ok: false,
+                    message: e,
+                }),
+            );
+        }
+    };
+
+    let options = req.options;
+    let status = Arc::new(std::sync::Mutex::new(flasher::FlashStatus::new(
+        options.target,
+        &segments,
+    )));
+    conn_state.flash = Some(status.clone());
+    let mut link = xmodem::Link::new(data_rx, conn_state.write_tx());
+    let broadcast_tx = conn_state.broadcast_tx.clone();
+    let zmodem_active = conn_state.zmodem_active.clone();
+    let zmodem_data_tx_shared = conn_state.zmodem_data_tx_shared.clone();
+    let tab_id = req.tab_id.clone();
+    let names: Vec<&str> = segments.iter().map(|s| s.name.as_str()).collect();
+    let message = format!(
+        "Flashing {} through the {} bootloader",
+        names.join(", "),
+        options.target.name()
+    );
+    tracing::info!("{} (tab {})", message, tab_id);
+    let _ = broadcast_tx.send(flash_notification(&status.lock().unwrap()));
+
+    tokio::spawn(async move {
+        let mut backend = options.target.backend(options.baud);
+        let progress_tx = broadcast_tx.clone();
+        let status_for_events = status.clone();
+        let mut last_progress = std::time::Instant::now();
+        let mut cancelled = false;
+        let result = flasher::run(
+            backend.as_mut(),
+            &options,
+            &segments,
+            &mut link,
+            &port,
+            |event| {
+                let mut status = status_for_events.lock().unwrap();
+                let urgent = status.update(event);
+                if urgent || last_progress.elapsed() >= std::time::Duration::from_millis(200) {
+                    last_progress = std::time::Instant::now();
+                    let _ = progress_tx.send(flash_notification(&status));
+                }
+            },
+            async {
+                let result = (&mut cancel_rx).await;
+                cancelled = result.is_ok();
+                transfer_cancelled(result)
+            },
+        )
+        .await;
+        drop(link);
+        end_transfer(&zmodem_active, &zmodem_data_tx_shared).await;
+
+        let status = {
+            let mut status = status.lock().unwrap();
+            match result {
+                Ok(_) => status.finish(flasher::FlashState::Completed, None),
+                Err(_) if cancelled => status.finish(flasher::FlashState::Cancelled, None),
+                Err(e) => status.finish(flasher::FlashState::Failed, Some(e)),
+            }
+            status.clone()
+        };
+        tracing::info!(
+            "Flash of {} ended (tab {}): {:?} {}",
+            status.files.join(", "),
+            tab_id,
+            status.state,
+            status.message.as_deref().unwrap_or("")
+        );
+        let _ = broadcast_tx.send(flash_notification(&status));
+    });
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse { ok: true, message }),
+    )
+}
+
+async fn flash_status(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let connections = state.connections.lock().await;
+    let tab_id = query.tab_id.unwrap_or_default();
+    let status = connections
+        .get(&tab_id)
+        .and_then(|cs| cs.flash.as_ref())
+        .map(|status| status.lock().unwrap().clone());
+    Json(serde_json::json!({ "flash": status }))
+}
+
+// ---------------------------------------------------------------------------
+// ZMODEM REST handlers
+// ---------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+struct ZmodemSendRequest {
+    tab_id: String,
+    paths: Vec<String>,
+}
+
+/// Send files to a remote `rz`, answering its ZRINIT.
+async fn zmodem_send(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ZmodemSendRequest>,
+) -> impl IntoResponse {
+    let paths: Vec<PathBuf> = req.paths.iter().map(|p| PathBuf::from(expand_home(p))).collect();
+    let mut sender = match zmodem::ZmodemSender::new(paths) {
+        Ok(sender) => sender,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse {
+                    ok: false,
+                    message: e,
+                }),
+            );
+        }
+    };
+
+    let mut connections = state.connections.lock().await;
+    let conn_state = match connections.get_mut(&req.tab_id) {
+        Some(cs) => cs,
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse {
+                    ok: false,
+                    message: "No connection for this tab".to_string(),
+                }),
+            );
+        }
+    };
+    let (mut data_rx, mut cancel_rx) = match begin_transfer(conn_state).await {
+        Ok(rx) => rx,
+        Err(e) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(ApiResponse {
+                    ok: false,
+                    message: e,
+                }),
+            );
+        }
+    };
+
+    let write_tx = conn_state.write_tx();
+    let broadcast_tx = conn_state.broadcast_tx.clone();
+    let zmodem_active = conn_state.zmodem_active.clone();
+    let zmodem_data_tx_shared = conn_state.zmodem_data_tx_shared.clone();
+    let tab_id = req.tab_id.clone();
+    let state = state.clone();
+    tracing::info!("ZMODEM: starting upload of {} files (tab {})", req.paths.len(), tab_id);
+    let _ = broadcast_tx.send(transfer_notification(
+        serde_json::json!({"type": "zmodem", "state": "started"}),
+    ));
+
+    tokio::spawn(async move {
+        let transfer_start = std::time::Instant::now();
+        let mut file_start = transfer_start;
+        let mut last_progress = transfer_start;
+        // The remote's ZRINIT was already shown as terminal output, so
+        // start with our own ZRQINIT to have it sent again
+        let mut response = sender.process(&[]);
+        let error = loop {
+            if !response.is_empty() && write_tx.send(response).await.is_err() {
+                break Some("Connection closed".to_string());
+            }
+
+            if let Some(completed) = sender.take_completed() {
+                let _ = broadcast_tx.send(transfer_notification(serde_json::json!({
+                    "type": "zmodem",
+                    "state": "file_complete",
+                    "filename": completed.filename,
+                    "size": completed.size,
+                    "elapsedMs": file_start.elapsed().as_millis() as u64
+                })));
+                let record = transfers::Record::new(
+                    "zmodem",
+                    transfers::Direction::Send,
+                    &completed.filename,
+                    completed.size,
+                    file_start.elapsed(),
+                    transfers::Status::Completed,
+                );
+                record_transfer(&state, &tab_id, record).await;
+                file_start = std::time::Instant::now();
+            }
+            if last_progress.elapsed() >= std::time::Duration::from_millis(200) {
+                last_progress = std::time::Instant::now();
+                if let Some(filename) = sender.current_filename() {
+                    let _ = broadcast_tx.send(transfer_notification(serde_json::json!({
+                        "type": "zmodem",
+                        "state": "progress",
+                        "filename": filename,
+                        "sent": sender.current_bytes(),
+                        "total": sender.current_file_size()
+                    })));
+                }
+            }
+            if sender.is_done() {
+                break sender.error().map(str::to_string);
+            }
+
+            tokio::select! {
+                received = tokio::time::timeout(TRANSFER_IDLE_TIMEOUT, data_rx.recv()) => match received {
+                    Ok(Some(incoming)) => response = sender.process(&incoming),
+                    Ok(None) => break Some("Connection closed".to_string()),
+                    Err(_) => {
+                        let _ = write_tx.send(zmodem::ABORT_SEQUENCE.to_vec()).await;
+                        break Some("The receiver stopped responding".to_string());
+                    }
+                },
+                cancelled = &mut cancel_rx => {
+                    let _ = write_tx.send(zmodem::ABORT_SEQUENCE.to_vec()).await;
+                    break Some(transfer_cancelled(cancelled));
+                }
+            }
+        };
+        drop(data_rx);
+        end_transfer(&zmodem_active, &zmodem_data_tx_shared).await;
+
+        let notification = match error {
+            None => {
+                let files = sender.sent_files();
+                tracing::info!("ZMODEM upload complete (tab {}): {:?}", tab_id, files);
+                serde_json::json!({
+                    "type": "zmodem",
+                    "state": "completed",
+                    "files": files,
+                    "totalBytes": sender.total_bytes(),
+                    "elapsedMs": transfer_start.elapsed().as_millis() as u64
+                })
+            }
+            Some(e) => {
+                tracing::error!("ZMODEM upload failed (tab {}): {}", tab_id, e);
+                if let Some(filename) = sender.current_filename() {
+                    let mut record = transfers::Record::new(
+                        "zmodem",
+                        transfers::Direction::Send,
+                        &filename,
+                        sender.current_bytes(),
+                        file_start.elapsed(),
+                        transfers::Status::Failed,
+                    );
+                    record.message = Some(e.clone());
+                    record_transfer(&state, &tab_id, record).await;
+                }
+                serde_json::json!({"type": "zmodem", "state": "failed", "message": e})
+            }
+        };
+        let _ = broadcast_tx.send(transfer_notification(notification));
+    });
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse {
+            ok: true,
+            message: "Sending with ZMODEM".to_string(),
+        }),
+    )
+}
+
+#[derive(Deserialize)]
+struct TransferCancelRequest {
+    tab_id: String,
+}
+
+/// Cancel the tab's file transfer, whichever protocol it uses. The remote
+/// side is sent the CAN abort sequence.
+async fn zmodem_cancel(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<TransferCancelRequest>,
+) -> impl IntoResponse {
+    let mut connections = state.connections.lock().await;
+    let cancel = connections
+        .get_mut(&req.tab_id)
+        .and_then(|cs| cs.transfer_cancel.take());
+    match cancel.map(|tx| tx.send(())) {
+        Some(Ok(())) => (
+            StatusCode::OK,
+            Json(ApiResponse {
+                ok: true,
+                message: "Transfer cancelled".to_string(),
+            }),
+        ),
+        _ => (
+            StatusCode::CONFLICT,
+            Json(ApiResponse {
+                ok: false,
+                message: "No transfer in progress".to_string(),
+            }),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct TransferAnswerRequest {
+    tab_id: String,
+    action: zmodem::FileAction,
+}
+
+/// Tell a ZMODEM receive what to do with a file that already exists, after
+/// a `file_exists` notification.
+async fn zmodem_resolve(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<TransferAnswerRequest>,
+) -> impl IntoResponse {
+    let connections = state.connections.lock().await;
+    let answer = connections
+        .get(&req.tab_id)
+        .and_then(|cs| cs.transfer_answer.as_ref());
+    match answer.map(|tx| tx.send(req.action)) {
+        Some(Ok(())) => (
+            StatusCode::OK,
+            Json(ApiResponse {
+                ok: true,
+                message: "Answer sent".to_string(),
+            }),
+        ),
+        _ => (
+            StatusCode::CONFLICT,
+            Json(ApiResponse {
+                ok: false,
+                message: "No transfer in progress".to_string(),
+            }),
+        ),
+    }
+}
+
+/// Names of the files received on the tab that are still on record.
+async fn zmodem_list_files(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let transfers = state.transfers.lock().await;
+    let tab_id = query.tab_id.unwrap_or_default();
+    let names: Vec<String> = match transfers.get(&tab_id) {
+        Some(history) => history
+            .received_files()
+            .filter_map(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+            .collect(),
+        None => Vec::new(),
+    };
+    Json(serde_json::json!({ "files": names }))
+}
+
+async fn zmodem_download_file(
+    State(state): State<Arc<AppState>>,
+    AxumPath(filename): AxumPath<String>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let tab_id = query.tab_id.unwrap_or_default();
+    let found_path = state
+        .transfers
+        .lock()
+        .await
+        .get(&tab_id)
+        .and_then(|history| history.find_received(&filename).map(|p| p.to_path_buf()));
+    match found_path {
+        Some(path) => download_response(&path).await,
+        None => transfer_not_found(),
+    }
+}
+
+fn transfer_not_found() -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse {
+            ok: false,
+            message: "File not found".to_string(),
+        }),
+    )
+        .into_response()
+}
+
+/// Send a file as an attachment, streamed from disk in chunks.
+async fn download_response(path: &std::path::Path) -> axum::response::Response {
+    let opened = match tokio::fs::File::open(path).await {
+        Ok(file) => file.metadata().await.map(|m| (file, m.len())),
+        Err(e) => Err(e),
+    };
+    let (file, len) = match opened {
+        Ok(opened) => opened,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return transfer_not_found(),
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse {
+                    ok: false,
+                    message: format!("Failed to read file: {}", e),
+                }),
+            )
+                .into_response();
+        }
+    };
+    let stream = futures::stream::try_unfold(file, |mut file| async move {
+        let mut chunk = vec![0u8; 64 * 1024];
+        let n = file.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok::<_, std::io::Error>(None);
+        }
+        chunk.truncate(n);
+        Ok(Some((chunk, file)))
+    });
+    let filename = path
+        .file_name()
+        .map(|n| n.to_string_lossy().replace('"', "_"))
+        .unwrap_or_default();
+    (
+        StatusCode::OK,
+        [
+            (
+                axum::http::header::CONTENT_TYPE,
+                "application/octet-stream".to_string(),
+            ),
+            (
+                axum::http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+            (axum::http::header::CONTENT_LENGTH, len.to_string()),
+        ],
+        axum::body::Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+/// The tab's transfers, newest first. `available` tells whether a received
+/// file is still on disk.
+async fn transfers_list(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let transfers = state.transfers.lock().await;
+    let tab_id = query.tab_id.unwrap_or_default();
+    let records: Vec<serde_json::Value> = transfers
+        .get(&tab_id)
+        .map(|history| {
+            history
+                .records()
+                .rev()
+                .map(|record| {
+                    let mut value = serde_json::json!(record);
+                    value["available"] = record.path.as_ref().is_some_and(|p| p.is_file()).into();
+                    value
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Json(serde_json::json!({ "transfers": records }))
+}
+
+/// Where the tab's transfer `id` saved its file.
+async fn transfer_path(state: &AppState, tab_id: &str, id: u64) -> Option<PathBuf> {
+    state
+        .transfers
+        .lock()
+        .await
+        .get(tab_id)
+        .and_then(|history| history.get(id))
+        .and_then(|record| record.path.clone())
+}
+
+/// Download a received file again.
+async fn transfer_download(
+    State(state): State<Arc<AppState>>,
+    AxumPath(id): AxumPath<u64>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let tab_id = query.tab_id.unwrap_or_default();
+    match transfer_path(&state, &tab_id, id).await {
+        Some(path) => download_response(&path).await,
+        None => transfer_not_found(),
+    }
+}
+
+/// Delete a received file from disk and drop the transfer from history.
+async fn transfer_delete(
+    State(state): State<Arc<AppState>>,
+    AxumPath(id): AxumPath<u64>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let tab_id = query.tab_id.unwrap_or_default();
+    let mut transfers = state.transfers.lock().await;
+    let Some(history) = transfers.get_mut(&tab_id).filter(|h| h.get(id).is_some()) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse {
+                ok: false,
+                message: "Transfer not found".to_string(),
+            }),
+        );
+    };
+    if let Some(path) = history.get(id).and_then(|r| r.path.clone()) {
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => tracing::info!("Deleted received file {}", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ApiResponse {
+                        ok: false,
+                        message: format!("Failed to delete {}: {}", path.display(), e),
+                    }),
+                );
+            }
+        }
+    }
+    history.remove(id);
+    (
+        StatusCode::OK,
+        Json(ApiResponse {
+            ok: true,
+            message: "Deleted".to_string(),
+        }),
+    )
+}
+
+/// Show a received file in the system file manager.
+async fn transfer_reveal(
+    State(state): State<Arc<AppState>>,
+    AxumPath(id): AxumPath<u64>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let tab_id = query.tab_id.unwrap_or_default();
+    let path = match transfer_path(&state, &tab_id, id).await {
+        Some(path) if path.is_file() => path,
+        _ => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse {
+                    ok: false,
+                    message: "File not found".to_string(),
+                }),
+            );
+        }
+    };
+    match reveal_in_file_manager(&path) {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(ApiResponse {
+                ok: true,
+                message: path.display().to_string(),
+            }),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse {
+                ok: false,
+                message: format!("Failed to open the file manager: {}", e),
+            }),
+        ),
+    }
+}
+
+fn reveal_in_file_manager(path: &std::path::Path) -> std::io::Result<()> {
+    #[cfg(target_os = "macos")]
+    let mut command = {
+        let mut command = std::process::Command::new("open");
+        command.arg("-R").arg(path);
+        command
+    };
+    // Explorer wants the path quoted after the switch, in one argument,
+    // which `arg` would quote as a whole
+    #[cfg(target_os = "windows")]
+    let mut command = {
+        use std::os::windows::process::CommandExt;
+        let mut command = std::process::Command::new("explorer");
+        command.raw_arg(format!("/select,\"{}\"", path.display()));
+        command
+    };
+    // No common way to select a file; open its folder
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    let mut command = {
+        let mut command = std::process::Command::new("xdg-open");
+        command.arg(path.parent().unwrap_or(path));
+        command
+    };
+    let mut child = command.spawn()?;
+    // Reap it when it exits, so it doesn't linger as a zombie
+    tokio::task::spawn_blocking(move || child.wait());
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Scrollback REST handlers
+// ---------------------------------------------------------------------------
+
+#[derive(Serialize)]
+struct ScrollbackInfoResponse {
+    length: usize,
+    capacity: usize,
+    total_written: u64,
+    first_offset: u64,
+    total_lines: u64,
+    spill: bool,
+    disk_bytes: u64,
+}
+
+async fn scrollback_info(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TabIdQuery>,
+) -> impl IntoResponse {
+    let connections = state.connections.lock().await;
+    let tab_id = query.tab_id.unwrap_or_default();
+
+    match connections.get(&tab_id) {
+        Some(conn_state) => {
+            let stats = conn_state.scrollback.stats();
+            Json(ScrollbackInfoResponse {
+                length: stats.length,
+                capacity: stats.capacity,
+                total_written: stats.total_written,
+                first_offset: stats.first_offset,
+                total_lines: stats.total_lines,
+                spill: stats.spill,
+                disk_bytes: stats.disk_bytes,
+            })
+            .into_response()
+        }
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse {
+                ok: false,
+                message: "No connection for this tab".to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ScrollbackResizeRequest {
+    tab_id: String,
+    size: usize,
+}
+
+async fn scrollback_resize(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ScrollbackResizeRequest>,
+) -> impl IntoResponse {
+    let connections = state.connections.lock().await;
+
+    match connections.get(&req.tab_id) {
+        Some(conn_state) => {
+            conn_state.scrollback.set_capacity(req.size);
+            (
+                StatusCode::OK,
+                Json(ApiResponse {
+                    ok: true,
+                    message: format!(
+                        "Scrollback size set to {} bytes",
+                        conn_state.scrollback.capacity()
+                    ),
+                }),
+            )
+        }
+        None => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                ok: false,
+                message: "No connection for this tab".to_string(),
+            }),
+        ),
+    }
+}
+
+/// Look up the scrollback handle for a tab without holding the connections
+/// lock during disk reads.
+async fn tab_scrollback(
+    state: &Arc<AppState>,
+    tab_id: &str,
+) -> Option<Arc<scrollback::Scrollback>> {
+    let connections = state.connections.lock().await;
+    connections.get(tab_id).map(|cs| cs.scrollback.clone())
+}
+
+fn no_scrollback_response() -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse {
+            ok: false,
+            message: "No connection for this tab".to_string(),
+        }),
+    )
+        .into_response()
+}
+
+fn scrollback_read_error(e: std::io::Error) -> axum::response::Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ApiResponse {
+            ok: false,
+            message: format!("Failed to read scrollback: {}", e),
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct ScrollbackRangeQuery {
+    tab_id: String,
+    start: u64,
+    end: u64,
+}
+
+async fn scrollback_range(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ScrollbackRangeQuery>,
+) -> impl IntoResponse {
+    let sb = match tab_scrollback(&state, &query.tab_id).await {
+        Some(sb) => sb,
+        None => return no_scrollback_response(),
+    };
+    let (start, end) = (query.start, query.end);
+
+    match tokio::task::spawn_blocking(move || sb.read_range(start, end)).await {
+        Ok(Ok(data)) => (
+            StatusCode::OK,
+            [(axum::http::header::CONTENT_TYPE, "application/octet-stream")],
+            data,
+        )
+            .into_response(),
+        Ok(Err(e)) => scrollback_read_error(e),
+        Err(e) => scrollback_read_error(std::io::Error::other(e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct ScrollbackLinesQuery {
+    tab_id: String,
+    start: u64,
+    count: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct ScrollbackLinesResponse {
+    start: u64,
+    total_lines: u64,
+    lines: Vec<String>,
+}
+
+async fn scrollback_lines(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ScrollbackLinesQuery>,
+) -> impl IntoResponse {
+    let sb = match tab_scrollback(&state, &query.tab_id).await {
+        Some(sb) => sb,
+        None => return no_scrollback_response(),
+    };
+    let start = query.start;
+    let count = query.count.unwrap_or(1000);
+
+    let result = tokio::task::spawn_blocking(move || {
+        sb.read_lines(start, count)
+            .map(|(start, lines)| (start, sb.stats().total_lines, lines))
+    })
+    .await;
+    match result {
+        Ok(Ok((start, total_lines, lines))) => Json(ScrollbackLinesResponse {
+            start,
+            total_lines,
+            lines,
+        })
+        .into_response(),
+        Ok(Err(e)) => scrollback_read_error(e),
+        Err(e) => scrollback_read_error(std::io::Error::other(e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct ScrollbackSearchQuery {
+    tab_id: String,
+    q: String,
+    ignore_case: Option<bool>,
+    context: Option<usize>,
+    limit: Option<usize>,
+}
+
+async fn scrollback_search(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ScrollbackSearchQuery>,
+) -> impl IntoResponse {
+    if query.q.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse {
+                ok: false,
+                message: "Empty search pattern".to_string(),
+            }),
+        )
+            .into_response();
+    }
+
+    let re = match regex::RegexBuilder::new(&query.q)
+        .case_insensitive(query.ignore_case.unwrap_or(false))
+        .build()
+    {
+        Ok(re) => re,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse {
+                    ok: false,
+                    message: format!("Invalid pattern: {}", e),
+                }),
+            )
+                .into_response();
+        }
+    };
+
+    let sb = match tab_scrollback(&state, &query.tab_id).await {
+        Some(sb) => sb,
+        None => return no_scrollback_response(),
+    };
+    let context = query.context.unwrap_or(2);
+    let limit = query.limit.unwrap_or(1000);
+
+    match tokio::task::spawn_blocking(move || search::search(&sb, &re, context, limit)).await {
+        Ok(Ok(result)) => Json(result).into_response(),
+        Ok(Err(e)) => scrollback_read_error(e),
+        Err(e) => scrollback_read_error(std::io::Error::other(e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct ScrollbackExportQuery {
+    tab_id: String,
+    format: Option<export::ExportFormat>,
+}
+
+async fn scrollback_export(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ScrollbackExportQuery>,
+) -> impl IntoResponse {
+    let sb = match tab_scrollback(&state, &query.tab_id).await {
+        Some(sb) => sb,
+        None => return no_scrollback_response(),
+    };
+    let format = query.format.unwrap_or(export::ExportFormat::Raw);
+    let stamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let filename = format!("scrollback-{}.{}", stamp, format.extension());
+
+    // Convert on a blocking thread and stream the result, so exporting a
+    // multi-GB spilled scrollback doesn't buffer it all in memory.
+    let (tx, rx) = mpsc::channel::<std::io::Result<Vec<u8>>>(8);
+    let title = format!("serial-rs scrollback ({})", query.tab_id);
+    tokio::task::spawn_blocking(move || {
+        let mut exporter = export::Exporter::new(format);
+        if tx.blocking_send(Ok(exporter.header(&title))).is_err() {
+            return;
+        }
+        let result = sb.scan_chunks(|chunk| tx.blocking_send(Ok(exporter.feed(chunk))).is_ok());
+        match result {
+            Ok(()) => {
+                let _ = tx.blocking_send(Ok(exporter.footer()));
+            }
+            Err(e) => {
+                tracing::error!("Scrollback export failed: {}", e);
+                let _ = tx.blocking_send(Err(e));
+            }
+        }
+    });
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    });
+
+    (
+        StatusCode::OK,
+        [
+            (
+                axum::http::header::CONTENT_TYPE,
+                format.content_type().to_string(),
+            ),
+            (
+                axum::http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        axum::body::Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+// ---------------------------------------------------------------------------
+// Tab restore
+// ---------------------------------------------------------------------------
+
+fn saved_tab(
+    tab_id: &str,
+    conn_state: &ConnectionState,
+    session_id: Option<String>,
+) -> restore::SavedTab {
+    let connection = match &conn_state.connection {
+        ConnectionKind::Serial(c) => restore::SavedConnection::Serial {
+            config: c.config.clone(),
+            device: c.device.clone(),
+        },
+        ConnectionKind::Ssh(c) => {
+            let mut config = c.config.clone();
+            config.password = None;
+            restore::SavedConnection::Ssh { config }
+        }
+    };
+    let stats = conn_state.scrollback.stats();
+    restore::SavedTab {
+        tab_id: tab_id.to_string(),
+        connection,
+        scrollback_size: stats.capacity,
+        scrollback_spill: stats.spill,
+        log_path: conn_state.log_file.as_ref().map(|(path, _)| path.clone()),
+        session_id,
+    }
+}
+
+/// Save the open tabs, plus any from the previous run not yet reopened, so
+/// they can be restored after a restart. Scrollback snapshots are only
+/// rewritten for tabs that produced output since the last save.
+async fn persist_tabs(state: &Arc<AppState>) {
+    let mut written = state.restore_written.lock().await;
+
+    let mut tabs = Vec::new();
+    let mut snapshots = Vec::new();
+    {
+        let connections = state.connections.lock().await;
+        let tab_sessions = state.tab_sessions.lock().await;
+        for (tab_id, conn_state) in connections.iter() {
+            let session_id = tab_sessions.get(tab_id).cloned();
+            tabs.push(saved_tab(tab_id, conn_state, session_id));
+            let total = conn_state.scrollback.stats().total_written;
+            if written.get(tab_id) != Some(&total) {
+                written.insert(tab_id.clone(), total);
+                snapshots.push((tab_id.clone(), conn_state.scrollback.clone()));
+            }
+        }
+        written.retain(|tab_id, _| connections.contains_key(tab_id));
+        // A tab whose reopen failed may have been connected by hand since
+        tabs.extend(
+            state
+                .pending_restore
+                .lock()
+                .await
+                .iter()
+                .filter(|tab| !connections.contains_key(&tab.tab_id))
+                .cloned(),
+        );
+    }
+
+    let result = tokio::task::spawn_blocking(move || {
+        let dir = app_data_dir();
+        for (tab_id, sb) in snapshots {
+            restore::save_snapshot(&dir, &tab_id, &sb.tail(restore::SNAPSHOT_MAX))?;
+        }
+        restore::save(&dir, &tabs)
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::error!("Failed to save tabs for restore: {}", e),
+        Err(e) => tracing::error!("Tab save task failed: {}", e),
+    }
+}
+
+#[derive(Serialize)]
+struct RestoreEntry {
+    tab_id: String,
+    connection_type: String,
+    label: String,
+    /// Port the device resolves to now (serial), or `ssh://host:port`
+    port: Option<String>,
+    available: bool,
+    log_path: Option<String>,
+}
+
+async fn restore_list(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let pending = state.pending_restore.lock().await.clone();
+    let entries: Vec<RestoreEntry> = pending
+        .iter()
+        .map(|tab| {
+            let (connection_type, port) = match &tab.connection {
+                restore::SavedConnection::Serial { config, device } => (
+                    "serial",
+                    restore::resolve_port(device.as_ref(), &config.port),
+                ),
+                restore::SavedConnection::Ssh { config } => (
+                    "ssh",
+                    Some(format!("ssh://{}:{}", config.host, config.port)),
+                ),
+            };
+            RestoreEntry {
+                tab_id: tab.tab_id.clone(),
+                connection_type: connection_type.to_string(),
+                label: tab.label(),
+                available: port.is_some(),
+                port,
+                log_path: tab.log_path.clone(),
+            }
+        })
+        .collect();
+    Json(serde_json::json!({ "tabs": entries }))
+}
+
+#[derive(Deserialize)]
+struct RestoreRequest {
+    /// Tabs to reopen; all pending tabs if omitted
+    tab_ids: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct RestoreResult {
+    tab_id: String,
+    ok: bool,
+    message: String,
+    connection_type: String,
+    label: String,
+    log_path: Option<String>,
+}
+
+/// Reopen one saved tab: preload its scrollback snapshot, reconnect, and
+/// resume logging. Returns the label to show on success.
+async fn reopen_tab(state: &Arc<AppState>, tab: restore::SavedTab) -> Result<String, String> {
+    let scrollback = new_scrollback(&tab.tab_id, Some(tab.scrollback_size), tab.scrollback_spill);
+    let dir = app_data_dir();
+    let tab_id = tab.tab_id.clone();
+    if let Ok(Some(data)) =
+        tokio::task::spawn_blocking(move || restore::load_snapshot(&dir, &tab_id)).await
+    {
+        scrollback.push(&data);
+    }
+    if let Some(session_id) = tab.session_id {
+        link_tab_session(state, &tab.tab_id, session_id).await;
+    }
+
+    let result = match tab.connection {
+        restore::SavedConnection::Serial { mut config, device } => {
+            match restore::resolve_port(device.as_ref(), &config.port) {
+                Some(port) => {
+                    if port != config.port {
+                        tracing::info!("Restore: {} re-enumerated as {}", config.port, port);
+                    }
+                    config.port = port;
+                    let label = format!("{} @ {}", config.port, config.baud_rate);
+                    open_serial_tab(state, tab.tab_id.clone(), config, scrollback)
+                        .await
+                        .map(|_| label)
+                }
+                None => Err((
+                    StatusCode::NOT_FOUND,
+                    format!("Device {} not found", config.port),
+                )),
+            }
+        }
+        restore::SavedConnection::Ssh { config } => {
+            let label = format!("SSH {}@{}:{}", config.username, config.host, config.port);
+            open_ssh_tab(state, tab.tab_id.clone(), config, scrollback)
+                .await
+                .map(|_| label)
+        }
+    };
+    let label = result.map_err(|(_, message)| message)?;
+
+    if let Some(log_path) = tab.log_path {
+        match open_log_file(&log_path).await {
+            Ok(log) => {
+                let mut connections = state.connections.lock().await;
+                if let Some(conn_state) = connections.get_mut(&tab.tab_id) {
+                    conn_state.log_file = Some(log);
+                    conn_state.status_changed();
+                }
+            }
+            Err(e) => tracing::error!("Restore: failed to reopen log {}: {}", log_path, e),
+        }
+    }
+    Ok(label)
+}
+
+async fn restore_open(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<RestoreRequest>,
+) -> impl IntoResponse {
+    let selected: Vec<restore::SavedTab> = {
+        let mut pending = state.pending_restore.lock().await;
+        let (selected, rest) = pending.drain(..).partition(|tab| match &req.tab_ids {
+            Some(ids) => ids.contains(&tab.tab_id),
+            None => true,
+        });
+        *pending = rest;
+        selected
+    };
+
+    let mut results = Vec::new();
+    for tab in selected {
+        let tab_id = tab.tab_id.clone();
+        let log_path = tab.log_path.clone();
+        let connection_type = match tab.connection {
+            restore::SavedConnection::Serial { .. } => "serial",
+            restore::SavedConnection::Ssh { .. } => "ssh",
+        };
+        let fallback_label = tab.label();
+        let result = match reopen_tab(&state, tab.clone()).await {
+            Ok(label) => RestoreResult {
+                tab_id,
+                ok: true,
+                message: format!("Reopened {}", label),
+                connection_type: connection_type.to_string(),
+                label,
+                log_path,
+            },
+            Err(message) => {
+                tracing::warn!("Restore of tab {} failed: {}", tab_id, message);
+                // Keep it for another try, e.g. once the device is back
+                state.pending_restore.lock().await.push(tab);
+                RestoreResult {
+                    tab_id,
                     ok: false,
-                    message: format!("Failed to read file: {}", e),
+                    message,
+                    connection_type: connection_type.to_string(),
+                    label: fallback_label,
+                    log_path: None,
+                }
+            }
+        };
+        results.push(result);
+    }
+
+    persist_tabs(&state).await;
+    Json(serde_json::json!({ "results": results }))
+}
+
+async fn restore_discard(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    state.pending_restore.lock().await.clear();
+    persist_tabs(&state).await;
+    Json(ApiResponse {
+        ok: true,
+        message: "Discarded saved tabs".to_string(),
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Session store REST handlers
+// ---------------------------------------------------------------------------
+
+fn store_error_response(e: sessions::StoreError) -> axum::response::Response {
+    let status = match &e {
+        sessions::StoreError::NotFound(_) => StatusCode::NOT_FOUND,
+        sessions::StoreError::Invalid(_) => StatusCode::BAD_REQUEST,
+        sessions::StoreError::Io(_) | sessions::StoreError::Keychain(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    if status == StatusCode::INTERNAL_SERVER_ERROR {
+        tracing::error!("{}", e);
+    }
+    (
+        status,
+        Json(ApiResponse {
+            ok: false,
+            message: e.to_string(),
+        }),
+    )
+        .into_response()
+}
+
+fn store_ok_response(message: &str) -> axum::response::Response {
+    Json(ApiResponse {
+        ok: true,
+        message: message.to_string(),
+    })
+    .into_response()
+}
+
+/// Run a session store call on a blocking thread, as it writes the file
+/// and talks to the keychain.
+async fn with_store<T: Send + 'static>(
+    state: &Arc<AppState>,
+    f: impl FnOnce(&sessions::SessionStore) -> Result<T, sessions::StoreError> + Send + 'static,
+) -> Result<T, sessions::StoreError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || f(&state.sessions))
+        .await
+        .unwrap_or_else(|e| Err(sessions::StoreError::Io(std::io::Error::other(e))))
+}
+
+async fn sessions_list(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let listed = with_store(&state, |store| {
+        let (mut sessions, folders) = store.list();
+        store.fill_passwords(&mut sessions);
+        Ok((sessions, folders))
+    })
+    .await;
+    match listed {
+        Ok((sessions, folders)) => Json(serde_json::json!({ "sessions": sessions, "folders": folders })).into_response(),
+        Err(e) => store_error_response(e),
+    }
+}
+
+async fn session_create(
+    State(state): State<Arc<AppState>>,
+    Json(session): Json<sessions::Session>,
+) -> impl IntoResponse {
+    match with_store(&state, move |store| store.create(session)).await {
+        Ok(session) => (StatusCode::CREATED, Json(session)).into_response(),
+        Err(e) => store_error_response(e),
+    }
+}
+
+async fn session_get(
+    State(state): State<Arc<AppState>>,
+    AxumPath(id): AxumPath<String>,
+) -> impl IntoResponse {
+    let session = with_store(&state, move |store| {
+        let mut session = store
+            .get(&id)
+            .ok_or_else(|| sessions::StoreError::NotFound(format!("Session {}", id)))?;
+        store.fill_passwords(std::slice::from_mut(&mut session));
+        Ok(session)
+    })
+    .await;
+    match session {
+        Ok(session) => Json(session).into_response(),
+        Err(e) => store_error_response(e),
+    }
+}
+
+async fn session_update(
+    State(state): State<Arc<AppState>>,
+    AxumPath(id): AxumPath<String>,
+    Json(session): Json<sessions::Session>,
+) -> impl IntoResponse {
+    match with_store(&state, move |store| store.update(&id, session)).await {
+        Ok(session) => Json(session).into_response(),
+        Err(e) => store_error_response(e),
+    }
+}
+
+async fn session_delete(
+    State(state): State<Arc<AppState>>,
+    AxumPath(id): AxumPath<String>,
+) -> impl IntoResponse {
+    let deleted = {
+        let id = id.clone();
+        with_store(&state, move |store| store.delete(&id)).await
+    };
+    match deleted {
+        Ok(()) => {
+            let account = secrets::auto_login_account(&id);
+            if let Ok(Err(e)) = tokio::task::spawn_blocking(move || secrets::delete(&account)).await {
+                tracing::warn!("{}", e);
+            }
+            store_ok_response("Session deleted")
+        }
+        Err(e) => store_error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct FolderRequest {
+    name: String,
+}
+
+async fn folder_create(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<FolderRequest>,
+) -> impl IntoResponse {
+    match with_store(&state, move |store| store.add_folder(&req.name)).await {
+        Ok(()) => store_ok_response("Folder created"),
+        Err(e) => store_error_response(e),
+    }
+}
+
+/// Rename a folder; the body carries the new name.
+async fn folder_rename(
+    State(state): State<Arc<AppState>>,
+    AxumPath(name): AxumPath<String>,
+    Json(req): Json<FolderRequest>,
+) -> impl IntoResponse {
+    match with_store(&state, move |store| store.rename_folder(&name, &req.name)).await {
+        Ok(()) => store_ok_response("Folder renamed"),
+        Err(e) => store_error_response(e),
+    }
+}
+
+async fn folder_delete(
+    State(state): State<Arc<AppState>>,
+    AxumPath(name): AxumPath<String>,
+) -> impl IntoResponse {
+    match with_store(&state, move |store| store.delete_folder(&name)).await {
+        Ok(()) => store_ok_response("Folder deleted"),
+        Err(e) => store_error_response(e),
+    }
+}
+
+async fn session_defaults_get(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.sessions.defaults())
+}
+
+async fn session_defaults_put(
+    State(state): State<Arc<AppState>>,
+    Json(defaults): Json<serde_json::Map<String, serde_json::Value>>,
+) -> impl IntoResponse {
+    match with_store(&state, move |store| store.set_defaults(defaults)).await {
+        Ok(()) => store_ok_response("Defaults saved"),
+        Err(e) => store_error_response(e),
+    }
+}
+
+async fn ssh_info_get(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match with_store(&state, |store| Ok(store.ssh_info())).await {
+        Ok(info) => Json(info).into_response(),
+        Err(e) => store_error_response(e),
+    }
+}
+
+async fn ssh_info_put(
+    State(state): State<Arc<AppState>>,
+    Json(info): Json<serde_json::Map<String, serde_json::Value>>,
+) -> impl IntoResponse {
+    match with_store(&state, move |store| store.set_ssh_info(info)).await {
+        Ok(()) => store_ok_response("SSH info saved"),
+        Err(e) => store_error_response(e),
+    }
+}
+
+/// One-time migration of sessions, defaults and SSH info that older
+/// versions kept in the WebView's localStorage.
+async fn sessions_import_local(
+    State(state): State<Arc<AppState>>,
+    Json(import): Json<sessions::LocalStorageImport>,
+) -> impl IntoResponse {
+    match with_store(&state, move |store| store.import_local_storage(import)).await {
+        Ok(report) => {
+            tracing::info!(
+                "Imported {} session(s) from localStorage ({} already present)",
+                report.imported,
+                report.skipped
+            );
+            Json(report).into_response()
+        }
+        Err(e) => store_error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct SessionImportRequest {
+    source: importers::ImportSource,
+    /// File or directory to read; the program's usual location if omitted
+    path: Option<String>,
+    /// Folder to put the imported sessions in
+    folder: Option<String>,
+    /// Only report what would be imported
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Import saved sessions from PuTTY, minicom or SecureCRT.
+async fn sessions_import(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<SessionImportRequest>,
+) -> impl IntoResponse {
+    let paths = match &req.path {
+        Some(path) => vec![PathBuf::from(expand_home(path))],
+        None => importers::default_paths(req.source),
+    };
+    let source = req.source;
+    let mut report = match tokio::task::spawn_blocking(move || importers::import(source, &paths)).await {
+        Ok(report) => report,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse {
+                    ok: false,
+                    message: format!("Import failed: {}", e),
                 }),
             )
-                .into_response(),
-        },
-        None => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse {
-                ok: false,
-                message: "File not found".to_string(),
-            }),
-        )
-            .into_response(),
+                .into_response();
+        }
+    };
+
+    if let Some(folder) = req.folder.as_deref().map(str::trim).filter(|f| !f.is_empty()) {
+        for session in report.sessions.iter_mut() {
+            session.folder = Some(match &session.folder {
+                Some(sub) => format!("{}/{}", folder, sub),
+                None => folder.to_string(),
+            });
+        }
     }
+
+    let (sessions, duplicates) = if req.dry_run {
+        (report.sessions, Vec::new())
+    } else {
+        let imported = std::mem::take(&mut report.sessions);
+        match with_store(&state, move |store| store.add_imported(imported)).await {
+            Ok(result) => result,
+            Err(e) => return store_error_response(e),
+        }
+    };
+    tracing::info!(
+        "{} import: {} session(s){}, {} skipped, {} unsupported option(s)",
+        source.name(),
+        sessions.len(),
+        if req.dry_run { " found" } else { " added" },
+        report.skipped.len() + duplicates.len(),
+        report.unsupported.len()
+    );
+    Json(serde_json::json!({
+        "ok": true,
+        "dry_run": req.dry_run,
+        "sessions": sessions,
+        "duplicates": duplicates,
+        "unsupported": report.unsupported,
+        "skipped": report.skipped,
+    }))
+    .into_response()
 }
 
 // ---------------------------------------------------------------------------
@@ -876,26 +4810,13 @@ async fn log_start(
         );
     }
 
-    // Expand ~ to home directory
-    let path = if req.path.starts_with("~/") {
-        if let Some(home) = dirs::home_dir() {
-            home.join(&req.path[2..]).to_string_lossy().to_string()
-        } else {
-            req.path.clone()
-        }
-    } else {
-        req.path.clone()
-    };
-
-    match tokio::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&path)
-        .await
-    {
-        Ok(file) => {
+    match open_log_file(&req.path).await {
+        Ok((path, file)) => {
             tracing::info!("Started logging to {} (tab {})", path, req.tab_id);
             conn_state.log_file = Some((path.clone(), file));
+            conn_state.status_changed();
+            drop(connections);
+            persist_tabs(&state).await;
             (
                 StatusCode::OK,
                 Json(ApiResponse {
@@ -905,7 +4826,7 @@ async fn log_start(
             )
         }
         Err(e) => {
-            tracing::error!("Failed to open log file {}: {}", path, e);
+            tracing::error!("Failed to open log file {}: {}", req.path, e);
             (
                 StatusCode::BAD_REQUEST,
                 Json(ApiResponse {
@@ -944,6 +4865,9 @@ async fn log_stop(
     match conn_state.log_file.take() {
         Some((path, _file)) => {
             tracing::info!("Stopped logging to {} (tab {})", path, req.tab_id);
+            conn_state.status_changed();
+            drop(connections);
+            persist_tabs(&state).await;
             (
                 StatusCode::OK,
                 Json(ApiResponse {
@@ -991,47 +4915,196 @@ async fn log_status(
 // ZMODEM interceptor task (per-tab)
 // ---------------------------------------------------------------------------
 
-/// Spawn a task that subscribes to the tab's broadcast channel, detects ZMODEM
-/// init sequences, and handles the transfer using a pure Rust ZMODEM receiver.
+/// A file transfer found by a connection's `IncomingRouter`.
+enum TransferDetection {
+    /// The remote runs `rz` and waits for files
+    UploadRequest,
+    /// The remote runs `sz`; the router has routed the connection to
+    /// `data_rx`, and `header` is the session's data so far
+    Receive {
+        header: Vec<u8>,
+        data_rx: mpsc::Receiver<Vec<u8>>,
+    },
+    /// The remote runs `trz`, `tsz` or `trz -d`
+    Trzsz(trzsz::Mode),
+    /// A Kermit sender started; the router has routed the connection,
+    /// starting with its first packet, to `data_rx`
+    Kermit { data_rx: mpsc::Receiver<Vec<u8>> },
+}
+
+/// Routes a connection's incoming data to the terminal, or to the file
+/// transfer that has taken over the connection. Watches terminal output
+/// for the start of a ZMODEM, trzsz or Kermit session, which is handed to
+/// the tab's interceptor; only what precedes the header reaches the
+/// terminal.
+pub(crate) struct IncomingRouter {
+    zmodem_active: Arc<AtomicBool>,
+    zmodem_data_tx_shared: Arc<Mutex<Option<mpsc::Sender<Vec<u8>>>>>,
+    detector: zmodem::ZmodemDetector,
+    trzsz: trzsz::Detector,
+    kermit: kermit::Detector,
+    detected_tx: mpsc::UnboundedSender<TransferDetection>,
+}
+
+impl IncomingRouter {
+    /// Take `data` from the connection, returning the part of it that is
+    /// terminal output.
+    pub(crate) async fn route<'a>(&mut self, data: &'a [u8]) -> &'a [u8] {
+        // If a transfer is active, route data to it
+        if self.zmodem_active.load(Ordering::Relaxed) {
+            // Not under the lock: the send waits while the transfer's
+            // channel is full, and ending the transfer takes the lock
+            let tx = self.zmodem_data_tx_shared.lock().await.clone();
+            if let Some(transfer_tx) = tx {
+                let _ = transfer_tx.send(data.to_vec()).await;
+                return &[];
+            }
+        }
+
+        let Some(found) = self.detector.feed(data) else {
+            if let Some(found) = self.trzsz.feed(data) {
+                // The remote waits for our answer, so nothing is lost
+                // before the interceptor takes the connection
+                let _ = self.detected_tx.send(TransferDetection::Trzsz(found.mode));
+                return &data[..found.offset];
+            }
+            if let Some(found) = self.kermit.feed(data) {
+                let Some(data_rx) = claim_transfer(&self.zmodem_active, &self.zmodem_data_tx_shared).await
+                else {
+                    return data;
+                };
+                let tx = self.zmodem_data_tx_shared.lock().await.clone();
+                if let Some(tx) = tx {
+                    let _ = tx.send(found.packet).await;
+                }
+                let _ = self.detected_tx.send(TransferDetection::Kermit { data_rx });
+                return &data[..found.offset];
+            }
+            return data;
+        };
+        match found.start {
+            zmodem::ZmodemStart::Send => {
+                let _ = self.detected_tx.send(TransferDetection::UploadRequest);
+                data
+            }
+            zmodem::ZmodemStart::Receive => {
+                let Some(data_rx) = claim_transfer(&self.zmodem_active, &self.zmodem_data_tx_shared).await
+                else {
+                    return data;
+                };
+                let _ = self.detected_tx.send(TransferDetection::Receive {
+                    header: found.header,
+                    data_rx,
+                });
+                &data[..found.offset]
+            }
+        }
+    }
+}
+
+/// Spawn a task that handles the transfers the tab's `IncomingRouter`
+/// finds: ZMODEM downloads with a pure Rust receiver, trzsz and Kermit
+/// downloads, and upload requests by asking the user for files. Returns the router
+/// for the connection's reader.
 fn spawn_zmodem_interceptor_for_tab(
     tab_id: String,
     broadcast_tx: broadcast::Sender<Vec<u8>>,
     zmodem_active: Arc<AtomicBool>,
     zmodem_data_tx_shared: Arc<Mutex<Option<mpsc::Sender<Vec<u8>>>>>,
     state: Arc<AppState>,
-) {
-    let mut broadcast_rx = broadcast_tx.subscribe();
+) -> IncomingRouter {
+    let (detected_tx, mut detected_rx) = mpsc::unbounded_channel();
+    let router = IncomingRouter {
+        zmodem_active: zmodem_active.clone(),
+        zmodem_data_tx_shared: zmodem_data_tx_shared.clone(),
+        detector: zmodem::ZmodemDetector::new(),
+        trzsz: trzsz::Detector::new(),
+        kermit: kermit::Detector::new(),
+        detected_tx,
+    };
 
     tokio::spawn(async move {
-        loop {
-            let data = match broadcast_rx.recv().await {
-                Ok(data) => data,
-                Err(broadcast::error::RecvError::Closed) => break,
-                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+        // Ends when the router goes away with the connection
+        while let Some(detection) = detected_rx.recv().await {
+            let (data, mut zmodem_rx) = match detection {
+                TransferDetection::Receive { header, data_rx } => (header, data_rx),
+                TransferDetection::Trzsz(trzsz::Mode::Download) => {
+                    tracing::info!("trzsz download detected (tab {})", tab_id);
+                    start_trzsz_download(&state, &tab_id).await;
+                    continue;
+                }
+                TransferDetection::Kermit { data_rx } => {
+                    tracing::info!("Kermit sender detected (tab {}), starting receive", tab_id);
+                    start_kermit_receive(&state, &tab_id, data_rx).await;
+                    continue;
+                }
+                TransferDetection::Trzsz(trzsz::Mode::Upload) => {
+                    // trz is waiting; the user picks files and starts the
+                    // upload through /api/trzsz/upload
+                    tracing::info!("trzsz upload request detected (tab {}), asking for files", tab_id);
+                    let _ = broadcast_tx.send(transfer_notification(
+                        serde_json::json!({"type": "trzsz", "state": "upload_request"}),
+                    ));
+                    continue;
+                }
+                TransferDetection::Trzsz(trzsz::Mode::Directory) => {
+                    let write_tx = state.connections.lock().await.get(&tab_id).map(|cs| cs.write_tx());
+                    if let Some(write_tx) = write_tx {
+                        let _ = write_tx.send(trzsz::decline_line()).await;
+                    }
+                    let _ = broadcast_tx.send(transfer_notification(serde_json::json!({
+                        "type": "trzsz",
+                        "state": "failed",
+                        "message": "Directory transfers aren't supported",
+                        "partial": null
+                    })));
+                    continue;
+                }
+                TransferDetection::UploadRequest => {
+                    // rz is waiting; the user picks files and starts the
+                    // upload through /api/zmodem/send
+                    tracing::info!("ZMODEM receiver detected (tab {}), asking for files", tab_id);
+                    let _ = broadcast_tx.send(transfer_notification(
+                        serde_json::json!({"type": "zmodem", "state": "upload_request"}),
+                    ));
+                    continue;
+                }
             };
 
-            // Only scan when not already in ZMODEM mode
-            if zmodem_active.load(Ordering::Relaxed) {
-                continue;
-            }
-
-            if !zmodem::detect_zmodem(&data) {
-                continue;
-            }
-
             tracing::info!("ZMODEM init sequence detected (tab {}), starting receive", tab_id);
 
-            // Save to ~/Downloads
-            let download_dir = dirs::download_dir()
-                .unwrap_or_else(|| PathBuf::from("/tmp"));
-            let mut receiver = zmodem::ZmodemReceiver::new(download_dir);
-
-            // Set up an mpsc channel for routing data to the ZMODEM handler
-            let (zmodem_tx, mut zmodem_rx) = mpsc::channel::<Vec<u8>>(256);
+            let settings = download_settings(&state, &tab_id).await;
+            let download_dir = match download_directory(&settings) {
+                Ok(dir) => dir,
+                Err(message) => {
+                    tracing::error!("ZMODEM receive refused (tab {}): {}", tab_id, message);
+                    drop(zmodem_rx);
+                    end_transfer(&zmodem_active, &zmodem_data_tx_shared).await;
+                    let _ = broadcast_tx.send(transfer_notification(serde_json::json!({
+                        "type": "zmodem",
+                        "state": "failed",
+                        "message": message,
+                        "partial": null
+                    })));
+                    continue;
+                }
+            };
+            let mut receiver =
+                zmodem::ZmodemReceiver::new(download_dir.clone(), settings.on_collision, settings.resume);
 
-            // Activate ZMODEM mode
-            zmodem_active.store(true, Ordering::SeqCst);
-            *zmodem_data_tx_shared.lock().await = Some(zmodem_tx);
+            // The router has activated ZMODEM mode; get the write channel
+            // for responses back to serial/SSH
+            let (answer_tx, mut answer_rx) = mpsc::unbounded_channel();
+            let (mut cancel_rx, write_tx) = {
+                let mut connections = state.connections.lock().await;
+                let Some(conn_state) = connections.get_mut(&tab_id) else {
+                    drop(zmodem_rx);
+                    end_transfer(&zmodem_active, &zmodem_data_tx_shared).await;
+                    continue;
+                };
+                conn_state.transfer_answer = Some(answer_tx);
+                (register_transfer_cancel(conn_state), Some(conn_state.write_tx()))
+            };
 
             // Notify clients that ZMODEM started
             let _ = broadcast_tx.send(
@@ -1042,21 +5115,14 @@ fn spawn_zmodem_interceptor_for_tab(
                 .into_bytes(),
             );
 
-            // Get write channel for sending responses back to serial/SSH
-            let write_tx = {
-                let connections = state.connections.lock().await;
-                match connections.get(&tab_id) {
-                    Some(conn_state) => match &conn_state.connection {
-                        ConnectionKind::Serial(c) => Some(c.tx_to_serial.clone()),
-                        ConnectionKind::Ssh(c) => Some(c.tx_to_ssh.clone()),
-                    },
-                    None => None,
-                }
-            };
-
             // Feed initial ZMODEM data to the receiver
             tracing::info!("ZMODEM: feeding initial {} bytes to receiver (tab {})", data.len(), tab_id);
-            let response = receiver.process(&data);
+            let mut response = receiver.process(&data);
+            if receiver.needs_catch_up() {
+                let (caught_up, more) = zmodem_catch_up(receiver).await;
+                receiver = caught_up;
+                response.extend(more);
+            }
             tracing::info!("ZMODEM: initial response {} bytes (tab {})", response.len(), tab_id);
             if !response.is_empty() {
                 if let Some(ref tx) = write_tx {
@@ -1075,8 +5141,38 @@ fn spawn_zmodem_interceptor_for_tab(
             let transfer_start = std::time::Instant::now();
             let mut file_start = std::time::Instant::now();
             let mut last_progress_time = std::time::Instant::now();
-            while let Some(incoming) = zmodem_rx.recv().await {
-                let response = receiver.process(&incoming);
+            // Set when the transfer is given up on; `true` if the remote
+            // side should be told to stop
+            let mut failure: Option<(String, bool)> = None;
+            let mut asked = false;
+            loop {
+                // The sender keeps repeating its file offer while the user
+                // decides, so the idle timeout still only fires on silence
+                let mut response = tokio::select! {
+                    Some(action) = answer_rx.recv() => receiver.resolve(action),
+                    received = tokio::time::timeout(TRANSFER_IDLE_TIMEOUT, zmodem_rx.recv()) => {
+                        match received {
+                            Ok(Some(incoming)) => receiver.process(&incoming),
+                            Ok(None) => {
+                                failure = Some(("Connection closed".to_string(), false));
+                                break;
+                            }
+                            Err(_) => {
+                                failure = Some(("The sender stopped responding".to_string(), true));
+                                break;
+                            }
+                        }
+                    }
+                    cancelled = &mut cancel_rx => {
+                        failure = Some((transfer_cancelled(cancelled), true));
+                        break;
+                    }
+                };
+                if receiver.needs_catch_up() {
+                    let (caught_up, more) = zmodem_catch_up(receiver).await;
+                    receiver = caught_up;
+                    response.extend(more);
+                }
                 if !response.is_empty() {
                     if let Some(ref tx) = write_tx {
                         if let Err(e) = tx.send(response).await {
@@ -1086,6 +5182,39 @@ fn spawn_zmodem_interceptor_for_tab(
                     }
                 }
 
+                // Ask once per file whether to replace one that exists
+                match receiver.pending() {
+                    Some(pending) if !asked => {
+                        asked = true;
+                        let _ = broadcast_tx.send(transfer_notification(serde_json::json!({
+                            "type": "zmodem",
+                            "state": "file_exists",
+                            "filename": pending.filename,
+                            "size": pending.size,
+                            "existing": pending.existing
+                        })));
+                    }
+                    Some(_) => {}
+                    None => asked = false,
+                }
+                if let Some(filename) = receiver.take_skipped() {
+                    let _ = broadcast_tx.send(transfer_notification(serde_json::json!({
+                        "type": "zmodem",
+                        "state": "file_skipped",
+                        "filename": filename
+                    })));
+                    let record = transfers::Record::new(
+                        "zmodem",
+                        transfers::Direction::Receive,
+                        &filename,
+                        0,
+                        std::time::Duration::ZERO,
+                        transfers::Status::Skipped,
+                    );
+                    record_transfer(&state, &tab_id, record).await;
+                    file_start = std::time::Instant::now();
+                }
+
                 // Check if a file just completed
                 if let Some(completed) = receiver.take_completed() {
                     let file_elapsed = file_start.elapsed().as_millis() as u64;
@@ -1102,6 +5231,16 @@ fn spawn_zmodem_interceptor_for_tab(
                         )
                         .into_bytes(),
                     );
+                    let mut record = transfers::Record::new(
+                        "zmodem",
+                        transfers::Direction::Receive,
+                        &completed.filename,
+                        completed.size,
+                        file_start.elapsed(),
+                        transfers::Status::Completed,
+                    );
+                    record.path = receiver.received_files().last().cloned();
+                    record_transfer(&state, &tab_id, record).await;
                     file_start = std::time::Instant::now();
                 }
 
@@ -1126,12 +5265,53 @@ fn spawn_zmodem_interceptor_for_tab(
                 }
 
                 if receiver.is_done() {
+                    if let Some(e) = receiver.error() {
+                        failure = Some((e.to_string(), false));
+                    }
                     break;
                 }
             }
 
+            if let Some((message, tell_remote)) = failure {
+                let current = receiver
+                    .current_filename()
+                    .map(|name| (name.to_string(), receiver.current_bytes()));
+                let partial = receiver.abort();
+                if let Some((filename, bytes)) = current {
+                    let mut record = transfers::Record::new(
+                        "zmodem",
+                        transfers::Direction::Receive,
+                        &filename,
+                        bytes,
+                        file_start.elapsed(),
+                        transfers::Status::Failed,
+                    );
+                    // Kept to be resumed
+                    if partial.is_none() {
+                        record.path = Some(download_dir.join(&filename));
+                    }
+                    record.message = Some(message.clone());
+                    record_transfer(&state, &tab_id, record).await;
+                }
+                if tell_remote {
+                    if let Some(ref tx) = write_tx {
+                        let _ = tx.send(zmodem::ABORT_SEQUENCE.to_vec()).await;
+                    }
+                }
+                tracing::error!("ZMODEM transfer failed (tab {}): {}", tab_id, message);
+                drop(zmodem_rx);
+                end_transfer(&zmodem_active, &zmodem_data_tx_shared).await;
+                let _ = broadcast_tx.send(transfer_notification(serde_json::json!({
+                    "type": "zmodem",
+                    "state": "failed",
+                    "message": message,
+                    "partial": partial
+                })));
+                continue;
+            }
+
             // Collect received files
-            let files: Vec<PathBuf> = receiver.received_files().to_vec();
+            let files = receiver.received_files();
 
             let file_names: Vec<String> = files
                 .iter()
@@ -1147,17 +5327,9 @@ fn spawn_zmodem_interceptor_for_tab(
                 file_names
             );
 
-            // Store files in the tab's connection state
-            {
-                let mut connections = state.connections.lock().await;
-                if let Some(conn_state) = connections.get_mut(&tab_id) {
-                    conn_state.zmodem_files = files;
-                }
-            }
-
             // Deactivate ZMODEM mode
-            *zmodem_data_tx_shared.lock().await = None;
-            zmodem_active.store(false, Ordering::SeqCst);
+            drop(zmodem_rx);
+            end_transfer(&zmodem_active, &zmodem_data_tx_shared).await;
 
             // Compute transfer stats
             let elapsed_ms = transfer_start.elapsed().as_millis() as u64;
@@ -1179,6 +5351,8 @@ fn spawn_zmodem_interceptor_for_tab(
             );
         }
     });
+
+    router
 }
 
 // ---------------------------------------------------------------------------
@@ -1219,8 +5393,41 @@ async fn static_handler(uri: axum::http::Uri) -> impl IntoResponse {
 // ---------------------------------------------------------------------------
 
 async fn start_axum_server() {
+    let pending_restore = restore::load(&app_data_dir());
+    if !pending_restore.is_empty() {
+        tracing::info!("{} tab(s) from the previous run can be restored", pending_restore.len());
+    }
+
+    let keychain = sessions::Keychain {
+        get: secrets::get,
+        set: secrets::set,
+        delete: secrets::delete,
+    };
+    let sessions = tokio::task::spawn_blocking(move || sessions::SessionStore::open(&app_config_dir(), keychain))
+        .await
+        .expect("Session store task panicked");
+    tracing::info!("Session store: {}", sessions.path().display());
+
     let state = Arc::new(AppState {
         connections: Mutex::new(HashMap::new()),
+        pending_restore: Mutex::new(pending_restore),
+        restore_written: Mutex::new(HashMap::new()),
+        sessions,
+        hook_scripts: Mutex::new(HashMap::new()),
+        tab_sessions: Mutex::new(HashMap::new()),
+        triggers: Mutex::new(HashMap::new()),
+        schedules: Mutex::new(HashMap::new()),
+        transfers: Mutex::new(HashMap::new()),
+    });
+
+    // Periodically save open tabs and their scrollback for restore
+    let state_for_persist = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RESTORE_SAVE_INTERVAL);
+        loop {
+            interval.tick().await;
+            persist_tabs(&state_for_persist).await;
+        }
     });
 
     let cors = CorsLayer::very_permissive();
@@ -1232,8 +5439,57 @@ async fn start_axum_server() {
         .route("/api/ssh/connect", post(ssh_connect))
         .route("/api/status", get(status))
         .route("/ws", get(ws_handler))
+        .route("/api/input-group", get(input_group_list).post(input_group_update))
+        .route("/api/input-group/send", post(input_group_send))
         .route("/api/zmodem/files", get(zmodem_list_files))
+        .route("/api/zmodem/send", post(zmodem_send))
+        .route("/api/zmodem/cancel", post(zmodem_cancel))
+        .route("/api/zmodem/resolve", post(zmodem_resolve))
         .route("/api/zmodem/download/{filename}", get(zmodem_download_file))
+        .route("/api/transfers", get(transfers_list))
+        .route("/api/transfers/{id}", delete(transfer_delete))
+        .route("/api/transfers/{id}/download", get(transfer_download))
+        .route("/api/transfers/{id}/reveal", post(transfer_reveal))
+        .route("/api/scrollback/info", get(scrollback_info))
+        .route("/api/scrollback/resize", post(scrollback_resize))
+        .route("/api/scrollback/range", get(scrollback_range))
+        .route("/api/scrollback/lines", get(scrollback_lines))
+        .route("/api/scrollback/search", get(scrollback_search))
+        .route("/api/scrollback/export", get(scrollback_export))
+        .route("/api/restore", get(restore_list).post(restore_open))
+        .route("/api/restore/discard", post(restore_discard))
+        .route("/api/sessions", get(sessions_list).post(session_create))
+        .route("/api/sessions/folders", post(folder_create))
+        .route("/api/sessions/folders/{name}", put(folder_rename).delete(folder_delete))
+        .route("/api/sessions/defaults", get(session_defaults_get).put(session_defaults_put))
+        .route("/api/sessions/ssh-info", get(ssh_info_get).put(ssh_info_put))
+        .route("/api/sessions/import-local", post(sessions_import_local))
+        .route("/api/sessions/import", post(sessions_import))
+        .route(
+            "/api/sessions/{id}",
+            get(session_get).put(session_update).delete(session_delete),
+        )
+        .route("/api/sessions/{id}/auto-login", get(auto_login_get))
+        .route("/api/sessions/{id}/auto-login/password", put(auto_login_password_put))
+        .route("/api/script/start", post(script_start))
+        .route("/api/script/status", get(script_status))
+        .route("/api/script/abort", post(script_abort))
+        .route("/api/hooks/attach", post(hooks_attach))
+        .route("/api/hooks/detach", post(hooks_detach))
+        .route("/api/hooks/status", get(hooks_status))
+        .route("/api/triggers", get(triggers_get).put(triggers_put))
+        .route("/api/triggers/counters/reset", post(trigger_counters_reset))
+        .route("/api/schedule", get(schedule_get).put(schedule_put))
+        .route("/api/xmodem/send", post(xmodem_send))
+        .route("/api/xmodem/receive", post(xmodem_receive))
+        .route("/api/kermit/send", post(kermit_send))
+        .route("/api/kermit/receive", post(kermit_receive))
+        .route("/api/trzsz/upload", post(trzsz_upload))
+        .route("/api/upload/start", post(upload_start))
+        .route("/api/upload/status", get(upload_status))
+        .route("/api/upload/cancel", post(upload_cancel))
+        .route("/api/flash/start", post(flash_start))
+        .route("/api/flash/status", get(flash_status))
         .route("/api/log/start", post(log_start))
         .route("/api/log/stop", post(log_stop))
         .route("/api/log/status", get(log_status))
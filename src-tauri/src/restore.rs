@@ -0,0 +1,253 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ssh::SshConfig;
+use crate::PortConfig;
+
+const STATE_FILE: &str = "tabs.json";
+const SNAPSHOT_DIR: &str = "restore";
+
+/// Newest scrollback bytes kept per tab across restarts.
+pub const SNAPSHOT_MAX: usize = 4 * 1024 * 1024; // 4MB
+
+/// USB identity of a serial device, used to find it again after it has
+/// been re-enumerated under a different port name.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+impl DeviceIdentity {
+    fn from_port_type(pt: &serialport::SerialPortType) -> Option<Self> {
+        match pt {
+            serialport::SerialPortType::UsbPort(info) => Some(DeviceIdentity {
+                vid: info.vid,
+                pid: info.pid,
+                serial_number: info.serial_number.clone(),
+                manufacturer: info.manufacturer.clone(),
+                product: info.product.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Look up the identity of a currently present port.
+    pub fn for_port(port_name: &str) -> Option<Self> {
+        serialport::available_ports()
+            .ok()?
+            .into_iter()
+            .find(|p| p.port_name == port_name)
+            .and_then(|p| Self::from_port_type(&p.port_type))
+    }
+
+    fn matches(&self, other: &DeviceIdentity) -> bool {
+        self.vid == other.vid
+            && self.pid == other.pid
+            && (self.serial_number.is_none() || self.serial_number == other.serial_number)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SavedConnection {
+    Serial {
+        config: PortConfig,
+        device: Option<DeviceIdentity>,
+    },
+    /// SSH config with the password removed
+    Ssh { config: SshConfig },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SavedTab {
+    pub tab_id: String,
+    pub connection: SavedConnection,
+    pub scrollback_size: usize,
+    pub scrollback_spill: bool,
+    pub log_path: Option<String>,
+    /// Session the tab was opened from
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+impl SavedTab {
+    /// Human-readable label, matching the one the frontend shows on connect.
+    pub fn label(&self) -> String {
+        match &self.connection {
+            SavedConnection::Serial { config, .. } => {
+                format!("{} @ {}", config.port, config.baud_rate)
+            }
+            SavedConnection::Ssh { config } => {
+                format!("SSH {}@{}:{}", config.username, config.host, config.port)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SavedState {
+    tabs: Vec<SavedTab>,
+}
+
+/// Choose which present port corresponds to a saved device. Prefers the
+/// original name, then a port of the same flavor (e.g. `cu.` vs `tty.` on
+/// macOS), then any port with a matching identity. Without an identity,
+/// the original name is used if it still exists.
+pub fn pick_port(
+    identity: Option<&DeviceIdentity>,
+    original: &str,
+    ports: &[(String, Option<DeviceIdentity>)],
+) -> Option<String> {
+    let identity = match identity {
+        Some(id) => id,
+        None => {
+            return ports
+                .iter()
+                .find(|(name, _)| name == original)
+                .map(|(name, _)| name.clone());
+        }
+    };
+
+    let candidates: Vec<&String> = ports
+        .iter()
+        .filter(|(_, id)| id.as_ref().is_some_and(|id| identity.matches(id)))
+        .map(|(name, _)| name)
+        .collect();
+
+    let flavor = |name: &str| {
+        let base = name.rsplit('/').next().unwrap_or(name);
+        base.split('.').next().unwrap_or(base).to_string()
+    };
+
+    candidates
+        .iter()
+        .find(|name| name.as_str() == original)
+        .or_else(|| candidates.iter().find(|name| flavor(name) == flavor(original)))
+        .or_else(|| candidates.first())
+        .map(|name| name.to_string())
+}
+
+/// Re-resolve a saved serial device against the ports present right now.
+pub fn resolve_port(identity: Option<&DeviceIdentity>, original: &str) -> Option<String> {
+    let ports: Vec<(String, Option<DeviceIdentity>)> = serialport::available_ports()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|p| {
+            let id = DeviceIdentity::from_port_type(&p.port_type);
+            (p.port_name, id)
+        })
+        .collect();
+    pick_port(identity, original, &ports)
+}
+
+fn snapshot_path(dir: &Path, tab_id: &str) -> PathBuf {
+    let safe: String = tab_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    dir.join(SNAPSHOT_DIR).join(format!("{}.bin", safe))
+}
+
+/// Load the tabs saved by the previous run.
+pub fn load(dir: &Path) -> Vec<SavedTab> {
+    let path = dir.join(STATE_FILE);
+    let data = match std::fs::read(&path) {
+        Ok(d) => d,
+        Err(_) => return Vec::new(),
+    };
+    match serde_json::from_slice::<SavedState>(&data) {
+        Ok(state) => state.tabs,
+        Err(e) => {
+            tracing::warn!("Ignoring unreadable {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+/// Write the tab list atomically and drop snapshots of tabs no longer in it.
+pub fn save(dir: &Path, tabs: &[SavedTab]) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir.join(SNAPSHOT_DIR))?;
+    let data = serde_json::to_vec_pretty(&SavedState {
+        tabs: tabs.to_vec(),
+    })
+    .map_err(std::io::Error::other)?;
+    let tmp = dir.join(format!("{}.tmp", STATE_FILE));
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, dir.join(STATE_FILE))?;
+
+    let keep: Vec<PathBuf> = tabs.iter().map(|t| snapshot_path(dir, &t.tab_id)).collect();
+    for entry in std::fs::read_dir(dir.join(SNAPSHOT_DIR))?.flatten() {
+        if !keep.contains(&entry.path()) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+    Ok(())
+}
+
+pub fn save_snapshot(dir: &Path, tab_id: &str, data: &[u8]) -> std::io::Result<()> {
+    let path = snapshot_path(dir, tab_id);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, &path)
+}
+
+pub fn load_snapshot(dir: &Path, tab_id: &str) -> Option<Vec<u8>> {
+    std::fs::read(snapshot_path(dir, tab_id)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(vid: u16, pid: u16, serial: Option<&str>) -> Option<DeviceIdentity> {
+        Some(DeviceIdentity {
+            vid,
+            pid,
+            serial_number: serial.map(|s| s.to_string()),
+            manufacturer: None,
+            product: None,
+        })
+    }
+
+    #[test]
+    fn test_pick_port_by_identity() {
+        let saved = id(0x10c4, 0xea60, Some("A1")).unwrap();
+        let ports = vec![
+            ("/dev/ttyUSB0".to_string(), id(0x0403, 0x6001, Some("B2"))),
+            ("/dev/ttyUSB1".to_string(), id(0x10c4, 0xea60, Some("A1"))),
+        ];
+        assert_eq!(
+            pick_port(Some(&saved), "/dev/ttyUSB0", &ports),
+            Some("/dev/ttyUSB1".to_string())
+        );
+        assert_eq!(pick_port(Some(&id(1, 2, None).unwrap()), "/dev/ttyUSB0", &ports), None);
+    }
+
+    #[test]
+    fn test_pick_port_prefers_same_flavor() {
+        let saved = id(0x10c4, 0xea60, None).unwrap();
+        let ports = vec![
+            ("/dev/tty.usbserial-2".to_string(), id(0x10c4, 0xea60, None)),
+            ("/dev/cu.usbserial-2".to_string(), id(0x10c4, 0xea60, None)),
+        ];
+        assert_eq!(
+            pick_port(Some(&saved), "/dev/cu.usbserial-1", &ports),
+            Some("/dev/cu.usbserial-2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_pick_port_without_identity() {
+        let ports = vec![("/dev/ttyS0".to_string(), None)];
+        assert_eq!(pick_port(None, "/dev/ttyS0", &ports), Some("/dev/ttyS0".to_string()));
+        assert_eq!(pick_port(None, "/dev/ttyS1", &ports), None);
+    }
+}
@@ -0,0 +1,364 @@
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+
+/// Longest `every` interval accepted: a year.
+const MAX_INTERVAL_SECS: u64 = 366 * 24 * 60 * 60;
+
+/// Something sent to a tab on a schedule.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Job {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    /// Text to send
+    pub data: String,
+    /// Append `\r`, like pressing Enter
+    #[serde(default)]
+    pub line: bool,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub when: When,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum When {
+    /// Every N seconds, counted from connect
+    Every(u64),
+    /// Five-field cron expression (`min hour day month weekday`), local time
+    Cron(String),
+}
+
+impl Job {
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut data = self.data.clone().into_bytes();
+        if self.line {
+            data.push(b'\r');
+        }
+        data
+    }
+}
+
+/// A job with its run history, as reported in tab status.
+#[derive(Serialize, Clone, Debug)]
+pub struct JobStatus {
+    #[serde(flatten)]
+    pub job: Job,
+    /// Unix time in milliseconds; `None` while disabled or disconnected
+    pub next_run: Option<i64>,
+    pub last_run: Option<i64>,
+    pub runs: u64,
+}
+
+// ---------------------------------------------------------------------------
+// Cron expressions
+// ---------------------------------------------------------------------------
+
+/// A parsed cron expression. Each field is a bit set of allowed values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Day of month and weekday both restricted: either one matching is
+    /// enough, as in standard cron
+    day_or_weekday: bool,
+}
+
+/// Parse one field: `*`, `N`, `A-B`, with an optional `/STEP`, separated
+/// by commas.
+fn parse_field(field: &str, min: u32, max: u32, what: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid {} field: {}", what, field);
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (
+                a.parse().map_err(|_| invalid())?,
+                b.parse().map_err(|_| invalid())?,
+            )
+        } else {
+            let n = range.parse().map_err(|_| invalid())?;
+            // `N/STEP` means from N to the end
+            (n, if part.contains('/') { max } else { n })
+        };
+        if step == 0 || lo < min || hi > max || lo > hi {
+            return Err(invalid());
+        }
+        for v in (lo..=hi).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Cron, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "Expected 5 cron fields, got {}: {}",
+                fields.len(),
+                expr
+            ));
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7, "weekday")?;
+        // Both 0 and 7 mean Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59, "minute")?,
+            hours: parse_field(fields[1], 0, 23, "hour")?,
+            days: parse_field(fields[2], 1, 31, "day")?,
+            months: parse_field(fields[3], 1, 12, "month")?,
+            weekdays,
+            day_or_weekday: fields[2] != "*" && fields[4] != "*",
+        })
+    }
+
+    fn day_matches(&self, t: &NaiveDateTime) -> bool {
+        let day = self.days & (1 << t.day()) != 0;
+        let weekday = self.weekdays & (1 << t.weekday().num_days_from_sunday()) != 0;
+        if self.day_or_weekday {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+
+    /// The first matching minute after `after`.
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut t = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        // A match is always found within a few years, unless the
+        // expression can never match (like February 31st)
+        let limit = after + Duration::days(366 * 5);
+        while t < limit {
+            if self.months & (1 << t.month()) == 0 {
+                let (y, m) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = chrono::NaiveDate::from_ymd_opt(y, m, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(&t) {
+                t = (t.date() + Duration::days(1)).and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << t.hour()) == 0 {
+                t = t.with_minute(0)? + Duration::hours(1);
+            } else if self.minutes & (1 << t.minute()) == 0 {
+                t += Duration::minutes(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+/// Next run of a cron job in local time, skipping times that don't exist
+/// because of a DST change.
+fn next_cron_run(cron: &Cron, after: DateTime<Local>) -> Option<DateTime<Local>> {
+    let mut naive = after.naive_local();
+    for _ in 0..4 {
+        naive = cron.next_after(naive)?;
+        if let Some(t) = Local.from_local_datetime(&naive).earliest() {
+            if t > after {
+                return Some(t);
+            }
+        }
+    }
+    None
+}
+
+// ---------------------------------------------------------------------------
+// Per-tab schedule
+// ---------------------------------------------------------------------------
+
+/// The jobs of one tab and when each runs next.
+#[derive(Default)]
+pub struct TabSchedule {
+    jobs: Vec<(JobStatus, Option<Cron>)>,
+}
+
+impl TabSchedule {
+    /// Replace the jobs. Run history is kept for jobs with the same id.
+    /// Next runs are left unset until `start`.
+    pub fn set_jobs(&mut self, jobs: Vec<Job>) -> Result<(), String> {
+        let mut parsed = Vec::with_capacity(jobs.len());
+        for (i, job) in jobs.into_iter().enumerate() {
+            let label = if job.name.is_empty() {
+                format!("Job {}", i + 1)
+            } else {
+                job.name.clone()
+            };
+            let cron = match &job.when {
+                When::Every(0) => {
+                    return Err(format!("{}: interval must be at least 1 second", label))
+                }
+                When::Every(secs) if *secs > MAX_INTERVAL_SECS => {
+                    return Err(format!(
+                        "{}: interval must be at most {} seconds (a year)",
+                        label, MAX_INTERVAL_SECS
+                    ))
+                }
+                When::Every(_) => None,
+                When::Cron(expr) => {
+                    Some(Cron::parse(expr).map_err(|e| format!("{}: {}", label, e))?)
+                }
+            };
+            let (last_run, runs) = self
+                .jobs
+                .iter()
+                .find(|(s, _)| !job.id.is_empty() && s.job.id == job.id)
+                .map(|(s, _)| (s.last_run, s.runs))
+                .unwrap_or((None, 0));
+            parsed.push((
+                JobStatus {
+                    job,
+                    next_run: None,
+                    last_run,
+                    runs,
+                },
+                cron,
+            ));
+        }
+        self.jobs = parsed;
+        Ok(())
+    }
+
+    pub fn status(&self) -> Vec<JobStatus> {
+        self.jobs.iter().map(|(s, _)| s.clone()).collect()
+    }
+
+    /// Plan the next run of every enabled job, counting from `now`.
+    pub fn start(&mut self, now: DateTime<Local>) {
+        for (status, cron) in &mut self.jobs {
+            status.next_run = next_run(&status.job, cron.as_ref(), now);
+        }
+    }
+
+    /// Clear next runs; nothing is due until `start` is called again.
+    pub fn stop(&mut self) {
+        for (status, _) in &mut self.jobs {
+            status.next_run = None;
+        }
+    }
+
+    /// The earliest planned run, in Unix milliseconds.
+    pub fn next_wakeup(&self) -> Option<i64> {
+        self.jobs.iter().filter_map(|(s, _)| s.next_run).min()
+    }
+
+    /// Take the jobs due at `now`, recording the run and planning the next.
+    pub fn take_due(&mut self, now: DateTime<Local>) -> Vec<Job> {
+        let now_ms = now.timestamp_millis();
+        let mut due = Vec::new();
+        for (status, cron) in &mut self.jobs {
+            if status.next_run.is_some_and(|t| t <= now_ms) {
+                status.last_run = Some(now_ms);
+                status.runs += 1;
+                status.next_run = next_run(&status.job, cron.as_ref(), now);
+                due.push(status.job.clone());
+            }
+        }
+        due
+    }
+}
+
+fn next_run(job: &Job, cron: Option<&Cron>, now: DateTime<Local>) -> Option<i64> {
+    if !job.enabled {
+        return None;
+    }
+    match (&job.when, cron) {
+        (When::Every(secs), _) => {
+            let interval = i64::try_from(*secs).ok()?.checked_mul(1000)?;
+            Some(now.timestamp_millis().saturating_add(interval))
+        }
+        (When::Cron(_), Some(cron)) => next_cron_run(cron, now).map(|t| t.timestamp_millis()),
+        (When::Cron(_), None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_cron_next() {
+        let next = |expr: &str, after: &str| Cron::parse(expr).unwrap().next_after(at(after));
+        assert_eq!(
+            next("*/15 * * * *", "2024-03-01 10:07"),
+            Some(at("2024-03-01 10:15"))
+        );
+        assert_eq!(
+            next("0 9 * * 1-5", "2024-03-01 09:00"),
+            Some(at("2024-03-04 09:00"))
+        );
+        assert_eq!(
+            next("30 2 29 2 *", "2024-03-01 00:00"),
+            Some(at("2028-02-29 02:30"))
+        );
+        // Day of month or Sunday
+        assert_eq!(
+            next("0 0 15 * 7", "2024-03-01 12:00"),
+            Some(at("2024-03-03 00:00"))
+        );
+        assert_eq!(next("0 0 31 2 *", "2024-03-01 12:00"), None);
+        assert!(Cron::parse("* * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+    }
+
+    #[test]
+    fn test_take_due() {
+        let jobs: Vec<Job> = serde_json::from_str(
+            r#"[
+                {"id": "a", "data": "\u0000", "every": 60},
+                {"id": "b", "data": "uptime", "line": true, "cron": "0 * * * *"},
+                {"id": "c", "data": "x", "every": 1, "enabled": false}
+            ]"#,
+        )
+        .unwrap();
+        let mut schedule = TabSchedule::default();
+        schedule.set_jobs(jobs.clone()).unwrap();
+        assert_eq!(schedule.next_wakeup(), None);
+
+        let t0 = Local.with_ymd_and_hms(2024, 3, 1, 10, 59, 30).unwrap();
+        schedule.start(t0);
+        assert_eq!(schedule.next_wakeup(), Some(t0.timestamp_millis() + 30_000));
+
+        let due = schedule.take_due(t0 + Duration::seconds(30));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].bytes(), b"uptime\r");
+        let due = schedule.take_due(t0 + Duration::seconds(60));
+        assert_eq!(due[0].id, "a");
+
+        // History survives an update
+        schedule.set_jobs(jobs).unwrap();
+        assert_eq!(schedule.status()[0].runs, 1);
+        assert!(schedule
+            .set_jobs(serde_json::from_str(r#"[{"data": "x", "every": 0}]"#).unwrap())
+            .is_err());
+        let huge = format!(r#"[{{"data": "x", "every": {}}}]"#, u64::MAX);
+        assert!(schedule
+            .set_jobs(serde_json::from_str(&huge).unwrap())
+            .is_err());
+    }
+}
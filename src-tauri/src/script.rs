@@ -0,0 +1,674 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+use crate::ansi::AnsiStripper;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Received text kept for matching; older text is dropped first.
+const MATCH_BUFFER_MAX: usize = 64 * 1024;
+
+/// Upper bound for instructions executed by one run, so a `goto` loop
+/// without any waiting can't spin forever.
+const MAX_STEPS: u64 = 1_000_000;
+
+#[derive(Debug)]
+enum Cond {
+    /// `expect` matched its Nth pattern (1-based)
+    Match(usize),
+    Timeout,
+}
+
+#[derive(Debug)]
+enum Instr {
+    Send(Vec<u8>),
+    /// Wait for the first of `patterns`. With `required`, a timeout fails
+    /// the script; otherwise it just sets the result to 0.
+    Expect {
+        patterns: Vec<Regex>,
+        timeout: Option<Duration>,
+        required: bool,
+    },
+    If(Cond, String),
+    Goto(String),
+    Sleep(Duration),
+    SetTimeout(Duration),
+    Dtr(bool),
+    Rts(bool),
+    Break(Duration),
+    LogStart(String),
+    LogStop,
+    Print(String),
+    Fail(String),
+    Exit,
+}
+
+/// A parsed script.
+///
+/// One command per line; `#` starts a comment and `:name` defines a label.
+///
+/// ```text
+/// set timeout 5
+/// sendline ""
+/// expect "login:" /[Pp]assword:/
+/// if timeout goto retry
+/// if match 2 goto password
+/// sendline "root"
+/// :password
+/// require "# " timeout 30s
+/// send "reboot\r"
+/// ```
+///
+/// Strings take `\r \n \t \e \\ \" \xHH` escapes; `/.../` is a regex.
+#[derive(Debug)]
+pub struct Script {
+    /// Instruction and its source line
+    instrs: Vec<(usize, Instr)>,
+    labels: HashMap<String, usize>,
+}
+
+/// Something a script does to its tab. Implemented by the server for a
+/// live connection.
+#[async_trait]
+pub trait ScriptHost: Send + Sync {
+    async fn send(&self, data: &[u8]) -> Result<(), String>;
+    async fn set_dtr(&self, on: bool) -> Result<(), String>;
+    async fn set_rts(&self, on: bool) -> Result<(), String>;
+    async fn send_break(&self, duration: Duration) -> Result<(), String>;
+    /// Returns the expanded log path.
+    async fn log_start(&self, path: &str) -> Result<String, String>;
+    async fn log_stop(&self) -> Result<(), String>;
+    /// Show a message in the tab.
+    fn print(&self, message: &str);
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RunState {
+    Running,
+    Finished,
+    Failed,
+    Aborted,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ScriptStatus {
+    pub name: String,
+    pub state: RunState,
+    /// Source line being executed (or where the script stopped)
+    pub line: usize,
+    pub message: Option<String>,
+    /// Result of the last `expect`: matched pattern (1-based), 0 on timeout
+    pub last_match: usize,
+}
+
+impl ScriptStatus {
+    pub fn new(name: &str) -> Self {
+        ScriptStatus {
+            name: name.to_string(),
+            state: RunState::Running,
+            line: 0,
+            message: None,
+            last_match: 0,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Parser
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    /// Bytes: `\xHH` escapes stand for raw bytes, not characters
+    Str(Vec<u8>),
+    Regex(String),
+}
+
+fn unescape(s: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+    let mut utf8 = [0; 4];
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => out.push(b'\r'),
+            Some('n') => out.push(b'\n'),
+            Some('t') => out.push(b'\t'),
+            Some('e') => out.push(0x1b),
+            Some('0') => out.push(0),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let b = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("Invalid escape \\x{}", hex))?;
+                out.push(b);
+            }
+            Some(other) => out.extend_from_slice(other.encode_utf8(&mut utf8).as_bytes()),
+            None => return Err("Trailing backslash".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+/// A string literal where text is needed rather than bytes to send.
+fn text(bytes: &[u8]) -> Result<String, String> {
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| "\\x escapes above \\x7f only work in send and sendline".to_string())
+}
+
+/// Quote `s` as a script string literal.
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            c if (c as u32) < 0x20 || c == '\x7f' => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Quote a regex as a script `/.../` literal.
+pub fn quote_regex(re: &str) -> String {
+    let mut out = String::with_capacity(re.len() + 2);
+    out.push('/');
+    let mut chars = re.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                out.push(c);
+                if let Some(next) = chars.next() {
+                    out.push(next);
+                }
+            }
+            '/' => out.push_str("\\/"),
+            c => out.push(c),
+        }
+    }
+    out.push('/');
+    out
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == '#' {
+            break;
+        }
+        if c == '"' || c == '/' {
+            chars.next();
+            let mut raw = String::new();
+            let mut closed = false;
+            while let Some((_, ch)) = chars.next() {
+                if ch == '\\' {
+                    raw.push(ch);
+                    if let Some((_, next)) = chars.next() {
+                        raw.push(next);
+                    }
+                    continue;
+                }
+                if ch == c {
+                    closed = true;
+                    break;
+                }
+                raw.push(ch);
+            }
+            if !closed {
+                return Err(format!("Unterminated {}", if c == '"' { "string" } else { "regex" }));
+            }
+            tokens.push(if c == '"' {
+                Token::Str(unescape(&raw)?)
+            } else {
+                Token::Regex(raw.replace("\\/", "/"))
+            });
+            continue;
+        }
+        let mut end = line.len();
+        while let Some(&(i, ch)) = chars.peek() {
+            if ch.is_whitespace() {
+                end = i;
+                break;
+            }
+            chars.next();
+        }
+        tokens.push(Token::Word(line[start..end].to_string()));
+    }
+    Ok(tokens)
+}
+
+/// Parse `500ms`, `2s`, `1.5s` or a bare number of seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(sec) = s.strip_suffix('s') {
+        (sec, 1.0)
+    } else {
+        (s, 1.0)
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite() && *n >= 0.0)
+        .map(|n| Duration::from_secs_f64(n * scale))
+        .ok_or_else(|| format!("Invalid duration: {}", s))
+}
+
+fn parse_on_off(s: Option<&Token>) -> Result<bool, String> {
+    match s {
+        Some(Token::Word(w)) if w == "on" => Ok(true),
+        Some(Token::Word(w)) if w == "off" => Ok(false),
+        _ => Err("Expected on or off".to_string()),
+    }
+}
+
+fn bytes_arg(tokens: &[Token], what: &str) -> Result<Vec<u8>, String> {
+    match tokens {
+        [Token::Str(s)] => Ok(s.clone()),
+        [Token::Word(s)] => Ok(s.clone().into_bytes()),
+        _ => Err(format!("{} takes one string", what)),
+    }
+}
+
+fn text_arg(tokens: &[Token], what: &str) -> Result<String, String> {
+    text(&bytes_arg(tokens, what)?)
+}
+
+fn parse_expect(args: &[Token], required: bool) -> Result<Instr, String> {
+    let mut patterns = Vec::new();
+    let mut timeout = None;
+    let mut i = 0;
+    while i < args.len() {
+        match &args[i] {
+            Token::Str(s) => {
+                patterns.push(Regex::new(&regex::escape(&text(s)?)).map_err(|e| e.to_string())?)
+            }
+            Token::Regex(r) => patterns.push(Regex::new(r).map_err(|e| format!("Invalid regex: {}", e))?),
+            Token::Word(w) if w == "timeout" => {
+                let value = match args.get(i + 1) {
+                    Some(Token::Word(v)) => v,
+                    _ => return Err("timeout needs a duration".to_string()),
+                };
+                timeout = Some(parse_duration(value)?);
+                i += 1;
+            }
+            Token::Word(w) => return Err(format!("Unexpected {}", w)),
+        }
+        i += 1;
+    }
+    if patterns.is_empty() {
+        return Err("expect needs at least one pattern".to_string());
+    }
+    Ok(Instr::Expect {
+        patterns,
+        timeout,
+        required,
+    })
+}
+
+fn parse_line(tokens: &[Token]) -> Result<Instr, String> {
+    let (command, args) = match tokens.split_first() {
+        Some((Token::Word(c), args)) => (c.as_str(), args),
+        _ => return Err("Expected a command".to_string()),
+    };
+    let word = |i: usize| match args.get(i) {
+        Some(Token::Word(w)) => Some(w.as_str()),
+        _ => None,
+    };
+    Ok(match command {
+        "send" => Instr::Send(bytes_arg(args, "send")?),
+        "sendline" => {
+            let mut data = bytes_arg(args, "sendline")?;
+            data.push(b'\r');
+            Instr::Send(data)
+        }
+        "expect" => parse_expect(args, false)?,
+        "require" => parse_expect(args, true)?,
+        "if" => {
+            let (cond, rest) = match word(0) {
+                Some("timeout") => (Cond::Timeout, 1),
+                Some("match") => {
+                    let n = word(1)
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .filter(|n| *n > 0)
+                        .ok_or("if match needs a pattern number")?;
+                    (Cond::Match(n), 2)
+                }
+                _ => return Err("Expected if timeout or if match N".to_string()),
+            };
+            match (word(rest), word(rest + 1)) {
+                (Some("goto"), Some(label)) if args.len() == rest + 2 => {
+                    Instr::If(cond, label.to_string())
+                }
+                _ => return Err("Expected goto LABEL".to_string()),
+            }
+        }
+        "goto" => match args {
+            [Token::Word(label)] => Instr::Goto(label.clone()),
+            _ => return Err("goto takes a label".to_string()),
+        },
+        "sleep" => Instr::Sleep(parse_duration(word(0).ok_or("sleep needs a duration")?)?),
+        "set" => match (word(0), word(1)) {
+            (Some("timeout"), Some(d)) => Instr::SetTimeout(parse_duration(d)?),
+            _ => return Err("Expected set timeout DURATION".to_string()),
+        },
+        "dtr" => Instr::Dtr(parse_on_off(args.first())?),
+        "rts" => Instr::Rts(parse_on_off(args.first())?),
+        "break" => Instr::Break(match word(0) {
+            Some(d) => parse_duration(d)?,
+            None => Duration::from_millis(250),
+        }),
+        "log" => match word(0) {
+            Some("start") => Instr::LogStart(text_arg(&args[1..], "log start")?),
+            Some("stop") => Instr::LogStop,
+            _ => return Err("Expected log start PATH or log stop".to_string()),
+        },
+        "print" => Instr::Print(text_arg(args, "print")?),
+        "fail" => Instr::Fail(text_arg(args, "fail").unwrap_or_else(|_| "Script failed".to_string())),
+        "exit" => Instr::Exit,
+        other => return Err(format!("Unknown command: {}", other)),
+    })
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Script, String> {
+        let mut instrs = Vec::new();
+        let mut labels = HashMap::new();
+        for (i, line) in source.lines().enumerate() {
+            let number = i + 1;
+            let trimmed = line.trim();
+            if let Some(label) = trimmed.strip_prefix(':') {
+                let label = label.trim();
+                if label.is_empty() || labels.insert(label.to_string(), instrs.len()).is_some() {
+                    return Err(format!("line {}: Invalid or duplicate label", number));
+                }
+                continue;
+            }
+            let tokens = tokenize(trimmed).map_err(|e| format!("line {}: {}", number, e))?;
+            if tokens.is_empty() {
+                continue;
+            }
+            let instr = parse_line(&tokens).map_err(|e| format!("line {}: {}", number, e))?;
+            instrs.push((number, instr));
+        }
+        for (number, instr) in &instrs {
+            if let Instr::If(_, label) | Instr::Goto(label) = instr {
+                if !labels.contains_key(label) {
+                    return Err(format!("line {}: Unknown label {}", number, label));
+                }
+            }
+        }
+        Ok(Script { instrs, labels })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Runner
+// ---------------------------------------------------------------------------
+
+/// Received output, with escape sequences stripped, waiting to be matched.
+pub struct MatchBuffer {
+    stripper: AnsiStripper,
+    text: String,
+}
+
+impl Default for MatchBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MatchBuffer {
+    pub fn new() -> Self {
+        MatchBuffer {
+            stripper: AnsiStripper::new(),
+            text: String::new(),
+        }
+    }
+
+    /// Forget output received so far.
+    pub fn clear(&mut self) {
+        self.text.clear();
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        let mut plain = Vec::with_capacity(data.len());
+        self.stripper.feed(data, &mut plain);
+        self.text.push_str(&String::from_utf8_lossy(&plain));
+        if self.text.len() > MATCH_BUFFER_MAX {
+            let mut cut = self.text.len() - MATCH_BUFFER_MAX;
+            while !self.text.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.text.drain(..cut);
+        }
+    }
+
+    /// Find the earliest match of any pattern; consume text up to its end
+    /// and return the 1-based pattern number.
+    pub fn take_match(&mut self, patterns: &[Regex]) -> Option<usize> {
+        self.take_match_text(patterns).map(|(index, _)| index)
+    }
+
+    /// Like `take_match`, also returning the matched text.
+    pub fn take_match_text(&mut self, patterns: &[Regex]) -> Option<(usize, String)> {
+        let (index, start, end) = patterns
+            .iter()
+            .enumerate()
+            .filter_map(|(i, re)| re.find(&self.text).map(|m| (i, m.start(), m.end())))
+            .min_by_key(|&(_, start, _)| start)?;
+        let text = self.text[start..end].to_string();
+        self.text.drain(..end);
+        Some((index + 1, text))
+    }
+}
+
+/// Run `script` to completion. `rx` is the tab's output; `is_notification`
+/// tells apart frames that aren't device output. Progress is recorded in
+/// `status`.
+pub async fn run(
+    script: &Script,
+    host: &dyn ScriptHost,
+    mut rx: broadcast::Receiver<Vec<u8>>,
+    is_notification: fn(&[u8]) -> bool,
+    status: &Mutex<ScriptStatus>,
+) -> Result<(), String> {
+    let mut buffer = MatchBuffer::new();
+    let mut timeout = DEFAULT_TIMEOUT;
+    let mut last_match = 0usize;
+    let mut pc = 0usize;
+    let mut steps = 0u64;
+
+    while let Some((line, instr)) = script.instrs.get(pc) {
+        steps += 1;
+        if steps > MAX_STEPS {
+            return Err(format!("line {}: Step limit reached", line));
+        }
+        status.lock().unwrap().line = *line;
+        pc += 1;
+        let fail = |e: String| format!("line {}: {}", line, e);
+
+        match instr {
+            Instr::Send(data) => host.send(data).await.map_err(fail)?,
+            Instr::Expect {
+                patterns,
+                timeout: t,
+                required,
+            } => {
+                let deadline = Instant::now() + t.unwrap_or(timeout);
+                last_match = loop {
+                    if let Some(n) = buffer.take_match(patterns) {
+                        break n;
+                    }
+                    match tokio::time::timeout_at(deadline, rx.recv()).await {
+                        Err(_) => break 0,
+                        Ok(Ok(data)) => {
+                            if !is_notification(&data) {
+                                buffer.push(&data);
+                            }
+                        }
+                        Ok(Err(broadcast::error::RecvError::Lagged(_))) => {}
+                        Ok(Err(broadcast::error::RecvError::Closed)) => {
+                            return Err(fail("Connection closed".to_string()));
+                        }
+                    }
+                };
+                status.lock().unwrap().last_match = last_match;
+                if *required && last_match == 0 {
+                    return Err(fail("Timed out waiting for output".to_string()));
+                }
+            }
+            Instr::If(cond, label) => {
+                let taken = match cond {
+                    Cond::Timeout => last_match == 0,
+                    Cond::Match(n) => last_match == *n,
+                };
+                if taken {
+                    pc = script.labels[label];
+                }
+            }
+            Instr::Goto(label) => pc = script.labels[label],
+            Instr::Sleep(d) => tokio::time::sleep(*d).await,
+            Instr::SetTimeout(d) => timeout = *d,
+            Instr::Dtr(on) => host.set_dtr(*on).await.map_err(fail)?,
+            Instr::Rts(on) => host.set_rts(*on).await.map_err(fail)?,
+            Instr::Break(d) => host.send_break(*d).await.map_err(fail)?,
+            Instr::LogStart(path) => {
+                let path = host.log_start(path).await.map_err(fail)?;
+                host.print(&format!("Logging to {}", path));
+            }
+            Instr::LogStop => host.log_stop().await.map_err(fail)?,
+            Instr::Print(message) => host.print(message),
+            Instr::Fail(message) => return Err(fail(message.clone())),
+            Instr::Exit => break,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct MockHost {
+        sent: Mutex<Vec<u8>>,
+        printed: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ScriptHost for MockHost {
+        async fn send(&self, data: &[u8]) -> Result<(), String> {
+            self.sent.lock().unwrap().extend_from_slice(data);
+            Ok(())
+        }
+        async fn set_dtr(&self, _on: bool) -> Result<(), String> {
+            Ok(())
+        }
+        async fn set_rts(&self, _on: bool) -> Result<(), String> {
+            Err("Not a serial connection".to_string())
+        }
+        async fn send_break(&self, _duration: Duration) -> Result<(), String> {
+            Ok(())
+        }
+        async fn log_start(&self, path: &str) -> Result<String, String> {
+            Ok(path.to_string())
+        }
+        async fn log_stop(&self) -> Result<(), String> {
+            Ok(())
+        }
+        fn print(&self, message: &str) {
+            self.printed.lock().unwrap().push(message.to_string());
+        }
+    }
+
+    fn no_notifications(_: &[u8]) -> bool {
+        false
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Script::parse("send \"a\\r\"\n:top\nexpect /x+/ timeout 500ms\nif match 1 goto top").is_ok());
+        assert_eq!(Script::parse("bogus").unwrap_err(), "line 1: Unknown command: bogus");
+        assert_eq!(Script::parse("\n\ngoto nowhere").unwrap_err(), "line 3: Unknown label nowhere");
+        assert!(Script::parse("send \"open").is_err());
+        assert!(Script::parse("expect timeout 5").is_err());
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn test_byte_escapes() {
+        let high: String = (0x80..=0xff).map(|b| format!("\\x{:02x}", b)).collect();
+        let script = Script::parse(&format!("send \"{}\"\nsendline \"\\xffé\"", high)).unwrap();
+        match &script.instrs[..] {
+            [(_, Instr::Send(a)), (_, Instr::Send(b))] => {
+                assert_eq!(*a, (0x80..=0xffu8).collect::<Vec<u8>>());
+                assert_eq!(*b, b"\xff\xc3\xa9\r");
+            }
+            _ => panic!("expected two sends"),
+        }
+        assert!(Script::parse("print \"\\xff\"").is_err());
+        assert!(Script::parse("print \"\\x41\"").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_run_expect_and_branch() {
+        let script = Script::parse(
+            "sendline \"\"\n\
+             expect \"login:\" /[Pp]assword:/ timeout 2s\n\
+             if match 2 goto password\n\
+             sendline \"root\"\n\
+             :password\n\
+             require \"# \"\n\
+             print \"done\"",
+        )
+        .unwrap();
+        let (tx, rx) = broadcast::channel(16);
+        let host = MockHost::default();
+        let status = Mutex::new(ScriptStatus::new("test"));
+
+        let feeder = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            tx.send(b"\x1b[1mboard login:\x1b[0m ".to_vec()).unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            tx.send(b"root@board:~# ".to_vec()).unwrap();
+            tx
+        });
+        run(&script, &host, rx, no_notifications, &status).await.unwrap();
+        drop(feeder.await);
+
+        assert_eq!(&*host.sent.lock().unwrap(), b"\rroot\r");
+        assert_eq!(*host.printed.lock().unwrap(), vec!["done"]);
+        assert_eq!(status.lock().unwrap().line, 7);
+    }
+
+    #[tokio::test]
+    async fn test_run_timeout_and_errors() {
+        let (_tx, rx) = broadcast::channel::<Vec<u8>>(16);
+        let host = MockHost::default();
+        let status = Mutex::new(ScriptStatus::new("test"));
+        let script = Script::parse("expect \"x\" timeout 10ms\nif timeout goto out\nfail \"no\"\n:out\nrts on").unwrap();
+        let err = run(&script, &host, rx, no_notifications, &status).await.unwrap_err();
+        assert_eq!(err, "line 5: Not a serial connection");
+        assert_eq!(status.lock().unwrap().last_match, 0);
+    }
+}
@@ -0,0 +1,815 @@
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+
+/// Default per-tab scrollback size when the client doesn't ask for one.
+pub const DEFAULT_SCROLLBACK_SIZE: usize = 128 * 1024; // 128KB
+
+/// Upper bound for a requested scrollback size.
+pub const MAX_SCROLLBACK_SIZE: usize = 256 * 1024 * 1024; // 256MB
+
+/// Size of each frame used when replaying scrollback to a new client.
+pub const REPLAY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Size of each on-disk segment file when spilling is enabled.
+const SEGMENT_SIZE: u64 = 16 * 1024 * 1024; // 16MB
+
+/// Largest byte range returned by a single `read_range` call.
+pub const MAX_RANGE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Largest number of lines returned by a single `read_lines` call.
+pub const MAX_LINE_COUNT: usize = 10_000;
+
+/// Most bytes a single `read_lines` call scans, so a line range made of a
+/// few huge lines still returns in bounded time.
+const MAX_LINE_SCAN_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Longest line `scan_lines` hands out; the rest of a longer one (binary
+/// dumps, progress bars redrawn with CR) is cut off.
+pub const MAX_SCAN_LINE_LENGTH: usize = 64 * 1024;
+
+/// A line-start offset is recorded every this many lines.
+const LINE_MARK_INTERVAL: u64 = 1024;
+
+/// Fixed-capacity byte ring. The backing buffer grows lazily up to `cap`
+/// and then wraps, so appending never shifts existing data.
+struct Ring {
+    buf: Vec<u8>,
+    cap: usize,
+    /// Index of the oldest byte once the buffer has wrapped.
+    head: usize,
+}
+
+impl Ring {
+    fn new(cap: usize) -> Self {
+        Ring {
+            buf: Vec::new(),
+            cap,
+            head: 0,
+        }
+    }
+
+    /// Append `data`, handing every byte that falls out of the ring to
+    /// `evict` (oldest first).
+    fn push(&mut self, data: &[u8], evict: &mut dyn FnMut(&[u8])) {
+        if self.cap == 0 {
+            evict(data);
+            return;
+        }
+
+        if data.len() >= self.cap {
+            let (a, b) = self.as_slices();
+            evict(a);
+            evict(b);
+            let split = data.len() - self.cap;
+            evict(&data[..split]);
+            self.buf = data[split..].to_vec();
+            self.head = 0;
+            return;
+        }
+
+        let mut rest = data;
+        if self.buf.len() < self.cap {
+            let n = rest.len().min(self.cap - self.buf.len());
+            self.buf.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+        }
+
+        while !rest.is_empty() {
+            let n = rest.len().min(self.cap - self.head);
+            evict(&self.buf[self.head..self.head + n]);
+            self.buf[self.head..self.head + n].copy_from_slice(&rest[..n]);
+            self.head = (self.head + n) % self.cap;
+            rest = &rest[n..];
+        }
+    }
+
+    fn as_slices(&self) -> (&[u8], &[u8]) {
+        let (tail, front) = self.buf.split_at(self.head);
+        (front, tail)
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        let (a, b) = self.as_slices();
+        let mut out = Vec::with_capacity(a.len() + b.len());
+        out.extend_from_slice(a);
+        out.extend_from_slice(b);
+        out
+    }
+
+    /// Copy `len` bytes starting `offset` bytes after the oldest byte.
+    fn copy_range(&self, offset: usize, len: usize, out: &mut Vec<u8>) {
+        let (a, b) = self.as_slices();
+        if offset < a.len() {
+            let n = len.min(a.len() - offset);
+            out.extend_from_slice(&a[offset..offset + n]);
+            out.extend_from_slice(&b[..len - n]);
+        } else {
+            let start = offset - a.len();
+            out.extend_from_slice(&b[start..start + len]);
+        }
+    }
+
+    fn set_capacity(&mut self, cap: usize, evict: &mut dyn FnMut(&[u8])) {
+        let data = self.to_vec();
+        let split = data.len().saturating_sub(cap);
+        evict(&data[..split]);
+        self.buf = data[split..].to_vec();
+        self.cap = cap;
+        self.head = 0;
+    }
+}
+
+/// Append-only store of evicted scrollback, split into fixed-size segment
+/// files under a per-tab directory. The directory is removed on drop.
+struct SegmentStore {
+    dir: PathBuf,
+    len: u64,
+    current: Option<(u64, BufWriter<File>)>,
+}
+
+impl SegmentStore {
+    fn create(dir: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(SegmentStore {
+            dir,
+            len: 0,
+            current: None,
+        })
+    }
+
+    fn append(&mut self, mut data: &[u8]) -> std::io::Result<()> {
+        while !data.is_empty() {
+            let index = self.len / SEGMENT_SIZE;
+            if self.current.as_ref().map(|(i, _)| *i) != Some(index) {
+                if let Some((_, mut w)) = self.current.take() {
+                    w.flush()?;
+                }
+                let file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(segment_path(&self.dir, index))?;
+                self.current = Some((index, BufWriter::new(file)));
+            }
+            let room = (SEGMENT_SIZE - self.len % SEGMENT_SIZE) as usize;
+            let n = data.len().min(room);
+            if let Some((_, ref mut w)) = self.current {
+                w.write_all(&data[..n])?;
+            }
+            self.len += n as u64;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.current {
+            Some((_, ref mut w)) => w.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn segment_path(dir: &Path, index: u64) -> PathBuf {
+    dir.join(format!("seg-{:06}.bin", index))
+}
+
+/// Read `[start, end)` from the segment files in `dir` into `out`.
+fn read_segments(dir: &Path, start: u64, end: u64, out: &mut Vec<u8>) -> std::io::Result<()> {
+    let mut pos = start;
+    while pos < end {
+        let index = pos / SEGMENT_SIZE;
+        let seg_offset = pos % SEGMENT_SIZE;
+        let n = (end - pos).min(SEGMENT_SIZE - seg_offset);
+        let mut file = File::open(segment_path(dir, index))?;
+        file.seek(SeekFrom::Start(seg_offset))?;
+        let old_len = out.len();
+        out.resize(old_len + n as usize, 0);
+        file.read_exact(&mut out[old_len..])?;
+        pos += n;
+    }
+    Ok(())
+}
+
+impl Drop for SegmentStore {
+    fn drop(&mut self) {
+        self.current = None;
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            tracing::warn!("Failed to remove scrollback spill dir {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+enum SpillOp {
+    Append(Vec<u8>),
+    /// Answered once everything appended before is on disk
+    Flush(mpsc::Sender<std::io::Result<()>>),
+}
+
+/// Hands evicted bytes to a thread that owns the `SegmentStore`, so the
+/// connection reader never waits on the disk. Dropping it lets the thread
+/// finish its writes and remove the directory.
+struct Spill {
+    tx: mpsc::Sender<SpillOp>,
+    dir: PathBuf,
+    /// Bytes handed to the writer
+    len: u64,
+    /// Set by the writer when a write fails
+    failed: Arc<AtomicBool>,
+}
+
+impl Spill {
+    fn create(dir: PathBuf) -> std::io::Result<Self> {
+        let mut store = SegmentStore::create(dir.clone())?;
+        let (tx, rx) = mpsc::channel();
+        let failed = Arc::new(AtomicBool::new(false));
+        let writer_failed = failed.clone();
+        std::thread::Builder::new()
+            .name("scrollback-spill".to_string())
+            .spawn(move || {
+                for op in rx {
+                    match op {
+                        SpillOp::Append(data) => {
+                            if let Err(e) = store.append(&data) {
+                                tracing::error!("Scrollback spill write failed, disabling spill: {}", e);
+                                writer_failed.store(true, Ordering::Relaxed);
+                                return;
+                            }
+                        }
+                        SpillOp::Flush(reply) => {
+                            let _ = reply.send(store.flush());
+                        }
+                    }
+                }
+            })?;
+        Ok(Spill {
+            tx,
+            dir,
+            len: 0,
+            failed,
+        })
+    }
+
+    fn append(&mut self, data: &[u8]) {
+        self.len += data.len() as u64;
+        let _ = self.tx.send(SpillOp::Append(data.to_vec()));
+    }
+}
+
+/// The part of a range that has to come from disk, read outside the lock.
+struct DiskRead {
+    tx: mpsc::Sender<SpillOp>,
+    dir: PathBuf,
+    start: u64,
+    end: u64,
+}
+
+/// A range copied out under the lock, apart from what is on disk.
+struct RangeRead {
+    disk: Option<DiskRead>,
+    memory: Vec<u8>,
+}
+
+impl RangeRead {
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        if let Some(disk) = self.disk {
+            let stopped = || std::io::Error::other("Scrollback spill writer stopped");
+            let (reply_tx, reply_rx) = mpsc::channel();
+            disk.tx.send(SpillOp::Flush(reply_tx)).map_err(|_| stopped())?;
+            reply_rx.recv().map_err(|_| stopped())??;
+            read_segments(&disk.dir, disk.start, disk.end, &mut out)?;
+        }
+        out.extend(self.memory);
+        Ok(out)
+    }
+}
+
+struct Inner {
+    ring: Ring,
+    spill: Option<Spill>,
+    /// Total number of bytes ever pushed.
+    total: u64,
+    /// Bytes that have left the ring (to disk or dropped).
+    evicted: u64,
+    /// Newlines contained in the evicted bytes.
+    evicted_lines: u64,
+    /// Total number of newlines ever pushed.
+    lines: u64,
+    /// `line_marks[k]` is the byte offset where line `(k + 1) * LINE_MARK_INTERVAL` starts.
+    line_marks: Vec<u64>,
+}
+
+impl Inner {
+    /// Offset of the oldest byte that can still be read.
+    fn first_offset(&self) -> u64 {
+        if self.spill.is_some() {
+            0
+        } else {
+            self.evicted
+        }
+    }
+
+    fn evict_fn(&mut self) -> (&mut Ring, impl FnMut(&[u8]) + '_) {
+        let Inner {
+            ring,
+            spill,
+            evicted,
+            evicted_lines,
+            ..
+        } = self;
+        let evict = move |bytes: &[u8]| {
+            if bytes.is_empty() {
+                return;
+            }
+            *evicted += bytes.len() as u64;
+            *evicted_lines += bytes.iter().filter(|&&b| b == b'\n').count() as u64;
+            if spill.as_ref().is_some_and(|s| s.failed.load(Ordering::Relaxed)) {
+                *spill = None;
+            }
+            if let Some(spill) = spill {
+                spill.append(bytes);
+            }
+        };
+        (ring, evict)
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        for (i, _) in data.iter().enumerate().filter(|(_, &b)| b == b'\n') {
+            self.lines += 1;
+            if self.lines.is_multiple_of(LINE_MARK_INTERVAL) {
+                self.line_marks.push(self.total + i as u64 + 1);
+            }
+        }
+        self.total += data.len() as u64;
+        let (ring, mut evict) = self.evict_fn();
+        ring.push(data, &mut evict);
+    }
+
+    /// Copy out `[start, end)`, leaving the part on disk for
+    /// `RangeRead::finish` to read once the lock is released.
+    fn read_range(&self, start: u64, end: u64) -> RangeRead {
+        let start = start.max(self.first_offset());
+        let end = end.min(self.total).min(start.saturating_add(MAX_RANGE_SIZE));
+        let mut read = RangeRead {
+            disk: None,
+            memory: Vec::new(),
+        };
+        if start >= end {
+            return read;
+        }
+        if start < self.evicted {
+            if let Some(ref spill) = self.spill {
+                read.disk = Some(DiskRead {
+                    tx: spill.tx.clone(),
+                    dir: spill.dir.clone(),
+                    start,
+                    end: end.min(self.evicted),
+                });
+            }
+        }
+        let mem_start = start.max(self.evicted);
+        if mem_start < end {
+            self.ring.copy_range(
+                (mem_start - self.evicted) as usize,
+                (end - mem_start) as usize,
+                &mut read.memory,
+            );
+        }
+        read
+    }
+
+    /// Byte offset and line number of a readable line start at or before `line`.
+    fn seek_line(&self, line: u64) -> (u64, u64) {
+        let mark = ((line / LINE_MARK_INTERVAL) as usize).min(self.line_marks.len());
+        let (mut offset, mut number) = if mark == 0 {
+            (0, 0)
+        } else {
+            (self.line_marks[mark - 1], mark as u64 * LINE_MARK_INTERVAL)
+        };
+        if offset < self.first_offset() {
+            // The mark has been dropped; resume from the oldest readable
+            // byte, which sits somewhere inside line `evicted_lines`.
+            offset = self.first_offset();
+            number = self.evicted_lines;
+        }
+        (offset, number)
+    }
+}
+
+/// Point-in-time counters describing a tab's scrollback.
+pub struct ScrollbackStats {
+    pub length: usize,
+    pub capacity: usize,
+    pub total_written: u64,
+    pub first_offset: u64,
+    pub total_lines: u64,
+    pub spill: bool,
+    pub disk_bytes: u64,
+}
+
+/// Per-tab scrollback shared between the connection reader and WebSocket
+/// clients. Appends are O(1) in the buffer size. With spilling enabled,
+/// bytes that fall out of memory are kept in an on-disk segment store.
+pub struct Scrollback {
+    inner: Mutex<Inner>,
+}
+
+impl Scrollback {
+    pub fn new(capacity: usize) -> Self {
+        Scrollback {
+            inner: Mutex::new(Inner {
+                ring: Ring::new(capacity.min(MAX_SCROLLBACK_SIZE)),
+                spill: None,
+                total: 0,
+                evicted: 0,
+                evicted_lines: 0,
+                lines: 0,
+                line_marks: Vec::new(),
+            }),
+        }
+    }
+
+    /// Like `new`, but evicted data is kept in segment files under `dir`,
+    /// written by a thread of its own.
+    pub fn with_spill(capacity: usize, dir: PathBuf) -> std::io::Result<Self> {
+        let sb = Self::new(capacity);
+        sb.inner.lock().unwrap().spill = Some(Spill::create(dir)?);
+        Ok(sb)
+    }
+
+    pub fn push(&self, data: &[u8]) {
+        self.inner.lock().unwrap().push(data);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.lock().unwrap().ring.cap
+    }
+
+    pub fn stats(&self) -> ScrollbackStats {
+        let inner = self.inner.lock().unwrap();
+        ScrollbackStats {
+            length: inner.ring.buf.len(),
+            capacity: inner.ring.cap,
+            total_written: inner.total,
+            first_offset: inner.first_offset(),
+            total_lines: inner.lines,
+            spill: inner.spill.is_some(),
+            disk_bytes: inner.spill.as_ref().map(|s| s.len).unwrap_or(0),
+        }
+    }
+
+    /// Resize the in-memory buffer, keeping the newest bytes.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        let (ring, mut evict) = inner.evict_fn();
+        ring.set_capacity(capacity.min(MAX_SCROLLBACK_SIZE), &mut evict);
+    }
+
+    /// The in-memory buffer as it is now, in frames of at most
+    /// `chunk_size` bytes, so a multi-MB replay doesn't go out as one giant
+    /// WebSocket message. Each frame is copied when it is taken; what left
+    /// memory meanwhile is skipped.
+    pub fn replay_chunks(&self, chunk_size: usize) -> ReplayChunks<'_> {
+        let inner = self.inner.lock().unwrap();
+        ReplayChunks {
+            sb: self,
+            offset: inner.evicted,
+            end: inner.total,
+            chunk_size: chunk_size.max(1),
+        }
+    }
+
+    /// Copy out the newest `max` bytes held in memory.
+    pub fn tail(&self, max: usize) -> Vec<u8> {
+        let inner = self.inner.lock().unwrap();
+        let len = inner.ring.buf.len();
+        let n = len.min(max);
+        let mut out = Vec::with_capacity(n);
+        inner.ring.copy_range(len - n, n, &mut out);
+        out
+    }
+
+    /// Read the absolute byte range `[start, end)`, clamped to what is still
+    /// available and to `MAX_RANGE_SIZE`.
+    pub fn read_range(&self, start: u64, end: u64) -> std::io::Result<Vec<u8>> {
+        let read = self.inner.lock().unwrap().read_range(start, end);
+        read.finish()
+    }
+
+    /// Call `f` with every readable byte, oldest first, in chunks of up to
+    /// `REPLAY_CHUNK_SIZE`, until it returns `false`. Like `scan_lines`, the
+    /// lock is only held while copying each chunk.
+    pub fn scan_chunks(&self, mut f: impl FnMut(&[u8]) -> bool) -> std::io::Result<()> {
+        let (mut offset, end) = {
+            let inner = self.inner.lock().unwrap();
+            (inner.first_offset(), inner.total)
+        };
+        while offset < end {
+            let chunk = {
+                let inner = self.inner.lock().unwrap();
+                offset = offset.max(inner.first_offset());
+                inner.read_range(offset, end.min(offset + REPLAY_CHUNK_SIZE as u64))
+            }
+            .finish()?;
+            if chunk.is_empty() || !f(&chunk) {
+                break;
+            }
+            offset += chunk.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Call `f` with each readable line (including its trailing newline, cut
+    /// off at `MAX_SCAN_LINE_LENGTH`) and its line number, oldest first,
+    /// until it returns `false`. The lock is
+    /// only held while copying each chunk, so the connection keeps writing
+    /// during long scans.
+    pub fn scan_lines(&self, mut f: impl FnMut(u64, &[u8]) -> bool) -> std::io::Result<()> {
+        let (mut offset, mut number, end) = {
+            let inner = self.inner.lock().unwrap();
+            let (offset, number) = inner.seek_line(0);
+            (offset, number, inner.total)
+        };
+
+        let mut line = Vec::new();
+        while offset < end {
+            let chunk = {
+                let inner = self.inner.lock().unwrap();
+                if offset < inner.first_offset() {
+                    // Data we hadn't reached yet was dropped meanwhile
+                    offset = inner.first_offset();
+                    number = inner.evicted_lines;
+                    line.clear();
+                }
+                inner.read_range(offset, end.min(offset + REPLAY_CHUNK_SIZE as u64))
+            }
+            .finish()?;
+            if chunk.is_empty() {
+                break;
+            }
+            offset += chunk.len() as u64;
+            for &b in &chunk {
+                if line.len() < MAX_SCAN_LINE_LENGTH {
+                    line.push(b);
+                }
+                if b == b'\n' {
+                    if !f(number, &line) {
+                        return Ok(());
+                    }
+                    line.clear();
+                    number += 1;
+                }
+            }
+        }
+        if !line.is_empty() {
+            f(number, &line);
+        }
+        Ok(())
+    }
+
+    /// Read up to `count` lines starting at line number `first`. Returns the
+    /// number of the first line actually returned (older lines may have been
+    /// dropped) and the lines, each keeping its trailing newline.
+    /// At most `MAX_LINE_SCAN_SIZE` bytes are scanned, and like
+    /// `scan_lines` the lock is only held while copying each chunk.
+    pub fn read_lines(&self, first: u64, count: usize) -> std::io::Result<(u64, Vec<String>)> {
+        let count = count.min(MAX_LINE_COUNT);
+        let (mut offset, mut number, end) = {
+            let inner = self.inner.lock().unwrap();
+            let (offset, number) = inner.seek_line(first);
+            (offset, number, inner.total)
+        };
+        let mut first = first.max(number);
+        let end = end.min(offset.saturating_add(MAX_LINE_SCAN_SIZE));
+
+        let mut lines = Vec::new();
+        let mut current: Vec<u8> = Vec::new();
+        'scan: while lines.len() < count && offset < end {
+            let chunk = {
+                let inner = self.inner.lock().unwrap();
+                if offset < inner.first_offset() {
+                    // What we had read so far was dropped meanwhile
+                    offset = inner.first_offset();
+                    number = inner.evicted_lines;
+                    first = first.max(number);
+                    lines.clear();
+                    current.clear();
+                }
+                inner.read_range(offset, end.min(offset + REPLAY_CHUNK_SIZE as u64))
+            }
+            .finish()?;
+            if chunk.is_empty() {
+                break;
+            }
+            offset += chunk.len() as u64;
+            for &b in &chunk {
+                if number >= first {
+                    current.push(b);
+                }
+                if b == b'\n' {
+                    if number >= first {
+                        lines.push(String::from_utf8_lossy(&current).into_owned());
+                        current.clear();
+                        if lines.len() == count {
+                            break 'scan;
+                        }
+                    }
+                    number += 1;
+                }
+            }
+        }
+        // Trailing partial line (no newline yet, or cut off by the limit)
+        if lines.len() < count && !current.is_empty() {
+            lines.push(String::from_utf8_lossy(&current).into_owned());
+        }
+        Ok((first, lines))
+    }
+
+    /// Stop spilling and delete the segment files, for a tab being closed.
+    /// Clients still attached keep what is in memory.
+    pub fn discard_spill(&self) {
+        // The writer removes the files once it sees the spill is gone
+        self.inner.lock().unwrap().spill = None;
+    }
+}
+
+/// Frames of a scrollback replay; see `Scrollback::replay_chunks`.
+pub struct ReplayChunks<'a> {
+    sb: &'a Scrollback,
+    offset: u64,
+    end: u64,
+    chunk_size: usize,
+}
+
+impl Iterator for ReplayChunks<'_> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let inner = self.sb.inner.lock().unwrap();
+        self.offset = self.offset.max(inner.evicted);
+        if self.offset >= self.end {
+            return None;
+        }
+        let n = (self.end - self.offset).min(self.chunk_size as u64) as usize;
+        let mut chunk = Vec::with_capacity(n);
+        inner.ring.copy_range((self.offset - inner.evicted) as usize, n, &mut chunk);
+        self.offset += n as u64;
+        Some(chunk)
+    }
+}
+
+/// Per-tab directory for spilled scrollback segments.
+pub fn spill_dir(tab_id: &str) -> PathBuf {
+    let safe: String = tab_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let stamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    dirs::data_local_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("serial-rs")
+        .join("scrollback")
+        .join(format!("{}-{}", safe, stamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(sb: &Scrollback) -> Vec<u8> {
+        sb.inner.lock().unwrap().ring.to_vec()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("serial-rs-test-{}-{}", name, std::process::id()))
+    }
+
+    /// The spill writer removes its directory after it stops.
+    fn wait_removed(dir: &Path) -> bool {
+        for _ in 0..100 {
+            if !dir.exists() {
+                return true;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        false
+    }
+
+    #[test]
+    fn test_push_and_wrap() {
+        let sb = Scrollback::new(8);
+        sb.push(b"hello");
+        assert_eq!(contents(&sb), b"hello");
+        sb.push(b"world!");
+        assert_eq!(contents(&sb), b"loworld!");
+        assert_eq!(sb.stats().length, 8);
+        assert_eq!(sb.stats().total_written, 11);
+    }
+
+    #[test]
+    fn test_oversized_push() {
+        let sb = Scrollback::new(4);
+        sb.push(b"ab");
+        sb.push(b"0123456789");
+        assert_eq!(contents(&sb), b"6789");
+    }
+
+    #[test]
+    fn test_set_capacity_keeps_newest() {
+        let sb = Scrollback::new(8);
+        sb.push(b"abcdefghij");
+        sb.set_capacity(4);
+        assert_eq!(contents(&sb), b"ghij");
+        sb.push(b"kl");
+        assert_eq!(contents(&sb), b"ijkl");
+        sb.set_capacity(16);
+        sb.push(b"mn");
+        assert_eq!(contents(&sb), b"ijklmn");
+    }
+
+    #[test]
+    fn test_replay_chunks() {
+        let sb = Scrollback::new(10);
+        sb.push(b"0123456789abc");
+        let chunks: Vec<_> = sb.replay_chunks(4).collect();
+        assert_eq!(chunks, vec![b"3456".to_vec(), b"789a".to_vec(), b"bc".to_vec()]);
+        assert_eq!(Scrollback::new(10).replay_chunks(4).next(), None);
+
+        // Output arriving during a replay neither extends it nor gets
+        // replayed after it was evicted
+        let mut replay = sb.replay_chunks(4);
+        assert_eq!(replay.next().unwrap(), b"3456");
+        sb.push(b"defgh");
+        assert_eq!(replay.next().unwrap(), b"89ab");
+        assert_eq!(replay.next().unwrap(), b"c");
+        assert_eq!(replay.next(), None);
+    }
+
+    #[test]
+    fn test_read_range_without_spill() {
+        let sb = Scrollback::new(4);
+        sb.push(b"abcdefgh");
+        assert_eq!(sb.read_range(0, 100).unwrap(), b"efgh");
+        assert_eq!(sb.read_range(5, 7).unwrap(), b"fg");
+        assert_eq!(sb.stats().first_offset, 4);
+    }
+
+    #[test]
+    fn test_spill_keeps_everything() {
+        let dir = temp_dir("spill");
+        let sb = Scrollback::with_spill(4, dir.clone()).unwrap();
+        for chunk in [&b"line1\n"[..], b"line2\n", b"line3\npart"] {
+            sb.push(chunk);
+        }
+        assert_eq!(sb.read_range(0, 100).unwrap(), b"line1\nline2\nline3\npart");
+        assert_eq!(sb.read_range(3, 9).unwrap(), b"e1\nlin");
+        let (first, lines) = sb.read_lines(1, 10).unwrap();
+        assert_eq!(first, 1);
+        assert_eq!(lines, vec!["line2\n", "line3\n", "part"]);
+        drop(sb);
+        assert!(wait_removed(&dir));
+    }
+
+    #[test]
+    fn test_discard_spill() {
+        let dir = temp_dir("discard");
+        let sb = Scrollback::with_spill(4, dir.clone()).unwrap();
+        sb.push(b"line1\nline2\n");
+        assert!(dir.exists());
+        sb.discard_spill();
+        assert!(wait_removed(&dir));
+        assert!(!sb.stats().spill);
+        sb.push(b"more");
+        assert_eq!(sb.read_range(0, 100).unwrap(), b"more");
+    }
+
+    #[test]
+    fn test_read_lines_after_drop() {
+        let sb = Scrollback::new(8);
+        sb.push(b"aa\nbb\ncc\ndd\n");
+        // Only "b\ncc\ndd\n" is left in memory, which starts inside line 1
+        let (first, lines) = sb.read_lines(0, 10).unwrap();
+        assert_eq!(first, 1);
+        assert_eq!(lines, vec!["b\n", "cc\n", "dd\n"]);
+    }
+
+    #[test]
+    fn test_scan_lines_cuts_long_lines() {
+        let sb = Scrollback::new(4 * MAX_SCAN_LINE_LENGTH);
+        sb.push(&vec![b'x'; 2 * MAX_SCAN_LINE_LENGTH]);
+        sb.push(b"\nshort\n");
+        let mut lines = Vec::new();
+        sb.scan_lines(|number, line| {
+            lines.push((number, line.len()));
+            true
+        })
+        .unwrap();
+        assert_eq!(lines, vec![(0, MAX_SCAN_LINE_LENGTH), (1, 6)]);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::ansi::AnsiStripper;
+use crate::scrollback::Scrollback;
+
+/// Upper bound for the number of matches returned by one search.
+pub const MAX_MATCHES: usize = 10_000;
+
+/// Upper bound for context lines on each side of a match.
+pub const MAX_CONTEXT: usize = 20;
+
+#[derive(Serialize, Debug)]
+pub struct SearchMatch {
+    /// Zero-based line number, same numbering as `/api/scrollback/lines`
+    pub line: u64,
+    pub text: String,
+    /// Character (not byte) ranges of each match within `text`
+    pub spans: Vec<(usize, usize)>,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchResult {
+    pub matches: Vec<SearchMatch>,
+    /// More matches exist beyond `limit`
+    pub truncated: bool,
+    pub searched_lines: u64,
+}
+
+fn char_span(text: &str, start: usize, end: usize) -> (usize, usize) {
+    let s = text[..start].chars().count();
+    (s, s + text[start..end].chars().count())
+}
+
+/// Run `re` over every readable scrollback line with escape sequences
+/// stripped, collecting up to `limit` matches with `context` lines around each.
+pub fn search(
+    sb: &Scrollback,
+    re: &Regex,
+    context: usize,
+    limit: usize,
+) -> std::io::Result<SearchResult> {
+    let context = context.min(MAX_CONTEXT);
+    let limit = limit.min(MAX_MATCHES);
+
+    let mut stripper = AnsiStripper::new();
+    let mut before: VecDeque<String> = VecDeque::with_capacity(context + 1);
+    let mut pending: Vec<usize> = Vec::new();
+    let mut matches: Vec<SearchMatch> = Vec::new();
+    let mut truncated = false;
+    let mut searched_lines = 0u64;
+    let mut stripped = Vec::new();
+
+    sb.scan_lines(|number, raw| {
+        stripped.clear();
+        stripper.feed(raw, &mut stripped);
+        if stripped.last() == Some(&b'\n') {
+            stripped.pop();
+        }
+        let text = String::from_utf8_lossy(&stripped).into_owned();
+        searched_lines += 1;
+
+        pending.retain(|&i| {
+            matches[i].after.push(text.clone());
+            matches[i].after.len() < context
+        });
+
+        if re.is_match(&text) {
+            if matches.len() >= limit {
+                truncated = true;
+                return false;
+            }
+            let spans = re
+                .find_iter(&text)
+                .map(|m| char_span(&text, m.start(), m.end()))
+                .collect();
+            matches.push(SearchMatch {
+                line: number,
+                text: text.clone(),
+                spans,
+                before: before.iter().cloned().collect(),
+                after: Vec::new(),
+            });
+            if context > 0 {
+                pending.push(matches.len() - 1);
+            }
+        }
+
+        if context > 0 {
+            before.push_back(text);
+            if before.len() > context {
+                before.pop_front();
+            }
+        }
+        true
+    })?;
+
+    Ok(SearchResult {
+        matches,
+        truncated,
+        searched_lines,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_with_context() {
+        let sb = Scrollback::new(4096);
+        sb.push(b"boot\r\n\x1b[31mkernel panic\x1b[0m: oops\r\nreboot\r\nPanic again\r\n");
+        let re = regex::RegexBuilder::new("panic")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let result = search(&sb, &re, 1, 100).unwrap();
+        assert_eq!(result.searched_lines, 4);
+        assert!(!result.truncated);
+        assert_eq!(result.matches.len(), 2);
+
+        let first = &result.matches[0];
+        assert_eq!(first.line, 1);
+        assert_eq!(first.text, "kernel panic: oops");
+        assert_eq!(first.spans, vec![(7, 12)]);
+        assert_eq!(first.before, vec!["boot"]);
+        assert_eq!(first.after, vec!["reboot"]);
+        assert!(result.matches[1].after.is_empty());
+    }
+
+    #[test]
+    fn test_search_limit() {
+        let sb = Scrollback::new(4096);
+        sb.push(b"a\na\na\n");
+        let re = Regex::new("a").unwrap();
+        let result = search(&sb, &re, 0, 2).unwrap();
+        assert_eq!(result.matches.len(), 2);
+        assert!(result.truncated);
+    }
+}
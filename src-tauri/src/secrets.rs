@@ -0,0 +1,45 @@
+use keyring::{Entry, Error};
+
+/// Keychain service name that entries are stored under.
+const SERVICE: &str = "com.serialrs.terminal";
+
+fn entry(account: &str) -> Result<Entry, String> {
+    Entry::new(SERVICE, account).map_err(|e| format!("Keychain unavailable: {}", e))
+}
+
+/// Keychain account holding a session's auto-login password.
+pub fn auto_login_account(session_id: &str) -> String {
+    format!("auto-login/{}", session_id)
+}
+
+/// Keychain account holding a session's saved SSH password.
+pub fn session_password_account(session_id: &str) -> String {
+    format!("session-password/{}", session_id)
+}
+
+/// Keychain account holding the quick-connect SSH password.
+pub const SSH_INFO_ACCOUNT: &str = "ssh-info/password";
+
+/// Read a secret from the system keychain. Blocks; call from a blocking
+/// task.
+pub fn get(account: &str) -> Result<Option<String>, String> {
+    match entry(account)?.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!("Failed to read from keychain: {}", e)),
+    }
+}
+
+pub fn set(account: &str, secret: &str) -> Result<(), String> {
+    entry(account)?
+        .set_password(secret)
+        .map_err(|e| format!("Failed to save to keychain: {}", e))
+}
+
+/// Remove a secret. Removing one that doesn't exist is not an error.
+pub fn delete(account: &str) -> Result<(), String> {
+    match entry(account)?.delete_credential() {
+        Ok(()) | Err(Error::NoEntry) => Ok(()),
+        Err(e) => Err(format!("Failed to remove from keychain: {}", e)),
+    }
+}
@@ -0,0 +1,144 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_serial::{Parity, SerialPort, SerialStream};
+
+/// A serial port shared between the reader task, the writer task and
+/// anything that needs the modem control lines. Unlike `tokio::io::split`,
+/// every clone can still reach the port itself.
+///
+/// The lock is only held for the duration of a single poll or control
+/// call, never across an await.
+#[derive(Clone)]
+pub struct SharedSerial(Arc<Mutex<SerialStream>>);
+
+impl SharedSerial {
+    pub fn new(port: SerialStream) -> Self {
+        SharedSerial(Arc::new(Mutex::new(port)))
+    }
+
+    pub fn set_dtr(&self, on: bool) -> Result<(), String> {
+        self.0
+            .lock()
+            .unwrap()
+            .write_data_terminal_ready(on)
+            .map_err(|e| format!("Failed to set DTR: {}", e))
+    }
+
+    pub fn set_rts(&self, on: bool) -> Result<(), String> {
+        self.0
+            .lock()
+            .unwrap()
+            .write_request_to_send(on)
+            .map_err(|e| format!("Failed to set RTS: {}", e))
+    }
+
+    pub fn baud_rate(&self) -> Result<u32, String> {
+        self.0
+            .lock()
+            .unwrap()
+            .baud_rate()
+            .map_err(|e| format!("Failed to read the baud rate: {}", e))
+    }
+
+    pub fn set_baud_rate(&self, baud: u32) -> Result<(), String> {
+        self.0
+            .lock()
+            .unwrap()
+            .set_baud_rate(baud)
+            .map_err(|e| format!("Failed to set the baud rate: {}", e))
+    }
+
+    pub fn parity(&self) -> Result<Parity, String> {
+        self.0
+            .lock()
+            .unwrap()
+            .parity()
+            .map_err(|e| format!("Failed to read the parity: {}", e))
+    }
+
+    pub fn set_parity(&self, parity: Parity) -> Result<(), String> {
+        self.0
+            .lock()
+            .unwrap()
+            .set_parity(parity)
+            .map_err(|e| format!("Failed to set the parity: {}", e))
+    }
+
+    /// Hold the line in the break condition for `duration`.
+    pub async fn send_break(&self, duration: Duration) -> Result<(), String> {
+        self.0
+            .lock()
+            .unwrap()
+            .set_break()
+            .map_err(|e| format!("Failed to send break: {}", e))?;
+        tokio::time::sleep(duration).await;
+        self.0
+            .lock()
+            .unwrap()
+            .clear_break()
+            .map_err(|e| format!("Failed to clear break: {}", e))
+    }
+}
+
+impl crate::flasher::Control for SharedSerial {
+    fn set_dtr(&self, on: bool) -> Result<(), String> {
+        SharedSerial::set_dtr(self, on)
+    }
+
+    fn set_rts(&self, on: bool) -> Result<(), String> {
+        SharedSerial::set_rts(self, on)
+    }
+
+    fn baud_rate(&self) -> Result<u32, String> {
+        SharedSerial::baud_rate(self)
+    }
+
+    fn set_baud_rate(&self, baud: u32) -> Result<(), String> {
+        SharedSerial::set_baud_rate(self, baud)
+    }
+
+    fn parity(&self) -> Result<Parity, String> {
+        SharedSerial::parity(self)
+    }
+
+    fn set_parity(&self, parity: Parity) -> Result<(), String> {
+        SharedSerial::set_parity(self, parity)
+    }
+}
+
+impl AsyncRead for SharedSerial {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut port = self.0.lock().unwrap();
+        Pin::new(&mut *port).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SharedSerial {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut port = self.0.lock().unwrap();
+        Pin::new(&mut *port).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut port = self.0.lock().unwrap();
+        Pin::new(&mut *port).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut port = self.0.lock().unwrap();
+        Pin::new(&mut *port).poll_shutdown(cx)
+    }
+}
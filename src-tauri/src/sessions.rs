@@ -0,0 +1,812 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::autologin::AutoLogin;
+use crate::secrets;
+use crate::triggers::TriggerRule;
+use crate::zmodem::DownloadSettings;
+
+const STORE_FILE: &str = "sessions.json";
+const STORE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionKind {
+    Serial,
+    Ssh,
+}
+
+/// A saved connection profile. Field names match the frontend's session
+/// objects so they round-trip unchanged.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: SessionKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+
+    // Serial
+    /// Serial port name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baud_rate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_bits: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_bits: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parity: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow_control: Option<String>,
+
+    // SSH
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Only in requests and responses: the store keeps it in the keychain
+    /// and an empty one removes it from there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Whether the keychain holds a password for this session
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub password_saved: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<String>,
+
+    /// Unix time in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+
+    /// Trigger rules applied to tabs connected from this session
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub triggers: Vec<TriggerRule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_login: Option<AutoLogin>,
+    /// Where and how ZMODEM downloads are saved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zmodem: Option<DownloadSettings>,
+
+    /// Everything else the frontend keeps per session (font and theme
+    /// overrides), stored as-is
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Session {
+    /// An empty profile with only the name and type set.
+    pub fn new(kind: SessionKind, name: &str) -> Self {
+        Session {
+            id: String::new(),
+            name: name.to_string(),
+            kind,
+            folder: None,
+            port: None,
+            baud_rate: None,
+            data_bits: None,
+            stop_bits: None,
+            parity: None,
+            flow_control: None,
+            host: None,
+            ssh_port: None,
+            username: None,
+            password: None,
+            password_saved: false,
+            key_file: None,
+            created_at: None,
+            updated_at: None,
+            triggers: Vec::new(),
+            auto_login: None,
+            zmodem: None,
+            extra: Map::new(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), StoreError> {
+        if let Some(auto_login) = &self.auto_login {
+            auto_login.validate().map_err(StoreError::Invalid)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct StoreData {
+    version: u32,
+    #[serde(default)]
+    sessions: Vec<Session>,
+    /// Folder names, including folders with no sessions in them
+    #[serde(default)]
+    folders: Vec<String>,
+    /// Frontend default settings (font, theme, behavior toggles)
+    #[serde(default)]
+    defaults: Map<String, Value>,
+    /// Last quick-connect SSH details
+    #[serde(default)]
+    ssh_info: Map<String, Value>,
+}
+
+impl Default for StoreData {
+    fn default() -> Self {
+        StoreData {
+            version: STORE_VERSION,
+            sessions: Vec::new(),
+            folders: Vec::new(),
+            defaults: Map::new(),
+            ssh_info: Map::new(),
+        }
+    }
+}
+
+/// Data exported from the frontend's `localStorage` by older versions.
+#[derive(Deserialize, Default, Debug)]
+pub struct LocalStorageImport {
+    #[serde(default)]
+    pub sessions: Vec<Value>,
+    /// `serial-rs-defaults`; its `folders` list becomes store folders
+    #[serde(default)]
+    pub defaults: Option<Map<String, Value>>,
+    #[serde(default)]
+    pub ssh_info: Option<Map<String, Value>>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// Sessions whose id already exists in the store
+    pub skipped: usize,
+    /// Entries that could not be read, with the reason
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    NotFound(String),
+    Invalid(String),
+    Io(std::io::Error),
+    Keychain(String),
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::NotFound(what) => write!(f, "{} not found", what),
+            StoreError::Invalid(msg) => f.write_str(msg),
+            StoreError::Io(e) => write!(f, "Failed to save sessions: {}", e),
+            StoreError::Keychain(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+pub fn gen_id() -> String {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    format!(
+        "{:x}{:04x}",
+        now_millis(),
+        COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff
+    )
+}
+
+fn clean_folder(folder: Option<String>) -> Option<String> {
+    folder
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty())
+}
+
+/// Where saved passwords go instead of the JSON file: the `secrets`
+/// functions, or a stand-in in tests.
+#[derive(Clone, Copy)]
+pub struct Keychain {
+    pub get: fn(&str) -> Result<Option<String>, String>,
+    pub set: fn(&str, &str) -> Result<(), String>,
+    pub delete: fn(&str) -> Result<(), String>,
+}
+
+/// Key of the quick-connect password in the SSH info
+const SSH_INFO_PASSWORD: &str = "password";
+/// Set in the SSH info while the keychain holds its password
+const SSH_INFO_PASSWORD_SAVED: &str = "passwordSaved";
+
+/// Session profiles, folders and frontend defaults, kept in a JSON file in
+/// the app config directory. Every change is written through immediately,
+/// and passwords go to the keychain. Calls that change the store or fill
+/// in passwords block; run them on a blocking thread.
+pub struct SessionStore {
+    path: PathBuf,
+    data: Mutex<StoreData>,
+    keychain: Keychain,
+}
+
+impl SessionStore {
+    /// Open the store in `dir`. A missing file gives an empty store; an
+    /// unreadable one is moved aside so it isn't overwritten. Passwords
+    /// saved in the file by older versions are moved to the keychain.
+    pub fn open(dir: &Path, keychain: Keychain) -> Self {
+        let path = dir.join(STORE_FILE);
+        let data = match std::fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice::<StoreData>(&bytes) {
+                Ok(data) => data,
+                Err(e) => {
+                    let backup = path.with_extension("json.bad");
+                    tracing::warn!(
+                        "Unreadable {} ({}), moving it to {}",
+                        path.display(),
+                        e,
+                        backup.display()
+                    );
+                    let _ = std::fs::rename(&path, &backup);
+                    StoreData::default()
+                }
+            },
+            Err(_) => StoreData::default(),
+        };
+        let store = SessionStore {
+            path,
+            data: Mutex::new(data),
+            keychain,
+        };
+        if let Err(e) = store.move_plaintext_passwords() {
+            tracing::warn!("Passwords in {} left in place: {}", store.path.display(), e);
+        }
+        store
+    }
+
+    fn move_plaintext_passwords(&self) -> Result<(), StoreError> {
+        let data = self.data.lock().unwrap();
+        let in_file = data.sessions.iter().any(|s| s.password.is_some())
+            || data.ssh_info.contains_key(SSH_INFO_PASSWORD);
+        drop(data);
+        if !in_file {
+            return Ok(());
+        }
+        self.modify(|data| {
+            if let Some(password) = data.ssh_info.remove(SSH_INFO_PASSWORD) {
+                self.stash_ssh_password(&mut data.ssh_info, password)?;
+            }
+            Ok(())
+        })?;
+        tracing::info!("Moved saved passwords from {} to the keychain", self.path.display());
+        Ok(())
+    }
+
+    /// Move the passwords of `sessions` to the keychain, so only
+    /// `password_saved` is left of them.
+    fn stash_passwords(&self, sessions: &mut [Session]) -> Result<(), StoreError> {
+        for session in sessions.iter_mut() {
+            let Some(password) = session.password.take() else {
+                continue;
+            };
+            let account = secrets::session_password_account(&session.id);
+            if password.is_empty() {
+                (self.keychain.delete)(&account).map_err(StoreError::Keychain)?;
+                session.password_saved = false;
+            } else {
+                (self.keychain.set)(&account, &password).map_err(StoreError::Keychain)?;
+                session.password_saved = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn stash_ssh_password(
+        &self,
+        info: &mut Map<String, Value>,
+        password: Value,
+    ) -> Result<(), StoreError> {
+        match password.as_str().filter(|p| !p.is_empty()) {
+            Some(password) => {
+                (self.keychain.set)(secrets::SSH_INFO_ACCOUNT, password)
+                    .map_err(StoreError::Keychain)?;
+                info.insert(SSH_INFO_PASSWORD_SAVED.to_string(), Value::Bool(true));
+            }
+            None => {
+                (self.keychain.delete)(secrets::SSH_INFO_ACCOUNT).map_err(StoreError::Keychain)?;
+                info.remove(SSH_INFO_PASSWORD_SAVED);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill in the saved passwords of `sessions` from the keychain. One
+    /// that can't be read is left out.
+    pub fn fill_passwords(&self, sessions: &mut [Session]) {
+        for session in sessions.iter_mut().filter(|s| s.password_saved) {
+            match (self.keychain.get)(&secrets::session_password_account(&session.id)) {
+                Ok(password) => session.password = password,
+                Err(e) => tracing::warn!("{}", e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn save(&self, data: &StoreData) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let bytes = serde_json::to_vec_pretty(data).map_err(std::io::Error::other)?;
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, bytes)?;
+        // Passwords the keychain didn't take stay in the file
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600))?;
+        }
+        std::fs::rename(&tmp, &self.path)
+    }
+
+    /// Apply `f` to the data and write it out. Nothing changes if `f` fails
+    /// or the file can't be written.
+    fn modify<T>(
+        &self,
+        f: impl FnOnce(&mut StoreData) -> Result<T, StoreError>,
+    ) -> Result<T, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        let mut updated = data.clone();
+        let result = f(&mut updated)?;
+        // Whatever f added with a password, e.g. an import
+        self.stash_passwords(&mut updated.sessions)?;
+        self.save(&updated)?;
+        *data = updated;
+        Ok(result)
+    }
+
+    /// All sessions, and all folders (explicit ones plus any a session uses).
+    pub fn list(&self) -> (Vec<Session>, Vec<String>) {
+        let data = self.data.lock().unwrap();
+        let mut folders = data.folders.clone();
+        for folder in data.sessions.iter().filter_map(|s| s.folder.as_ref()) {
+            if !folders.contains(folder) {
+                folders.push(folder.clone());
+            }
+        }
+        folders.sort();
+        (data.sessions.clone(), folders)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Session> {
+        let data = self.data.lock().unwrap();
+        data.sessions.iter().find(|s| s.id == id).cloned()
+    }
+
+    /// Add a session, assigning an id if it has none.
+    pub fn create(&self, mut session: Session) -> Result<Session, StoreError> {
+        session.validate()?;
+        if session.id.is_empty() {
+            session.id = gen_id();
+        }
+        if session.name.trim().is_empty() {
+            session.name = "Untitled".to_string();
+        }
+        session.folder = clean_folder(session.folder);
+        let now = now_millis();
+        session.created_at.get_or_insert(now);
+        session.updated_at = Some(now);
+        self.modify(|data| {
+            if data.sessions.iter().any(|s| s.id == session.id) {
+                return Err(StoreError::Invalid(format!(
+                    "Session {} already exists",
+                    session.id
+                )));
+            }
+            self.stash_passwords(std::slice::from_mut(&mut session))?;
+            data.sessions.push(session.clone());
+            Ok(session)
+        })
+    }
+
+    /// Replace a session, keeping its id and creation time.
+    pub fn update(&self, id: &str, mut session: Session) -> Result<Session, StoreError> {
+        session.validate()?;
+        session.id = id.to_string();
+        session.folder = clean_folder(session.folder);
+        session.updated_at = Some(now_millis());
+        self.modify(|data| {
+            let existing = data
+                .sessions
+                .iter_mut()
+                .find(|s| s.id == id)
+                .ok_or_else(|| StoreError::NotFound(format!("Session {}", id)))?;
+            session.created_at = existing.created_at.or(session.created_at);
+            if session.password.is_none() {
+                session.password_saved = existing.password_saved;
+            }
+            self.stash_passwords(std::slice::from_mut(&mut session))?;
+            *existing = session.clone();
+            Ok(session)
+        })
+    }
+
+    /// Add sessions converted from another program. Sessions with the same
+    /// type and name as an existing one are left out and their names
+    /// returned, so repeating an import doesn't create duplicates.
+    pub fn add_imported(
+        &self,
+        sessions: Vec<Session>,
+    ) -> Result<(Vec<Session>, Vec<String>), StoreError> {
+        let now = now_millis();
+        self.modify(|data| {
+            let mut added = Vec::new();
+            let mut duplicates = Vec::new();
+            for mut session in sessions {
+                if data
+                    .sessions
+                    .iter()
+                    .any(|s| s.kind == session.kind && s.name == session.name)
+                {
+                    duplicates.push(session.name);
+                    continue;
+                }
+                session.id = gen_id();
+                session.folder = clean_folder(session.folder);
+                session.created_at = Some(now);
+                session.updated_at = Some(now);
+                self.stash_passwords(std::slice::from_mut(&mut session))?;
+                data.sessions.push(session.clone());
+                added.push(session);
+            }
+            Ok((added, duplicates))
+        })
+    }
+
+    pub fn delete(&self, id: &str) -> Result<(), StoreError> {
+        let saved = self.modify(|data| {
+            let index = data
+                .sessions
+                .iter()
+                .position(|s| s.id == id)
+                .ok_or_else(|| StoreError::NotFound(format!("Session {}", id)))?;
+            Ok(data.sessions.remove(index).password_saved)
+        })?;
+        if saved {
+            if let Err(e) = (self.keychain.delete)(&secrets::session_password_account(id)) {
+                tracing::warn!("{}", e);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add_folder(&self, name: &str) -> Result<(), StoreError> {
+        let name = clean_folder(Some(name.to_string()))
+            .ok_or_else(|| StoreError::Invalid("Folder name is empty".to_string()))?;
+        self.modify(|data| {
+            if !data.folders.contains(&name) {
+                data.folders.push(name);
+            }
+            Ok(())
+        })
+    }
+
+    /// Rename a folder and move its sessions along. Renaming onto an
+    /// existing folder merges the two.
+    pub fn rename_folder(&self, from: &str, to: &str) -> Result<(), StoreError> {
+        let to = clean_folder(Some(to.to_string()))
+            .ok_or_else(|| StoreError::Invalid("Folder name is empty".to_string()))?;
+        self.modify(|data| {
+            let mut found = false;
+            for s in data.sessions.iter_mut() {
+                if s.folder.as_deref() == Some(from) {
+                    s.folder = Some(to.clone());
+                    found = true;
+                }
+            }
+            if data.folders.iter().any(|f| f == from) {
+                found = true;
+            }
+            if !found {
+                return Err(StoreError::NotFound(format!("Folder {}", from)));
+            }
+            data.folders.retain(|f| f != from && f != &to);
+            data.folders.push(to);
+            Ok(())
+        })
+    }
+
+    /// Remove a folder. Its sessions are kept and moved to the top level.
+    pub fn delete_folder(&self, name: &str) -> Result<(), StoreError> {
+        self.modify(|data| {
+            data.folders.retain(|f| f != name);
+            for s in data.sessions.iter_mut() {
+                if s.folder.as_deref() == Some(name) {
+                    s.folder = None;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    pub fn defaults(&self) -> Map<String, Value> {
+        self.data.lock().unwrap().defaults.clone()
+    }
+
+    pub fn set_defaults(&self, defaults: Map<String, Value>) -> Result<(), StoreError> {
+        self.modify(|data| {
+            data.defaults = defaults;
+            Ok(())
+        })
+    }
+
+    /// The last quick-connect SSH details, with the password filled in
+    /// from the keychain.
+    pub fn ssh_info(&self) -> Map<String, Value> {
+        let mut info = self.data.lock().unwrap().ssh_info.clone();
+        if info.remove(SSH_INFO_PASSWORD_SAVED).is_some() {
+            match (self.keychain.get)(secrets::SSH_INFO_ACCOUNT) {
+                Ok(Some(password)) => {
+                    info.insert(SSH_INFO_PASSWORD.to_string(), Value::String(password));
+                }
+                Ok(None) => {}
+                Err(e) => tracing::warn!("{}", e),
+            }
+        }
+        info
+    }
+
+    /// Replace the quick-connect SSH details. Details without a password
+    /// remove the saved one.
+    pub fn set_ssh_info(&self, mut info: Map<String, Value>) -> Result<(), StoreError> {
+        self.modify(|data| {
+            let password = info.remove(SSH_INFO_PASSWORD).unwrap_or(Value::Null);
+            info.remove(SSH_INFO_PASSWORD_SAVED);
+            self.stash_ssh_password(&mut info, password)?;
+            data.ssh_info = info;
+            Ok(())
+        })
+    }
+
+    /// Merge data from the frontend's `localStorage`. Sessions already in
+    /// the store (by id) are left alone, and stored defaults and SSH info
+    /// win over imported ones, so importing twice is harmless.
+    pub fn import_local_storage(
+        &self,
+        import: LocalStorageImport,
+    ) -> Result<ImportReport, StoreError> {
+        self.modify(|data| {
+            let mut report = ImportReport::default();
+            for (i, value) in import.sessions.into_iter().enumerate() {
+                let mut session = match serde_json::from_value::<Session>(value) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        report.errors.push(format!("Session #{}: {}", i + 1, e));
+                        continue;
+                    }
+                };
+                if session.id.is_empty() {
+                    session.id = gen_id();
+                }
+                if data.sessions.iter().any(|s| s.id == session.id) {
+                    report.skipped += 1;
+                    continue;
+                }
+                session.folder = clean_folder(session.folder);
+                data.sessions.push(session);
+                report.imported += 1;
+            }
+
+            if let Some(mut defaults) = import.defaults {
+                if let Some(Value::Array(folders)) = defaults.remove("folders") {
+                    for folder in folders.iter().filter_map(|f| f.as_str()) {
+                        if let Some(folder) = clean_folder(Some(folder.to_string())) {
+                            if !data.folders.contains(&folder) {
+                                data.folders.push(folder);
+                            }
+                        }
+                    }
+                }
+                for (key, value) in defaults {
+                    data.defaults.entry(key).or_insert(value);
+                }
+            }
+
+            if let Some(mut info) = import.ssh_info {
+                if data.ssh_info.is_empty() {
+                    if let Some(password) = info.remove(SSH_INFO_PASSWORD) {
+                        self.stash_ssh_password(&mut info, password)?;
+                    }
+                    data.ssh_info = info;
+                }
+            }
+            Ok(report)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "serial-rs-sessions-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    static KEYCHAIN: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+    fn keychain_get(account: &str) -> Result<Option<String>, String> {
+        let entries = KEYCHAIN.lock().unwrap();
+        Ok(entries.iter().find(|(a, _)| a == account).map(|(_, s)| s.clone()))
+    }
+
+    fn keychain_set(account: &str, secret: &str) -> Result<(), String> {
+        keychain_delete(account)?;
+        KEYCHAIN.lock().unwrap().push((account.to_string(), secret.to_string()));
+        Ok(())
+    }
+
+    fn keychain_delete(account: &str) -> Result<(), String> {
+        KEYCHAIN.lock().unwrap().retain(|(a, _)| a != account);
+        Ok(())
+    }
+
+    const TEST_KEYCHAIN: Keychain = Keychain {
+        get: keychain_get,
+        set: keychain_set,
+        delete: keychain_delete,
+    };
+
+    fn serial_session(name: &str, folder: Option<&str>) -> Session {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "type": "serial",
+            "folder": folder,
+            "port": "/dev/ttyUSB0",
+            "baudRate": 115200,
+            "fontSize": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_crud_persists() {
+        let dir = temp_dir("crud");
+        let store = SessionStore::open(&dir, TEST_KEYCHAIN);
+        let created = store.create(serial_session("board", Some("lab"))).unwrap();
+        assert!(!created.id.is_empty());
+
+        let mut edited = created.clone();
+        edited.baud_rate = Some(9600);
+        store.update(&created.id, edited).unwrap();
+        assert!(matches!(
+            store.update("missing", created.clone()),
+            Err(StoreError::NotFound(_))
+        ));
+
+        let reopened = SessionStore::open(&dir, TEST_KEYCHAIN);
+        let (sessions, folders) = reopened.list();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].baud_rate, Some(9600));
+        assert_eq!(sessions[0].created_at, created.created_at);
+        assert!(sessions[0].extra.contains_key("fontSize"));
+        assert_eq!(folders, vec!["lab"]);
+
+        reopened.delete(&created.id).unwrap();
+        assert!(reopened.list().0.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_folders() {
+        let dir = temp_dir("folders");
+        let store = SessionStore::open(&dir, TEST_KEYCHAIN);
+        let a = store.create(serial_session("a", Some("old"))).unwrap();
+        store.add_folder("empty").unwrap();
+        store.rename_folder("old", "new").unwrap();
+        assert_eq!(store.get(&a.id).unwrap().folder.as_deref(), Some("new"));
+        assert_eq!(store.list().1, vec!["empty", "new"]);
+
+        store.delete_folder("new").unwrap();
+        assert_eq!(store.get(&a.id).unwrap().folder, None);
+        assert_eq!(store.list().1, vec!["empty"]);
+        assert!(store.rename_folder("nope", "x").is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_import_local_storage() {
+        let dir = temp_dir("import");
+        let store = SessionStore::open(&dir, TEST_KEYCHAIN);
+        let import = || LocalStorageImport {
+            sessions: vec![
+                serde_json::json!({ "id": "s1", "name": "ssh", "type": "ssh", "host": "h", "sshPort": 22 }),
+                serde_json::json!({ "id": "s2", "name": "bad" }),
+            ],
+            defaults: serde_json::json!({ "fontSize": 16, "folders": ["work"] })
+                .as_object()
+                .cloned(),
+            ssh_info: None,
+        };
+        let report = store.import_local_storage(import()).unwrap();
+        assert_eq!(report.imported, 1);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(store.list().1, vec!["work"]);
+        assert_eq!(store.defaults().get("fontSize"), Some(&serde_json::json!(16)));
+        assert!(!store.defaults().contains_key("folders"));
+
+        let again = store.import_local_storage(import()).unwrap();
+        assert_eq!((again.imported, again.skipped), (0, 1));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_passwords_stay_out_of_the_file() {
+        let dir = temp_dir("passwords");
+        let file = dir.join(STORE_FILE);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            &file,
+            serde_json::json!({
+                "version": 1,
+                "sessions": [{ "id": "old", "name": "old", "type": "ssh", "password": "hunter2" }],
+                "ssh_info": { "host": "h", "password": "quick" },
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let store = SessionStore::open(&dir, TEST_KEYCHAIN);
+        let mut session = Session::new(SessionKind::Ssh, "new");
+        session.password = Some("secret".to_string());
+        let created = store.create(session).unwrap();
+        assert_eq!(created.password, None);
+        assert!(created.password_saved);
+        let on_disk = std::fs::read_to_string(&file).unwrap();
+        for password in ["hunter2", "quick", "secret"] {
+            assert!(!on_disk.contains(password), "{} in {}", password, on_disk);
+        }
+
+        let (mut sessions, _) = store.list();
+        store.fill_passwords(&mut sessions);
+        let passwords: Vec<_> = sessions.iter().map(|s| s.password.as_deref()).collect();
+        assert_eq!(passwords, vec![Some("hunter2"), Some("secret")]);
+        assert_eq!(store.ssh_info().get("password"), Some(&serde_json::json!("quick")));
+
+        // Saving without the password keeps it; an empty one removes it
+        let mut edited = store.get(&created.id).unwrap();
+        edited.name = "renamed".to_string();
+        assert!(store.update(&created.id, edited.clone()).unwrap().password_saved);
+        edited.password = Some(String::new());
+        assert!(!store.update(&created.id, edited).unwrap().password_saved);
+        assert_eq!(keychain_get(&secrets::session_password_account(&created.id)), Ok(None));
+
+        store.delete("old").unwrap();
+        assert_eq!(keychain_get(&secrets::session_password_account("old")), Ok(None));
+        store.set_ssh_info(Map::new()).unwrap();
+        assert!(store.ssh_info().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
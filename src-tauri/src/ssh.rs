@@ -1,4 +1,3 @@
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -12,8 +11,7 @@ use tokio::sync::{broadcast, mpsc, Mutex};
 
 struct SshClientHandler {
     broadcast_tx: broadcast::Sender<Vec<u8>>,
-    zmodem_active: Arc<AtomicBool>,
-    zmodem_data_tx: Arc<Mutex<Option<mpsc::Sender<Vec<u8>>>>>,
+    router: crate::IncomingRouter,
 }
 
 #[async_trait]
@@ -34,23 +32,11 @@ impl client::Handler for SshClientHandler {
         data: &[u8],
         _session: &mut client::Session,
     ) -> Result<(), Self::Error> {
-        // During ZMODEM mode, route data to the ZMODEM handler instead of broadcast
-        if self.zmodem_active.load(Ordering::Relaxed) {
-            let tx = self.zmodem_data_tx.lock().await;
-            if let Some(ref zmodem_tx) = *tx {
-                tracing::debug!("SSH data -> ZMODEM channel: {} bytes", data.len());
-                match zmodem_tx.send(data.to_vec()).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        tracing::error!("SSH data -> ZMODEM channel send failed: {}", e);
-                    }
-                }
-                return Ok(());
-            } else {
-                tracing::warn!("SSH: zmodem_active=true but zmodem_data_tx is None, falling through to broadcast");
-            }
+        // File transfers take what isn't terminal output
+        let data = self.router.route(data).await;
+        if !data.is_empty() {
+            let _ = self.broadcast_tx.send(data.to_vec());
         }
-        let _ = self.broadcast_tx.send(data.to_vec());
         Ok(())
     }
 }
@@ -206,17 +192,14 @@ impl SshConnection {
     pub async fn connect(
         config: SshConfig,
         broadcast_tx: broadcast::Sender<Vec<u8>>,
-        scrollback: Arc<Mutex<std::collections::VecDeque<u8>>>,
-        scrollback_max: usize,
-        zmodem_active: Arc<AtomicBool>,
-        zmodem_data_tx: Arc<Mutex<Option<mpsc::Sender<Vec<u8>>>>>,
+        scrollback: Arc<crate::scrollback::Scrollback>,
+        router: crate::IncomingRouter,
     ) -> Result<Self, String> {
         let ssh_config = russh::client::Config::default();
 
         let handler = SshClientHandler {
             broadcast_tx: broadcast_tx.clone(),
-            zmodem_active,
-            zmodem_data_tx,
+            router,
         };
 
         let mut handle = tokio::time::timeout(
@@ -280,13 +263,7 @@ impl SshConnection {
             let mut rx = bc_rx_scrollback;
             loop {
                 match rx.recv().await {
-                    Ok(data) => {
-                        let mut sb = scrollback_clone.lock().await;
-                        sb.extend(&data);
-                        while sb.len() > scrollback_max {
-                            sb.pop_front();
-                        }
-                    }
+                    Ok(data) => scrollback_clone.push(&data),
                     Err(broadcast::error::RecvError::Closed) => break,
                     Err(broadcast::error::RecvError::Lagged(_)) => continue,
                 }
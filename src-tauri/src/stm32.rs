@@ -0,0 +1,371 @@
+//! The STM32 system memory bootloader on a USART (ST application note
+//! AN3155). The link is 8E1; the host sends 0x7F so the bootloader can
+//! measure the baud rate, then commands as a byte and its complement.
+//! Addresses and data carry an XOR checksum, and every step is answered
+//! with ACK or NACK.
+
+use std::time::Duration;
+
+use tokio_serial::Parity;
+
+use crate::flasher::{Backend, Control, Segment};
+use crate::xmodem::Link;
+
+const ACK: u8 = 0x79;
+const NACK: u8 = 0x1f;
+const INIT: u8 = 0x7f;
+
+const GET: u8 = 0x00;
+const GET_ID: u8 = 0x02;
+const READ_MEMORY: u8 = 0x11;
+const GO: u8 = 0x21;
+const WRITE_MEMORY: u8 = 0x31;
+const ERASE: u8 = 0x43;
+const EXTENDED_ERASE: u8 = 0x44;
+
+/// Most the bootloader reads or writes at once
+const BLOCK: usize = 256;
+
+const INIT_TIMEOUT: Duration = Duration::from_millis(500);
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+/// A mass erase of a large part takes a while
+const MASS_ERASE_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn with_xor(bytes: &[u8]) -> Vec<u8> {
+    let mut out = bytes.to_vec();
+    out.push(bytes.iter().fold(0, |x, &b| x ^ b));
+    out
+}
+
+/// The bootloader, reached at `baud`.
+pub struct Bootloader {
+    baud: u32,
+    /// Takes EXTENDED_ERASE rather than ERASE
+    extended_erase: bool,
+}
+
+impl Bootloader {
+    pub fn new(baud: u32) -> Self {
+        Bootloader {
+            baud,
+            extended_erase: false,
+        }
+    }
+}
+
+async fn ack(link: &mut Link, timeout: Duration, what: &str) -> Result<(), String> {
+    match link.byte(timeout).await? {
+        Some(ACK) => Ok(()),
+        Some(NACK) => Err(format!("The bootloader refused {}", what)),
+        Some(b) => Err(format!(
+            "Unexpected {:#04x} from the bootloader after {}",
+            b, what
+        )),
+        None => Err(format!("No answer from the bootloader to {}", what)),
+    }
+}
+
+async fn command(link: &mut Link, op: u8, what: &str) -> Result<(), String> {
+    link.send(vec![op, !op]).await?;
+    ack(link, ACK_TIMEOUT, what).await
+}
+
+async fn read_bytes(link: &mut Link, count: usize, what: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(count);
+    while out.len() < count {
+        match link.byte(ACK_TIMEOUT).await? {
+            Some(b) => out.push(b),
+            None => return Err(format!("The bootloader stopped answering {}", what)),
+        }
+    }
+    Ok(out)
+}
+
+async fn send_address(link: &mut Link, address: u32) -> Result<(), String> {
+    link.send(with_xor(&address.to_be_bytes())).await?;
+    ack(link, ACK_TIMEOUT, &format!("the address {:#010x}", address)).await
+}
+
+#[async_trait::async_trait]
+impl Backend for Bootloader {
+    fn parity(&self) -> Parity {
+        Parity::Even
+    }
+
+    fn connect_baud(&self) -> u32 {
+        self.baud
+    }
+
+    fn erases(&self) -> bool {
+        true
+    }
+
+    fn check(&self, segments: &[Segment]) -> Result<(), String> {
+        match segments.iter().find(|s| s.address % 4 != 0) {
+            Some(s) => Err(format!(
+                "{} is at {:#x}; STM32 addresses must be multiples of 4",
+                s.name, s.address
+            )),
+            None => Ok(()),
+        }
+    }
+
+    async fn connect(&mut self, link: &mut Link, _control: &dyn Control) -> Result<String, String> {
+        link.send(vec![INIT]).await?;
+        match link.byte(INIT_TIMEOUT).await? {
+            // NACK: it already has the baud rate from an earlier attempt
+            Some(ACK | NACK) => {}
+            Some(b) => return Err(format!("Unexpected {:#04x} from the bootloader", b)),
+            None => {
+                return Err(
+                    "No answer from the STM32 bootloader; is BOOT0 high at reset?".to_string(),
+                );
+            }
+        }
+
+        command(link, GET, "GET").await?;
+        let count = read_bytes(link, 1, "GET").await?[0] as usize;
+        let answer = read_bytes(link, count + 1, "GET").await?;
+        ack(link, ACK_TIMEOUT, "GET").await?;
+        let version = answer[0];
+        self.extended_erase = answer[1..].contains(&EXTENDED_ERASE);
+
+        command(link, GET_ID, "GET ID").await?;
+        let count = read_bytes(link, 1, "GET ID").await?[0] as usize;
+        let id = read_bytes(link, count + 1, "GET ID").await?;
+        ack(link, ACK_TIMEOUT, "GET ID").await?;
+        let id: String = id.iter().map(|b| format!("{:02X}", b)).collect();
+        Ok(format!(
+            "STM32 (product ID 0x{}, bootloader {}.{})",
+            id,
+            version >> 4,
+            version & 0x0f
+        ))
+    }
+
+    /// Mass erase: which pages a segment covers differs between families.
+    async fn erase(&mut self, link: &mut Link, _segments: &[Segment]) -> Result<(), String> {
+        if self.extended_erase {
+            command(link, EXTENDED_ERASE, "EXTENDED ERASE").await?;
+            link.send(vec![0xff, 0xff, 0x00]).await?;
+        } else {
+            command(link, ERASE, "ERASE").await?;
+            link.send(vec![0xff, 0x00]).await?;
+        }
+        ack(link, MASS_ERASE_TIMEOUT, "the mass erase").await
+    }
+
+    async fn write(
+        &mut self,
+        link: &mut Link,
+        segment: &Segment,
+        progress: &mut (dyn FnMut(u64) + Send),
+    ) -> Result<(), String> {
+        let mut done = 0;
+        for block in segment.data.chunks(BLOCK) {
+            let address = segment.address + done as u32;
+            command(link, WRITE_MEMORY, "WRITE MEMORY").await?;
+            send_address(link, address).await?;
+            let mut packet = vec![(block.len() - 1) as u8];
+            packet.extend(block);
+            link.send(with_xor(&packet)).await?;
+            ack(
+                link,
+                ACK_TIMEOUT,
+                &format!("the write at {:#010x}", address),
+            )
+            .await?;
+            done += block.len();
+            progress(done as u64);
+        }
+        Ok(())
+    }
+
+    async fn verify(
+        &mut self,
+        link: &mut Link,
+        segment: &Segment,
+        progress: &mut (dyn FnMut(u64) + Send),
+    ) -> Result<(), String> {
+        let mut done = 0;
+        for block in segment.data.chunks(BLOCK) {
+            let address = segment.address + done as u32;
+            command(link, READ_MEMORY, "READ MEMORY").await?;
+            send_address(link, address).await?;
+            let count = (block.len() - 1) as u8;
+            link.send(vec![count, !count]).await?;
+            ack(link, ACK_TIMEOUT, "the read length").await?;
+            let read = read_bytes(link, block.len(), "READ MEMORY").await?;
+            if let Some(i) = (0..block.len()).find(|&i| read[i] != block[i]) {
+                return Err(format!(
+                    "{}: flash at {:#010x} reads {:02X}, expected {:02X}",
+                    segment.name,
+                    address + i as u32,
+                    read[i],
+                    block[i]
+                ));
+            }
+            done += block.len();
+            progress(done as u64);
+        }
+        Ok(())
+    }
+
+    /// Without a reset to follow, jump to the lowest segment, taken to
+    /// hold the vector table.
+    async fn finish(
+        &mut self,
+        link: &mut Link,
+        segments: &[Segment],
+        reset_follows: bool,
+    ) -> Result<(), String> {
+        match segments.first() {
+            Some(first) if !reset_follows => {
+                command(link, GO, "GO").await?;
+                send_address(link, first.address).await
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use tokio::sync::mpsc;
+
+    struct NoLines;
+
+    impl Control for NoLines {
+        fn set_dtr(&self, _: bool) -> Result<(), String> {
+            Ok(())
+        }
+        fn set_rts(&self, _: bool) -> Result<(), String> {
+            Ok(())
+        }
+        fn baud_rate(&self) -> Result<u32, String> {
+            Ok(115200)
+        }
+        fn set_baud_rate(&self, _: u32) -> Result<(), String> {
+            Ok(())
+        }
+        fn parity(&self) -> Result<Parity, String> {
+            Ok(Parity::Even)
+        }
+        fn set_parity(&self, _: Parity) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    /// A bootloader (v3.1, extended erase) with 4 KiB of flash at
+    /// 0x08000000 whose byte at 0x08000100 is stuck at zero. Returns the
+    /// flash when the host hangs up.
+    fn fake_bootloader(
+        mut from_host: mpsc::Receiver<Vec<u8>>,
+        to_host: mpsc::Sender<Vec<u8>>,
+    ) -> tokio::task::JoinHandle<Vec<u8>> {
+        tokio::spawn(async move {
+            const BASE: usize = 0x0800_0000;
+            let mut flash = vec![0u8; 0x1000];
+            let mut input = VecDeque::new();
+            async fn take(
+                input: &mut VecDeque<u8>,
+                from_host: &mut mpsc::Receiver<Vec<u8>>,
+                count: usize,
+            ) -> Option<Vec<u8>> {
+                while input.len() < count {
+                    input.extend(from_host.recv().await?);
+                }
+                Some(input.drain(..count).collect())
+            }
+            let check = |bytes: &[u8]| bytes.iter().fold(0, |x, &b| x ^ b) == 0;
+            let address =
+                |bytes: &[u8]| u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize - BASE;
+
+            assert_eq!(take(&mut input, &mut from_host, 1).await.unwrap(), [INIT]);
+            to_host.send(vec![ACK]).await.unwrap();
+            while let Some(op) = take(&mut input, &mut from_host, 2).await {
+                assert_eq!(op[0], !op[1]);
+                to_host.send(vec![ACK]).await.unwrap();
+                let answer = match op[0] {
+                    GET => vec![3, 0x31, GET, GET_ID, EXTENDED_ERASE, ACK],
+                    GET_ID => vec![1, 0x04, 0x13, ACK],
+                    EXTENDED_ERASE => {
+                        assert_eq!(
+                            take(&mut input, &mut from_host, 3).await.unwrap(),
+                            [0xff, 0xff, 0]
+                        );
+                        flash.fill(0xff);
+                        vec![ACK]
+                    }
+                    WRITE_MEMORY => {
+                        let at = address(&take(&mut input, &mut from_host, 5).await.unwrap());
+                        to_host.send(vec![ACK]).await.unwrap();
+                        let count =
+                            take(&mut input, &mut from_host, 1).await.unwrap()[0] as usize + 1;
+                        let mut packet = vec![(count - 1) as u8];
+                        packet.extend(take(&mut input, &mut from_host, count + 1).await.unwrap());
+                        assert!(check(&packet));
+                        flash[at..at + count].copy_from_slice(&packet[1..=count]);
+                        flash[0x100] = 0;
+                        vec![ACK]
+                    }
+                    READ_MEMORY => {
+                        let at = address(&take(&mut input, &mut from_host, 5).await.unwrap());
+                        to_host.send(vec![ACK]).await.unwrap();
+                        let count =
+                            take(&mut input, &mut from_host, 2).await.unwrap()[0] as usize + 1;
+                        to_host.send(vec![ACK]).await.unwrap();
+                        flash[at..at + count].to_vec()
+                    }
+                    GO => {
+                        take(&mut input, &mut from_host, 5).await.unwrap();
+                        vec![ACK]
+                    }
+                    _ => vec![NACK],
+                };
+                to_host.send(answer).await.unwrap();
+            }
+            flash
+        })
+    }
+
+    #[tokio::test]
+    async fn test_flash() {
+        let (to_host, from_device) = mpsc::channel(64);
+        let (to_device, from_host) = mpsc::channel(64);
+        let device = fake_bootloader(from_host, to_host);
+        let mut link = Link::new(from_device, to_device);
+
+        let mut bootloader = Bootloader::new(115200);
+        assert_eq!(
+            bootloader.connect(&mut link, &NoLines).await,
+            Ok("STM32 (product ID 0x0413, bootloader 3.1)".to_string())
+        );
+        assert!(bootloader.extended_erase);
+        bootloader.erase(&mut link, &[]).await.unwrap();
+
+        let data: Vec<u8> = (0..0x180u32).map(|i| i as u8 | 1).collect();
+        let segment = Segment::new("app.bin", 0x0800_0000, data.clone());
+        let mut written = Vec::new();
+        bootloader
+            .write(&mut link, &segment, &mut |n| written.push(n))
+            .await
+            .unwrap();
+        assert_eq!(written, [0x100, 0x180]);
+        assert_eq!(
+            bootloader.verify(&mut link, &segment, &mut |_| {}).await,
+            Err("app.bin: flash at 0x08000100 reads 00, expected 01".to_string())
+        );
+        assert!(bootloader
+            .finish(&mut link, &[segment], false)
+            .await
+            .is_ok());
+
+        drop(link);
+        let flash = device.await.unwrap();
+        assert_eq!(&flash[..0x100], &data[..0x100]);
+        assert_eq!(flash[0x180], 0xff);
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Records kept per tab; the oldest are dropped first.
+const MAX_RECORDS: usize = 500;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Receive,
+    Send,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Completed,
+    Skipped,
+    Failed,
+}
+
+/// One file sent or received.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    pub id: u64,
+    /// "zmodem", "xmodem", "ymodem", "trzsz" or "kermit"
+    pub protocol: &'static str,
+    pub direction: Direction,
+    pub filename: String,
+    /// Where a received file was saved; `None` for sent files and
+    /// failed downloads whose partial file was removed
+    pub path: Option<PathBuf>,
+    /// Size of the file, or the bytes done when it failed
+    pub size: u64,
+    pub duration_ms: u64,
+    pub bytes_per_sec: u64,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Unix time in milliseconds when the file finished
+    pub timestamp: i64,
+}
+
+impl Record {
+    pub fn new(
+        protocol: &'static str,
+        direction: Direction,
+        filename: &str,
+        size: u64,
+        duration: Duration,
+        status: Status,
+    ) -> Self {
+        let duration_ms = duration.as_millis() as u64;
+        Record {
+            id: 0,
+            protocol,
+            direction,
+            filename: filename.to_string(),
+            path: None,
+            size,
+            duration_ms,
+            bytes_per_sec: (size * 1000).checked_div(duration_ms).unwrap_or(0),
+            status,
+            message: None,
+            timestamp: chrono::Local::now().timestamp_millis(),
+        }
+    }
+}
+
+/// A tab's transfers, oldest first.
+#[derive(Default)]
+pub struct History {
+    records: VecDeque<Record>,
+    next_id: u64,
+}
+
+impl History {
+    /// Add a record, giving it the next ID.
+    pub fn push(&mut self, mut record: Record) -> u64 {
+        self.next_id += 1;
+        record.id = self.next_id;
+        if self.records.len() == MAX_RECORDS {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+        self.next_id
+    }
+
+    pub fn records(&self) -> impl DoubleEndedIterator<Item = &Record> {
+        self.records.iter()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Record> {
+        self.records.iter().find(|r| r.id == id)
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Record> {
+        let index = self.records.iter().position(|r| r.id == id)?;
+        self.records.remove(index)
+    }
+
+    /// Saved files, oldest first.
+    pub fn received_files(&self) -> impl Iterator<Item = &Path> {
+        self.records
+            .iter()
+            .filter(|r| r.direction == Direction::Receive)
+            .filter_map(|r| r.path.as_deref())
+    }
+
+    /// The latest saved file with this name.
+    pub fn find_received(&self, filename: &str) -> Option<&Path> {
+        self.received_files()
+            .filter(|p| p.file_name().is_some_and(|n| n.to_string_lossy() == filename))
+            .last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(name: &str, dir: &str) -> Record {
+        let mut record = Record::new(
+            "zmodem",
+            Direction::Receive,
+            name,
+            4000,
+            Duration::from_millis(2000),
+            Status::Completed,
+        );
+        record.path = Some(PathBuf::from(dir).join(name));
+        record
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history = History::default();
+        let first = history.push(received("a.bin", "/old"));
+        history.push(Record::new(
+            "xmodem",
+            Direction::Send,
+            "fw.bin",
+            10,
+            Duration::ZERO,
+            Status::Failed,
+        ));
+        let latest = history.push(received("a.bin", "/new"));
+        assert_eq!(history.get(first).unwrap().bytes_per_sec, 2000);
+        assert_eq!(history.get(2).unwrap().bytes_per_sec, 0);
+
+        // Sent files aren't offered for download
+        assert_eq!(history.received_files().count(), 2);
+        assert_eq!(history.find_received("a.bin"), Some(Path::new("/new/a.bin")));
+        assert_eq!(history.remove(latest).unwrap().id, latest);
+        assert_eq!(history.find_received("a.bin"), Some(Path::new("/old/a.bin")));
+        assert!(history.remove(latest).is_none());
+        assert_eq!(history.find_received("b.bin"), None);
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let mut history = History::default();
+        for i in 0..MAX_RECORDS + 3 {
+            history.push(received(&format!("{}.bin", i), "/dl"));
+        }
+        assert_eq!(history.records().count(), MAX_RECORDS);
+        // IDs keep counting after the oldest are dropped
+        assert_eq!(history.records().next().unwrap().id, 4);
+        assert_eq!(history.records().next_back().unwrap().id, MAX_RECORDS as u64 + 3);
+    }
+}
@@ -0,0 +1,167 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// A rule run against every received line of a tab.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TriggerRule {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    /// Regex searched for in each line (escape sequences removed)
+    pub pattern: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub actions: Vec<TriggerAction>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// What a matching rule does. `data`, `path` and `message` can refer to
+/// capture groups as `$1` or `${name}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TriggerAction {
+    /// Send text to the connection
+    Send { data: String },
+    /// Start logging to a file, unless already logging
+    LogStart { path: String },
+    LogStop,
+    /// Desktop notification
+    Notify { message: String },
+    /// Increment a named counter
+    Counter { name: String },
+    Disconnect,
+}
+
+/// Enabled rules of a tab, compiled.
+pub struct TriggerSet {
+    rules: Vec<(Regex, TriggerRule)>,
+}
+
+/// A rule that matched a line, with the actions to perform in order.
+#[derive(Debug, PartialEq)]
+pub struct Fired {
+    pub rule: String,
+    pub actions: Vec<TriggerAction>,
+}
+
+impl TriggerSet {
+    pub fn compile(rules: &[TriggerRule]) -> Result<Self, String> {
+        let mut compiled = Vec::new();
+        for (i, rule) in rules.iter().enumerate() {
+            let label = if rule.name.is_empty() {
+                format!("Rule {}", i + 1)
+            } else {
+                rule.name.clone()
+            };
+            if rule.actions.is_empty() {
+                return Err(format!("{}: no actions", label));
+            }
+            let re = Regex::new(&rule.pattern).map_err(|e| format!("{}: {}", label, e))?;
+            if rule.enabled {
+                let mut rule = rule.clone();
+                rule.name = label;
+                compiled.push((re, rule));
+            }
+        }
+        Ok(TriggerSet { rules: compiled })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Rules matching `line`, in rule order.
+    pub fn evaluate(&self, line: &str) -> Vec<Fired> {
+        self.rules
+            .iter()
+            .filter_map(|(re, rule)| {
+                let caps = re.captures(line)?;
+                let expand = |template: &str| {
+                    let mut out = String::new();
+                    caps.expand(template, &mut out);
+                    out
+                };
+                let actions = rule
+                    .actions
+                    .iter()
+                    .map(|action| match action {
+                        TriggerAction::Send { data } => TriggerAction::Send { data: expand(data) },
+                        TriggerAction::LogStart { path } => {
+                            TriggerAction::LogStart { path: expand(path) }
+                        }
+                        TriggerAction::Notify { message } => {
+                            TriggerAction::Notify { message: expand(message) }
+                        }
+                        other => other.clone(),
+                    })
+                    .collect();
+                Some(Fired {
+                    rule: rule.name.clone(),
+                    actions,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(json: &str) -> Vec<TriggerRule> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let set = TriggerSet::compile(&rules(
+            r#"[
+                {"name": "panic", "pattern": "Kernel panic - (.*)", "actions": [
+                    {"type": "notify", "message": "panic: $1"},
+                    {"type": "counter", "name": "panics"}
+                ]},
+                {"pattern": "login:", "enabled": false, "actions": [{"type": "send", "data": "root\r"}]},
+                {"pattern": "(?P<n>\\d+)% done", "actions": [{"type": "send", "data": "${n}\r"}]}
+            ]"#,
+        ))
+        .unwrap();
+
+        assert!(set.evaluate("login: ").is_empty());
+        assert_eq!(
+            set.evaluate("Kernel panic - not syncing"),
+            [Fired {
+                rule: "panic".into(),
+                actions: vec![
+                    TriggerAction::Notify {
+                        message: "panic: not syncing".into()
+                    },
+                    TriggerAction::Counter {
+                        name: "panics".into()
+                    },
+                ],
+            }]
+        );
+        assert_eq!(
+            set.evaluate("flash 40% done"),
+            [Fired {
+                rule: "Rule 3".into(),
+                actions: vec![TriggerAction::Send { data: "40\r".into() }],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let err = TriggerSet::compile(&rules(
+            r#"[{"name": "bad", "pattern": "(", "actions": [{"type": "disconnect"}]}]"#,
+        ))
+        .err()
+        .unwrap();
+        assert!(err.starts_with("bad: "));
+        assert!(TriggerSet::compile(&rules(r#"[{"pattern": "x", "actions": []}]"#)).is_err());
+    }
+}
@@ -0,0 +1,840 @@
+//! trzsz: file transfer started by the remote's `trz` (upload) or `tsz`
+//! (download), in text lines that survive SSH, tmux and the like.
+//!
+//! Every message is a line `#TYPE:value`. Strings and binary values are
+//! zlib-compressed and base64-encoded; file data is too, unless the remote
+//! offers binary mode, where it follows a `#DATA:<length>` line with a few
+//! bytes escaped.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use tokio::io::AsyncReadExt;
+
+use crate::xmodem::{Event, Link, Transferred};
+
+/// What we tell the remote we are; we speak its protocol 2.
+const VERSION: &str = "1.1.6";
+const PROTOCOL: u64 = 2;
+
+/// Printed by `trz`/`tsz`: `::TRZSZ:TRANSFER:<mode>:<version>:<id>`
+const MAGIC: &[u8] = b"::TRZSZ:TRANSFER:";
+/// Longest magic past its prefix that is still waited for
+const MAX_MAGIC: usize = 64;
+
+/// How long to wait for a line when the remote doesn't say.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(20);
+/// Data chunks start this size and double while the link keeps up.
+const MIN_CHUNK: usize = 1024;
+const DEFAULT_MAX_CHUNK: usize = 1024 * 1024;
+/// The biggest chunks the remote may pick; trzsz's own default is 10 MiB
+const MAX_CHUNK: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// `trz`: the remote receives, so we upload
+    Upload,
+    /// `tsz`: the remote sends
+    Download,
+    /// `trz -d`: directories, not supported
+    Directory,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Detected {
+    pub mode: Mode,
+    pub version: String,
+    /// Where the magic starts in the data fed last; 0 if it began in an
+    /// earlier read
+    pub offset: usize,
+}
+
+enum Parsed {
+    Found(Mode, String),
+    Incomplete,
+    Invalid,
+}
+
+/// Finds the trzsz magic in connection output, even when it is split
+/// across reads.
+#[derive(Default)]
+pub struct Detector {
+    held: Vec<u8>,
+}
+
+impl Detector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, data: &[u8]) -> Option<Detected> {
+        let held = self.held.len();
+        let mut stream = std::mem::take(&mut self.held);
+        stream.extend_from_slice(data);
+
+        let mut from = 0;
+        while let Some(pos) = find(&stream[from..], MAGIC).map(|p| p + from) {
+            match parse_magic(&stream[pos + MAGIC.len()..]) {
+                Parsed::Found(mode, version) => {
+                    return Some(Detected {
+                        mode,
+                        version,
+                        offset: pos.saturating_sub(held),
+                    });
+                }
+                Parsed::Incomplete if stream.len() - pos < MAGIC.len() + MAX_MAGIC => {
+                    self.held = stream[pos..].to_vec();
+                    return None;
+                }
+                _ => from = pos + 1,
+            }
+        }
+        // Keep what could be the start of the magic
+        let keep = stream.len().saturating_sub(MAGIC.len() - 1);
+        self.held = stream[keep..].to_vec();
+        None
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+/// Parse `<mode>:<version>[:<id>]` up to the end of the line.
+fn parse_magic(rest: &[u8]) -> Parsed {
+    let mode = match rest.first() {
+        None => return Parsed::Incomplete,
+        Some(b'R') => Mode::Upload,
+        Some(b'S') => Mode::Download,
+        Some(b'D') => Mode::Directory,
+        Some(_) => return Parsed::Invalid,
+    };
+    match rest.get(1) {
+        None => return Parsed::Incomplete,
+        Some(b':') => {}
+        Some(_) => return Parsed::Invalid,
+    }
+    let fields = &rest[2..];
+    let Some(end) = fields
+        .iter()
+        .position(|b| !(b.is_ascii_digit() || *b == b'.' || *b == b':'))
+    else {
+        return Parsed::Incomplete;
+    };
+    let fields = String::from_utf8_lossy(&fields[..end]);
+    let version = fields.split(':').next().unwrap_or_default();
+    let parts: Vec<&str> = version.split('.').collect();
+    if parts.len() != 3 || parts.iter().any(|p| p.is_empty()) {
+        return Parsed::Invalid;
+    }
+    Parsed::Found(mode, version.to_string())
+}
+
+// ---------------------------------------------------------------------------
+// Encoding
+// ---------------------------------------------------------------------------
+
+fn encode(data: &[u8]) -> String {
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    // Writing to a Vec can't fail
+    let _ = encoder.write_all(data);
+    BASE64.encode(encoder.finish().unwrap_or_default())
+}
+
+/// Decode a value of at most `limit` bytes.
+fn decode(text: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let compressed = BASE64
+        .decode(text.trim_ascii())
+        .map_err(|e| format!("Bad data from the remote: {}", e))?;
+    let mut data = Vec::new();
+    ZlibDecoder::new(&compressed[..])
+        .take(limit as u64 + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| format!("Bad data from the remote: {}", e))?;
+    if data.len() > limit {
+        return Err("Too much data from the remote".to_string());
+    }
+    Ok(data)
+}
+
+/// Binary mode escapes: each byte and the two bytes sent for it.
+type Escapes = Vec<(u8, [u8; 2])>;
+
+fn escape(data: &[u8], escapes: &Escapes) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 16);
+    for &b in data {
+        match escapes.iter().find(|(plain, _)| *plain == b) {
+            Some((_, code)) => out.extend_from_slice(code),
+            None => out.push(b),
+        }
+    }
+    out
+}
+
+fn unescape(data: &[u8], escapes: &Escapes) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let b = data[i];
+        if escapes.iter().any(|(_, code)| code[0] == b) {
+            let next = data.get(i + 1).copied();
+            let plain = escapes
+                .iter()
+                .find(|(_, code)| Some(code[1]) == next && code[0] == b)
+                .map(|(plain, _)| *plain)
+                .ok_or_else(|| "Bad escape in data from the remote".to_string())?;
+            out.push(plain);
+            i += 2;
+        } else {
+            out.push(b);
+            i += 1;
+        }
+    }
+    Ok(out)
+}
+
+// ---------------------------------------------------------------------------
+// Session
+// ---------------------------------------------------------------------------
+
+/// Transfer settings the remote sends after we accept.
+struct Config {
+    binary: bool,
+    escapes: Escapes,
+    newline: Vec<u8>,
+    timeout: Duration,
+    max_chunk: usize,
+    /// tmux redraws the remote's output, wrapping long lines and adding
+    /// escape sequences
+    junk: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            binary: false,
+            escapes: Vec::new(),
+            newline: b"\n".to_vec(),
+            timeout: DEFAULT_TIMEOUT,
+            max_chunk: DEFAULT_MAX_CHUNK,
+            junk: false,
+        }
+    }
+}
+
+impl Config {
+    fn parse(json: &[u8]) -> Result<Self, String> {
+        let value: serde_json::Value = serde_json::from_slice(json)
+            .map_err(|e| format!("Bad configuration from the remote: {}", e))?;
+        let mut config = Config {
+            binary: value["binary"].as_bool().unwrap_or(false),
+            junk: value["tmux_output_junk"].as_bool().unwrap_or(false),
+            ..Config::default()
+        };
+        if let Some(newline) = value["newline"].as_str() {
+            config.newline = newline.as_bytes().to_vec();
+        }
+        if let Some(secs) = value["timeout"].as_u64().filter(|&s| s > 0) {
+            config.timeout = Duration::from_secs(secs);
+        }
+        if let Some(max) = value["max_buffer_size"].as_u64() {
+            config.max_chunk = (max as usize).clamp(MIN_CHUNK, MAX_CHUNK);
+        }
+        // Pairs of strings whose characters are byte values
+        for pair in value["escape_chars"].as_array().into_iter().flatten() {
+            let bytes = |i: usize| -> Option<Vec<u8>> {
+                pair.get(i)?
+                    .as_str()?
+                    .chars()
+                    .map(|c| u8::try_from(c as u32).ok())
+                    .collect()
+            };
+            match (bytes(0).as_deref(), bytes(1).as_deref()) {
+                (Some(&[plain]), Some(&[a, b])) => config.escapes.push((plain, [a, b])),
+                _ => return Err("Bad escape table from the remote".to_string()),
+            }
+        }
+        Ok(config)
+    }
+}
+
+struct Session<'a> {
+    link: &'a mut Link,
+    config: Config,
+}
+
+impl<'a> Session<'a> {
+    /// Accept the transfer and take the remote's settings.
+    async fn start(link: &'a mut Link) -> Result<Session<'a>, String> {
+        let mut session = Session {
+            link,
+            config: Config::default(),
+        };
+        session.send_string("ACT", &action(true)).await?;
+        // The remote's prompt may precede it
+        let cfg = session.recv_line("CFG", true).await?;
+        session.config = Config::parse(&decode(&cfg, session.config.max_chunk)?)?;
+        Ok(session)
+    }
+
+    async fn send_line(&self, typ: &str, value: &str) -> Result<(), String> {
+        let mut line = format!("#{}:{}", typ, value).into_bytes();
+        line.extend_from_slice(&self.config.newline);
+        self.link.send(line).await
+    }
+
+    async fn send_integer(&self, typ: &str, value: u64) -> Result<(), String> {
+        self.send_line(typ, &value.to_string()).await
+    }
+
+    async fn send_string(&self, typ: &str, value: &str) -> Result<(), String> {
+        self.send_binary(typ, value.as_bytes()).await
+    }
+
+    async fn send_binary(&self, typ: &str, value: &[u8]) -> Result<(), String> {
+        self.send_line(typ, &encode(value)).await
+    }
+
+    async fn send_data(&self, data: &[u8]) -> Result<(), String> {
+        if !self.config.binary {
+            return self.send_binary("DATA", data).await;
+        }
+        let escaped = escape(data, &self.config.escapes);
+        let mut message = format!("#DATA:{}\n", escaped.len()).into_bytes();
+        message.extend(escaped);
+        self.link.send(message).await
+    }
+
+    async fn byte(&mut self) -> Result<u8, String> {
+        self.link
+            .byte(self.config.timeout)
+            .await?
+            .ok_or_else(|| "The remote stopped responding".to_string())
+    }
+
+    /// Read the next `#typ:` line and return its value. The remote
+    /// reporting a failure, or exiting, ends the transfer with its message.
+    async fn recv_line(&mut self, typ: &str, may_have_junk: bool) -> Result<Vec<u8>, String> {
+        let junk = may_have_junk || self.config.junk;
+        // Windows remotes end lines with "!\n"; any other line break is
+        // the console redrawing
+        let bang = self.config.newline == b"!\n";
+        // Room for a chunk in base64, a third longer than compressed, and
+        // for what tmux adds
+        let max_line = self.config.max_chunk * 2;
+        let mut line = Vec::new();
+        loop {
+            if line.len() > max_line {
+                return Err("Line from the remote is too long".to_string());
+            }
+            match self.byte().await? {
+                b'\n' if bang && line.last() != Some(&b'!') => {}
+                // A line wrapped by tmux
+                b'\n' if !bang && junk && line.last() == Some(&b'\r') => {
+                    line.pop();
+                }
+                b'\n' => break,
+                b'\r' if bang => {}
+                0x03 => return Err("Interrupted".to_string()),
+                b => line.push(b),
+            }
+        }
+        if bang {
+            line.pop();
+        }
+        if junk {
+            line = strip_escapes(&line);
+        }
+
+        let prefix = format!("#{}:", typ);
+        if let Some(pos) = rfind(&line, prefix.as_bytes()) {
+            return Ok(line[pos + prefix.len()..].trim_ascii_end().to_vec());
+        }
+        let Some(pos) = line.iter().rposition(|&b| b == b'#') else {
+            return Err(format!(
+                "Unexpected output from the remote: {}",
+                String::from_utf8_lossy(&line)
+            ));
+        };
+        let message = &line[pos + 1..];
+        let (got, value) = match message.iter().position(|&b| b == b':') {
+            Some(colon) => (&message[..colon], &message[colon + 1..]),
+            None => (message, &[][..]),
+        };
+        match got {
+            b"fail" | b"FAIL" | b"EXIT" => Err(decode(value, self.config.max_chunk)
+                .map(|m| String::from_utf8_lossy(&m).to_string())
+                .unwrap_or_else(|_| "The remote failed".to_string())),
+            _ => Err(format!(
+                "Expected {} from the remote but got {}",
+                typ,
+                String::from_utf8_lossy(got)
+            )),
+        }
+    }
+
+    async fn recv_integer(&mut self, typ: &str) -> Result<u64, String> {
+        let value = self.recv_line(typ, false).await?;
+        String::from_utf8_lossy(&value)
+            .parse()
+            .map_err(|_| format!("Bad {} from the remote", typ))
+    }
+
+    async fn recv_binary(&mut self, typ: &str) -> Result<Vec<u8>, String> {
+        let value = self.recv_line(typ, false).await?;
+        decode(&value, self.config.max_chunk)
+    }
+
+    async fn recv_string(&mut self, typ: &str) -> Result<String, String> {
+        let value = self.recv_binary(typ).await?;
+        Ok(String::from_utf8_lossy(&value).to_string())
+    }
+
+    async fn recv_data(&mut self) -> Result<Vec<u8>, String> {
+        if !self.config.binary {
+            return self.recv_binary("DATA").await;
+        }
+        let len = self.recv_integer("DATA").await?;
+        // Every byte is sent as two at most
+        if len > self.config.max_chunk as u64 * 2 {
+            return Err(format!("The remote sent a chunk of {} bytes, too big", len));
+        }
+        let len = len as usize;
+        let mut data = Vec::with_capacity(len);
+        for _ in 0..len {
+            data.push(self.byte().await?);
+        }
+        unescape(&data, &self.config.escapes)
+    }
+
+    async fn check_integer(&mut self, expected: u64) -> Result<(), String> {
+        let got = self.recv_integer("SUCC").await?;
+        if got != expected {
+            return Err(format!("The remote got {} instead of {}", got, expected));
+        }
+        Ok(())
+    }
+}
+
+/// Remove terminal escape sequences, which tmux mixes into the output.
+fn strip_escapes(line: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(line.len());
+    let mut i = 0;
+    while i < line.len() {
+        if line[i] != 0x1b {
+            out.push(line[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        if line.get(i) == Some(&b'[') {
+            // CSI: parameters up to a final byte
+            i += 1;
+            while i < line.len() && !(0x40..=0x7e).contains(&line[i]) {
+                i += 1;
+            }
+        }
+        i += 1;
+    }
+    out
+}
+
+/// What we answer the remote's magic with.
+fn action(confirm: bool) -> String {
+    serde_json::json!({
+        "lang": "rust",
+        "confirm": confirm,
+        "version": VERSION,
+        "support_dir": false,
+        "protocol": PROTOCOL,
+    })
+    .to_string()
+}
+
+/// A line that makes the remote give up, e.g. when the user cancels.
+pub fn fail_line(message: &str) -> Vec<u8> {
+    format!("#fail:{}\n", encode(message.as_bytes())).into_bytes()
+}
+
+/// The answer turning the remote's request down.
+pub fn decline_line() -> Vec<u8> {
+    format!("#ACT:{}\n", encode(action(false).as_bytes())).into_bytes()
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        "file"
+    } else {
+        "files"
+    }
+}
+
+/// Send files to the remote's `trz`, streaming them from disk.
+pub async fn upload(
+    link: &mut Link,
+    paths: &[PathBuf],
+    mut on_event: impl FnMut(Event) + Send,
+) -> Result<Vec<Transferred>, String> {
+    let mut session = Session::start(link).await?;
+    session.send_integer("NUM", paths.len() as u64).await?;
+    session.check_integer(paths.len() as u64).await?;
+
+    let mut sent = Vec::new();
+    for path in paths {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let size = file
+            .metadata()
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+            .len();
+
+        session.send_string("NAME", &name).await?;
+        let remote_name = session.recv_string("SUCC").await?;
+        session.send_integer("SIZE", size).await?;
+        session.check_integer(size).await?;
+        on_event(Event::FileStart {
+            name: name.clone(),
+            size: Some(size),
+        });
+
+        let mut digest = md5::Context::new();
+        let mut chunk = MIN_CHUNK;
+        let mut done = 0u64;
+        while done < size {
+            let started = Instant::now();
+            let mut data = vec![0u8; chunk.min((size - done) as usize)];
+            file.read_exact(&mut data)
+                .await
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            session.send_data(&data).await?;
+            digest.consume(&data);
+            session.check_integer(data.len() as u64).await?;
+            done += data.len() as u64;
+            on_event(Event::Progress {
+                name: name.clone(),
+                bytes: done,
+                size: Some(size),
+            });
+            // Bigger chunks while the remote keeps up, small again when slow
+            let elapsed = started.elapsed();
+            if data.len() == chunk && elapsed < Duration::from_millis(500) {
+                chunk = (chunk * 2).min(session.config.max_chunk);
+            } else if elapsed >= Duration::from_secs(2) {
+                chunk = MIN_CHUNK;
+            }
+        }
+
+        let digest = digest.compute().0;
+        session.send_binary("MD5", &digest).await?;
+        if session.recv_binary("SUCC").await? != digest {
+            return Err(format!("MD5 check of {} failed on the remote", name));
+        }
+        on_event(Event::FileDone {
+            name: name.clone(),
+            bytes: size,
+        });
+        tracing::info!("trzsz: sent {} as {}", name, remote_name);
+        sent.push(Transferred {
+            name,
+            size,
+            path: None,
+        });
+    }
+
+    let names: Vec<&str> = sent.iter().map(|f| f.name.as_str()).collect();
+    session
+        .send_string(
+            "EXIT",
+            &format!("Received {} {}: {}", sent.len(), plural(sent.len()), names.join(", ")),
+        )
+        .await?;
+    Ok(sent)
+}
+
+/// Receive the files the remote's `tsz` sends into `dir`. Names already
+/// taken get a " (n)" suffix unless `overwrite` is set.
+pub async fn download(
+    link: &mut Link,
+    dir: &Path,
+    overwrite: bool,
+    mut on_event: impl FnMut(Event) + Send,
+) -> Result<Vec<Transferred>, String> {
+    let mut session = Session::start(link).await?;
+    let count = session.recv_integer("NUM").await?;
+    session.send_integer("SUCC", count).await?;
+
+    let mut received = Vec::new();
+    for i in 0..count {
+        let remote_name = session.recv_string("NAME").await?;
+        let name = Path::new(&remote_name)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .filter(|n| !n.is_empty())
+            .unwrap_or_else(|| format!("trzsz_recv_{}", i));
+        let mut path = dir.join(&name);
+        if !overwrite && path.exists() {
+            path = crate::zmodem::unique_path(&path);
+        }
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(name);
+        session.send_string("SUCC", &name).await?;
+        let size = session.recv_integer("SIZE").await?;
+        session.send_integer("SUCC", size).await?;
+
+        let mut file = std::fs::File::create(&path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        on_event(Event::FileStart {
+            name: name.clone(),
+            size: Some(size),
+        });
+        let mut digest = md5::Context::new();
+        let mut done = 0u64;
+        while done < size {
+            let data = session.recv_data().await?;
+            file.write_all(&data)
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+            digest.consume(&data);
+            session.send_integer("SUCC", data.len() as u64).await?;
+            done += data.len() as u64;
+            on_event(Event::Progress {
+                name: name.clone(),
+                bytes: done,
+                size: Some(size),
+            });
+        }
+
+        let digest = digest.compute().0;
+        if session.recv_binary("MD5").await? != digest {
+            return Err(format!("MD5 check of {} failed", name));
+        }
+        session.send_binary("SUCC", &digest).await?;
+        on_event(Event::FileDone {
+            name: name.clone(),
+            bytes: done,
+        });
+        received.push(Transferred {
+            name,
+            size: done,
+            path: Some(path),
+        });
+    }
+
+    session
+        .send_string(
+            "EXIT",
+            &format!("Saved {} {} to {}", received.len(), plural(received.len()), dir.display()),
+        )
+        .await?;
+    Ok(received)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xmodem::{link_pair, test_dir};
+
+    /// The remote end: reads our action and answers with `cfg`.
+    async fn remote(link: &mut Link, cfg: serde_json::Value) -> Session<'_> {
+        let mut remote = Session {
+            link,
+            config: Config::default(),
+        };
+        let action = remote.recv_binary("ACT").await.unwrap();
+        let action: serde_json::Value = serde_json::from_slice(&action).unwrap();
+        assert_eq!(action["confirm"], true);
+        remote.send_string("CFG", &cfg.to_string()).await.unwrap();
+        remote.config = Config::parse(cfg.to_string().as_bytes()).unwrap();
+        remote
+    }
+
+    fn binary_config() -> serde_json::Value {
+        serde_json::json!({
+            "binary": true,
+            "escape_chars": [["\u{ee}", "\u{ee}\u{ee}"], ["~", "\u{ee}1"], ["\u{18}", "\u{ee}A"]],
+            "max_buffer_size": 4096
+        })
+    }
+
+    #[test]
+    fn test_detect() {
+        let line = b"$ tsz a.txt\r\n\x1b7\x07::TRZSZ:TRANSFER:S:1.1.6:1234567890100\r\n";
+        let expected = Detected {
+            mode: Mode::Download,
+            version: "1.1.6".into(),
+            offset: 16,
+        };
+        assert_eq!(Detector::new().feed(line), Some(expected));
+
+        // Split anywhere, it is found once the line is complete
+        for split in 1..line.len() - 2 {
+            let mut detector = Detector::new();
+            let (first, second) = line.split_at(split);
+            assert_eq!(detector.feed(first), None, "split at {}", split);
+            let found = detector.feed(second).unwrap();
+            assert_eq!(found.mode, Mode::Download);
+            assert_eq!(found.offset, 16usize.saturating_sub(split));
+        }
+
+        assert_eq!(Detector::new().feed(b"::TRZSZ:TRANSFER:X:1.1.6\r\n"), None);
+        assert_eq!(Detector::new().feed(b"echo ::TRZSZ:TRANSFER:R:1.1\r\n"), None);
+        let found = Detector::new().feed(b"::TRZSZ:TRANSFER:R:1.1.3\n").unwrap();
+        assert_eq!((found.mode, found.offset), (Mode::Upload, 0));
+    }
+
+    #[test]
+    fn test_encoding() {
+        let data: Vec<u8> = (0..2000u32).map(|i| (i * 31 % 256) as u8).collect();
+        assert_eq!(decode(encode(&data).as_bytes(), 2000).unwrap(), data);
+        assert!(decode(encode(&data).as_bytes(), 1999).is_err());
+        assert!(decode(b"not base64!", 2000).is_err());
+
+        let config = Config::parse(binary_config().to_string().as_bytes()).unwrap();
+        let escaped = escape(b"a~\xee\x18b", &config.escapes);
+        assert_eq!(escaped, b"a\xee1\xee\xee\xeeAb");
+        assert_eq!(unescape(&escaped, &config.escapes).unwrap(), b"a~\xee\x18b");
+        assert!(unescape(b"\xeeZ", &config.escapes).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_junk_lines() {
+        let (mut ours, theirs) = link_pair();
+        let mut session = Session {
+            link: &mut ours,
+            config: Config {
+                junk: true,
+                ..Config::default()
+            },
+        };
+        // Wrapped at the pane width, with cursor movement mixed in
+        theirs.send(b"\x1b[2;1H#SUCC:12\r\n\x1b[?25l34\n".to_vec()).await.unwrap();
+        assert_eq!(session.recv_integer("SUCC").await.unwrap(), 1234);
+
+        let fail = format!("#fail:{}\n", encode(b"No space left"));
+        theirs.send(fail.into_bytes()).await.unwrap();
+        assert_eq!(session.recv_integer("SUCC").await.unwrap_err(), "No space left");
+    }
+
+    #[tokio::test]
+    async fn test_oversized_data() {
+        let (mut ours, theirs) = link_pair();
+        let mut session = Session {
+            link: &mut ours,
+            config: Config::parse(binary_config().to_string().as_bytes()).unwrap(),
+        };
+        // Chunks and lines are bounded by the chunk size agreed on
+        theirs.send(b"#DATA:8193\n".to_vec()).await.unwrap();
+        assert!(session.recv_data().await.is_err());
+        let mut line = b"#SUCC:".to_vec();
+        line.resize(8192 + 16, b'1');
+        theirs.send(line).await.unwrap();
+        assert_eq!(
+            session.recv_integer("SUCC").await.unwrap_err(),
+            "Line from the remote is too long"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        let dir = test_dir("trzsz-upload");
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        std::fs::write(dir.join("fw.bin"), &data).unwrap();
+        let (mut ours, mut theirs) = link_pair();
+
+        let server = tokio::spawn(async move {
+            let mut remote = remote(&mut theirs, binary_config()).await;
+            let count = remote.recv_integer("NUM").await.unwrap();
+            remote.send_integer("SUCC", count).await.unwrap();
+            let name = remote.recv_string("NAME").await.unwrap();
+            remote.send_string("SUCC", &name).await.unwrap();
+            let size = remote.recv_integer("SIZE").await.unwrap();
+            remote.send_integer("SUCC", size).await.unwrap();
+            let mut received = Vec::new();
+            while (received.len() as u64) < size {
+                let chunk = remote.recv_data().await.unwrap();
+                remote.send_integer("SUCC", chunk.len() as u64).await.unwrap();
+                received.extend(chunk);
+            }
+            let digest = remote.recv_binary("MD5").await.unwrap();
+            remote.send_binary("SUCC", &digest).await.unwrap();
+            let exit = remote.recv_string("EXIT").await.unwrap();
+            (name, received, digest, exit)
+        });
+
+        let mut events = Vec::new();
+        let sent = upload(&mut ours, &[dir.join("fw.bin")], |e| events.push(e))
+            .await
+            .unwrap();
+        let (name, received, digest, exit) = server.await.unwrap();
+        assert_eq!(name, "fw.bin");
+        assert_eq!(received, data);
+        assert_eq!(digest, md5::compute(&data).0);
+        assert_eq!(exit, "Received 1 file: fw.bin");
+        assert_eq!(sent[0].size, 20_000);
+        assert_eq!(
+            events.last(),
+            Some(&Event::FileDone {
+                name: "fw.bin".into(),
+                bytes: 20_000
+            })
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download() {
+        let dir = test_dir("trzsz-download");
+        std::fs::write(dir.join("log.txt"), b"older").unwrap();
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * 13 % 256) as u8).collect();
+        let (mut ours, mut theirs) = link_pair();
+
+        let to_send = data.clone();
+        let server = tokio::spawn(async move {
+            let mut remote = remote(&mut theirs, serde_json::json!({ "binary": false })).await;
+            remote.send_integer("NUM", 2).await.unwrap();
+            remote.check_integer(2).await.unwrap();
+            let mut local_names = Vec::new();
+            for (name, data) in [("/var/log/log.txt", &to_send[..]), ("empty", &[][..])] {
+                remote.send_string("NAME", name).await.unwrap();
+                local_names.push(remote.recv_string("SUCC").await.unwrap());
+                remote.send_integer("SIZE", data.len() as u64).await.unwrap();
+                remote.check_integer(data.len() as u64).await.unwrap();
+                for chunk in data.chunks(1500) {
+                    remote.send_data(chunk).await.unwrap();
+                    remote.check_integer(chunk.len() as u64).await.unwrap();
+                }
+                let digest = md5::compute(data).0;
+                remote.send_binary("MD5", &digest).await.unwrap();
+                assert_eq!(remote.recv_binary("SUCC").await.unwrap(), digest);
+            }
+            let exit = remote.recv_string("EXIT").await.unwrap();
+            (local_names, exit)
+        });
+
+        let received = download(&mut ours, &dir, false, |_| {}).await.unwrap();
+        let (local_names, exit) = server.await.unwrap();
+        // The existing file is kept
+        assert_eq!(local_names, ["log (1).txt", "empty"]);
+        assert!(exit.starts_with("Saved 2 files to "));
+        assert_eq!(std::fs::read(dir.join("log.txt")).unwrap(), b"older");
+        assert_eq!(std::fs::read(dir.join("log (1).txt")).unwrap(), data);
+        assert_eq!(received[1].path, Some(dir.join("empty")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,486 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::Instant;
+
+use crate::script::MatchBuffer;
+
+/// Largest file accepted for upload.
+pub const MAX_UPLOAD: u64 = 64 * 1024 * 1024;
+
+/// How often progress is reported while sending.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadMode {
+    /// The file's bytes as they are, in chunks
+    #[default]
+    Raw,
+    /// Text, one line at a time, with line endings converted
+    Ascii,
+    /// Intel HEX or S-records, checked before sending, one record a line
+    Hex,
+}
+
+impl UploadMode {
+    /// What a piece of the file is called in messages.
+    fn piece_name(self) -> &'static str {
+        match self {
+            UploadMode::Raw => "chunk",
+            UploadMode::Ascii => "line",
+            UploadMode::Hex => "record",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    #[default]
+    Cr,
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    fn bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::Cr => b"\r",
+            LineEnding::Lf => b"\n",
+            LineEnding::Crlf => b"\r\n",
+        }
+    }
+}
+
+/// How to send a file.
+#[derive(Deserialize, Clone, Debug)]
+pub struct UploadOptions {
+    #[serde(default)]
+    pub mode: UploadMode,
+    /// Bytes per write in raw mode
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+    /// Pause after each character
+    #[serde(default)]
+    pub char_delay_ms: u64,
+    /// Pause after each line (ASCII) or chunk (raw)
+    #[serde(default)]
+    pub line_delay_ms: u64,
+    /// Sent at the end of each line in ASCII mode
+    #[serde(default)]
+    pub line_ending: LineEnding,
+    /// ASCII and HEX modes: regex to wait for after each line before
+    /// sending the next
+    #[serde(default)]
+    pub prompt: Option<String>,
+    #[serde(default = "default_prompt_timeout")]
+    pub prompt_timeout_ms: u64,
+    /// Regex for the device turning a line down, watched for with the prompt
+    #[serde(default)]
+    pub reject: Option<String>,
+    /// HEX mode: addresses the data must stay within
+    #[serde(default)]
+    pub min_address: Option<u32>,
+    #[serde(default)]
+    pub max_address: Option<u32>,
+}
+
+/// The compiled patterns of `UploadOptions`.
+#[derive(Default)]
+pub struct Patterns {
+    pub prompt: Option<Regex>,
+    pub reject: Option<Regex>,
+}
+
+fn default_chunk_size() -> usize {
+    256
+}
+
+fn default_prompt_timeout() -> u64 {
+    5000
+}
+
+impl UploadOptions {
+    /// Check the options, compiling the prompt and reject patterns.
+    pub fn validate(&self) -> Result<Patterns, String> {
+        if self.chunk_size == 0 {
+            return Err("Chunk size must be at least 1 byte".to_string());
+        }
+        let prompt = match self.prompt.as_deref() {
+            Some(_) if self.mode == UploadMode::Raw => {
+                return Err("Waiting for a prompt needs ASCII or HEX mode".to_string());
+            }
+            Some(_) if self.prompt_timeout_ms == 0 => {
+                return Err("Prompt timeout must be at least 1 ms".to_string());
+            }
+            Some(p) if !p.is_empty() => {
+                Some(Regex::new(p).map_err(|e| format!("Invalid prompt: {}", e))?)
+            }
+            _ => None,
+        };
+        let reject = match self.reject.as_deref() {
+            Some(r) if !r.is_empty() => {
+                if prompt.is_none() {
+                    return Err("Watching for rejections needs a prompt".to_string());
+                }
+                Some(Regex::new(r).map_err(|e| format!("Invalid reject pattern: {}", e))?)
+            }
+            _ => None,
+        };
+        if let Some((low, high)) = self.address_range() {
+            if low > high {
+                return Err("The address range ends before it starts".to_string());
+            }
+        }
+        Ok(Patterns { prompt, reject })
+    }
+
+    /// HEX mode: the addresses allowed, if limited.
+    pub fn address_range(&self) -> Option<(u32, u32)> {
+        match (self.min_address, self.max_address) {
+            (None, None) => None,
+            (low, high) => Some((low.unwrap_or(0), high.unwrap_or(u32::MAX))),
+        }
+    }
+}
+
+/// One piece of a file as written: part of the file, then the line ending
+/// in ASCII and HEX modes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Piece<'a> {
+    pub data: &'a [u8],
+    pub ending: &'static [u8],
+}
+
+impl Piece<'_> {
+    fn size(&self) -> usize {
+        self.data.len() + self.ending.len()
+    }
+
+    fn to_vec(self) -> Vec<u8> {
+        [self.data, self.ending].concat()
+    }
+}
+
+/// The pieces of a file, cut as they are sent so that nothing is copied
+/// up front.
+#[derive(Clone)]
+pub struct Pieces<'a> {
+    rest: &'a [u8],
+    mode: UploadMode,
+    chunk_size: usize,
+    ending: &'static [u8],
+}
+
+/// Cut a file into the pieces written one after another: chunks in raw
+/// mode, lines with the configured ending in ASCII mode, and the same
+/// without blank lines (so one record a line) in HEX mode.
+pub fn split<'a>(data: &'a [u8], options: &UploadOptions) -> Pieces<'a> {
+    Pieces {
+        rest: data,
+        mode: options.mode,
+        chunk_size: options.chunk_size.max(1),
+        ending: options.line_ending.bytes(),
+    }
+}
+
+impl<'a> Iterator for Pieces<'a> {
+    type Item = Piece<'a>;
+
+    fn next(&mut self) -> Option<Piece<'a>> {
+        while !self.rest.is_empty() {
+            if self.mode == UploadMode::Raw {
+                let (chunk, rest) = self.rest.split_at(self.chunk_size.min(self.rest.len()));
+                self.rest = rest;
+                return Some(Piece {
+                    data: chunk,
+                    ending: b"",
+                });
+            }
+            // A final newline ends the last line rather than starting an
+            // empty one
+            let (line, rest) = match self.rest.iter().position(|&b| b == b'\n') {
+                Some(i) => (&self.rest[..i], &self.rest[i + 1..]),
+                None => (self.rest, &[][..]),
+            };
+            self.rest = rest;
+            if self.mode == UploadMode::Hex && line.trim_ascii().is_empty() {
+                continue;
+            }
+            return Some(Piece {
+                data: line.strip_suffix(b"\r").unwrap_or(line),
+                ending: self.ending,
+            });
+        }
+        None
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Progress of an upload, as reported in status and notifications.
+#[derive(Serialize, Clone, Debug)]
+pub struct UploadStatus {
+    pub filename: String,
+    pub mode: UploadMode,
+    pub state: UploadState,
+    /// Bytes written, including converted line endings
+    pub sent: u64,
+    pub total: u64,
+    /// Lines (ASCII) or chunks (raw) written
+    pub pieces_sent: usize,
+    pub pieces_total: usize,
+    pub elapsed_ms: u64,
+    pub message: Option<String>,
+    /// HEX mode: the record the device didn't take
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_record: Option<crate::hexfile::Record>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl UploadStatus {
+    pub fn new(filename: &str, mode: UploadMode, pieces: Pieces) -> Self {
+        let (total, pieces_total) = pieces.fold((0, 0), |(total, count), piece| {
+            (total + piece.size() as u64, count + 1)
+        });
+        UploadStatus {
+            filename: filename.to_string(),
+            mode,
+            state: UploadState::Running,
+            sent: 0,
+            total,
+            pieces_sent: 0,
+            pieces_total,
+            elapsed_ms: 0,
+            message: None,
+            failed_record: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// Record how the upload ended.
+    pub fn finish(&mut self, state: UploadState, message: Option<String>) {
+        self.state = state;
+        self.message = message;
+        self.elapsed_ms = self.started.elapsed().as_millis() as u64;
+    }
+}
+
+/// Write `pieces` to the connection with the pacing in `options`. `rx` is
+/// the tab's output, watched for the prompt and rejections in `patterns`;
+/// `is_notification` tells apart frames that aren't device output.
+/// `progress` is called with the status every so often while sending.
+#[allow(clippy::too_many_arguments)]
+pub async fn send(
+    pieces: Pieces<'_>,
+    options: &UploadOptions,
+    patterns: &Patterns,
+    write_tx: &mpsc::Sender<Vec<u8>>,
+    mut rx: broadcast::Receiver<Vec<u8>>,
+    is_notification: fn(&[u8]) -> bool,
+    status: &Mutex<UploadStatus>,
+    progress: impl Fn(&UploadStatus),
+) -> Result<(), String> {
+    let mut last_progress = Instant::now();
+    let mut buffer = MatchBuffer::new();
+    let char_delay = Duration::from_millis(options.char_delay_ms);
+    let line_delay = Duration::from_millis(options.line_delay_ms);
+    let closed = || "Connection closed".to_string();
+
+    for (i, piece) in pieces.enumerate() {
+        // Only output that follows this line can answer it
+        while let Ok(data) = rx.try_recv() {
+            if !is_notification(&data) {
+                buffer.push(&data);
+            }
+        }
+        buffer.clear();
+
+        if char_delay.is_zero() {
+            write_tx.send(piece.to_vec()).await.map_err(|_| closed())?;
+        } else {
+            for &b in piece.data.iter().chain(piece.ending) {
+                write_tx.send(vec![b]).await.map_err(|_| closed())?;
+                tokio::time::sleep(char_delay).await;
+            }
+        }
+
+        if let Some(prompt) = &patterns.prompt {
+            let deadline = Instant::now() + Duration::from_millis(options.prompt_timeout_ms);
+            let watched: Vec<Regex> = std::iter::once(prompt)
+                .chain(&patterns.reject)
+                .cloned()
+                .collect();
+            loop {
+                match buffer.take_match_text(&watched) {
+                    Some((1, _)) => break,
+                    Some((_, answer)) => {
+                        return Err(format!(
+                            "The device rejected {} {}: {}",
+                            options.mode.piece_name(),
+                            i + 1,
+                            answer.trim()
+                        ));
+                    }
+                    None => {}
+                }
+                match tokio::time::timeout_at(deadline, rx.recv()).await {
+                    Err(_) => {
+                        return Err(format!(
+                            "No prompt after {} {}",
+                            options.mode.piece_name(),
+                            i + 1
+                        ));
+                    }
+                    Ok(Ok(data)) => {
+                        if !is_notification(&data) {
+                            buffer.push(&data);
+                        }
+                    }
+                    Ok(Err(broadcast::error::RecvError::Lagged(_))) => {}
+                    Ok(Err(broadcast::error::RecvError::Closed)) => return Err(closed()),
+                }
+            }
+        }
+        if !line_delay.is_zero() {
+            tokio::time::sleep(line_delay).await;
+        }
+
+        let mut status = status.lock().unwrap();
+        status.sent += piece.size() as u64;
+        status.pieces_sent = i + 1;
+        status.elapsed_ms = status.started.elapsed().as_millis() as u64;
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            progress(&status);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(json: &str) -> UploadOptions {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn pieces(data: &[u8], options: &UploadOptions) -> Vec<Vec<u8>> {
+        split(data, options).map(Piece::to_vec).collect()
+    }
+
+    #[test]
+    fn test_split() {
+        let raw = options(r#"{"chunk_size": 4}"#);
+        assert_eq!(pieces(b"0123456789", &raw), [&b"0123"[..], b"4567", b"89"]);
+
+        let ascii = options(r#"{"mode": "ascii", "line_ending": "crlf"}"#);
+        assert_eq!(
+            pieces(b"a\r\nb\n\nc\n", &ascii),
+            [&b"a\r\n"[..], b"b\r\n", b"\r\n", b"c\r\n"]
+        );
+        assert_eq!(pieces(b"no newline", &ascii), [b"no newline\r\n"]);
+
+        assert!(options(r#"{"prompt": "> "}"#).validate().is_err());
+        assert!(options(r#"{"mode": "ascii", "prompt": "("}"#)
+            .validate()
+            .is_err());
+        assert!(options(r#"{"chunk_size": 0}"#).validate().is_err());
+
+        let hex = options(r#"{"mode": "hex", "reject": "ERR"}"#);
+        assert_eq!(pieces(b":00000001FF\n\n", &hex), [b":00000001FF\r"]);
+        assert!(hex.validate().is_err());
+        assert!(options(r#"{"min_address": 16, "max_address": 15}"#)
+            .validate()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_waits_for_prompt() {
+        let opts = options(r#"{"mode": "ascii", "prompt": "> $", "prompt_timeout_ms": 200}"#);
+        let patterns = opts.validate().unwrap();
+        let data = b"one\ntwo\nthree\n";
+        let (write_tx, mut write_rx) = mpsc::channel(16);
+        let (out_tx, out_rx) = broadcast::channel(16);
+
+        // A device that echoes and prompts after two lines, then goes quiet
+        let device = tokio::spawn(async move {
+            for _ in 0..2 {
+                let line = write_rx.recv().await.unwrap();
+                out_tx.send(line).unwrap();
+                out_tx.send(b"\x1b[1m> \x1b[0m".to_vec()).unwrap();
+            }
+            write_rx.recv().await.unwrap();
+            out_tx
+        });
+
+        let status = Mutex::new(UploadStatus::new("f.txt", opts.mode, split(data, &opts)));
+        let result = send(
+            split(data, &opts),
+            &opts,
+            &patterns,
+            &write_tx,
+            out_rx,
+            |_| false,
+            &status,
+            |_| {},
+        )
+        .await;
+        assert_eq!(result, Err("No prompt after line 3".to_string()));
+        let status = status.into_inner().unwrap();
+        assert_eq!(status.pieces_sent, 2);
+        assert_eq!(status.sent, 8);
+        assert_eq!(status.total, 14);
+        drop(device.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_send_stops_on_reject() {
+        let opts = options(r#"{"mode": "hex", "prompt": "OK", "reject": "ERR \\d+"}"#);
+        let patterns = opts.validate().unwrap();
+        let data = b":00\n:01\n:02\n";
+        let (write_tx, mut write_rx) = mpsc::channel(16);
+        let (out_tx, out_rx) = broadcast::channel(16);
+
+        // Takes the first record and turns down the second
+        let device = tokio::spawn(async move {
+            write_rx.recv().await.unwrap();
+            out_tx.send(b"OK\r\n".to_vec()).unwrap();
+            write_rx.recv().await.unwrap();
+            out_tx.send(b"ERR 7\r\n".to_vec()).unwrap();
+            out_tx
+        });
+
+        let status = Mutex::new(UploadStatus::new("f.hex", opts.mode, split(data, &opts)));
+        let result = send(
+            split(data, &opts),
+            &opts,
+            &patterns,
+            &write_tx,
+            out_rx,
+            |_| false,
+            &status,
+            |_| {},
+        )
+        .await;
+        assert_eq!(
+            result,
+            Err("The device rejected record 2: ERR 7".to_string())
+        );
+        assert_eq!(status.into_inner().unwrap().pieces_sent, 1);
+        drop(device.await.unwrap());
+    }
+}
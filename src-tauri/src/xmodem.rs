@@ -0,0 +1,725 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// Padding of the last block (CP/M end of file)
+const SUB: u8 = 0x1a;
+/// Sent by a receiver that wants CRC-16 instead of checksums
+const CRC_REQUEST: u8 = b'C';
+
+/// Attempts per block (and per start request) before giving up.
+const MAX_RETRIES: usize = 10;
+/// How long a sender waits for the receiver to start.
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long to wait for a reply to a block, or for the next block.
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often a receiver repeats its start request.
+const SOLICIT_INTERVAL: Duration = Duration::from_secs(3);
+/// Longest gap between bytes within a block.
+const BYTE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// 128-byte blocks, CRC or checksum
+    Xmodem,
+    /// 1024-byte blocks, CRC
+    #[serde(rename = "xmodem-1k")]
+    Xmodem1k,
+    /// Batch of named files, 1024-byte blocks, CRC
+    Ymodem,
+}
+
+impl Protocol {
+    pub fn name(self) -> &'static str {
+        match self {
+            Protocol::Xmodem | Protocol::Xmodem1k => "xmodem",
+            Protocol::Ymodem => "ymodem",
+        }
+    }
+
+    fn block_size(self) -> usize {
+        match self {
+            Protocol::Xmodem => 128,
+            Protocol::Xmodem1k | Protocol::Ymodem => 1024,
+        }
+    }
+}
+
+/// Progress of a transfer, reported as it goes.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    FileStart {
+        name: String,
+        size: Option<u64>,
+    },
+    Progress {
+        name: String,
+        bytes: u64,
+        size: Option<u64>,
+    },
+    FileDone {
+        name: String,
+        bytes: u64,
+    },
+}
+
+/// A file sent or received.
+#[derive(Debug, PartialEq)]
+pub struct Transferred {
+    pub name: String,
+    pub size: u64,
+    /// Where a received file was saved
+    pub path: Option<PathBuf>,
+}
+
+/// CRC-16/XMODEM (polynomial 0x1021, initial value 0).
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// The connection a transfer runs over: bytes from the device arrive on
+/// `rx`, bytes for the device go to `tx`.
+pub struct Link {
+    rx: mpsc::Receiver<Vec<u8>>,
+    tx: mpsc::Sender<Vec<u8>>,
+    pending: VecDeque<u8>,
+}
+
+impl Link {
+    pub fn new(rx: mpsc::Receiver<Vec<u8>>, tx: mpsc::Sender<Vec<u8>>) -> Self {
+        Link {
+            rx,
+            tx,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub async fn send(&self, data: Vec<u8>) -> Result<(), String> {
+        self.tx
+            .send(data)
+            .await
+            .map_err(|_| "Connection closed".to_string())
+    }
+
+    /// Next byte from the device, or `None` after `timeout`.
+    pub async fn byte(&mut self, timeout: Duration) -> Result<Option<u8>, String> {
+        if self.pending.is_empty() {
+            match tokio::time::timeout(timeout, self.rx.recv()).await {
+                Err(_) => return Ok(None),
+                Ok(None) => return Err("Connection closed".to_string()),
+                Ok(Some(data)) => self.pending.extend(data),
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    /// Drop whatever was received but not read yet.
+    pub fn purge(&mut self) {
+        self.pending.clear();
+        while self.rx.try_recv().is_ok() {}
+    }
+
+    /// A second CAN right after the first one means the other side gave up.
+    async fn cancelled(&mut self) -> Result<bool, String> {
+        Ok(self.byte(BYTE_TIMEOUT).await? == Some(CAN))
+    }
+
+    /// Tell the other side to give up.
+    pub async fn cancel(&self) {
+        let _ = self.send(vec![CAN; 8]).await;
+    }
+}
+
+/// Two links joined to each other, for testing a transfer's two ends.
+#[cfg(test)]
+pub(crate) fn link_pair() -> (Link, Link) {
+    let (a_tx, a_rx) = mpsc::channel(64);
+    let (b_tx, b_rx) = mpsc::channel(64);
+    (Link::new(a_rx, b_tx), Link::new(b_rx, a_tx))
+}
+
+/// A temporary directory for a test's files.
+#[cfg(test)]
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("serial-rs-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn cancelled_by_remote() -> String {
+    "Cancelled by the other side".to_string()
+}
+
+// ---------------------------------------------------------------------------
+// Sender
+// ---------------------------------------------------------------------------
+
+/// Wait for the receiver to ask for a transfer. Returns whether it wants
+/// CRC-16.
+async fn wait_start(link: &mut Link) -> Result<bool, String> {
+    let deadline = tokio::time::Instant::now() + START_TIMEOUT;
+    loop {
+        let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+        match link.byte(left).await? {
+            None => return Err("The receiver didn't start".to_string()),
+            Some(CRC_REQUEST) => return Ok(true),
+            Some(NAK) => return Ok(false),
+            Some(CAN) if link.cancelled().await? => return Err(cancelled_by_remote()),
+            Some(_) => {}
+        }
+    }
+}
+
+/// Wait for ACK or NAK; `Ok(false)` means send again.
+async fn wait_ack(link: &mut Link) -> Result<bool, String> {
+    loop {
+        match link.byte(BLOCK_TIMEOUT).await? {
+            None | Some(NAK) => return Ok(false),
+            Some(ACK) => return Ok(true),
+            Some(CAN) if link.cancelled().await? => return Err(cancelled_by_remote()),
+            Some(_) => {}
+        }
+    }
+}
+
+/// Header blocks are padded with zeros, data blocks with SUB.
+fn block_packet(seq: u8, data: &[u8], pad: u8, crc: bool) -> Vec<u8> {
+    let size = if data.len() > 128 { 1024 } else { 128 };
+    let mut packet = Vec::with_capacity(size + 5);
+    packet.extend([if size == 1024 { STX } else { SOH }, seq, !seq]);
+    packet.extend_from_slice(data);
+    packet.resize(size + 3, pad);
+    let payload = &packet[3..];
+    if crc {
+        let crc = crc16(payload);
+        packet.extend(crc.to_be_bytes());
+    } else {
+        packet.push(checksum(payload));
+    }
+    packet
+}
+
+async fn send_block(
+    link: &mut Link,
+    seq: u8,
+    data: &[u8],
+    pad: u8,
+    crc: bool,
+) -> Result<(), String> {
+    let packet = block_packet(seq, data, pad, crc);
+    for _ in 0..MAX_RETRIES {
+        link.send(packet.clone()).await?;
+        if wait_ack(link).await? {
+            return Ok(());
+        }
+        link.purge();
+    }
+    link.cancel().await;
+    Err(format!("Block {} not acknowledged", seq))
+}
+
+async fn send_eot(link: &mut Link) -> Result<(), String> {
+    for _ in 0..MAX_RETRIES {
+        link.send(vec![EOT]).await?;
+        if wait_ack(link).await? {
+            return Ok(());
+        }
+    }
+    Err("End of file not acknowledged".to_string())
+}
+
+async fn send_data(
+    link: &mut Link,
+    name: &str,
+    data: &[u8],
+    block_size: usize,
+    crc: bool,
+    on_event: &mut (impl FnMut(Event) + Send),
+) -> Result<(), String> {
+    let size = Some(data.len() as u64);
+    let mut seq = 1u8;
+    let mut offset = 0;
+    while offset < data.len() {
+        // Finish with a short block rather than padding most of a long one
+        let block = if data.len() - offset <= 128 {
+            128
+        } else {
+            block_size
+        };
+        let end = (offset + block).min(data.len());
+        send_block(link, seq, &data[offset..end], SUB, crc).await?;
+        offset = end;
+        seq = seq.wrapping_add(1);
+        on_event(Event::Progress {
+            name: name.to_string(),
+            bytes: offset as u64,
+            size,
+        });
+    }
+    send_eot(link).await
+}
+
+/// Block 0 of a YMODEM file: its base name (never the local directory it
+/// sits in) and size, which must fit in one 1024-byte block.
+fn ymodem_header(name: &str, size: u64) -> Result<Vec<u8>, String> {
+    let base = Path::new(name)
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .ok_or_else(|| format!("{} is not a file name", name))?;
+    let mut header = base.as_bytes().to_vec();
+    header.push(0);
+    header.extend(size.to_string().into_bytes());
+    if header.len() > 1024 {
+        return Err(format!("The name {} is too long for a YMODEM header", base));
+    }
+    Ok(header)
+}
+
+/// Send `files` (name and contents). XMODEM sends only the first file; its
+/// name isn't transferred.
+pub async fn send(
+    protocol: Protocol,
+    files: &[(String, Vec<u8>)],
+    link: &mut Link,
+    mut on_event: impl FnMut(Event) + Send,
+) -> Result<Vec<Transferred>, String> {
+    // Refuse names that can't be sent before anything goes out
+    let headers = match protocol {
+        Protocol::Ymodem => files
+            .iter()
+            .map(|(name, data)| ymodem_header(name, data.len() as u64).map(Some))
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => vec![None; files.len()],
+    };
+    let mut sent = Vec::new();
+    for ((name, data), header) in files.iter().zip(headers) {
+        let crc = wait_start(link).await?;
+        on_event(Event::FileStart {
+            name: name.clone(),
+            size: Some(data.len() as u64),
+        });
+        let crc = match header {
+            Some(header) => {
+                send_block(link, 0, &header, 0, crc).await?;
+                wait_start(link).await?
+            }
+            None => crc,
+        };
+        send_data(link, name, data, protocol.block_size(), crc, &mut on_event).await?;
+        on_event(Event::FileDone {
+            name: name.clone(),
+            bytes: data.len() as u64,
+        });
+        sent.push(Transferred {
+            name: name.clone(),
+            size: data.len() as u64,
+            path: None,
+        });
+        if protocol != Protocol::Ymodem {
+            break;
+        }
+    }
+    if protocol == Protocol::Ymodem {
+        // An empty header ends the batch
+        let crc = wait_start(link).await?;
+        send_block(link, 0, &[], 0, crc).await?;
+    }
+    Ok(sent)
+}
+
+// ---------------------------------------------------------------------------
+// Receiver
+// ---------------------------------------------------------------------------
+
+enum Packet {
+    Block {
+        seq: u8,
+        data: Vec<u8>,
+    },
+    Eot,
+    /// Nothing arrived in time
+    Timeout,
+    /// Garbled or incomplete
+    Bad,
+}
+
+async fn read_packet(link: &mut Link, crc: bool, timeout: Duration) -> Result<Packet, String> {
+    let size = match link.byte(timeout).await? {
+        None => return Ok(Packet::Timeout),
+        Some(SOH) => 128,
+        Some(STX) => 1024,
+        Some(EOT) => return Ok(Packet::Eot),
+        Some(CAN) if link.cancelled().await? => return Err(cancelled_by_remote()),
+        Some(_) => return Ok(Packet::Bad),
+    };
+    let trailer = if crc { 2 } else { 1 };
+    let mut packet = Vec::with_capacity(size + 2 + trailer);
+    while packet.len() < size + 2 + trailer {
+        match link.byte(BYTE_TIMEOUT).await? {
+            Some(b) => packet.push(b),
+            None => return Ok(Packet::Bad),
+        }
+    }
+    let (seq, complement) = (packet[0], packet[1]);
+    let data = &packet[2..2 + size];
+    let valid = if crc {
+        crc16(data).to_be_bytes() == packet[2 + size..]
+    } else {
+        checksum(data) == packet[2 + size]
+    };
+    if seq != !complement || !valid {
+        return Ok(Packet::Bad);
+    }
+    Ok(Packet::Block {
+        seq,
+        data: data.to_vec(),
+    })
+}
+
+/// Ask the sender to start and read the first block. XMODEM falls back
+/// to checksums if CRC requests go unanswered. Returns `None` if the
+/// sender ended the transfer instead.
+async fn solicit(
+    link: &mut Link,
+    checksum_fallback: bool,
+    crc: &mut bool,
+) -> Result<Option<(u8, Vec<u8>)>, String> {
+    let tries = START_TIMEOUT.as_secs() / SOLICIT_INTERVAL.as_secs();
+    for attempt in 0..tries {
+        *crc = !(checksum_fallback && attempt >= 3);
+        link.send(vec![if *crc { CRC_REQUEST } else { NAK }])
+            .await?;
+        match read_packet(link, *crc, SOLICIT_INTERVAL).await? {
+            Packet::Block { seq, data } => return Ok(Some((seq, data))),
+            Packet::Eot => {
+                link.send(vec![ACK]).await?;
+                return Ok(None);
+            }
+            Packet::Timeout => {}
+            Packet::Bad => link.purge(),
+        }
+    }
+    Err("The sender didn't start".to_string())
+}
+
+/// Read data blocks until EOT, passing each new one to `on_block`.
+async fn receive_blocks(
+    link: &mut Link,
+    crc: bool,
+    mut first: Option<(u8, Vec<u8>)>,
+    mut on_block: impl FnMut(Vec<u8>) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut expected = 1u8;
+    let mut errors = 0;
+    loop {
+        let packet = match first.take() {
+            Some((seq, data)) => Packet::Block { seq, data },
+            None => read_packet(link, crc, BLOCK_TIMEOUT).await?,
+        };
+        match packet {
+            Packet::Block { seq, data } if seq == expected => {
+                if let Err(e) = on_block(data) {
+                    link.cancel().await;
+                    return Err(e);
+                }
+                link.send(vec![ACK]).await?;
+                expected = expected.wrapping_add(1);
+                errors = 0;
+            }
+            // Our ACK was lost; the sender repeated the block
+            Packet::Block { seq, .. } if seq == expected.wrapping_sub(1) => {
+                link.send(vec![ACK]).await?;
+            }
+            Packet::Block { seq, .. } => {
+                link.cancel().await;
+                return Err(format!(
+                    "Block {} out of sequence, expected {}",
+                    seq, expected
+                ));
+            }
+            Packet::Eot => {
+                link.send(vec![ACK]).await?;
+                return Ok(());
+            }
+            Packet::Timeout | Packet::Bad => {
+                errors += 1;
+                if errors >= MAX_RETRIES {
+                    link.cancel().await;
+                    return Err(format!("Too many errors at block {}", expected));
+                }
+                link.purge();
+                link.send(vec![NAK]).await?;
+            }
+        }
+    }
+}
+
+/// Create `name` in `dir`, or a free " (n)" variant of it if the name is
+/// taken and `overwrite` isn't set. Returns the path and the name used.
+fn create_file(
+    dir: &Path,
+    name: &str,
+    overwrite: bool,
+) -> Result<(PathBuf, String, std::fs::File), String> {
+    let mut path = dir.join(name);
+    if !overwrite && path.exists() {
+        path = crate::zmodem::unique_path(&path);
+    }
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| name.to_string());
+    let file = std::fs::File::create(&path)
+        .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    Ok((path, name, file))
+}
+
+/// Take only the base name of a name sent by the remote side.
+fn safe_name(name: &str, fallback: &str) -> String {
+    Path::new(name)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| fallback.to_string())
+}
+
+/// Parse a YMODEM header block: name, then optionally size.
+fn parse_header(data: &[u8]) -> (String, Option<u64>) {
+    let mut fields = data.split(|&b| b == 0);
+    let name = String::from_utf8_lossy(fields.next().unwrap_or_default()).to_string();
+    let size = fields
+        .next()
+        .and_then(|info| std::str::from_utf8(info).ok())
+        .and_then(|info| info.split(' ').next())
+        .and_then(|size| size.parse().ok());
+    (name, size)
+}
+
+/// Receive files into `dir`. XMODEM carries no name, so the file is saved
+/// as `xmodem_name`; its padding at the end is removed. Names already
+/// taken get a " (n)" suffix unless `overwrite` is set.
+pub async fn receive(
+    protocol: Protocol,
+    dir: &Path,
+    xmodem_name: &str,
+    overwrite: bool,
+    link: &mut Link,
+    mut on_event: impl FnMut(Event) + Send,
+) -> Result<Vec<Transferred>, String> {
+    let mut received = Vec::new();
+    let mut crc = true;
+
+    if protocol != Protocol::Ymodem {
+        let first = solicit(link, true, &mut crc).await?;
+        let name = safe_name(xmodem_name, "xmodem.bin");
+        let (path, name, mut file) = create_file(dir, &name, overwrite)?;
+        on_event(Event::FileStart {
+            name: name.clone(),
+            size: None,
+        });
+        // Hold back the latest block: padding is only removed from the last
+        let mut held: Option<Vec<u8>> = None;
+        let mut bytes = 0u64;
+        if first.is_some() {
+            receive_blocks(link, crc, first, |data| {
+                if let Some(previous) = held.replace(data) {
+                    file.write_all(&previous).map_err(|e| e.to_string())?;
+                    bytes += previous.len() as u64;
+                    on_event(Event::Progress {
+                        name: name.clone(),
+                        bytes,
+                        size: None,
+                    });
+                }
+                Ok(())
+            })
+            .await?;
+        }
+        if let Some(last) = held {
+            let end = last.iter().rposition(|&b| b != SUB).map_or(0, |i| i + 1);
+            file.write_all(&last[..end]).map_err(|e| e.to_string())?;
+            bytes += end as u64;
+        }
+        on_event(Event::FileDone {
+            name: name.clone(),
+            bytes,
+        });
+        received.push(Transferred {
+            name,
+            size: bytes,
+            path: Some(path),
+        });
+        return Ok(received);
+    }
+
+    loop {
+        let header = match solicit(link, false, &mut crc).await? {
+            Some((0, data)) => data,
+            Some((seq, _)) => {
+                link.cancel().await;
+                return Err(format!("Expected a file header, got block {}", seq));
+            }
+            None => break,
+        };
+        link.send(vec![ACK]).await?;
+        let (name, size) = parse_header(&header);
+        if name.is_empty() {
+            break;
+        }
+        let name = safe_name(&name, &format!("ymodem_recv_{}", received.len()));
+        let (path, name, mut file) = create_file(dir, &name, overwrite)?;
+        on_event(Event::FileStart {
+            name: name.clone(),
+            size,
+        });
+
+        link.send(vec![CRC_REQUEST]).await?;
+        let mut bytes = 0u64;
+        receive_blocks(link, crc, None, |data| {
+            // Drop the padding past the announced size
+            let keep = match size {
+                Some(size) => (size.saturating_sub(bytes) as usize).min(data.len()),
+                None => data.len(),
+            };
+            file.write_all(&data[..keep]).map_err(|e| e.to_string())?;
+            bytes += keep as u64;
+            on_event(Event::Progress {
+                name: name.clone(),
+                bytes,
+                size,
+            });
+            Ok(())
+        })
+        .await?;
+        on_event(Event::FileDone {
+            name: name.clone(),
+            bytes,
+        });
+        received.push(Transferred {
+            name,
+            size: bytes,
+            path: Some(path),
+        });
+    }
+    Ok(received)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        let packet = block_packet(1, b"hi", SUB, false);
+        assert_eq!(packet.len(), 132);
+        assert_eq!(&packet[..5], &[SOH, 1, 0xfe, b'h', b'i']);
+        assert_eq!(packet[130], SUB);
+    }
+
+    #[tokio::test]
+    async fn test_xmodem_round_trip() {
+        let dir = test_dir("xmodem");
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        let (mut sender, mut receiver) = link_pair();
+
+        let files = vec![("fw.bin".to_string(), data.clone())];
+        let send =
+            tokio::spawn(
+                async move { send(Protocol::Xmodem1k, &files, &mut sender, |_| {}).await },
+            );
+        let mut events = Vec::new();
+        let received = receive(
+            Protocol::Xmodem,
+            &dir,
+            "../out.bin",
+            true,
+            &mut receiver,
+            |e| events.push(e),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(send.await.unwrap().unwrap()[0].size, 3000);
+        assert_eq!(received[0].name, "out.bin");
+        assert_eq!(received[0].size, 3000);
+        assert_eq!(std::fs::read(dir.join("out.bin")).unwrap(), data);
+        assert_eq!(
+            events.last(),
+            Some(&Event::FileDone {
+                name: "out.bin".into(),
+                bytes: 3000
+            })
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ymodem_batch() {
+        let dir = test_dir("ymodem");
+        let files = vec![
+            ("a.txt".to_string(), b"hello\x1a\x1a".to_vec()),
+            ("dir/b.bin".to_string(), vec![7u8; 1100]),
+            ("empty".to_string(), Vec::new()),
+        ];
+        // Taken names get a new one
+        std::fs::write(dir.join("a.txt"), b"older").unwrap();
+        let (mut sender, mut receiver) = link_pair();
+
+        let to_send = files.clone();
+        let send =
+            tokio::spawn(
+                async move { send(Protocol::Ymodem, &to_send, &mut sender, |_| {}).await },
+            );
+        let received = receive(Protocol::Ymodem, &dir, "", false, &mut receiver, |_| {})
+            .await
+            .unwrap();
+
+        assert_eq!(send.await.unwrap().unwrap().len(), 3);
+        let names: Vec<_> = received.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["a (1).txt", "b.bin", "empty"]);
+        // The size in the header keeps data that looks like padding
+        assert_eq!(std::fs::read(dir.join("a (1).txt")).unwrap(), files[0].1);
+        assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"older");
+        assert_eq!(std::fs::read(dir.join("b.bin")).unwrap(), files[1].1);
+        assert!(std::fs::read(dir.join("empty")).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_ymodem_header() {
+        assert_eq!(
+            ymodem_header("/home/me/fw/app.bin", 1234).unwrap(),
+            b"app.bin\x001234"
+        );
+        assert!(ymodem_header("/", 0).is_err());
+        assert!(ymodem_header(&"n".repeat(1020), 12345).is_err());
+        assert!(ymodem_header(&"n".repeat(1018), 12345).is_ok());
+    }
+}